@@ -0,0 +1,69 @@
+//! Measures how long it takes to parse a large synthetic stream, sequentially
+//! and, with the `rayon` feature, in parallel.
+//!
+//! Run with `cargo bench --features rayon`.
+
+use srcsrv::{ParseOptions, SrcSrvStream};
+use std::time::{Duration, Instant};
+
+/// A stream of about 30 MB, similar to the streams in Firefox's PDB files.
+fn make_stream() -> Vec<u8> {
+    let mut stream = String::from(
+        "SRCSRV: ini ------------------------------------------------\r\n\
+         VERSION=2\r\n\
+         VERCTRL=http\r\n\
+         SRCSRV: variables ------------------------------------------\r\n\
+         HGSERVER=https://hg.mozilla.org/mozilla-central\r\n\
+         SRCSRVVERCTRL=http\r\n\
+         HTTP_EXTRACT_TARGET=%hgserver%/raw-file/%var3%/%var2%\r\n\
+         SRCSRVTRG=%http_extract_target%\r\n\
+         SRCSRV: source files ---------------------------------------\r\n",
+    );
+    let mut i = 0;
+    while stream.len() < 30_000_000 {
+        let dir = format!("dom/media/module{}", i / 100);
+        stream.push_str(&format!(
+            "c:\\builds\\worker\\checkouts\\gecko\\{}\\File{}.cpp*{}/File{}.cpp*1706d4d54ec68fae1280305b70a02cb24c16ff68\r\n",
+            dir.replace('/', "\\"),
+            i,
+            dir,
+            i
+        ));
+        i += 1;
+    }
+    stream.push_str("SRCSRV: end ------------------------------------------------\r\n");
+    stream.into_bytes()
+}
+
+/// The fastest of several runs of `f`.
+fn measure(f: impl Fn()) -> Duration {
+    (0..10)
+        .map(|_| {
+            let start = Instant::now();
+            f();
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    let stream = make_stream();
+    let parse = |options: &ParseOptions| {
+        let parsed = SrcSrvStream::parse_with_options(&stream, options).unwrap();
+        assert!(parsed.source_file_entries().next().is_some());
+    };
+
+    let options = ParseOptions::new();
+    println!("sequential: {:?}", measure(|| parse(&options)));
+
+    #[cfg(feature = "rayon")]
+    {
+        let options = ParseOptions::new().parallel(true);
+        println!(
+            "parallel:   {:?} ({} threads)",
+            measure(|| parse(&options)),
+            rayon::current_num_threads()
+        );
+    }
+}
@@ -1,9 +1,29 @@
-use crate::errors::{EvalError, ParseError};
+use crate::errors::{EvalError, TemplateError};
+use std::borrow::Cow;
+use std::fmt;
 use std::result::Result;
 
-use memchr::{memchr, memchr2};
+use memchr::{memchr, memchr3};
 
+/// A parsed srcsrv template, such as the value of a variable in the variables
+/// section. Templates consist of literal text, variable references like
+/// `%var2%`, and function calls like `%fnbksl%(%var3%)`.
+///
+/// Use [`AstNode::walk`] to visit all nodes, for example to analyze which
+/// variables a template references.
+///
+/// ```
+/// use srcsrv::AstNode;
+///
+/// # fn wrapper() -> std::result::Result<(), srcsrv::TemplateError> {
+/// let node = AstNode::parse("%targ%\\%fnbksl%(%var3%)\\%fnfile%(%var1%)")?;
+/// assert_eq!(node.variables(), vec!["targ", "var3", "var1"]);
+/// assert_eq!(node.functions(), vec!["fnbksl", "fnfile"]);
+/// # Ok(())
+/// # }
+/// ```
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum AstNode<'a> {
     /// String concatenation of the evaluated child nodes.
     Sequence(Vec<AstNode<'a>>),
@@ -18,34 +38,45 @@ pub enum AstNode<'a> {
     FnBackslash(Box<AstNode<'a>>),
     /// Substitute with the file name extracted from the path.
     FnFile(Box<AstNode<'a>>),
+    /// A call to a function other than the built-in ones, e.g. `%fnfoo%(...)`,
+    /// with the function name (here `fnfoo`) and the argument.
+    Function(&'a str, Box<AstNode<'a>>),
 }
 
 impl<'a> AstNode<'a> {
-    pub fn parse(s: &'a str) -> Result<AstNode<'a>, ParseError> {
+    /// Parse a template.
+    pub fn parse(s: &'a str) -> Result<AstNode<'a>, TemplateError> {
         if s.is_empty() {
             return Ok(AstNode::LiteralString(""));
         }
-        let s = s.as_bytes();
         let (node, _rest) = Self::parse_all(s, false)?;
         Ok(node)
     }
 
     fn parse_all(
-        s: &'a [u8],
+        s: &'a str,
         stop_at_closing_paren: bool,
-    ) -> Result<(AstNode<'a>, &'a [u8]), ParseError> {
-        let (node, rest) = Self::parse_one(s, stop_at_closing_paren)?;
-        if rest.is_empty() || (stop_at_closing_paren && rest[0] == b')') {
+    ) -> Result<(AstNode<'a>, &'a str), TemplateError> {
+        // The number of unclosed literal '(' in a function argument. Literal
+        // parentheses can be interleaved with other nodes, e.g. in
+        // "(%var2%)", so this is tracked across nodes.
+        let mut paren_depth = 0;
+        let is_end = |rest: &str, paren_depth: usize| {
+            rest.is_empty() || (stop_at_closing_paren && paren_depth == 0 && rest.starts_with(')'))
+        };
+
+        let (node, rest) = Self::parse_one(s, stop_at_closing_paren, &mut paren_depth)?;
+        if is_end(rest, paren_depth) {
             return Ok((node, rest));
         }
 
         let mut nodes = vec![node];
         let mut rest = rest;
         loop {
-            let (node, r) = Self::parse_one(rest, stop_at_closing_paren)?;
+            let (node, r) = Self::parse_one(rest, stop_at_closing_paren, &mut paren_depth)?;
             nodes.push(node);
             rest = r;
-            if rest.is_empty() || (stop_at_closing_paren && rest[0] == b')') {
+            if is_end(rest, paren_depth) {
                 return Ok((AstNode::Sequence(nodes), rest));
             }
         }
@@ -53,28 +84,32 @@ impl<'a> AstNode<'a> {
 
     // s must not be empty
     fn parse_one(
-        s: &'a [u8],
+        s: &'a str,
         stop_at_closing_paren: bool,
-    ) -> Result<(AstNode<'a>, &'a [u8]), ParseError> {
-        if s[0] != b'%' {
+        paren_depth: &mut usize,
+    ) -> Result<(AstNode<'a>, &'a str), TemplateError> {
+        if !s.starts_with('%') {
             // We have a literal at the beginning.
             let literal_end = if stop_at_closing_paren {
-                memchr2(b'%', b')', s)
+                Self::find_argument_literal_end(s, paren_depth)
             } else {
-                memchr(b'%', s)
+                memchr(b'%', s.as_bytes())
             };
             let literal_end = literal_end.unwrap_or(s.len());
             let (literal, rest) = s.split_at(literal_end);
-            let string = std::str::from_utf8(literal).map_err(|_| ParseError::InvalidUtf8)?;
-            return Ok((AstNode::LiteralString(string), rest));
+            return Ok((AstNode::LiteralString(literal), rest));
+        }
+
+        // "%%" is an escaped literal percent sign.
+        if let Some(rest) = s.strip_prefix("%%") {
+            return Ok((AstNode::LiteralString(&s[..1]), rest));
         }
 
         // We start with a %.
         let s = &s[1..];
-        let second_percent_pos = memchr(b'%', s).ok_or(ParseError::MissingPercent)?;
+        let second_percent_pos = memchr(b'%', s.as_bytes()).ok_or(TemplateError::MissingPercent)?;
         let rest = &s[second_percent_pos + 1..];
-        let var_name =
-            std::str::from_utf8(&s[..second_percent_pos]).map_err(|_| ParseError::InvalidUtf8)?;
+        let var_name = &s[..second_percent_pos];
         match var_name.to_ascii_lowercase().as_str() {
             "fnvar" => {
                 let (node, rest) = Self::try_parse_args(rest, "fnvar")?;
@@ -88,58 +123,302 @@ impl<'a> AstNode<'a> {
                 let (node, rest) = Self::try_parse_args(rest, "fnfile")?;
                 Ok((AstNode::FnFile(Box::new(node)), rest))
             }
+            name if name.starts_with("fn") && name.len() > 2 && rest.starts_with('(') => {
+                let (node, rest) = Self::try_parse_args(rest, var_name)?;
+                Ok((AstNode::Function(var_name, Box::new(node)), rest))
+            }
             _ => Ok((AstNode::Variable(var_name), rest)),
         }
     }
 
-    fn try_parse_args(s: &'a [u8], function: &str) -> Result<(AstNode<'a>, &'a [u8]), ParseError> {
-        if s.is_empty() || s[0] != b'(' {
-            return Err(ParseError::MissingOpeningParen(function.to_string()));
+    /// Find the end of a literal inside a function argument: the next '%', or
+    /// the next ')' which doesn't close a '(' from the argument.
+    fn find_argument_literal_end(s: &str, paren_depth: &mut usize) -> Option<usize> {
+        let bytes = s.as_bytes();
+        let mut pos = 0;
+        while let Some(offset) = memchr3(b'%', b'(', b')', &bytes[pos..]) {
+            let i = pos + offset;
+            match bytes[i] {
+                b'(' => *paren_depth += 1,
+                b')' if *paren_depth > 0 => *paren_depth -= 1,
+                _ => return Some(i),
+            }
+            pos = i + 1;
+        }
+        None
+    }
+
+    fn try_parse_args(s: &'a str, function: &str) -> Result<(AstNode<'a>, &'a str), TemplateError> {
+        if !s.starts_with('(') {
+            return Err(TemplateError::MissingOpeningParen(function.to_string()));
         }
         let (node, rest) = Self::parse_all(&s[1..], true)?;
-        if rest.is_empty() || rest[0] != b')' {
-            return Err(ParseError::MissingClosingParen(function.to_string()));
+        if !rest.starts_with(')') {
+            return Err(TemplateError::MissingClosingParen(function.to_string()));
         }
         Ok((node, &rest[1..]))
     }
 
-    pub fn eval<F>(&self, f: &mut F) -> Result<String, EvalError>
+    /// Check that `s` can be parsed, without building the AST. This returns
+    /// the same error as [`AstNode::parse`] would.
+    pub(crate) fn validate(s: &str) -> Result<(), TemplateError> {
+        Self::validate_all(s, false).map(|_rest| ())
+    }
+
+    fn validate_all(s: &str, stop_at_closing_paren: bool) -> Result<&str, TemplateError> {
+        let mut paren_depth = 0;
+        let mut rest = s;
+        loop {
+            if rest.is_empty()
+                || (stop_at_closing_paren && paren_depth == 0 && rest.starts_with(')'))
+            {
+                return Ok(rest);
+            }
+            rest = Self::validate_one(rest, stop_at_closing_paren, &mut paren_depth)?;
+        }
+    }
+
+    // Mirrors parse_one. s must not be empty.
+    fn validate_one<'s>(
+        s: &'s str,
+        stop_at_closing_paren: bool,
+        paren_depth: &mut usize,
+    ) -> Result<&'s str, TemplateError> {
+        if !s.starts_with('%') {
+            let literal_end = if stop_at_closing_paren {
+                Self::find_argument_literal_end(s, paren_depth)
+            } else {
+                memchr(b'%', s.as_bytes())
+            };
+            return Ok(&s[literal_end.unwrap_or(s.len())..]);
+        }
+        if let Some(rest) = s.strip_prefix("%%") {
+            return Ok(rest);
+        }
+        let s = &s[1..];
+        let second_percent_pos = memchr(b'%', s.as_bytes()).ok_or(TemplateError::MissingPercent)?;
+        let rest = &s[second_percent_pos + 1..];
+        let var_name = &s[..second_percent_pos];
+        let function = if ["fnvar", "fnbksl", "fnfile"]
+            .iter()
+            .any(|f| var_name.eq_ignore_ascii_case(f))
+        {
+            var_name.to_ascii_lowercase()
+        } else if var_name.len() > 2
+            && var_name
+                .get(..2)
+                .is_some_and(|p| p.eq_ignore_ascii_case("fn"))
+            && rest.starts_with('(')
+        {
+            var_name.to_string()
+        } else {
+            return Ok(rest);
+        };
+        if !rest.starts_with('(') {
+            return Err(TemplateError::MissingOpeningParen(function));
+        }
+        let rest = Self::validate_all(&rest[1..], true)?;
+        if !rest.starts_with(')') {
+            return Err(TemplateError::MissingClosingParen(function));
+        }
+        Ok(&rest[1..])
+    }
+
+    /// Call `f` for this node and all of its descendants, in pre-order, i.e.
+    /// in the order in which they appear in the template text.
+    pub fn walk<F>(&self, f: &mut F)
     where
-        F: FnMut(&str) -> Result<String, EvalError>,
+        F: FnMut(&AstNode<'a>),
     {
+        f(self);
+        match self {
+            AstNode::Sequence(nodes) => {
+                for node in nodes {
+                    node.walk(f);
+                }
+            }
+            AstNode::LiteralString(_) | AstNode::Variable(_) => {}
+            AstNode::FnVar(node)
+            | AstNode::FnBackslash(node)
+            | AstNode::FnFile(node)
+            | AstNode::Function(_, node) => node.walk(f),
+        }
+    }
+
+    /// The names of the variables which are referenced by name, in the order
+    /// of their first reference, with their original case. Variables which
+    /// are referenced indirectly via `%fnvar%` are not included.
+    pub fn variables(&self) -> Vec<&'a str> {
+        let mut vars = Vec::new();
+        self.collect_variables(&mut vars);
+        dedup_preserving_order(vars)
+    }
+
+    /// The names of the functions which are called, in the order of their
+    /// first call: `fnvar`, `fnbksl`, `fnfile`, and the names of any other
+    /// functions with their original case.
+    pub fn functions(&self) -> Vec<&'a str> {
+        let mut functions = Vec::new();
+        self.walk(&mut |node| match node {
+            AstNode::FnVar(_) => functions.push("fnvar"),
+            AstNode::FnBackslash(_) => functions.push("fnbksl"),
+            AstNode::FnFile(_) => functions.push("fnfile"),
+            AstNode::Function(name, _) => functions.push(*name),
+            _ => {}
+        });
+        dedup_preserving_order(functions)
+    }
+
+    /// Append the names of all variables that are referenced by name in this
+    /// node to `vars`. Variables referenced via `%fnvar%` are not included.
+    pub(crate) fn collect_variables(&self, vars: &mut Vec<&'a str>) {
         match self {
             AstNode::Sequence(nodes) => {
-                let values: Result<Vec<String>, EvalError> =
-                    nodes.iter().map(|node| node.eval(f)).collect();
-                Ok(values?.join(""))
+                for node in nodes {
+                    node.collect_variables(vars);
+                }
+            }
+            AstNode::LiteralString(_) => {}
+            AstNode::Variable(var_name) => vars.push(var_name),
+            AstNode::FnVar(node)
+            | AstNode::FnBackslash(node)
+            | AstNode::FnFile(node)
+            | AstNode::Function(_, node) => node.collect_variables(vars),
+        }
+    }
+
+    /// Whether this node contains a `%fnvar%` call anywhere.
+    pub(crate) fn contains_fnvar(&self) -> bool {
+        match self {
+            AstNode::Sequence(nodes) => nodes.iter().any(|node| node.contains_fnvar()),
+            AstNode::LiteralString(_) | AstNode::Variable(_) => false,
+            AstNode::FnVar(_) => true,
+            AstNode::FnBackslash(node) | AstNode::FnFile(node) | AstNode::Function(_, node) => {
+                node.contains_fnvar()
             }
-            AstNode::LiteralString(s) => Ok(s.to_string()),
+        }
+    }
+
+    /// Evaluate this node. `f` is called to get the values of variables, and
+    /// `call` is called to evaluate [`AstNode::Function`] nodes, with the
+    /// function name and the evaluated argument. If `call` returns `None`, the
+    /// function name is treated as a variable which is followed by the argument
+    /// in parentheses.
+    ///
+    /// Literals and single variable values are returned without copying them.
+    pub(crate) fn eval<'v, F, G>(&self, f: &mut F, call: &mut G) -> Result<Cow<'v, str>, EvalError>
+    where
+        'a: 'v,
+        F: FnMut(&str) -> Result<Cow<'v, str>, EvalError>,
+        G: FnMut(&str, &str) -> Result<Option<String>, EvalError>,
+    {
+        match self {
+            AstNode::LiteralString(s) => Ok(Cow::Borrowed(s)),
             AstNode::Variable(var_name) => f(var_name),
+            AstNode::FnFile(node) => match node.eval(f, call)? {
+                Cow::Borrowed(val) => Ok(Cow::Borrowed(file_name(val))),
+                Cow::Owned(val) => match val.rsplit_once('\\') {
+                    Some((_base, file)) => Ok(Cow::Owned(file.to_string())),
+                    None => Ok(Cow::Owned(val)),
+                },
+            },
+            _ => {
+                let mut out = String::new();
+                self.eval_into(&mut out, f, call)?;
+                Ok(Cow::Owned(out))
+            }
+        }
+    }
+
+    /// Evaluate this node and append the result to `out`. See [`AstNode::eval`].
+    fn eval_into<'v, F, G>(
+        &self,
+        out: &mut String,
+        f: &mut F,
+        call: &mut G,
+    ) -> Result<(), EvalError>
+    where
+        'a: 'v,
+        F: FnMut(&str) -> Result<Cow<'v, str>, EvalError>,
+        G: FnMut(&str, &str) -> Result<Option<String>, EvalError>,
+    {
+        match self {
+            AstNode::Sequence(nodes) => {
+                for node in nodes {
+                    node.eval_into(out, f, call)?;
+                }
+            }
+            AstNode::LiteralString(s) => out.push_str(s),
+            AstNode::Variable(var_name) => out.push_str(&f(var_name)?),
             AstNode::FnVar(node) => {
-                let var_name = node.eval(f)?;
-                f(&var_name)
+                let var_name = node.eval(f, call)?;
+                out.push_str(&f(&var_name)?);
             }
             AstNode::FnBackslash(node) => {
-                let val = node.eval(f)?;
-                Ok(val.replace('/', "\\"))
+                let val = node.eval(f, call)?;
+                out.extend(val.chars().map(|c| if c == '/' { '\\' } else { c }));
             }
             AstNode::FnFile(node) => {
-                let val = node.eval(f)?;
-                match val.rsplit_once('\\') {
-                    Some((_base, file)) => Ok(file.to_string()),
-                    None => Ok(val),
+                let val = node.eval(f, call)?;
+                out.push_str(file_name(&val));
+            }
+            AstNode::Function(name, node) => {
+                let val = node.eval(f, call)?;
+                match call(name, &val)? {
+                    Some(result) => out.push_str(&result),
+                    None => {
+                        out.push_str(&f(name)?);
+                        out.push('(');
+                        out.push_str(&val);
+                        out.push(')');
+                    }
                 }
             }
         }
+        Ok(())
+    }
+}
+
+/// The part of `path` after the last backslash.
+fn file_name(path: &str) -> &str {
+    path.rsplit_once('\\').map_or(path, |(_base, file)| file)
+}
+
+/// Remove duplicates from `names`, ASCII case-insensitively, keeping the first
+/// occurrence of each name.
+fn dedup_preserving_order(names: Vec<&str>) -> Vec<&str> {
+    let mut result: Vec<&str> = Vec::with_capacity(names.len());
+    for name in names {
+        if !result.iter().any(|seen| seen.eq_ignore_ascii_case(name)) {
+            result.push(name);
+        }
+    }
+    result
+}
+
+/// Formats the node as template text, which parses back into the same node.
+/// Percent signs in literals are escaped as `%%`.
+impl fmt::Display for AstNode<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AstNode::Sequence(nodes) => nodes.iter().try_for_each(|node| node.fmt(f)),
+            AstNode::LiteralString(s) => f.write_str(&s.replace('%', "%%")),
+            AstNode::Variable(var_name) => write!(f, "%{}%", var_name),
+            AstNode::FnVar(node) => write!(f, "%fnvar%({})", node),
+            AstNode::FnBackslash(node) => write!(f, "%fnbksl%({})", node),
+            AstNode::FnFile(node) => write!(f, "%fnfile%({})", node),
+            AstNode::Function(name, node) => write!(f, "%{}%({})", name, node),
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{AstNode, ParseError};
+    use crate::{AstNode, EvalError, TemplateError};
+    use std::borrow::Cow;
 
     #[test]
-    fn basic_parsing() -> Result<(), ParseError> {
+    fn basic_parsing() -> Result<(), TemplateError> {
         assert_eq!(AstNode::parse("hello")?, AstNode::LiteralString("hello"));
         assert_eq!(
             AstNode::parse("hello%world%")?,
@@ -159,6 +438,145 @@ mod tests {
             AstNode::parse("%fnfile%(world)")?,
             AstNode::FnFile(Box::new(AstNode::LiteralString("world")))
         );
+        assert_eq!(
+            AstNode::parse("%fnLower%(%var2%)")?,
+            AstNode::Function("fnLower", Box::new(AstNode::Variable("var2")))
+        );
+        assert_eq!(
+            AstNode::parse("%fnvalue%(x)")?,
+            AstNode::Function("fnvalue", Box::new(AstNode::LiteralString("x")))
+        );
+        assert_eq!(
+            AstNode::parse("%fnbksl%(a(%var2%)b)c")?,
+            AstNode::Sequence(vec![
+                AstNode::FnBackslash(Box::new(AstNode::Sequence(vec![
+                    AstNode::LiteralString("a("),
+                    AstNode::Variable("var2"),
+                    AstNode::LiteralString(")b"),
+                ]))),
+                AstNode::LiteralString("c")
+            ])
+        );
+        assert_eq!(
+            AstNode::parse("%fnfile%(C:\\Program Files (x86)\\a.h)")?,
+            AstNode::FnFile(Box::new(AstNode::LiteralString(
+                "C:\\Program Files (x86)\\a.h"
+            )))
+        );
+        assert_eq!(
+            AstNode::parse("%fnbksl%(%fnvar%(%var2%))")?,
+            AstNode::FnBackslash(Box::new(AstNode::FnVar(Box::new(AstNode::Variable(
+                "var2"
+            )))))
+        );
+        assert_eq!(
+            AstNode::parse("%fnbksl%(a(b)"),
+            Err(TemplateError::MissingClosingParen("fnbksl".to_string()))
+        );
+        assert_eq!(
+            AstNode::parse("a%%20b%%%var2%")?,
+            AstNode::Sequence(vec![
+                AstNode::LiteralString("a"),
+                AstNode::LiteralString("%"),
+                AstNode::LiteralString("20b"),
+                AstNode::LiteralString("%"),
+                AstNode::Variable("var2"),
+            ])
+        );
+        assert_eq!(
+            AstNode::parse("%fnvalue% (x)")?,
+            AstNode::Sequence(vec![
+                AstNode::Variable("fnvalue"),
+                AstNode::LiteralString(" (x)")
+            ])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn walk() -> Result<(), TemplateError> {
+        let node = AstNode::parse("%fnvar%(%var2%)/%fnLower%(%Var3%)%fnfile%(%var3%)")?;
+        let mut literals = Vec::new();
+        node.walk(&mut |node| {
+            if let AstNode::LiteralString(s) = node {
+                literals.push(*s);
+            }
+        });
+        assert_eq!(literals, vec!["/"]);
+        assert_eq!(node.variables(), vec!["var2", "Var3"]);
+        assert_eq!(node.functions(), vec!["fnvar", "fnLower", "fnfile"]);
+        Ok(())
+    }
+
+    #[test]
+    fn display_round_trip() -> Result<(), TemplateError> {
+        for template in [
+            "https://example.com/a%%20b/%var2%",
+            "%fnbksl%(%fnvar%(%var2%))\\%fnfile%(a(b)%%)",
+            "%fnfoo%(x)%targ%",
+        ] {
+            let node = AstNode::parse(template)?;
+            assert_eq!(node.to_string(), template);
+            assert_eq!(AstNode::parse(&node.to_string())?, node);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn eval_borrows_when_possible() -> Result<(), EvalError> {
+        let value = String::from("C:\\src\\main.cpp");
+        let mut get_var = |_: &str| Ok(Cow::Borrowed(value.as_str()));
+        let mut call = |_: &str, _: &str| Ok(None);
+        let eval = |template: &'static str, get_var: &mut _, call: &mut _| {
+            AstNode::parse(template).unwrap().eval(get_var, call)
+        };
+        assert!(matches!(
+            eval("literal", &mut get_var, &mut call)?,
+            Cow::Borrowed("literal")
+        ));
+        assert!(matches!(
+            eval("%var2%", &mut get_var, &mut call)?,
+            Cow::Borrowed("C:\\src\\main.cpp")
+        ));
+        assert!(matches!(
+            eval("%fnfile%(%var2%)", &mut get_var, &mut call)?,
+            Cow::Borrowed("main.cpp")
+        ));
+        assert_eq!(
+            eval("%fnbksl%(a/%var2%)/%fnfoo%(x)", &mut get_var, &mut call)?,
+            "a\\C:\\src\\main.cpp/C:\\src\\main.cpp(x)"
+        );
         Ok(())
     }
+
+    #[test]
+    fn validate_agrees_with_parse() {
+        for template in [
+            "",
+            "hello",
+            "%var2%",
+            "100%%",
+            "%fnbksl%(%var3%)",
+            "%FNFILE%(a(b)c)",
+            "%fnvar%(%var2%)x",
+            "%fnfoo%(x)",
+            "%fnfoo%x",
+            "%fn%(x)",
+            "%var2",
+            "%fnvar%",
+            "%fnbksl%x",
+            "%fnfile%(abc",
+            "%fnfile%(a(b)",
+            "%fnFoo%(%var2%",
+            "%fnbksl%(%fnfile%(x)",
+            "%fnbksl%(%var2%))",
+        ] {
+            assert_eq!(
+                AstNode::validate(template),
+                AstNode::parse(template).map(|_| ()),
+                "{}",
+                template
+            );
+        }
+    }
 }
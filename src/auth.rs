@@ -0,0 +1,185 @@
+use crate::url_policy::url_scheme_and_host;
+use std::collections::HashMap;
+
+/// Supplies the request headers, usually an `Authorization` header, which are
+/// needed to download from a private source server.
+///
+/// Set it with [`FetchOptions::auth`](crate::FetchOptions::auth). Closures
+/// which take a URL and return the headers implement it as well.
+/// [`HostCredentials`] covers the common case of one credential per host.
+pub trait AuthProvider {
+    /// The extra headers, as `(name, value)` pairs, for a request to `url`.
+    /// Return an empty `Vec` for URLs which don't need authentication.
+    fn headers(&self, url: &str) -> Vec<(String, String)>;
+}
+
+impl<F> AuthProvider for F
+where
+    F: Fn(&str) -> Vec<(String, String)>,
+{
+    fn headers(&self, url: &str) -> Vec<(String, String)> {
+        self(url)
+    }
+}
+
+/// A credential for a source server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Credential {
+    /// `Authorization: Bearer <token>`. GitHub accepts personal access tokens
+    /// this way, for example for `raw.githubusercontent.com`, and Azure DevOps
+    /// accepts OAuth and Entra ID tokens.
+    Bearer(String),
+
+    /// `Authorization: Basic <base64 of username:password>`. Azure DevOps
+    /// accepts personal access tokens this way, with an empty username and
+    /// the token as the password.
+    Basic { username: String, password: String },
+
+    /// An arbitrary header, for servers with custom authentication schemes.
+    Header { name: String, value: String },
+}
+
+impl Credential {
+    /// The `(name, value)` pair of the request header for this credential.
+    pub fn header(&self) -> (String, String) {
+        match self {
+            Credential::Bearer(token) => ("Authorization".to_string(), format!("Bearer {token}")),
+            Credential::Basic { username, password } => {
+                let encoded = encode_base64(format!("{username}:{password}").as_bytes());
+                ("Authorization".to_string(), format!("Basic {encoded}"))
+            }
+            Credential::Header { name, value } => (name.clone(), value.clone()),
+        }
+    }
+}
+
+/// An [`AuthProvider`] with one [`Credential`] per host.
+///
+/// Credentials are only sent to `https` URLs whose host matches one of the
+/// added hosts exactly (ASCII case-insensitively), so that they don't leak
+/// to other hosts which are referenced in a PDB file.
+///
+/// ```
+/// use srcsrv::{Credential, FetchOptions, HostCredentials};
+///
+/// # let github_pat = String::new();
+/// # let azure_devops_pat = String::new();
+/// let credentials = HostCredentials::new()
+///     .add("raw.githubusercontent.com", Credential::Bearer(github_pat))
+///     .add(
+///         "dev.azure.com",
+///         Credential::Basic {
+///             username: String::new(),
+///             password: azure_devops_pat,
+///         },
+///     );
+/// let options = FetchOptions::new().auth(credentials);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HostCredentials {
+    /// lowercase host -> credential
+    credentials: HashMap<String, Credential>,
+}
+
+impl HostCredentials {
+    /// Create an empty set of credentials.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use `credential` for requests to `host`, replacing any previous
+    /// credential for the host. `host` must not include a port.
+    pub fn add(mut self, host: &str, credential: Credential) -> Self {
+        self.credentials
+            .insert(host.to_ascii_lowercase(), credential);
+        self
+    }
+}
+
+impl AuthProvider for HostCredentials {
+    fn headers(&self, url: &str) -> Vec<(String, String)> {
+        let host = match url_scheme_and_host(url) {
+            Some((scheme, host)) if scheme.eq_ignore_ascii_case("https") => host,
+            _ => return Vec::new(),
+        };
+        match self.credentials.get(&host.to_ascii_lowercase()) {
+            Some(credential) => vec![credential.header()],
+            None => Vec::new(),
+        }
+    }
+}
+
+/// Encode `bytes` as standard base64 with padding.
+fn encode_base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, &byte)| {
+            bits | (byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode_base64, AuthProvider, Credential, HostCredentials};
+
+    #[test]
+    fn base64() {
+        assert_eq!(encode_base64(b""), "");
+        assert_eq!(encode_base64(b"f"), "Zg==");
+        assert_eq!(encode_base64(b"fo"), "Zm8=");
+        assert_eq!(encode_base64(b"foobar"), "Zm9vYmFy");
+        assert_eq!(encode_base64(b":pat"), "OnBhdA==");
+    }
+
+    #[test]
+    fn host_credentials() {
+        let credentials = HostCredentials::new()
+            .add(
+                "raw.githubusercontent.com",
+                Credential::Bearer("ghp_x".to_string()),
+            )
+            .add(
+                "Dev.Azure.com",
+                Credential::Basic {
+                    username: String::new(),
+                    password: "pat".to_string(),
+                },
+            );
+        let header = |url| credentials.headers(url).into_iter().next();
+        assert_eq!(
+            header("https://raw.githubusercontent.com/org/repo/abc/main.cpp"),
+            Some(("Authorization".to_string(), "Bearer ghp_x".to_string()))
+        );
+        assert_eq!(
+            header("HTTPS://dev.azure.com:443/org/_apis/git/repositories/repo/items?path=/a.cpp"),
+            Some(("Authorization".to_string(), "Basic OnBhdA==".to_string()))
+        );
+        assert_eq!(
+            header("http://raw.githubusercontent.com/org/repo/abc/main.cpp"),
+            None
+        );
+        assert_eq!(
+            header("https://example.com/raw.githubusercontent.com"),
+            None
+        );
+        assert_eq!(
+            header("https://raw.githubusercontent.com.example.com/main.cpp"),
+            None
+        );
+        assert_eq!(
+            header(r#"https://attacker.example\@raw.githubusercontent.com/x.cpp"#),
+            None
+        );
+    }
+}
@@ -0,0 +1,149 @@
+use std::fmt;
+
+/// The arguments of a command, after the command name.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Args {
+    positional: Vec<String>,
+    flags: Vec<String>,
+    options: Vec<(String, String)>,
+}
+
+/// A problem with the command line, which is reported together with the
+/// usage of the command.
+#[derive(Debug, PartialEq, Eq)]
+pub struct UsageError(pub String);
+
+impl fmt::Display for UsageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for UsageError {}
+
+impl Args {
+    /// Parse `args`. `value_options` are the names of the options which take a
+    /// value, as `--name value` or `--name=value`, and `flags` are the names of
+    /// the options without a value. All other arguments which start with `-`
+    /// are rejected, unless they come after `--`.
+    pub fn parse(
+        args: impl IntoIterator<Item = String>,
+        value_options: &[&str],
+        flags: &[&str],
+    ) -> Result<Args, UsageError> {
+        let mut parsed = Args::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if arg == "--" {
+                parsed.positional.extend(args);
+                break;
+            }
+            if !arg.starts_with('-') || arg == "-" {
+                parsed.positional.push(arg);
+            } else if flags.contains(&arg.as_str()) {
+                parsed.flags.push(arg);
+            } else if value_options.contains(&arg.as_str()) {
+                let value = args
+                    .next()
+                    .ok_or_else(|| UsageError(format!("missing value for {}", arg)))?;
+                parsed.options.push((arg, value));
+            } else if let Some((name, value)) = arg
+                .split_once('=')
+                .filter(|(name, _)| value_options.contains(name))
+            {
+                parsed.options.push((name.to_string(), value.to_string()));
+            } else {
+                return Err(UsageError(format!("unknown option {}", arg)));
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// The positional arguments, which have to be exactly one for each of
+    /// `names`. The names are used in the error messages.
+    pub fn positionals<const N: usize>(&self, names: [&str; N]) -> Result<[&str; N], UsageError> {
+        if let Some(name) = names.get(self.positional.len()) {
+            return Err(UsageError(format!("missing {}", name)));
+        }
+        if let Some(arg) = self.positional.get(N) {
+            return Err(UsageError(format!("unexpected argument {}", arg)));
+        }
+        Ok(std::array::from_fn(|index| self.positional[index].as_str()))
+    }
+
+    /// The positional arguments, which have to be at least one. `name` is used
+    /// in the error message.
+    pub fn at_least_one_positional(&self, name: &str) -> Result<&[String], UsageError> {
+        match self.positional.is_empty() {
+            true => Err(UsageError(format!("missing {}", name))),
+            false => Ok(&self.positional),
+        }
+    }
+
+    /// Whether the flag `name` was given.
+    pub fn flag(&self, name: &str) -> bool {
+        self.flags.iter().any(|flag| flag == name)
+    }
+
+    /// The value of the option `name`, if it was given.
+    pub fn value(&self, name: &str) -> Option<&str> {
+        self.options
+            .iter()
+            .rev()
+            .find(|(option, _)| option == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// All values of the option `name`, which can be given several times.
+    pub fn values<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.options
+            .iter()
+            .filter(move |(option, _)| option == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Args, UsageError};
+
+    fn parse(args: &[&str]) -> Result<Args, UsageError> {
+        Args::parse(
+            args.iter().map(|arg| arg.to_string()),
+            &["--path", "--out"],
+            &["--json"],
+        )
+    }
+
+    #[test]
+    fn parse_args() {
+        let args = parse(&["--path", "a.cpp", "a.pdb", "--json", "--path=b.cpp"]).unwrap();
+        assert_eq!(args.positionals(["PDB"]), Ok(["a.pdb"]));
+        assert_eq!(
+            args.positionals(["PDB", "path"]),
+            Err(UsageError("missing path".to_string()))
+        );
+        assert!(args.flag("--json"));
+        assert!(!args.flag("--entries"));
+        assert_eq!(
+            args.values("--path").collect::<Vec<_>>(),
+            ["a.cpp", "b.cpp"]
+        );
+        assert_eq!(args.value("--path"), Some("b.cpp"));
+
+        let args = parse(&["a.pdb", "--", "--json"]).unwrap();
+        assert!(!args.flag("--json"));
+        assert_eq!(
+            args.positionals(["PDB"]),
+            Err(UsageError("unexpected argument --json".to_string()))
+        );
+        assert_eq!(
+            parse(&["--out"]),
+            Err(UsageError("missing value for --out".to_string()))
+        );
+        assert_eq!(
+            parse(&["--entries"]),
+            Err(UsageError("unknown option --entries".to_string()))
+        );
+    }
+}
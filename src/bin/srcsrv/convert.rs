@@ -0,0 +1,153 @@
+use crate::args::{Args, UsageError};
+use crate::CommandResult;
+use srcsrv::{SourceLink, SrcSrvStream, UnconvertedEntry};
+use std::error::Error;
+
+pub const USAGE: &str = "Usage: srcsrv convert --to sourcelink [--out <file>] <PDB or stream file>
+       srcsrv convert --from sourcelink (--files <file> | --pdb <file>) [--out <file>] <JSON or portable PDB file>
+
+Convert a srcsrv stream into a Source Link JSON document, or the reverse. The
+entries which can't be converted are printed to stderr.
+
+A srcsrv stream lists every source file, so converting from Source Link needs
+the paths of the files, from a list or from a native PDB file.
+
+Options:
+  --to sourcelink      Convert the srcsrv stream of the file to Source Link.
+  --from sourcelink    Convert the Source Link JSON document, or the Source
+                       Link information of the portable PDB file, to srcsrv.
+  --files <file>       A file with the paths of the source files, one per
+                       line, or - for stdin.
+  --pdb <file>         Use the source files of this PDB file.
+  --out <file>         Write the result to this file instead of stdout.";
+
+/// The original path and the reason of each entry which was not converted.
+type Unconverted = Vec<(String, String)>;
+
+/// The first bytes of the metadata of a portable PDB file.
+const PORTABLE_PDB_MAGIC: &[u8] = b"BSJB";
+
+pub fn run(args: Vec<String>) -> CommandResult {
+    let args = Args::parse(args, &["--to", "--from", "--files", "--pdb", "--out"], &[])?;
+    let [input_path] = args.positionals(["input file"])?;
+    let (output, unconverted) = match (args.value("--to"), args.value("--from")) {
+        (Some("sourcelink"), None) => {
+            let stream = crate::load_stream(input_path)?;
+            to_source_link(stream.stream())
+        }
+        (None, Some("sourcelink")) => {
+            let data = std::fs::read(input_path)?;
+            let source_link = match data.starts_with(PORTABLE_PDB_MAGIC) {
+                true => SourceLink::from_portable_pdb(&data)?
+                    .ok_or_else(|| format!("{} has no Source Link information", input_path))?,
+                false => SourceLink::parse_json(&data)?,
+            };
+            from_source_link(&source_link, &crate::read_source_files(&args)?)?
+        }
+        _ => {
+            let message = "expected either --to sourcelink or --from sourcelink";
+            return Err(UsageError(message.to_string()).into());
+        }
+    };
+    crate::write_output(args.value("--out"), &output)?;
+    for (path, reason) in &unconverted {
+        eprintln!("not converted: {}: {}", path, reason);
+    }
+    Ok(())
+}
+
+/// The Source Link JSON for `stream`, and the entries which were not
+/// converted.
+fn to_source_link(stream: &SrcSrvStream<'_>) -> (Vec<u8>, Unconverted) {
+    let conversion = stream.to_source_link();
+    let unconverted = conversion
+        .unconverted
+        .into_iter()
+        .filter_map(|entry| match entry {
+            UnconvertedEntry::NotDownload {
+                original_path,
+                method,
+            } => Some((
+                original_path,
+                format!("not a plain download ({:?})", method.kind()),
+            )),
+            UnconvertedEntry::EvalFailed {
+                original_path,
+                error,
+            } => Some((original_path, error.to_string())),
+            _ => None,
+        })
+        .collect();
+    let json = format!("{}\n", conversion.source_link.to_json());
+    (json.into_bytes(), unconverted)
+}
+
+/// The srcsrv stream for the `files` which `source_link` has URLs for, and the
+/// files which were not converted.
+fn from_source_link(
+    source_link: &SourceLink,
+    files: &[String],
+) -> Result<(Vec<u8>, Unconverted), Box<dyn Error>> {
+    let bytes = source_link.to_srcsrv_stream_builder(files).to_bytes()?;
+    let stream = SrcSrvStream::parse(&bytes)?;
+    let mut unconverted = Vec::new();
+    for file in files {
+        if stream.source_for_path(file, "")?.is_none() {
+            let reason = match source_link.source_for_path(file) {
+                Some(_) => "the URL contains a %, which srcsrv can't express",
+                None => "no Source Link document matches the path",
+            };
+            unconverted.push((file.clone(), reason.to_string()));
+        }
+    }
+    Ok((bytes, unconverted))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{from_source_link, to_source_link};
+    use srcsrv::{SourceLink, SrcSrvStream};
+
+    #[test]
+    fn convert_source_link() {
+        let source_link = SourceLink::parse_json(
+            br#"{
+                "documents": {
+                    "C:\\src\\app\\*": "https://example.com/app/0123abcd/*",
+                    "C:\\src\\app\\100%.cs": "https://example.com/app/0123abcd/100%25.cs"
+                }
+            }"#,
+        )
+        .unwrap();
+        let files = [
+            r#"C:\src\app\Program.cs"#.to_string(),
+            r#"C:\src\app\100%.cs"#.to_string(),
+            r#"C:\other\Other.cs"#.to_string(),
+        ];
+        let (bytes, unconverted) = from_source_link(&source_link, &files).unwrap();
+        assert_eq!(
+            unconverted,
+            [
+                (
+                    files[1].clone(),
+                    "the URL contains a %, which srcsrv can't express".to_string()
+                ),
+                (
+                    files[2].clone(),
+                    "no Source Link document matches the path".to_string()
+                ),
+            ]
+        );
+
+        let stream = SrcSrvStream::parse(&bytes).unwrap();
+        let (json, unconverted) = to_source_link(&stream);
+        assert!(unconverted.is_empty());
+        assert_eq!(
+            String::from_utf8(json).unwrap(),
+            concat!(
+                r#"{"documents":{"C:\\src\\app\\*":"https://example.com/app/0123abcd/*"}}"#,
+                "\n"
+            )
+        );
+    }
+}
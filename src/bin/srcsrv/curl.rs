@@ -0,0 +1,108 @@
+use srcsrv::{HttpStatusError, SourceFetcher};
+use std::error::Error;
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+/// A [`SourceFetcher`] which runs the `curl` program, so that the tool doesn't
+/// need an HTTP client of its own. `curl` is part of Windows 10 and later and
+/// of most other systems.
+pub struct Curl;
+
+impl SourceFetcher for Curl {
+    fn fetch(&self, url: &str) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        self.fetch_with_headers(url, &[])
+    }
+
+    fn fetch_with_headers(
+        &self,
+        url: &str,
+        headers: &[(String, String)],
+    ) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        request(url, headers, &[])
+    }
+}
+
+impl Curl {
+    /// Check that `url` can be downloaded, with a HEAD request, or with a GET
+    /// request whose response is discarded if the server doesn't support HEAD.
+    pub fn check(&self, url: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        match request(url, &[], &["--head"]) {
+            Err(error)
+                if matches!(
+                    error.downcast_ref::<HttpStatusError>(),
+                    Some(HttpStatusError { status: 405 | 501 })
+                ) =>
+            {
+                let null_device = if cfg!(windows) { "NUL" } else { "/dev/null" };
+                request(url, &[], &["--output", null_device]).map(drop)
+            }
+            result => result.map(drop),
+        }
+    }
+}
+
+/// Run curl for `url` with the additional `args`, and return the response body
+/// of a successful response.
+fn request(
+    url: &str,
+    headers: &[(String, String)],
+    args: &[&str],
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    // The headers are passed on stdin rather than as arguments, so that
+    // credentials don't show up in the process list.
+    let mut child = Command::new("curl")
+        .args(["--silent", "--show-error", "--location"])
+        .args(["--header", "@-", "--write-out", "%{http_code}"])
+        .args(args)
+        .arg("--")
+        .arg(url)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|error| io::Error::new(error.kind(), format!("Could not run curl: {}", error)))?;
+    let mut stdin = child.stdin.take().unwrap();
+    for (name, value) in headers {
+        writeln!(stdin, "{}: {}", name, value)?;
+    }
+    drop(stdin);
+    let output = child.wait_with_output()?;
+    let (body, status) = split_status(output.stdout);
+    match status {
+        Some(200..=299) => Ok(body),
+        Some(status) if status != 0 => Err(HttpStatusError { status }.into()),
+        _ => {
+            let message = String::from_utf8_lossy(&output.stderr);
+            Err(io::Error::other(message.trim().to_string()).into())
+        }
+    }
+}
+
+/// Split the output of curl into the response body and the HTTP status which
+/// `--write-out %{http_code}` appends to it. The status is 0 if no response
+/// was received.
+fn split_status(mut output: Vec<u8>) -> (Vec<u8>, Option<u16>) {
+    if output.len() < 3 {
+        return (output, None);
+    }
+    let status = output.split_off(output.len() - 3);
+    let status = std::str::from_utf8(&status)
+        .ok()
+        .and_then(|status| status.parse().ok());
+    (output, status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::split_status;
+
+    #[test]
+    fn curl_output() {
+        assert_eq!(
+            split_status(b"int main() {}\n200".to_vec()),
+            (b"int main() {}\n".to_vec(), Some(200))
+        );
+        assert_eq!(split_status(b"000".to_vec()), (Vec::new(), Some(0)));
+        assert_eq!(split_status(Vec::new()), (Vec::new(), None));
+    }
+}
@@ -0,0 +1,96 @@
+use crate::args::Args;
+use crate::CommandResult;
+use srcsrv::SrcSrvStream;
+use std::io::{self, Write};
+
+pub const USAGE: &str = "Usage: srcsrv dump [--entries] <PDB or stream file>
+
+Print the ini fields, the variables and the number of entries of the srcsrv
+stream, like `pdbstr -r -s:srcsrv`, but formatted for reading.
+
+Options:
+  --entries    Also print every entry, with the values of var1, ..., varN";
+
+pub fn run(args: Vec<String>) -> CommandResult {
+    let args = Args::parse(args, &[], &["--entries"])?;
+    let [pdb_path] = args.positionals(["PDB or stream file"])?;
+    let stream = crate::load_stream(pdb_path)?;
+    let stdout = io::stdout();
+    dump(stream.stream(), args.flag("--entries"), &mut stdout.lock())?;
+    Ok(())
+}
+
+fn dump(stream: &SrcSrvStream<'_>, entries: bool, out: &mut impl Write) -> io::Result<()> {
+    writeln!(out, "srcsrv stream version {}", stream.version())?;
+    let snapshot = stream.snapshot();
+
+    writeln!(out, "\nini:")?;
+    for (name, value) in &snapshot.ini_fields {
+        writeln!(out, "  {} = {}", name, value)?;
+    }
+    writeln!(out, "\nvariables:")?;
+    let name_width = snapshot
+        .variables
+        .iter()
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or(0);
+    for (name, value) in &snapshot.variables {
+        writeln!(out, "  {:width$} = {}", name, value, width = name_width)?;
+    }
+
+    writeln!(out, "\nentries: {}", snapshot.source_file_entries.len())?;
+    if entries {
+        for vars in &snapshot.source_file_entries {
+            writeln!(out, "  {}", vars.join(" * "))?;
+        }
+    }
+
+    if !stream.warnings().is_empty() {
+        writeln!(out, "\nwarnings:")?;
+        for warning in stream.warnings() {
+            writeln!(out, "  {}", warning)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::dump;
+    use srcsrv::SrcSrvStream;
+
+    #[test]
+    fn dump_stream() {
+        let stream = r#"SRCSRV: ini ------------------------------------------------
+VERSION=2
+VERCTRL=http
+SRCSRV: variables ------------------------------------------
+HTTP_ALIAS=https://example.com/
+SRCSRVTRG=%HTTP_ALIAS%%var2%
+SRCSRV: source files ---------------------------------------
+c:\build\a.cpp*a.cpp
+c:\build\b.cpp*b.cpp
+SRCSRV: end ------------------------------------------------"#;
+        let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
+        let mut out = Vec::new();
+        dump(&stream, true, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            r#"srcsrv stream version 2
+
+ini:
+  VERSION = 2
+  VERCTRL = http
+
+variables:
+  HTTP_ALIAS = https://example.com/
+  SRCSRVTRG  = %HTTP_ALIAS%%var2%
+
+entries: 2
+  c:\build\a.cpp * a.cpp
+  c:\build\b.cpp * b.cpp
+"#
+        );
+    }
+}
@@ -0,0 +1,213 @@
+use crate::args::{Args, UsageError};
+use crate::CommandResult;
+use srcsrv::{DebuggerSourcePath, EvalOptions, SrcSrvStream};
+use std::io::{self, BufRead, IsTerminal, Write};
+
+pub const USAGE: &str = "Usage: srcsrv eval [options] <PDB or stream file> [<template>...]
+
+Evaluate templates like %HTTP_ALIAS%/%var3%/%var2% against the variables of
+the srcsrv stream and the values of a file entry, and print the results. This
+is useful for trying out templates while writing an indexing script.
+
+If no templates are given, they are read from stdin, one per line. Lines which
+start with : are commands:
+  :entry <original path>    Use the values of the entry for this path.
+  :entry                    Print the values of the current entry.
+  :set <name>=<value>       Set var1 ... var10 of the current entry, or
+                            override a variable of the stream.
+
+Options:
+  --entry <original path>    Use the values of the entry for this path.
+                             Defaults to the first entry of the stream.
+  --set <name>=<value>       Like :set. Can be given several times.
+  --targ <dir>               The extraction base path (%targ%). Defaults to
+                             the srv* cache directory of _NT_SOURCE_PATH, or
+                             else to the placeholder %targ%.";
+
+pub fn run(args: Vec<String>) -> CommandResult {
+    let args = Args::parse(args, &["--entry", "--set", "--targ"], &[])?;
+    let positional = args.at_least_one_positional("PDB or stream file")?;
+    let (pdb_path, templates) = (&positional[0], &positional[1..]);
+    let stream = crate::load_stream(pdb_path)?;
+    let source_path = DebuggerSourcePath::from_env();
+    let targ = args
+        .value("--targ")
+        .or_else(|| source_path.as_ref()?.extraction_base_path())
+        .unwrap_or("%targ%");
+
+    let mut session = Session::new(stream.stream(), targ);
+    match args.value("--entry") {
+        Some(path) => session.select_entry(path).map_err(UsageError)?,
+        None => session.select_first_entry(),
+    }
+    for assignment in args.values("--set") {
+        session.set(assignment).map_err(UsageError)?;
+    }
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    if !templates.is_empty() {
+        for template in templates {
+            writeln!(out, "{}", session.evaluate(template)?)?;
+        }
+        return Ok(());
+    }
+
+    let stdin = io::stdin();
+    let interactive = stdin.is_terminal();
+    let mut lines = stdin.lock().lines();
+    loop {
+        if interactive {
+            write!(out, "> ")?;
+            out.flush()?;
+        }
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => break,
+        };
+        match session.handle_line(&line) {
+            Ok(output) => write!(out, "{}", output)?,
+            Err(error) => writeln!(out, "error: {}", error)?,
+        }
+    }
+    Ok(())
+}
+
+/// The stream, the values of the current entry and the variable overrides.
+struct Session<'s, 'a> {
+    stream: &'s SrcSrvStream<'a>,
+    targ: &'s str,
+    entry: Vec<String>,
+    options: EvalOptions,
+}
+
+impl<'s, 'a> Session<'s, 'a> {
+    fn new(stream: &'s SrcSrvStream<'a>, targ: &'s str) -> Self {
+        Session {
+            stream,
+            targ,
+            entry: Vec::new(),
+            options: EvalOptions::new(),
+        }
+    }
+
+    fn select_entry(&mut self, path: &str) -> Result<(), String> {
+        let (_, values) = self
+            .stream
+            .source_file_entries()
+            .find(|(entry_path, _)| entry_path.eq_ignore_ascii_case(path))
+            .ok_or_else(|| format!("{} was not found in the srcsrv stream", path))?;
+        self.entry = values.into_iter().map(str::to_string).collect();
+        Ok(())
+    }
+
+    fn select_first_entry(&mut self) {
+        if let Some((_, values)) = self.stream.source_file_entries().next() {
+            self.entry = values.into_iter().map(str::to_string).collect();
+        }
+    }
+
+    /// Handle `name=value`: set a value of the entry or override a variable.
+    fn set(&mut self, assignment: &str) -> Result<(), String> {
+        let (name, value) = assignment
+            .split_once('=')
+            .ok_or_else(|| format!("expected <name>=<value>, not {}", assignment))?;
+        let entry_index = name
+            .to_ascii_lowercase()
+            .strip_prefix("var")
+            .and_then(|index| index.parse::<usize>().ok())
+            .filter(|index| (1..=10).contains(index));
+        match entry_index {
+            Some(index) => {
+                if self.entry.len() < index {
+                    self.entry.resize(index, String::new());
+                }
+                self.entry[index - 1] = value.to_string();
+            }
+            None => self.options = std::mem::take(&mut self.options).var(name, value),
+        }
+        Ok(())
+    }
+
+    fn evaluate(&self, template: &str) -> Result<String, srcsrv::EvalError> {
+        let entry: Vec<&str> = self.entry.iter().map(String::as_str).collect();
+        self.stream
+            .evaluate_template_for_entry(template, &entry, self.targ, &self.options)
+    }
+
+    /// Handle a line of input, and return the output for it, with a trailing
+    /// newline.
+    fn handle_line(&mut self, line: &str) -> Result<String, String> {
+        let (command, arg) = match line.strip_prefix(':') {
+            Some(command) => command.split_once(' ').unwrap_or((command, "")),
+            None => {
+                let value = self.evaluate(line).map_err(|error| error.to_string())?;
+                return Ok(format!("{}\n", value));
+            }
+        };
+        match (command, arg.trim()) {
+            ("entry", "") => Ok(self
+                .entry
+                .iter()
+                .enumerate()
+                .map(|(index, value)| format!("var{:<3}{}\n", index + 1, value))
+                .collect()),
+            ("entry", path) => self.select_entry(path).map(|()| String::new()),
+            ("set", assignment) => self.set(assignment).map(|()| String::new()),
+            _ => Err(format!("unknown command :{}", command)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Session;
+    use srcsrv::SrcSrvStream;
+
+    #[test]
+    fn eval_session() {
+        let stream = r#"SRCSRV: ini ------------------------------------------------
+VERSION=2
+SRCSRV: variables ------------------------------------------
+HGSERVER=https://hg.mozilla.org/mozilla-central
+SRCSRVTRG=%hgserver%/raw-file/%var3%/%var2%
+SRCSRV: source files ---------------------------------------
+/builds/SSE.cpp*mozglue/build/SSE.cpp*1706d4d5
+/builds/Other.cpp*other/Other.cpp*0123abcd
+SRCSRV: end ------------------------------------------------"#;
+        let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
+        let mut session = Session::new(&stream, r#"C:\src"#);
+        session.select_first_entry();
+        let mut handle = |line: &str| session.handle_line(line);
+        assert_eq!(
+            handle("%srcsrvtrg%"),
+            Ok(
+                "https://hg.mozilla.org/mozilla-central/raw-file/1706d4d5/mozglue/build/SSE.cpp\n"
+                    .to_string()
+            )
+        );
+        assert_eq!(handle(":entry /builds/other.cpp"), Ok(String::new()));
+        assert_eq!(handle(":set var3=fedcba98"), Ok(String::new()));
+        assert_eq!(
+            handle(":set HGSERVER=https://mirror.example.com"),
+            Ok(String::new())
+        );
+        assert_eq!(
+            handle(r#"%targ%\%var3%"#),
+            Ok("C:\\src\\fedcba98\n".to_string())
+        );
+        assert_eq!(
+            handle(":entry"),
+            Ok("var1  /builds/Other.cpp\nvar2  other/Other.cpp\nvar3  fedcba98\n".to_string())
+        );
+        assert_eq!(
+            handle("%srcsrvtrg%"),
+            Ok("https://mirror.example.com/raw-file/fedcba98/other/Other.cpp\n".to_string())
+        );
+        assert_eq!(
+            handle("%var4%"),
+            Err("Could not resolve srcsrv variable name var4.".to_string())
+        );
+        assert_eq!(handle(":quit"), Err("unknown command :quit".to_string()));
+    }
+}
@@ -0,0 +1,110 @@
+use crate::args::{Args, UsageError};
+use crate::curl::Curl;
+use crate::CommandResult;
+use srcsrv::{
+    extract_source, fetch_source_with_options, DebuggerSourcePath, ErrorPersistenceTracker,
+    EvalOptions, ExecOptions, FetchOptions, SourceCache, SourceRetrievalMethod, SrcSrvStream,
+};
+use std::error::Error;
+use std::path::PathBuf;
+
+pub const USAGE: &str = "Usage: srcsrv fetch [options] <PDB or stream file>
+
+Get the source files of the PDB, like `srctool -x`, and print where each file
+was stored. Files are downloaded with curl, copied from file shares, or, with
+--allow-commands, extracted by running the command of the stream.
+
+Options:
+  --path <file>       The original path of a file to get, as in the stream.
+                      Can be given several times. Defaults to all files.
+  --out <dir>         The directory to store the files in, which is the
+                      extraction base path (%targ%) of the stream. Defaults to
+                      the srv* cache directory of _NT_SOURCE_PATH.
+  --allow-commands    Run the commands of the stream. Only use this for PDB
+                      files which you trust.";
+
+pub fn run(args: Vec<String>) -> CommandResult {
+    let args = Args::parse(args, &["--path", "--out"], &["--allow-commands"])?;
+    let [pdb_path] = args.positionals(["PDB or stream file"])?;
+    let stream = crate::load_stream(pdb_path)?;
+    let stream = stream.stream();
+    let out_dir = match args.value("--out") {
+        Some(out_dir) => out_dir.to_string(),
+        None => DebuggerSourcePath::from_env()
+            .and_then(|path| path.extraction_base_path().map(str::to_string))
+            .ok_or_else(|| UsageError("missing --out".to_string()))?,
+    };
+    let paths: Vec<&str> = match args.values("--path").next() {
+        Some(_) => args.values("--path").collect(),
+        None => stream.source_file_entries().map(|(path, _)| path).collect(),
+    };
+
+    let mut fetcher = Fetcher {
+        stream,
+        cache: SourceCache::new(out_dir),
+        eval_options: EvalOptions::new().recognize_commands(true),
+        allow_commands: args.flag("--allow-commands"),
+        tracker: ErrorPersistenceTracker::new(stream),
+    };
+    let mut failed = 0;
+    for path in &paths {
+        match fetcher.fetch(path) {
+            Ok(local_path) => println!("{} -> {}", path, local_path.display()),
+            Err(error) => {
+                eprintln!("{}: {}", path, error);
+                failed += 1;
+            }
+        }
+    }
+    match failed {
+        0 => Ok(()),
+        _ => Err(format!("could not get {} of {} files", failed, paths.len()).into()),
+    }
+}
+
+struct Fetcher<'a> {
+    stream: &'a SrcSrvStream<'a>,
+    cache: SourceCache,
+    eval_options: EvalOptions,
+    allow_commands: bool,
+    tracker: ErrorPersistenceTracker,
+}
+
+impl Fetcher<'_> {
+    /// Get the file for `path` and return where it was stored.
+    fn fetch(&mut self, path: &str) -> Result<PathBuf, Box<dyn Error>> {
+        let method = self
+            .cache
+            .source_for_path_with_vars(self.stream, path, &self.eval_options)?
+            .ok_or("not found in the srcsrv stream")?;
+        if let Some(cached_path) = self.cache.cached_path(&method) {
+            return Ok(cached_path);
+        }
+        match &method {
+            SourceRetrievalMethod::Download { .. }
+            | SourceRetrievalMethod::DownloadWithDecode { .. } => {
+                let target_path = self.cache.target_path(&method).unwrap();
+                let result =
+                    fetch_source_with_options(&Curl, &method, &target_path, &FetchOptions::new())?;
+                Ok(result.local_path)
+            }
+            SourceRetrievalMethod::CopyFile { source_path, .. } => {
+                let contents = std::fs::read(source_path)?;
+                Ok(self.cache.store(&method, &contents)?)
+            }
+            SourceRetrievalMethod::Other { .. } => {
+                Err("the stream doesn't say how to get this file".into())
+            }
+            _ if !self.allow_commands => {
+                Err("getting this file runs a command; pass --allow-commands to run it".into())
+            }
+            _ if self.tracker.should_skip(&method) => {
+                Err("skipped after an earlier error of the same version control system".into())
+            }
+            _ => {
+                let result = extract_source(&method, &ExecOptions::new(), &mut self.tracker)?;
+                Ok(result.local_path)
+            }
+        }
+    }
+}
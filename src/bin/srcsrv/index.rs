@@ -0,0 +1,139 @@
+use crate::args::{Args, UsageError};
+use crate::CommandResult;
+use srcsrv::{GitilesLayout, SrcSrvStream, SrcSrvStreamBuilder};
+use std::error::Error;
+
+pub const USAGE: &str = "Usage: srcsrv index --preset <preset> [options]
+
+Write a srcsrv stream for the source files below the checkout root, which can
+be added to the PDB file with `pdbstr -w -s:srcsrv -i:<file>` or with
+`srcsrv write-pdb`. The files are read from a list or from the PDB file.
+
+Presets and their options:
+  github     --repo <owner>/<repo>
+  hg         --url <server URL>, e.g. https://hg.mozilla.org/mozilla-central
+  gitiles    --url <repository URL>
+  azure      --repo <organization>/<project>/<repository>
+             [--api-version <version>], defaults to 7.1
+  p4         --port <P4PORT> --depot-root <depot path of the root>;
+             the revision is a changelist like @1234
+
+Options:
+  --root <dir>            The directory of the checkout at build time.
+  --revision <rev>        The commit, tag or changelist of the build.
+  --files <file>          A file with the paths of the source files, one per
+                          line, or - for stdin.
+  --pdb <file>            Index the source files of this PDB file.
+  --map <local>=<path>    Map the local directory to the repository path, for
+                          files outside of the root. Can be given several times.
+  --out <file>            Write the stream to this file instead of stdout.";
+
+const VALUE_OPTIONS: &[&str] = &[
+    "--preset",
+    "--root",
+    "--revision",
+    "--repo",
+    "--url",
+    "--api-version",
+    "--port",
+    "--depot-root",
+    "--files",
+    "--pdb",
+    "--map",
+    "--out",
+];
+
+pub fn run(args: Vec<String>) -> CommandResult {
+    let args = Args::parse(args, VALUE_OPTIONS, &[])?;
+    args.positionals([])?;
+    let files = crate::read_source_files(&args)?;
+    let files: Vec<&str> = files.iter().map(String::as_str).collect();
+
+    let bytes = index(&args, &files)?;
+    let entry_count = SrcSrvStream::parse(&bytes)?.source_file_entries().count();
+    crate::write_output(args.value("--out"), &bytes)?;
+    eprintln!("indexed {} of {} files", entry_count, files.len());
+    Ok(())
+}
+
+/// The stream for the `files` which are below the checkout root.
+fn index(args: &Args, files: &[&str]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let required = |name: &str| {
+        args.value(name)
+            .ok_or_else(|| UsageError(format!("missing {}", name)))
+    };
+    let root = required("--root")?;
+    let revision = required("--revision")?;
+    let preset = required("--preset")?;
+    let mut builder = match preset {
+        "github" => match required("--repo")?.split('/').collect::<Vec<_>>()[..] {
+            [owner, repo] => SrcSrvStreamBuilder::github(owner, repo, revision),
+            _ => return Err(UsageError("expected --repo <owner>/<repo>".to_string()).into()),
+        },
+        "hg" => SrcSrvStreamBuilder::hg(required("--url")?, revision),
+        "gitiles" => {
+            SrcSrvStreamBuilder::gitiles(required("--url")?, revision, GitilesLayout::PythonCommand)
+        }
+        "azure" => match required("--repo")?.split('/').collect::<Vec<_>>()[..] {
+            [organization, project, repository] => SrcSrvStreamBuilder::azure_devops(
+                organization,
+                project,
+                repository,
+                revision,
+                args.value("--api-version").unwrap_or("7.1"),
+            ),
+            _ => {
+                let message = "expected --repo <organization>/<project>/<repository>";
+                return Err(UsageError(message.to_string()).into());
+            }
+        },
+        "p4" => SrcSrvStreamBuilder::perforce(required("--port")?),
+        _ => return Err(UsageError(format!("unknown preset {}", preset)).into()),
+    };
+    for mapping in args.values("--map") {
+        let (local_prefix, repo_relative_prefix) = mapping
+            .split_once('=')
+            .ok_or_else(|| UsageError(format!("expected --map <local>=<path>, not {}", mapping)))?;
+        builder.map_path_prefix(local_prefix, repo_relative_prefix);
+    }
+    if preset == "p4" {
+        builder.add_perforce_entries_below(required("--depot-root")?, root, files, revision);
+    } else {
+        builder.add_http_entries_below(root, files);
+    }
+    Ok(builder.to_bytes()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{index, VALUE_OPTIONS};
+    use crate::args::Args;
+    use srcsrv::{SourceRetrievalMethod, SrcSrvStream};
+
+    #[test]
+    fn index_github() {
+        let args = [
+            "--preset=github",
+            "--repo=example/app",
+            "--revision=0123abcd",
+            r#"--root=C:\build\app"#,
+            r#"--map=C:\build\obj\include=include"#,
+        ];
+        let args = Args::parse(args.iter().map(|arg| arg.to_string()), VALUE_OPTIONS, &[]).unwrap();
+        let files = [
+            r#"C:\build\app\src\main.cpp"#,
+            r#"C:\build\obj\include\config.h"#,
+            r#"C:\Program Files\Microsoft Visual Studio\VC\include\vector"#,
+        ];
+        let bytes = index(&args, &files).unwrap();
+        let stream = SrcSrvStream::parse(&bytes).unwrap();
+        assert_eq!(stream.source_file_entries().count(), 2);
+        assert_eq!(
+            stream.source_for_path(files[1], "").unwrap(),
+            Some(SourceRetrievalMethod::Download {
+                url: "https://raw.githubusercontent.com/example/app/0123abcd/include/config.h"
+                    .to_string()
+            })
+        );
+    }
+}
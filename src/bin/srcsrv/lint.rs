@@ -0,0 +1,267 @@
+use crate::args::Args;
+use crate::CommandResult;
+use srcsrv::{CommandConcern, EvalOptions, SrcSrvStream, ValidationFinding};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::{self, Write};
+
+pub const USAGE: &str = "Usage: srcsrv lint [--json] [--deny-warnings] <PDB or stream file>
+
+Check the srcsrv stream for problems, evaluating every entry:
+
+errors
+  undefined-variable     A template references a variable which isn't defined.
+  unknown-function       A template calls a function other than %fnvar%,
+                         %fnbksl% and %fnfile%.
+  recursive-variable     A variable references itself.
+  missing-entry-field    The templates reference a var2 ... var10 field which
+                         the entry doesn't have.
+  eval-error             The entry can't be evaluated.
+warnings
+  parse-warning          A line was skipped, or the end marker is missing.
+  unused-variable        A variable isn't referenced by any SRCSRV* variable.
+  invalid-error-variable SRCSRVERRVAR doesn't name one of var1 ... var10.
+  suspicious-command     The command runs an unknown program, runs code,
+                         chains commands, writes outside of %targ%, or can be
+                         injected into by the entry.
+  insecure-url           The entry is downloaded over plain http.
+
+Problems which affect several entries are reported once, with the number of
+entries and the first of them. Exits with an error if there are errors.
+
+Options:
+  --json             Print a JSON array of the problems, with the \"severity\",
+                     \"code\", \"message\", \"entry_count\" and \"example_path\"
+                     of each.
+  --deny-warnings    Also exit with an error if there are warnings.";
+
+pub fn run(args: Vec<String>) -> CommandResult {
+    let args = Args::parse(args, &[], &["--json", "--deny-warnings"])?;
+    let [pdb_path] = args.positionals(["PDB or stream file"])?;
+    let stream = crate::load_stream(pdb_path)?;
+
+    let findings = lint(stream.stream());
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    if args.flag("--json") {
+        writeln!(out, "{}", serde_json::to_string(&findings)?)?;
+    } else {
+        for finding in &findings {
+            writeln!(out, "{}", finding)?;
+        }
+    }
+    let count = |severity| {
+        findings
+            .iter()
+            .filter(|finding| finding.severity == severity)
+            .count()
+    };
+    let (error_count, warning_count) = (count(Severity::Error), count(Severity::Warning));
+    if error_count > 0 || (warning_count > 0 && args.flag("--deny-warnings")) {
+        let message = format!("{} errors and {} warnings", error_count, warning_count);
+        return Err(message.into());
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem of the stream, or of one or more of its entries.
+#[derive(Debug, PartialEq, Eq, serde::Serialize)]
+struct Finding {
+    severity: Severity,
+    code: &'static str,
+    message: String,
+    /// The number of entries with this problem, or 0 for problems of the
+    /// stream as a whole.
+    entry_count: usize,
+    /// The original path of the first entry with this problem.
+    example_path: Option<String>,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{}[{}]: {}", severity, self.code, self.message)?;
+        match (self.entry_count, &self.example_path) {
+            (1, Some(path)) => write!(f, " (entry {})", path),
+            (count, Some(path)) => write!(f, " ({} entries, e.g. {})", count, path),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The findings of the stream first, then the findings of the entries, in
+/// the order in which they were first encountered.
+fn lint(stream: &SrcSrvStream<'_>) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let mut add = |severity, code, message: String| {
+        findings.push(Finding {
+            severity,
+            code,
+            message,
+            entry_count: 0,
+            example_path: None,
+        })
+    };
+    for warning in stream.warnings() {
+        add(Severity::Warning, "parse-warning", warning.to_string());
+    }
+    let mut entries_with_missing_fields = Vec::new();
+    for finding in stream.validate() {
+        let (severity, code) = match &finding {
+            ValidationFinding::UndefinedVariable { .. } => (Severity::Error, "undefined-variable"),
+            ValidationFinding::UnknownFunction { .. } => (Severity::Error, "unknown-function"),
+            ValidationFinding::RecursiveVariable { .. } => (Severity::Error, "recursive-variable"),
+            ValidationFinding::UnusedVariable { .. } => (Severity::Warning, "unused-variable"),
+            ValidationFinding::InvalidErrorVariable { .. } => {
+                (Severity::Warning, "invalid-error-variable")
+            }
+            ValidationFinding::MissingEntryFields {
+                original_path,
+                field_count,
+                required_field_count,
+            } => {
+                entries_with_missing_fields.push((
+                    original_path.clone(),
+                    format!(
+                        "The entry has {} values, but the templates reference var{}.",
+                        field_count, required_field_count
+                    ),
+                ));
+                continue;
+            }
+            _ => (Severity::Warning, "validation"),
+        };
+        add(severity, code, finding.to_string());
+    }
+
+    // The index of the finding for each code and message.
+    let mut entry_findings: HashMap<(&'static str, String), usize> = HashMap::new();
+    let mut add_entry = |severity, code, message: String, path: &str| {
+        let index = *entry_findings
+            .entry((code, message.clone()))
+            .or_insert_with(|| {
+                findings.push(Finding {
+                    severity,
+                    code,
+                    message,
+                    entry_count: 0,
+                    example_path: Some(path.to_string()),
+                });
+                findings.len() - 1
+            });
+        findings[index].entry_count += 1;
+    };
+    for (path, message) in &entries_with_missing_fields {
+        add_entry(
+            Severity::Error,
+            "missing-entry-field",
+            message.clone(),
+            path,
+        );
+    }
+    let missing_fields: HashSet<&str> = entries_with_missing_fields
+        .iter()
+        .map(|(path, _)| path.as_str())
+        .collect();
+    let has_command = stream.get_raw_var("SRCSRVCMD").is_some();
+    let options = EvalOptions::new().recognize_commands(true);
+    for (path, _) in stream.source_file_entries() {
+        let method = match stream.source_for_path_with_vars(path, "%targ%", &options) {
+            Ok(Some(method)) => method,
+            Ok(None) => continue,
+            // The missing fields were already reported.
+            Err(_) if missing_fields.contains(path) => continue,
+            Err(error) => {
+                add_entry(Severity::Error, "eval-error", error.to_string(), path);
+                continue;
+            }
+        };
+        if method.is_insecure() {
+            let message = "The file is downloaded over plain http.".to_string();
+            add_entry(Severity::Warning, "insecure-url", message, path);
+        }
+        if !has_command {
+            continue;
+        }
+        if let Ok(Some(analysis)) = stream.analyze_command_for_path(path, "%targ%") {
+            for concern in &analysis.concerns {
+                let message = describe_concern(concern);
+                add_entry(Severity::Warning, "suspicious-command", message, path);
+            }
+        }
+    }
+    findings
+}
+
+/// A message for `concern`, without the parts which differ between entries
+/// so that the entries with the same concern are reported together.
+fn describe_concern(concern: &CommandConcern) -> String {
+    match concern {
+        CommandConcern::UnknownProgram(program) => {
+            format!("The command runs the unknown program {}.", program)
+        }
+        CommandConcern::RunsCode(program) => format!("The command runs code with {}.", program),
+        CommandConcern::UnsafeOption { program, option } => {
+            format!(
+                "The command passes the unsafe option {} to {}.",
+                option, program
+            )
+        }
+        CommandConcern::ChainsCommands(operator) => {
+            format!("The command chains commands with {}.", operator)
+        }
+        CommandConcern::WritesOutsideTarget(_) => {
+            "The command writes outside of the extraction directory.".to_string()
+        }
+        CommandConcern::VariableInjection { variable, .. } => format!(
+            "The value of {} contains cmd.exe metacharacters which end up in the command.",
+            variable
+        ),
+        concern => format!("The command is suspicious: {:?}", concern),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::lint;
+    use srcsrv::SrcSrvStream;
+
+    #[test]
+    fn lint_stream() {
+        let stream = r#"SRCSRV: ini ------------------------------------------------
+VERSION=2
+SRCSRV: variables ------------------------------------------
+SRCSRVERRVAR=server
+UNUSED=unused
+SRCSRVTRG=%targ%\%var2%\%fnfile%(%var1%)
+SRCSRVCMD=tool.exe %var3% > "%srcsrvtrg%" & echo done
+SRCSRV: source files ---------------------------------------
+c:\build\a.cpp*abc*a.cpp
+c:\build\b.cpp*abc
+c:\build\c.cpp*abc*c.cpp
+SRCSRV: end ------------------------------------------------"#;
+        let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
+        let findings: Vec<String> = lint(&stream).iter().map(|f| f.to_string()).collect();
+        assert_eq!(
+            findings,
+            [
+                "warning[invalid-error-variable]: SRCSRVERRVAR names server, which is not one of var1, ..., var10.",
+                "warning[unused-variable]: The variable UNUSED is never used.",
+                r#"error[missing-entry-field]: The entry has 2 values, but the templates reference var3. (entry c:\build\b.cpp)"#,
+                r#"warning[suspicious-command]: The command chains commands with &. (2 entries, e.g. c:\build\a.cpp)"#,
+                r#"warning[suspicious-command]: The command runs the unknown program tool. (2 entries, e.g. c:\build\a.cpp)"#,
+                r#"warning[suspicious-command]: The command runs the unknown program echo. (2 entries, e.g. c:\build\a.cpp)"#,
+            ]
+        );
+    }
+}
@@ -0,0 +1,249 @@
+use crate::args::Args;
+use crate::CommandResult;
+use srcsrv::{CommandPreview, DebuggerSourcePath, EvalOptions, SourceRetrievalMethod};
+use std::io::{self, Write};
+
+pub const USAGE: &str = "Usage: srcsrv lookup [options] <PDB or stream file> <original path>
+
+Print how the debugger would get the source file for the original path: the
+URL to download, or the command to run with its environment, and the path at
+which the file is stored. Nothing is downloaded or run.
+
+Options:
+  --targ <dir>    The extraction base path (%targ%). Defaults to the srv*
+                  cache directory of _NT_SOURCE_PATH, or else to the
+                  placeholder %targ%.
+  --json          Print a JSON object with the path, the serialized
+                  retrieval method as \"source\", and the \"command\" preview.";
+
+pub fn run(args: Vec<String>) -> CommandResult {
+    let args = Args::parse(args, &["--targ"], &["--json"])?;
+    let [pdb_path, path] = args.positionals(["PDB or stream file", "original path"])?;
+    let stream = crate::load_stream(pdb_path)?;
+    let stream = stream.stream();
+    let source_path = DebuggerSourcePath::from_env();
+    let targ = args
+        .value("--targ")
+        .or_else(|| source_path.as_ref()?.extraction_base_path())
+        .unwrap_or("%targ%");
+
+    let options = EvalOptions::new().recognize_commands(true);
+    let method = stream
+        .source_for_path_with_vars(path, targ, &options)?
+        .ok_or_else(|| format!("{} was not found in the srcsrv stream", path))?;
+    let preview = stream.command_preview_for_path(path, targ)?;
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    if args.flag("--json") {
+        let json = serde_json::json!({ "path": path, "source": method, "command": preview });
+        writeln!(out, "{}", json)?;
+    } else {
+        print_method(&method, preview.as_ref(), &mut out)?;
+    }
+    Ok(())
+}
+
+fn print_method(
+    method: &SourceRetrievalMethod,
+    preview: Option<&CommandPreview>,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let (kind, fields, target_path) = describe(method);
+    let mut print = |label: &str, value: &str| writeln!(out, "{:12} {}", label, value);
+    print("kind", kind)?;
+    for (label, value) in fields {
+        print(label, &value)?;
+    }
+    if let Some(preview) = preview {
+        print("command", &preview.command)?;
+        let mut env: Vec<_> = preview.env.iter().collect();
+        env.sort();
+        for (index, (name, value)) in env.into_iter().enumerate() {
+            let label = if index == 0 { "env" } else { "" };
+            print(label, &format!("{}={}", name, value))?;
+        }
+    }
+    if let Some(target_path) = target_path {
+        print("target path", target_path)?;
+    }
+    Ok(())
+}
+
+/// The name of the variant of `method`, its fields other than the command,
+/// the environment and the target path, and its target path.
+fn describe(
+    method: &SourceRetrievalMethod,
+) -> (&'static str, Vec<(&'static str, String)>, Option<&str>) {
+    let field = |label, value: &str| (label, value.to_string());
+    let optional_field = |label, value: &Option<String>| value.as_deref().map(|v| field(label, v));
+    match method {
+        SourceRetrievalMethod::Download { url } => ("Download", vec![field("url", url)], None),
+        SourceRetrievalMethod::DownloadWithDecode { url, encoding } => (
+            "DownloadWithDecode",
+            vec![
+                field("url", url),
+                field("encoding", &format!("{:?}", encoding)),
+            ],
+            None,
+        ),
+        SourceRetrievalMethod::GitFile {
+            repo,
+            revision,
+            path,
+            target_path,
+            ..
+        } => (
+            "GitFile",
+            vec![
+                field("repo", repo),
+                field("revision", revision),
+                field("path", path),
+            ],
+            Some(target_path),
+        ),
+        SourceRetrievalMethod::TfsItem {
+            server,
+            item_path,
+            version,
+            target_path,
+            ..
+        } => (
+            "TfsItem",
+            vec![
+                field("server", server),
+                field("item path", item_path),
+                field("version", version),
+            ],
+            Some(target_path),
+        ),
+        SourceRetrievalMethod::Perforce {
+            port,
+            depot_path,
+            revision,
+            target_path,
+            ..
+        } => (
+            "Perforce",
+            optional_field("port", port)
+                .into_iter()
+                .chain([field("depot path", depot_path), field("revision", revision)])
+                .collect(),
+            Some(target_path),
+        ),
+        SourceRetrievalMethod::SourceDepot {
+            port,
+            depot_path,
+            revision,
+            target_path,
+            ..
+        } => (
+            "SourceDepot",
+            optional_field("port", port)
+                .into_iter()
+                .chain([field("depot path", depot_path), field("revision", revision)])
+                .collect(),
+            Some(target_path),
+        ),
+        SourceRetrievalMethod::Svn {
+            url,
+            revision,
+            target_path,
+            ..
+        } => (
+            "Svn",
+            vec![field("url", url), field("revision", revision)],
+            Some(target_path),
+        ),
+        SourceRetrievalMethod::Cvs {
+            root,
+            path,
+            revision,
+            target_path,
+            ..
+        } => (
+            "Cvs",
+            vec![
+                field("root", root),
+                field("path", path),
+                field("revision", revision),
+            ],
+            Some(target_path),
+        ),
+        SourceRetrievalMethod::CabExtract {
+            archive_path,
+            member,
+            target_path,
+            ..
+        } => (
+            "CabExtract",
+            Some(field("archive", archive_path))
+                .into_iter()
+                .chain(optional_field("member", member))
+                .collect(),
+            Some(target_path),
+        ),
+        SourceRetrievalMethod::CopyFile {
+            source_path,
+            target_path,
+        } => (
+            "CopyFile",
+            vec![field("source path", source_path)],
+            Some(target_path),
+        ),
+        SourceRetrievalMethod::ExecuteCommand {
+            version_ctrl,
+            target_path,
+            ..
+        } => (
+            "ExecuteCommand",
+            optional_field("version ctrl", version_ctrl)
+                .into_iter()
+                .collect(),
+            Some(target_path),
+        ),
+        SourceRetrievalMethod::Other { .. } => ("Other", Vec::new(), None),
+        _ => ("Unknown", Vec::new(), None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::print_method;
+    use srcsrv::{EvalOptions, SrcSrvStream};
+
+    #[test]
+    fn lookup_git_file() {
+        let stream = r#"SRCSRV: ini ------------------------------------------------
+VERSION=1
+SRCSRV: variables ------------------------------------------
+SRCSRVENV=GIT_PAGER=cat<BS>GIT_DIR=C:\repo.git
+SRCSRVTRG=%targ%\%var2%\%fnfile%(%var1%)
+SRCSRVCMD=git.exe -C "C:\repo" show %var2%:%var3% > "%srcsrvtrg%"
+SRCSRV: source files ---------------------------------------
+C:\repo\src\main.cpp*abc123*src/main.cpp
+SRCSRV: end ------------------------------------------------"#
+            .replace("<BS>", "\x08");
+        let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
+        let path = r#"C:\repo\src\main.cpp"#;
+        let options = EvalOptions::new().recognize_commands(true);
+        let method = stream
+            .source_for_path_with_vars(path, "%targ%", &options)
+            .unwrap()
+            .unwrap();
+        let preview = stream.command_preview_for_path(path, "%targ%").unwrap();
+        let mut out = Vec::new();
+        print_method(&method, preview.as_ref(), &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            r#"kind         GitFile
+repo         C:\repo
+revision     abc123
+path         src/main.cpp
+command      git.exe -C "C:\repo" show abc123:src/main.cpp > "%targ%\abc123\main.cpp"
+env          GIT_DIR=C:\repo.git
+             GIT_PAGER=cat
+target path  %targ%\abc123\main.cpp
+"#
+        );
+    }
+}
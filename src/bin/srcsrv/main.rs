@@ -0,0 +1,195 @@
+//! The `srcsrv` command line tool, which is built with the `cli` feature.
+
+mod args;
+mod convert;
+mod curl;
+mod dump;
+mod eval;
+mod fetch;
+mod index;
+mod lint;
+mod lookup;
+mod pack;
+mod stats;
+mod verify;
+mod write_pdb;
+
+use args::{Args, UsageError};
+use srcsrv::OwnedSrcSrvStream;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::process::ExitCode;
+
+/// The first bytes of a PDB file.
+const PDB_MAGIC: &[u8] = b"Microsoft C/C++ MSF 7.00\r\n";
+
+const USAGE: &str = "Usage: srcsrv <command> [<args>]
+
+Commands:
+  convert    Convert between srcsrv streams and Source Link
+  dump       Print the ini fields, variables and entries of a srcsrv stream
+  eval       Evaluate templates against a srcsrv stream and an entry
+  fetch      Download or extract source files into a directory
+  index      Write a srcsrv stream for the source files of a build
+  lint       Check a srcsrv stream for problems
+  lookup     Print how the source file for an original path is obtained
+  pack       Download the source files into a tar archive
+  stats      Summarize the srcsrv streams of many PDB files
+  verify     Check that the URLs of all entries can still be downloaded
+  write-pdb  Store a srcsrv stream in a PDB file
+
+Run `srcsrv <command> --help` for the arguments of a command.";
+
+/// The result of running a command.
+pub type CommandResult = Result<(), Box<dyn Error>>;
+
+/// A subcommand, with its usage text and the function which runs it with the
+/// arguments after the command name.
+struct Command {
+    name: &'static str,
+    usage: &'static str,
+    run: fn(Vec<String>) -> CommandResult,
+}
+
+const COMMANDS: &[Command] = &[
+    Command {
+        name: "convert",
+        usage: convert::USAGE,
+        run: convert::run,
+    },
+    Command {
+        name: "dump",
+        usage: dump::USAGE,
+        run: dump::run,
+    },
+    Command {
+        name: "eval",
+        usage: eval::USAGE,
+        run: eval::run,
+    },
+    Command {
+        name: "fetch",
+        usage: fetch::USAGE,
+        run: fetch::run,
+    },
+    Command {
+        name: "index",
+        usage: index::USAGE,
+        run: index::run,
+    },
+    Command {
+        name: "lint",
+        usage: lint::USAGE,
+        run: lint::run,
+    },
+    Command {
+        name: "lookup",
+        usage: lookup::USAGE,
+        run: lookup::run,
+    },
+    Command {
+        name: "pack",
+        usage: pack::USAGE,
+        run: pack::run,
+    },
+    Command {
+        name: "stats",
+        usage: stats::USAGE,
+        run: stats::run,
+    },
+    Command {
+        name: "verify",
+        usage: verify::USAGE,
+        run: verify::run,
+    },
+    Command {
+        name: "write-pdb",
+        usage: write_pdb::USAGE,
+        run: write_pdb::run,
+    },
+];
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let command_name = match args.next() {
+        Some(name) if name != "--help" && name != "-h" => name,
+        _ => {
+            println!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+    };
+    let command = match COMMANDS.iter().find(|command| command.name == command_name) {
+        Some(command) => command,
+        None => {
+            eprintln!("error: unknown command {}\n\n{}", command_name, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    let args: Vec<String> = args.collect();
+    if args.iter().any(|arg| arg == "--help" || arg == "-h") {
+        println!("{}", command.usage);
+        return ExitCode::SUCCESS;
+    }
+    match (command.run)(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => match error.downcast_ref::<UsageError>() {
+            Some(error) => {
+                eprintln!("error: {}\n\n{}", error, command.usage);
+                ExitCode::from(2)
+            }
+            None => {
+                eprintln!("error: {}", error);
+                ExitCode::FAILURE
+            }
+        },
+    }
+}
+
+/// Read the srcsrv stream from a PDB file, or from a file which contains the
+/// stream itself, e.g. a file which was written for `pdbstr`.
+pub fn load_stream(path: &str) -> Result<OwnedSrcSrvStream, Box<dyn Error>> {
+    if is_pdb_file(path)? {
+        return match OwnedSrcSrvStream::from_pdb_path(path)? {
+            Some(stream) => Ok(stream),
+            None => Err(format!("{} is not source-indexed", path).into()),
+        };
+    }
+    Ok(OwnedSrcSrvStream::parse(std::fs::read(path)?)?)
+}
+
+/// Whether the file at `path` starts like a PDB file.
+pub fn is_pdb_file(path: &str) -> io::Result<bool> {
+    let mut magic = [0; PDB_MAGIC.len()];
+    let magic_len = File::open(path)?.read(&mut magic)?;
+    Ok(&magic[..magic_len] == PDB_MAGIC)
+}
+
+/// The paths of the source files from the file given with `--files`, with
+/// one path per line, or `-` for stdin, or from the PDB file given with
+/// `--pdb`.
+pub fn read_source_files(args: &Args) -> Result<Vec<String>, Box<dyn Error>> {
+    let list = match (args.value("--files"), args.value("--pdb")) {
+        (Some("-"), None) => io::read_to_string(io::stdin())?,
+        (Some(path), None) => std::fs::read_to_string(path)?,
+        (None, Some(path)) => {
+            let mut pdb = pdb::PDB::open(File::open(path)?)?;
+            return Ok(srcsrv::pdb_source_files(&mut pdb)?);
+        }
+        _ => return Err(UsageError("expected either --files or --pdb".to_string()).into()),
+    };
+    Ok(list
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// Write `bytes` to the file at `path`, or to stdout if there is none.
+pub fn write_output(path: Option<&str>, bytes: &[u8]) -> io::Result<()> {
+    match path {
+        Some(path) => std::fs::write(path, bytes),
+        None => io::stdout().write_all(bytes),
+    }
+}
@@ -0,0 +1,127 @@
+use crate::args::{Args, UsageError};
+use crate::curl::Curl;
+use crate::CommandResult;
+use srcsrv::{write_source_pack, FetchOptions, UnfetchedEntry, SOURCE_PACK_MANIFEST_PATH};
+use std::fs::File;
+use std::io::BufWriter;
+
+pub const USAGE: &str = "Usage: srcsrv pack --out <file> [--jobs <n>] <PDB or stream file>
+
+Download every file of the srcsrv stream which is downloaded from a URL, and
+write the files to a tar archive, for debugging on machines without network
+access. Each file is stored at the host and path of its URL, and the archive
+lists the original path of each file in srcsrv-manifest.txt. Print the
+entries which could not be packed, and exit with an error if there are any.
+
+Options:
+  --out <file>  The tar archive to write.
+  --jobs <n>    The number of downloads to run at the same time. Defaults to 8.";
+
+pub fn run(args: Vec<String>) -> CommandResult {
+    let args = Args::parse(args, &["--out", "--jobs"], &[])?;
+    let [pdb_path] = args.positionals(["PDB or stream file"])?;
+    let out = args
+        .value("--out")
+        .ok_or_else(|| UsageError("missing --out".to_string()))?;
+    let jobs = match args.value("--jobs") {
+        Some(jobs) => match jobs.parse() {
+            Ok(jobs) if jobs > 0 => jobs,
+            _ => return Err(UsageError(format!("invalid --jobs {}", jobs)).into()),
+        },
+        None => 8,
+    };
+    let stream = crate::load_stream(pdb_path)?;
+
+    let options = FetchOptions::new().max_concurrent_downloads(jobs);
+    let writer = BufWriter::new(File::create(out)?);
+    let pack = write_source_pack(&Curl, stream.stream(), writer, &options, |progress| {
+        eprint!(
+            "\rdownloaded {} of {} files",
+            progress.completed, progress.total
+        );
+    })?;
+    eprintln!();
+    for entry in &pack.skipped {
+        let (path, problem) = describe_skipped(entry);
+        println!("{}: {}", path, problem);
+    }
+    println!(
+        "packed {} files into {}, see {} for their original paths",
+        pack.members.len(),
+        out,
+        SOURCE_PACK_MANIFEST_PATH
+    );
+    match pack.skipped.len() {
+        0 => Ok(()),
+        count => Err(format!("could not pack {} files", count).into()),
+    }
+}
+
+/// The original path of `entry` and why it was not packed.
+fn describe_skipped(entry: &UnfetchedEntry) -> (&str, String) {
+    match entry {
+        UnfetchedEntry::NotDownload {
+            original_path,
+            method,
+        } => (
+            original_path,
+            format!("not downloadable ({:?})", method.kind()),
+        ),
+        UnfetchedEntry::EvalFailed {
+            original_path,
+            error,
+        } => (original_path, error.to_string()),
+        UnfetchedEntry::FetchFailed {
+            original_path,
+            error,
+        } => (original_path, error.to_string()),
+        other => ("", format!("{:?}", other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::describe_skipped;
+    use srcsrv::{write_source_pack, FetchOptions, SrcSrvStream};
+    use std::error::Error;
+
+    #[test]
+    fn pack_stream() {
+        let stream = r#"SRCSRV: ini ------------------------------------------------
+VERSION=2
+SRCSRV: variables ------------------------------------------
+HTTP=https://example.com/%var2%
+SHARE=\\server\share\%var2%
+SRCSRVTRG=%fnvar%(%var3%)
+SRCSRV: source files ---------------------------------------
+c:\build\a.cpp*a.cpp*HTTP
+c:\build\missing.cpp*missing.cpp*HTTP
+c:\build\b.cpp*b.cpp*SHARE
+c:\build\c.cpp*c.cpp*UNKNOWN
+SRCSRV: end ------------------------------------------------"#;
+        let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
+        let fetch = |url: &str| -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+            match url.ends_with("missing.cpp") {
+                true => Err("HTTP status 404".into()),
+                false => Ok(b"int a;\n".to_vec()),
+            }
+        };
+        let mut archive = Vec::new();
+        let pack =
+            write_source_pack(&fetch, &stream, &mut archive, &FetchOptions::new(), |_| {}).unwrap();
+        assert_eq!(pack.members.len(), 1);
+        assert_eq!(pack.members[0].member_path, "example.com/a.cpp");
+        let skipped: Vec<(&str, String)> = pack.skipped.iter().map(describe_skipped).collect();
+        assert_eq!(skipped.len(), 3);
+        assert_eq!(skipped[0].0, r#"c:\build\missing.cpp"#);
+        assert!(skipped[0].1.contains("HTTP status 404"), "{}", skipped[0].1);
+        assert_eq!(
+            skipped[1],
+            (
+                r#"c:\build\b.cpp"#,
+                "not downloadable (CopyFile)".to_string()
+            )
+        );
+        assert_eq!(skipped[2].0, r#"c:\build\c.cpp"#);
+    }
+}
@@ -0,0 +1,231 @@
+use crate::args::Args;
+use crate::CommandResult;
+use srcsrv::{OwnedSrcSrvStream, RetrievalKind, SrcSrvStream};
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+pub const USAGE: &str = "Usage: srcsrv stats [--json] <PDB file or directory>...
+
+Summarize the srcsrv streams of many PDB files, e.g. of a symbol server: how
+many of the files are source-indexed, which version control systems and
+servers they use, how many entries there are of each kind, and how many
+entries are downloaded over plain http. Directories are searched recursively
+for .pdb files. Files which can't be read are reported on stderr.
+
+Options:
+  --json    Print the summary as a JSON object.";
+
+pub fn run(args: Vec<String>) -> CommandResult {
+    let args = Args::parse(args, &[], &["--json"])?;
+    let mut paths = Vec::new();
+    for path in args.at_least_one_positional("PDB file or directory")? {
+        find_pdb_files(Path::new(path), &mut paths)?;
+    }
+
+    let mut audit = Audit::default();
+    for path in &paths {
+        match load_optional_stream(path) {
+            Ok(stream) => audit.add(stream.as_ref().map(OwnedSrcSrvStream::stream)),
+            Err(error) => {
+                eprintln!("error: {}: {}", path.display(), error);
+                audit.file_count += 1;
+                audit.unreadable_count += 1;
+            }
+        }
+    }
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    if args.flag("--json") {
+        writeln!(out, "{}", serde_json::to_string(&audit)?)?;
+    } else {
+        print_audit(&audit, &mut out)?;
+    }
+    Ok(())
+}
+
+/// Add `path` to `paths` if it is a file, or the `.pdb` files below it if it
+/// is a directory, in sorted order.
+fn find_pdb_files(path: &Path, paths: &mut Vec<PathBuf>) -> io::Result<()> {
+    if !path.is_dir() {
+        paths.push(path.to_owned());
+        return Ok(());
+    }
+    let mut children = std::fs::read_dir(path)?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    children.sort();
+    for child in children {
+        let is_pdb = matches!(child.extension(), Some(ext) if ext.eq_ignore_ascii_case("pdb"));
+        if child.is_dir() || is_pdb {
+            find_pdb_files(&child, paths)?;
+        }
+    }
+    Ok(())
+}
+
+/// The srcsrv stream of the PDB or stream file at `path`, or `None` if the PDB
+/// file is not source-indexed.
+fn load_optional_stream(
+    path: &Path,
+) -> Result<Option<OwnedSrcSrvStream>, Box<dyn std::error::Error>> {
+    let path = path.to_str().ok_or("the path is not valid UTF-8")?;
+    match crate::is_pdb_file(path)? {
+        true => Ok(OwnedSrcSrvStream::from_pdb_path(path)?),
+        false => Ok(Some(crate::load_stream(path)?)),
+    }
+}
+
+/// The totals over the srcsrv streams of many files.
+#[derive(Debug, Default, serde::Serialize)]
+struct Audit {
+    /// The number of files, including the unreadable ones.
+    file_count: usize,
+    indexed_count: usize,
+    unreadable_count: usize,
+    /// The number of source-indexed files per version control system.
+    vcs_counts: BTreeMap<String, usize>,
+    /// The number of source-indexed files per server.
+    host_counts: BTreeMap<String, usize>,
+    entry_count: usize,
+    /// The number of entries per retrieval kind, over all files.
+    kind_counts: BTreeMap<RetrievalKind, usize>,
+    eval_error_count: usize,
+    /// The number of entries which are downloaded over plain http.
+    insecure_count: usize,
+}
+
+impl Audit {
+    /// Count a file which was read, with its stream if it is source-indexed.
+    fn add(&mut self, stream: Option<&SrcSrvStream<'_>>) {
+        self.file_count += 1;
+        let stream = match stream {
+            Some(stream) => stream,
+            None => return,
+        };
+        self.indexed_count += 1;
+        let vcs = match stream.vcs_kind() {
+            Some(kind) => format!("{:?}", kind),
+            None => "unknown".to_string(),
+        };
+        *self.vcs_counts.entry(vcs).or_default() += 1;
+
+        let stats = stream.stats();
+        for host in stats.hosts {
+            *self.host_counts.entry(host).or_default() += 1;
+        }
+        self.entry_count += stats.entry_count;
+        for (kind, count) in stats.kind_counts {
+            *self.kind_counts.entry(kind).or_default() += count;
+        }
+        self.eval_error_count += stats.eval_error_count;
+        self.insecure_count += stream.insecure_entries().len();
+    }
+}
+
+fn print_audit(audit: &Audit, out: &mut impl Write) -> io::Result<()> {
+    let percent = |count: usize, total: usize| match total {
+        0 => 0.0,
+        total => count as f64 * 100.0 / total as f64,
+    };
+    writeln!(out, "files                {}", audit.file_count)?;
+    writeln!(
+        out,
+        "source-indexed       {} ({:.1}%)",
+        audit.indexed_count,
+        percent(audit.indexed_count, audit.file_count)
+    )?;
+    writeln!(
+        out,
+        "not source-indexed   {}",
+        audit.file_count - audit.indexed_count - audit.unreadable_count
+    )?;
+    writeln!(out, "unreadable           {}", audit.unreadable_count)?;
+    writeln!(out, "\nversion control")?;
+    for (vcs, count) in &audit.vcs_counts {
+        writeln!(out, "  {:18} {}", vcs, count)?;
+    }
+    writeln!(out, "\nservers")?;
+    for (host, count) in &audit.host_counts {
+        writeln!(out, "  {:18} {}", host, count)?;
+    }
+    writeln!(out, "\nentries              {}", audit.entry_count)?;
+    for (kind, count) in &audit.kind_counts {
+        writeln!(out, "  {:18} {}", format!("{:?}", kind), count)?;
+    }
+    writeln!(
+        out,
+        "  {:18} {}",
+        "evaluation errors", audit.eval_error_count
+    )?;
+    let download_count = audit
+        .kind_counts
+        .get(&RetrievalKind::Download)
+        .copied()
+        .unwrap_or(0);
+    writeln!(
+        out,
+        "\nplain http downloads {} of {} ({:.1}%)",
+        audit.insecure_count,
+        download_count,
+        percent(audit.insecure_count, download_count)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{print_audit, Audit};
+    use srcsrv::SrcSrvStream;
+
+    #[test]
+    fn audit_streams() {
+        let http = r#"SRCSRV: ini ------------------------------------------------
+VERSION=2
+VERCTRL=http
+SRCSRV: variables ------------------------------------------
+SRCSRVTRG=%var2%
+SRCSRV: source files ---------------------------------------
+c:\build\a.cpp*http://example.com/a.cpp
+c:\build\b.cpp*https://example.com/b.cpp
+SRCSRV: end ------------------------------------------------"#;
+        let share = r#"SRCSRV: ini ------------------------------------------------
+VERSION=2
+SRCSRV: variables ------------------------------------------
+SRCSRVTRG=\\server\share\%var2%
+SRCSRV: source files ---------------------------------------
+c:\build\c.cpp*c.cpp
+SRCSRV: end ------------------------------------------------"#;
+        let mut audit = Audit::default();
+        audit.add(Some(&SrcSrvStream::parse(http.as_bytes()).unwrap()));
+        audit.add(Some(&SrcSrvStream::parse(share.as_bytes()).unwrap()));
+        audit.add(None);
+        audit.file_count += 1;
+        audit.unreadable_count += 1;
+
+        let mut out = Vec::new();
+        print_audit(&audit, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            r#"files                4
+source-indexed       2 (50.0%)
+not source-indexed   1
+unreadable           1
+
+version control
+  Http               1
+  unknown            1
+
+servers
+  example.com        1
+  server             1
+
+entries              3
+  Download           2
+  CopyFile           1
+  evaluation errors  0
+
+plain http downloads 1 of 2 (50.0%)
+"#
+        );
+    }
+}
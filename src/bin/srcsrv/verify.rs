@@ -0,0 +1,172 @@
+use crate::args::{Args, UsageError};
+use crate::curl::Curl;
+use crate::CommandResult;
+use srcsrv::{EvalOptions, SourceRetrievalMethod, SrcSrvStream};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+pub const USAGE: &str = "Usage: srcsrv verify [--jobs <n>] <PDB or stream file>
+
+Evaluate every entry of the srcsrv stream and check that its URL can still be
+downloaded, with a HEAD request, or a GET request if the server doesn't
+support HEAD. Print the entries with dead links, the entries which can't be
+downloaded without running a command, and the entries which fail to evaluate.
+Exits with an error if there are any.
+
+Options:
+  --jobs <n>    The number of requests to run at the same time. Defaults to 8.";
+
+pub fn run(args: Vec<String>) -> CommandResult {
+    let args = Args::parse(args, &["--jobs"], &[])?;
+    let [pdb_path] = args.positionals(["PDB or stream file"])?;
+    let jobs = match args.value("--jobs") {
+        Some(jobs) => match jobs.parse() {
+            Ok(jobs) if jobs > 0 => jobs,
+            _ => return Err(UsageError(format!("invalid --jobs {}", jobs)).into()),
+        },
+        None => 8,
+    };
+    let stream = crate::load_stream(pdb_path)?;
+
+    let report = verify(stream.stream(), jobs, |url| Curl.check(url));
+    for (path, problem) in &report.problems {
+        println!("{}: {}", path, problem);
+    }
+    println!(
+        "checked {} URLs of {} entries",
+        report.url_count, report.entry_count
+    );
+    match report.problems.len() {
+        0 => Ok(()),
+        count => Err(format!("{} of {} entries have problems", count, report.entry_count).into()),
+    }
+}
+
+struct Report {
+    entry_count: usize,
+    url_count: usize,
+    /// The original path and the problem of each entry which can't be
+    /// downloaded, in stream order.
+    problems: Vec<(String, String)>,
+}
+
+/// Evaluate the entries of `stream` and run `check` for each distinct URL, on
+/// `jobs` threads.
+fn verify<E: Display>(
+    stream: &SrcSrvStream<'_>,
+    jobs: usize,
+    check: impl Fn(&str) -> Result<(), E> + Sync,
+) -> Report {
+    // For each entry, the index of its URL or the problem.
+    let mut entries: Vec<(&str, Result<usize, String>)> = Vec::new();
+    let mut urls: Vec<String> = Vec::new();
+    let mut url_indexes: HashMap<String, usize> = HashMap::new();
+    let options = EvalOptions::new().recognize_commands(true);
+    for (path, _) in stream.source_file_entries() {
+        let result = match stream.source_for_path_with_vars(path, "", &options) {
+            Ok(Some(SourceRetrievalMethod::Download { url }))
+            | Ok(Some(SourceRetrievalMethod::DownloadWithDecode { url, .. })) => {
+                Ok(*url_indexes.entry(url.clone()).or_insert_with(|| {
+                    urls.push(url);
+                    urls.len() - 1
+                }))
+            }
+            Ok(Some(method)) => Err(format!("not downloadable ({:?})", method.kind())),
+            Ok(None) => Err("not found in the srcsrv stream".to_string()),
+            Err(error) => Err(error.to_string()),
+        };
+        entries.push((path, result));
+    }
+
+    let next_url = AtomicUsize::new(0);
+    let url_results = Mutex::new(vec![None; urls.len()]);
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.min(urls.len()) {
+            scope.spawn(|| loop {
+                let index = next_url.fetch_add(1, Ordering::Relaxed);
+                let url = match urls.get(index) {
+                    Some(url) => url,
+                    None => break,
+                };
+                let result = check(url).map_err(|error| error.to_string());
+                url_results.lock().unwrap()[index] = Some(result);
+            });
+        }
+    });
+    let url_results = url_results.into_inner().unwrap();
+
+    let problems = entries
+        .iter()
+        .filter_map(|(path, result)| {
+            let problem = match result {
+                Ok(url_index) => match &url_results[*url_index] {
+                    Some(Err(error)) => format!("dead link {}: {}", urls[*url_index], error),
+                    _ => return None,
+                },
+                Err(problem) => problem.clone(),
+            };
+            Some((path.to_string(), problem))
+        })
+        .collect();
+    Report {
+        entry_count: entries.len(),
+        url_count: urls.len(),
+        problems,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::verify;
+    use srcsrv::SrcSrvStream;
+    use std::sync::Mutex;
+
+    #[test]
+    fn verify_stream() {
+        let stream = r#"SRCSRV: ini ------------------------------------------------
+VERSION=2
+SRCSRV: variables ------------------------------------------
+HTTP=https://example.com/%var2%
+SHARE=\\server\share\%var2%
+SRCSRVTRG=%fnvar%(%var3%)
+SRCSRV: source files ---------------------------------------
+c:\build\a.cpp*a.cpp*HTTP
+c:\build\missing.cpp*missing.cpp*HTTP
+c:\build\b.cpp*b.cpp*SHARE
+c:\build\copy\a.cpp*a.cpp*HTTP
+c:\build\c.cpp*c.cpp*UNKNOWN
+SRCSRV: end ------------------------------------------------"#;
+        let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
+        let checked = Mutex::new(Vec::new());
+        let report = verify(&stream, 4, |url| {
+            checked.lock().unwrap().push(url.to_string());
+            match url.ends_with("missing.cpp") {
+                true => Err("HTTP status 404"),
+                false => Ok(()),
+            }
+        });
+        assert_eq!(checked.lock().unwrap().len(), 2);
+        assert_eq!(report.entry_count, 5);
+        assert_eq!(report.url_count, 2);
+        let problems: Vec<(&str, &str)> = report
+            .problems
+            .iter()
+            .map(|(path, problem)| (path.as_str(), problem.as_str()))
+            .collect();
+        assert_eq!(problems.len(), 3);
+        assert_eq!(
+            problems[0],
+            (
+                r#"c:\build\missing.cpp"#,
+                "dead link https://example.com/missing.cpp: HTTP status 404"
+            )
+        );
+        assert_eq!(
+            problems[1],
+            (r#"c:\build\b.cpp"#, "not downloadable (CopyFile)")
+        );
+        assert_eq!(problems[2].0, r#"c:\build\c.cpp"#);
+    }
+}
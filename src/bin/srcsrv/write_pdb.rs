@@ -0,0 +1,41 @@
+use crate::args::Args;
+use crate::CommandResult;
+use srcsrv::OwnedSrcSrvStream;
+
+pub const USAGE: &str = "Usage: srcsrv write-pdb [--out <file>] <PDB> <stream file>
+
+Store the srcsrv stream as the srcsrv stream of the PDB file, replacing an
+existing one, like `pdbstr -w -s:srcsrv -i:<stream file>`. The stream file can
+also be a PDB file, whose srcsrv stream is copied. The PDB file is modified in
+place, unless --out is given.
+
+Options:
+  --out <file>    Write the modified PDB file to this file.";
+
+pub fn run(args: Vec<String>) -> CommandResult {
+    let args = Args::parse(args, &["--out"], &[])?;
+    let [pdb_path, stream_path] = args.positionals(["PDB", "stream file"])?;
+    let stream = crate::load_stream(stream_path)?;
+    let stream = stream.stream();
+    for warning in stream.warnings() {
+        eprintln!("warning: {}: {}", stream_path, warning);
+    }
+
+    let pdb = std::fs::read(pdb_path)?;
+    let pdb = stream.write_to_pdb(&pdb)?;
+    let out_path = args.value("--out").unwrap_or(pdb_path);
+    std::fs::write(out_path, pdb)?;
+
+    // Read the stream back, to catch problems before the PDB file is used.
+    let written = OwnedSrcSrvStream::from_pdb_path(out_path)?
+        .ok_or_else(|| format!("{} has no srcsrv stream after writing it", out_path))?;
+    let entry_count = written.stream().source_file_entries().count();
+    if written.as_bytes() != stream.to_bytes() {
+        return Err(format!("the srcsrv stream of {} differs after writing it", out_path).into());
+    }
+    eprintln!(
+        "wrote the srcsrv stream with {} entries to {}",
+        entry_count, out_path
+    );
+    Ok(())
+}
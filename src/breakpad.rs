@@ -0,0 +1,87 @@
+use crate::{EvalError, LookupOptions, SourceRetrievalMethod, SrcSrvStream};
+use std::collections::BTreeMap;
+use std::result::Result;
+
+impl<'a> SrcSrvStream<'a> {
+    /// Look up the files of the `FILE` records of a Breakpad `.sym` file,
+    /// which was created from the same PDB file as this stream, and find out
+    /// how to obtain their sources. The results are keyed by the file ID.
+    ///
+    /// `sym_lines` are the lines of the `.sym` file; lines other than `FILE`
+    /// records are ignored, so all lines of the file can be passed. Breakpad
+    /// may change the case of paths and use `/` as a separator, so paths are
+    /// matched case-insensitively and with both separators treated as
+    /// equivalent.
+    ///
+    /// `extraction_base_path` is used as the value of the special `%targ%` variable
+    /// and should not include a trailing backslash.
+    ///
+    /// Files which are not in the stream are included with `Ok(None)`.
+    ///
+    /// ```
+    /// use srcsrv::SrcSrvStream;
+    ///
+    /// # fn wrapper(stream: &SrcSrvStream, sym_file: &str) -> std::result::Result<(), srcsrv::EvalError> {
+    /// let methods = stream.source_for_breakpad_files(sym_file.lines(), r#"C:\Debugger\Cached Sources"#);
+    /// for (file_id, method) in methods {
+    ///     println!("FILE {}: {:?}", file_id, method?);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn source_for_breakpad_files<'l>(
+        &self,
+        sym_lines: impl IntoIterator<Item = &'l str>,
+        extraction_base_path: &str,
+    ) -> BTreeMap<u32, Result<Option<SourceRetrievalMethod>, EvalError>> {
+        let (file_ids, paths): (Vec<u32>, Vec<&str>) =
+            sym_lines.into_iter().filter_map(parse_file_record).unzip();
+        let options = LookupOptions::new().normalize_separators(true);
+        let methods = self.source_for_paths_with_options(&paths, extraction_base_path, &options);
+        file_ids.into_iter().zip(methods).collect()
+    }
+}
+
+/// The ID and path of a `FILE <id> <path>` record.
+fn parse_file_record(line: &str) -> Option<(u32, &str)> {
+    let (id, path) = line.strip_prefix("FILE ")?.split_once(' ')?;
+    let path = path.trim_end_matches(['\r', '\n']);
+    Some((id.parse().ok()?, path))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{SourceRetrievalMethod, SrcSrvStream, SrcSrvStreamBuilder};
+
+    #[test]
+    fn breakpad_files() {
+        let bytes = SrcSrvStreamBuilder::new()
+            .set_var("SRCSRVTRG", "https://example.com/%var2%")
+            .add_source_file_entry(&[r#"C:\Build\Src\Main.cpp"#, "src/main.cpp"])
+            .add_source_file_entry(&[r#"C:\Build\Src\My File.h"#, "src/my%20file.h"])
+            .to_bytes()
+            .unwrap();
+        let stream = SrcSrvStream::parse(&bytes).unwrap();
+        let sym = "MODULE windows x86_64 6D1DFFC4DC524537962CCABC000820641 app.pdb\r\n\
+                   FILE 0 c:/build/src/main.cpp\r\n\
+                   FILE 7 c:\\build\\src\\my file.h\r\n\
+                   FILE 9 c:\\program files\\vc\\include\\vector\r\n\
+                   FUNC 1000 10 0 main\r\n";
+        let methods = stream.source_for_breakpad_files(sym.lines(), "");
+        let urls: Vec<(u32, Option<String>)> = methods
+            .into_iter()
+            .map(|(id, method)| match method.unwrap() {
+                Some(SourceRetrievalMethod::Download { url }) => (id, Some(url)),
+                _ => (id, None),
+            })
+            .collect();
+        assert_eq!(
+            urls,
+            [
+                (0, Some("https://example.com/src/main.cpp".to_string())),
+                (7, Some("https://example.com/src/my%20file.h".to_string())),
+                (9, None),
+            ]
+        );
+    }
+}
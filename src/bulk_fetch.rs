@@ -0,0 +1,338 @@
+use crate::fetch::fetch_source_contents_with_options;
+use crate::source_cache::write_atomically;
+use crate::url_policy::url_scheme_and_host;
+use crate::{ContentEncoding, FetchError, FetchOptions, SourceFetcher, SourceRetrievalMethod};
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::path::PathBuf;
+use std::result::Result;
+use std::sync::{Condvar, Mutex};
+
+/// The progress of [`fetch_sources`], passed to its progress callback.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FetchProgress {
+    /// The number of files which have been fetched or have failed.
+    pub completed: usize,
+    /// The total number of files.
+    pub total: usize,
+    /// The number of bytes downloaded so far, after decoding.
+    pub bytes: u64,
+}
+
+/// Download many source files concurrently, and store each at its target
+/// path, like [`fetch_source_with_options`](crate::fetch_source_with_options).
+///
+/// `downloads` are pairs of a retrieval method and the path where the file
+/// should be stored. Each distinct URL is downloaded only once, even if
+/// multiple files have the same URL. At most
+/// [`FetchOptions::max_concurrent_downloads`] downloads run at the same time,
+/// and at most [`FetchOptions::max_downloads_per_host`] of them from the same
+/// host.
+///
+/// `progress` is called after every completed download. Returns the result
+/// for each entry of `downloads`, in the same order. If the
+/// [`FetchOptions::cancellation`] token is cancelled, no new downloads are
+/// started, and the downloads which weren't completed fail with
+/// [`FetchError::Cancelled`].
+///
+/// ```
+/// use srcsrv::{fetch_sources, FetchOptions, SrcSrvStream};
+/// use std::path::Path;
+///
+/// # fn get(url: &str) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> { unimplemented!() }
+/// # fn wrapper(stream: &SrcSrvStream, paths: &[&str]) -> Result<(), Box<dyn std::error::Error>> {
+/// let cache_dir = Path::new("/tmp/sources");
+/// let debug_id = "6D1DFFC4DC524537962CCABC000820641";
+/// let mut downloads = Vec::new();
+/// for path in paths {
+///     if let (Some(method), Some(cache_path)) = (
+///         stream.source_for_path(path, "")?,
+///         stream.source_cache_path(debug_id, path)?,
+///     ) {
+///         downloads.push((method, cache_dir.join(cache_path)));
+///     }
+/// }
+/// let results = fetch_sources(&get, &downloads, &FetchOptions::new(), |progress| {
+///     eprintln!("{}/{} files, {} bytes", progress.completed, progress.total, progress.bytes);
+/// });
+/// # Ok(())
+/// # }
+/// ```
+pub fn fetch_sources(
+    fetcher: &(impl SourceFetcher + Sync + ?Sized),
+    downloads: &[(SourceRetrievalMethod, PathBuf)],
+    options: &FetchOptions,
+    progress: impl Fn(FetchProgress) + Sync,
+) -> Vec<Result<(), FetchError>> {
+    let methods: Vec<&SourceRetrievalMethod> = downloads.iter().map(|(method, _)| method).collect();
+    fetch_all(fetcher, &methods, options, progress, |index, contents| {
+        write_atomically(&downloads[index].1, contents).map_err(FetchError::Io)
+    })
+}
+
+/// Download the files for `methods` like [`fetch_sources`], and pass the
+/// contents of each file to `store`, with the index of its method.
+pub(crate) fn fetch_all(
+    fetcher: &(impl SourceFetcher + Sync + ?Sized),
+    methods: &[&SourceRetrievalMethod],
+    options: &FetchOptions,
+    progress: impl Fn(FetchProgress) + Sync,
+    store: impl Fn(usize, &[u8]) -> Result<(), FetchError> + Sync,
+) -> Vec<Result<(), FetchError>> {
+    // Group the downloads by URL.
+    let mut jobs: Vec<Job> = Vec::new();
+    let mut job_for_url: HashMap<(&str, Option<ContentEncoding>), usize> = HashMap::new();
+    let mut results: Vec<Option<Result<(), FetchError>>> = Vec::with_capacity(methods.len());
+    for (index, method) in methods.iter().copied().enumerate() {
+        let key = match method {
+            SourceRetrievalMethod::Download { url } => (url.as_str(), None),
+            SourceRetrievalMethod::DownloadWithDecode { url, encoding } => {
+                (url.as_str(), Some(*encoding))
+            }
+            _ => {
+                results.push(Some(Err(FetchError::UnsupportedMethod(method.kind()))));
+                continue;
+            }
+        };
+        results.push(None);
+        let job_index = *job_for_url.entry(key).or_insert_with(|| {
+            let host = url_scheme_and_host(key.0).map_or(key.0, |(_, host)| host);
+            jobs.push(Job {
+                method,
+                host: host.to_ascii_lowercase(),
+                download_indexes: Vec::new(),
+            });
+            jobs.len() - 1
+        });
+        jobs[job_index].download_indexes.push(index);
+    }
+
+    let completed = results.iter().filter(|result| result.is_some()).count();
+    let state = Mutex::new(State {
+        pending: (0..jobs.len()).collect(),
+        active_per_host: HashMap::new(),
+        results,
+        progress: FetchProgress {
+            completed,
+            total: methods.len(),
+            bytes: 0,
+        },
+    });
+    let job_finished = Condvar::new();
+
+    let worker = || loop {
+        let job = {
+            let mut state = state.lock().unwrap();
+            loop {
+                if state.pending.is_empty() || options.is_cancelled() {
+                    return;
+                }
+                let position = state.pending.iter().position(|&job_index| {
+                    let host = &jobs[job_index].host;
+                    state.active_per_host.get(host).copied().unwrap_or(0)
+                        < options.max_downloads_per_host
+                });
+                if let Some(position) = position {
+                    let job_index = state.pending.remove(position).unwrap();
+                    let job = &jobs[job_index];
+                    *state.active_per_host.entry(job.host.clone()).or_default() += 1;
+                    break job;
+                }
+                state = job_finished.wait(state).unwrap();
+            }
+        };
+
+        let (bytes, job_results): (u64, Vec<Result<(), FetchError>>) =
+            match fetch_source_contents_with_options(fetcher, job.method, options) {
+                Ok(contents) => (
+                    contents.len() as u64,
+                    job.download_indexes
+                        .iter()
+                        .map(|&index| store(index, &contents))
+                        .collect(),
+                ),
+                Err(err) => {
+                    let mut results: Vec<_> = job.download_indexes[1..]
+                        .iter()
+                        .map(|_| Err(copy_error(&err)))
+                        .collect();
+                    results.insert(0, Err(err));
+                    (0, results)
+                }
+            };
+
+        let mut state = state.lock().unwrap();
+        *state.active_per_host.get_mut(&job.host).unwrap() -= 1;
+        for (&index, result) in job.download_indexes.iter().zip(job_results) {
+            state.results[index] = Some(result);
+        }
+        state.progress.completed += job.download_indexes.len();
+        state.progress.bytes += bytes;
+        progress(state.progress);
+        job_finished.notify_all();
+    };
+
+    let thread_count = options.max_concurrent_downloads.min(jobs.len());
+    std::thread::scope(|scope| {
+        for _ in 0..thread_count {
+            scope.spawn(worker);
+        }
+    });
+
+    state
+        .into_inner()
+        .unwrap()
+        .results
+        .into_iter()
+        .map(|result| result.unwrap_or(Err(FetchError::Cancelled)))
+        .collect()
+}
+
+/// A distinct download.
+struct Job<'d> {
+    method: &'d SourceRetrievalMethod,
+    /// The lowercase host of the URL.
+    host: String,
+    /// The indexes of the downloads with this URL.
+    download_indexes: Vec<usize>,
+}
+
+struct State {
+    /// The indexes of the jobs which haven't been started.
+    pending: VecDeque<usize>,
+    active_per_host: HashMap<String, usize>,
+    results: Vec<Option<Result<(), FetchError>>>,
+    progress: FetchProgress,
+}
+
+/// A copy of `err` for the second and further downloads with the same URL.
+/// The source errors of downloads are replaced by their messages.
+fn copy_error(err: &FetchError) -> FetchError {
+    match err {
+        FetchError::Http(error) => FetchError::Http(error.to_string().into()),
+        FetchError::Transient { attempts, error } => FetchError::Transient {
+            attempts: *attempts,
+            error: error.to_string().into(),
+        },
+        FetchError::Decode(message) => FetchError::Decode(message.clone()),
+        FetchError::Io(error) => FetchError::Io(io::Error::new(error.kind(), error.to_string())),
+        FetchError::UnsupportedMethod(kind) => FetchError::UnsupportedMethod(*kind),
+        FetchError::UrlNotAllowed(url) => FetchError::UrlNotAllowed(url.clone()),
+        FetchError::Cancelled => FetchError::Cancelled,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fetch_sources, FetchProgress};
+    use crate::{
+        CancellationToken, FetchError, FetchOptions, HttpStatusError, SourceRetrievalMethod,
+    };
+    use std::error::Error;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    #[test]
+    fn bulk_fetch() {
+        let requests = Mutex::new(Vec::new());
+        let active = AtomicUsize::new(0);
+        let max_active = AtomicUsize::new(0);
+        let fetcher = |url: &str| -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+            requests.lock().unwrap().push(url.to_string());
+            let now_active = active.fetch_add(1, Ordering::SeqCst) + 1;
+            max_active.fetch_max(now_active, Ordering::SeqCst);
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            active.fetch_sub(1, Ordering::SeqCst);
+            match url.strip_suffix("missing.cpp") {
+                Some(_) => Err(HttpStatusError { status: 404 }.into()),
+                None => Ok(url.as_bytes().to_vec()),
+            }
+        };
+
+        let dir = std::env::temp_dir().join(format!("srcsrv-bulk-{}", std::process::id()));
+        let download = |url: &str, file: &str| {
+            (
+                SourceRetrievalMethod::Download {
+                    url: url.to_string(),
+                },
+                dir.join(file),
+            )
+        };
+        let downloads = vec![
+            download("https://a.example.com/1.cpp", "1.cpp"),
+            download("https://a.example.com/2.cpp", "2.cpp"),
+            download("https://a.example.com/3.cpp", "3.cpp"),
+            download("https://b.example.com/1.cpp", "b1.cpp"),
+            download("https://a.example.com/1.cpp", "copy/1.cpp"),
+            download("https://b.example.com/missing.cpp", "missing.cpp"),
+            download("https://b.example.com/missing.cpp", "missing2.cpp"),
+            (
+                SourceRetrievalMethod::Other {
+                    raw_var_values: Default::default(),
+                },
+                dir.join("other.cpp"),
+            ),
+        ];
+        let options = FetchOptions::new()
+            .max_concurrent_downloads(4)
+            .max_downloads_per_host(1);
+        let progress = Mutex::new(Vec::new());
+        let results = fetch_sources(&fetcher, &downloads, &options, |p| {
+            progress.lock().unwrap().push(p)
+        });
+
+        let first = std::fs::read(dir.join("1.cpp")).unwrap();
+        let copy = std::fs::read(dir.join("copy/1.cpp")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(first, b"https://a.example.com/1.cpp");
+        assert_eq!(copy, b"https://a.example.com/1.cpp");
+
+        assert!(results[..5].iter().all(|result| result.is_ok()));
+        assert!(matches!(results[5], Err(FetchError::Http(_))));
+        assert!(matches!(results[6], Err(FetchError::Http(_))));
+        assert!(matches!(results[7], Err(FetchError::UnsupportedMethod(_))));
+
+        // Each URL is requested once, and at most one request per host runs
+        // at a time.
+        assert_eq!(requests.into_inner().unwrap().len(), 5);
+        assert!(max_active.load(Ordering::SeqCst) <= 2);
+
+        let progress = progress.into_inner().unwrap();
+        assert_eq!(progress.len(), 5);
+        assert_eq!(
+            progress.last(),
+            Some(&FetchProgress {
+                completed: 8,
+                total: 8,
+                bytes: 27 * 3 + 27,
+            })
+        );
+    }
+
+    #[test]
+    fn bulk_fetch_cancellation() {
+        let token = CancellationToken::new();
+        let fetcher = |url: &str| -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+            token.cancel();
+            Ok(url.as_bytes().to_vec())
+        };
+        let downloads: Vec<_> = ["1.cpp", "2.cpp", "3.cpp"]
+            .iter()
+            .map(|file| {
+                (
+                    SourceRetrievalMethod::Download {
+                        url: format!("https://example.com/{file}"),
+                    },
+                    std::env::temp_dir().join(file),
+                )
+            })
+            .collect();
+        let options = FetchOptions::new()
+            .max_concurrent_downloads(1)
+            .cancellation(token.clone());
+        let results = fetch_sources(&fetcher, &downloads, &options, |_| {});
+        assert!(results
+            .iter()
+            .all(|result| matches!(result, Err(FetchError::Cancelled))));
+    }
+}
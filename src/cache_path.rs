@@ -0,0 +1,168 @@
+use crate::{EvalError, SourceRetrievalMethod, SrcSrvStream};
+use std::result::Result;
+
+/// The extraction base path used while evaluating the target path for a cache
+/// path. It is stripped from the result again.
+const EXTRACTION_BASE_PLACEHOLDER: &str = "<extraction base>";
+
+impl<'a> SrcSrvStream<'a> {
+    /// A stable relative path under which the source for `original_file_path`
+    /// can be stored in an on-disk cache: `<debug_id>/<relative target>`, with
+    /// `/` as the separator.
+    ///
+    /// `debug_id` identifies the PDB file, usually as a breakpad ID, i.e. the
+    /// uppercase hex GUID followed by the hex age. The relative target is the
+    /// path which the stream would extract the file to below `%targ%`, or the
+    /// host and path of the URL for files which are downloaded. This way,
+    /// multiple tools which use the same cache directory and the same debug
+    /// ID format find each other's files.
+    ///
+    /// Characters which are not allowed in Windows file names are replaced with
+    /// `_`, and `.` and `..` components are dropped, so the path always stays
+    /// within the cache directory.
+    ///
+    /// Returns `Ok(None)` if the file path was not found in the list of file
+    /// entries, or if the entry has no target path.
+    ///
+    /// ```
+    /// use srcsrv::SrcSrvStream;
+    ///
+    /// # fn wrapper(stream: &SrcSrvStream, cache_dir: &std::path::Path) -> std::result::Result<(), srcsrv::EvalError> {
+    /// if let Some(path) = stream.source_cache_path(
+    ///     "6D1DFFC4DC524537962CCABC000820641",
+    ///     r#"C:\build\renderdoc\renderdoc\data\glsl\gl_texsample.h"#,
+    /// )? {
+    ///     println!("{}", cache_dir.join(path).display());
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn source_cache_path(
+        &self,
+        debug_id: &str,
+        original_file_path: &str,
+    ) -> Result<Option<String>, EvalError> {
+        let method = match self.source_for_path(original_file_path, EXTRACTION_BASE_PLACEHOLDER)? {
+            Some(method) => method,
+            None => return Ok(None),
+        };
+        Ok(relative_target_path(&method, EXTRACTION_BASE_PLACEHOLDER)
+            .map(|target| format!("{debug_id}/{target}")))
+    }
+}
+
+/// The path below `extraction_base_path` where the file for `method` ends up,
+/// joined with `/`, or the host and path of the URL for downloads. See
+/// [`relative_cache_path`] for the sanitization.
+///
+/// Returns `None` for methods without a target path.
+pub(crate) fn relative_target_path(
+    method: &SourceRetrievalMethod,
+    extraction_base_path: &str,
+) -> Option<String> {
+    let target = match method {
+        SourceRetrievalMethod::Download { url }
+        | SourceRetrievalMethod::DownloadWithDecode { url, .. } => url_cache_path(url),
+        SourceRetrievalMethod::GitFile { target_path, .. }
+        | SourceRetrievalMethod::TfsItem { target_path, .. }
+        | SourceRetrievalMethod::Perforce { target_path, .. }
+        | SourceRetrievalMethod::SourceDepot { target_path, .. }
+        | SourceRetrievalMethod::Svn { target_path, .. }
+        | SourceRetrievalMethod::Cvs { target_path, .. }
+        | SourceRetrievalMethod::CabExtract { target_path, .. }
+        | SourceRetrievalMethod::CopyFile { target_path, .. }
+        | SourceRetrievalMethod::ExecuteCommand { target_path, .. } => target_path
+            .strip_prefix(extraction_base_path)
+            .unwrap_or(target_path),
+        SourceRetrievalMethod::Other { .. } => return None,
+    };
+    relative_cache_path(target)
+}
+
+/// The host and path of `url`, including the query, without the scheme and
+/// the fragment.
+fn url_cache_path(url: &str) -> &str {
+    let url = url.split_once("://").map_or(url, |(_, rest)| rest);
+    url.split('#').next().unwrap_or(url)
+}
+
+/// Join the components of `path` with `/`, dropping empty, `.` and `..`
+/// components and replacing characters which Windows doesn't allow in file
+/// names. Returns `None` if no component is left.
+fn relative_cache_path(path: &str) -> Option<String> {
+    let components: Vec<String> = path
+        .split(['/', '\\'])
+        .filter(|component| !matches!(*component, "" | "." | ".."))
+        .map(|component| {
+            component
+                .chars()
+                .map(|c| match c {
+                    '<' | '>' | ':' | '"' | '|' | '?' | '*' => '_',
+                    c if c.is_control() => '_',
+                    c => c,
+                })
+                .collect()
+        })
+        .collect();
+    if components.is_empty() {
+        return None;
+    }
+    Some(components.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::SrcSrvStream;
+
+    #[test]
+    fn source_cache_path() {
+        let stream = r#"SRCSRV: ini ------------------------------------------------
+VERSION=2
+SRCSRV: variables ------------------------------------------
+HTTP_ALIAS=https://example.com/src
+SRCSRVTRG=%HTTP_ALIAS%/%var2%/%var3%?raw=1#L1
+SRCSRV: source files ---------------------------------------
+c:\build\main.cpp*abc123*src/main.cpp
+c:\build\evil.cpp*..*../../evil.cpp
+SRCSRV: end ------------------------------------------------"#;
+        let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
+        let id = "6D1DFFC4DC524537962CCABC000820641";
+        assert_eq!(
+            stream
+                .source_cache_path(id, r#"C:\build\main.cpp"#)
+                .unwrap()
+                .as_deref(),
+            Some("6D1DFFC4DC524537962CCABC000820641/example.com/src/abc123/src/main.cpp_raw=1")
+        );
+        assert_eq!(
+            stream
+                .source_cache_path(id, r#"C:\build\evil.cpp"#)
+                .unwrap()
+                .as_deref(),
+            Some("6D1DFFC4DC524537962CCABC000820641/example.com/src/evil.cpp_raw=1")
+        );
+        assert_eq!(
+            stream
+                .source_cache_path(id, r#"C:\build\other.cpp"#)
+                .unwrap(),
+            None
+        );
+
+        let stream = r#"SRCSRV: ini ------------------------------------------------
+VERSION=1
+SRCSRV: variables ------------------------------------------
+SRCSRVTRG=%targ%\%var2%\%fnbksl%(%var3%)\%fnfile%(%var1%)
+SRCSRVCMD=p4.exe print -o %srcsrvtrg% -q "//depot/%var3%#%var2%"
+SRCSRV: source files ---------------------------------------
+d:\build\foo.cpp*4*src/foo.cpp
+SRCSRV: end ------------------------------------------------"#;
+        let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
+        assert_eq!(
+            stream
+                .source_cache_path(id, r#"d:\build\foo.cpp"#)
+                .unwrap()
+                .as_deref(),
+            Some("6D1DFFC4DC524537962CCABC000820641/4/src/foo.cpp/foo.cpp")
+        );
+    }
+}
@@ -0,0 +1,92 @@
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// A token which can be used to cancel long-running operations, such as
+/// downloads, from another thread.
+///
+/// Clones of a token share its state: cancelling one clone cancels them all.
+/// Cancelled operations fail with a `Cancelled` error and don't leave partial
+/// files behind.
+///
+/// ```
+/// use srcsrv::CancellationToken;
+///
+/// let token = CancellationToken::new();
+/// let token_for_ui = token.clone();
+/// std::thread::spawn(move || {
+///     // The user clicked "Cancel".
+///     token_for_ui.cancel();
+/// })
+/// .join()
+/// .unwrap();
+/// assert!(token.is_cancelled());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<(Mutex<bool>, Condvar)>);
+
+impl CancellationToken {
+    /// Create a token which is not cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel the operations which use this token.
+    pub fn cancel(&self) {
+        let (cancelled, condvar) = &*self.0;
+        *cancelled.lock().unwrap() = true;
+        condvar.notify_all();
+    }
+
+    /// Whether [`CancellationToken::cancel`] has been called.
+    pub fn is_cancelled(&self) -> bool {
+        *self.0 .0.lock().unwrap()
+    }
+
+    /// Sleep for `duration`, or until the token is cancelled. Returns `false`
+    /// if the token was cancelled.
+    pub(crate) fn sleep(&self, duration: Duration) -> bool {
+        let (cancelled, condvar) = &*self.0;
+        let deadline = Instant::now() + duration;
+        let mut cancelled = cancelled.lock().unwrap();
+        while !*cancelled {
+            let now = Instant::now();
+            if now >= deadline {
+                return true;
+            }
+            cancelled = condvar.wait_timeout(cancelled, deadline - now).unwrap().0;
+        }
+        false
+    }
+}
+
+impl PartialEq for CancellationToken {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for CancellationToken {}
+
+#[cfg(test)]
+mod tests {
+    use super::CancellationToken;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn cancel_during_sleep() {
+        let token = CancellationToken::new();
+        assert!(token.sleep(Duration::from_millis(1)));
+
+        let start = Instant::now();
+        let canceller = token.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            canceller.cancel();
+        });
+        assert!(!token.sleep(Duration::from_secs(60)));
+        thread.join().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(30));
+        assert!(token.is_cancelled());
+        assert!(!token.sleep(Duration::from_secs(60)));
+    }
+}
@@ -0,0 +1,71 @@
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+/// A string which is hashed and compared ASCII case-insensitively, so that
+/// `&CaseInsensitiveStr` can be used as a map key without storing a lowercase
+/// copy, and the map can be queried with a string of any lifetime.
+#[repr(transparent)]
+pub(crate) struct CaseInsensitiveStr(str);
+
+impl CaseInsensitiveStr {
+    pub(crate) fn new(s: &str) -> &CaseInsensitiveStr {
+        // SAFETY: CaseInsensitiveStr is a repr(transparent) wrapper around str.
+        unsafe { &*(s as *const str as *const CaseInsensitiveStr) }
+    }
+
+    /// The string, with its original casing.
+    pub(crate) fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq for CaseInsensitiveStr {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.eq_ignore_ascii_case(&other.0)
+    }
+}
+
+impl Eq for CaseInsensitiveStr {}
+
+impl Hash for CaseInsensitiveStr {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // Lowercase in chunks, so that the hasher isn't called for every byte.
+        let mut buf = [0; 64];
+        for chunk in self.0.as_bytes().chunks(buf.len()) {
+            let lowercase = &mut buf[..chunk.len()];
+            lowercase.copy_from_slice(chunk);
+            lowercase.make_ascii_lowercase();
+            state.write(lowercase);
+        }
+        // Like str, terminate the bytes so that ("ab", "c") and ("a", "bc")
+        // hash differently in composite keys.
+        state.write_u8(0xff);
+    }
+}
+
+impl fmt::Debug for CaseInsensitiveStr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.0, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CaseInsensitiveStr;
+    use std::collections::HashMap;
+
+    #[test]
+    fn case_insensitive_key() {
+        let long_path = format!(r#"C:\{}\File.cpp"#, "Dir".repeat(50));
+        let mut map = HashMap::new();
+        map.insert(CaseInsensitiveStr::new(r#"C:\build\Foo.cpp"#), 1);
+        map.insert(CaseInsensitiveStr::new(&long_path), 2);
+        assert_eq!(
+            map.get(CaseInsensitiveStr::new(r#"c:\BUILD\foo.CPP"#)),
+            Some(&1)
+        );
+        let uppercase_path = long_path.to_ascii_uppercase();
+        assert_eq!(map.get(CaseInsensitiveStr::new(&uppercase_path)), Some(&2));
+        assert_eq!(map.get(CaseInsensitiveStr::new(r#"C:\build\Foo.cp"#)), None);
+    }
+}
@@ -0,0 +1,181 @@
+use crate::digest::{md5, sha1, sha256};
+use crate::from_pdb::for_each_pdb_source_file;
+use crate::{EvalError, PdbError, SourceRetrievalMethod, SrcSrvStream};
+use rustc_hash::FxHashMap;
+use std::convert::TryInto;
+use std::result::Result;
+
+/// The checksum of a source file's contents, as recorded by the compiler in
+/// the line information of a PDB file.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SourceChecksum {
+    /// An MD5 checksum.
+    Md5([u8; 16]),
+    /// A SHA-1 checksum.
+    Sha1([u8; 20]),
+    /// A SHA-256 checksum.
+    Sha256([u8; 32]),
+}
+
+impl SourceChecksum {
+    /// Returns `None` if the PDB has no checksum for the file, or if the
+    /// checksum doesn't have the length that its kind requires.
+    pub(crate) fn from_pdb(checksum: &pdb::FileChecksum) -> Option<Self> {
+        match *checksum {
+            pdb::FileChecksum::None => None,
+            pdb::FileChecksum::Md5(bytes) => bytes.try_into().ok().map(SourceChecksum::Md5),
+            pdb::FileChecksum::Sha1(bytes) => bytes.try_into().ok().map(SourceChecksum::Sha1),
+            pdb::FileChecksum::Sha256(bytes) => bytes.try_into().ok().map(SourceChecksum::Sha256),
+        }
+    }
+
+    /// The raw checksum bytes.
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            SourceChecksum::Md5(bytes) => bytes,
+            SourceChecksum::Sha1(bytes) => bytes,
+            SourceChecksum::Sha256(bytes) => bytes,
+        }
+    }
+
+    /// Check whether `contents` has this checksum, i.e. whether the fetched
+    /// source file is the one that was compiled.
+    ///
+    /// The checksum is computed over the file as the compiler read it, so
+    /// the check fails if the contents were changed on the way, for example
+    /// by a line ending conversion during a version control checkout.
+    pub fn verify(&self, contents: &[u8]) -> bool {
+        match self {
+            SourceChecksum::Md5(bytes) => md5(contents) == *bytes,
+            SourceChecksum::Sha1(bytes) => sha1(contents) == *bytes,
+            SourceChecksum::Sha256(bytes) => sha256(contents) == *bytes,
+        }
+    }
+}
+
+/// The checksums of the source files of a PDB file, see
+/// [`PdbSourceChecksums::from_pdb`].
+#[derive(Debug, Clone, Default)]
+pub struct PdbSourceChecksums {
+    /// Keyed by the lowercase path.
+    checksums: FxHashMap<String, SourceChecksum>,
+}
+
+impl PdbSourceChecksums {
+    /// Collect the checksums of the source files of all modules in `pdb`.
+    pub fn from_pdb<'s, S: pdb::Source<'s> + 's>(
+        pdb: &mut pdb::PDB<'s, S>,
+    ) -> Result<Self, PdbError> {
+        let mut checksums = PdbSourceChecksums::default();
+        for_each_pdb_source_file(pdb, |name, checksum| {
+            if let Some(checksum) = SourceChecksum::from_pdb(checksum) {
+                checksums.insert(&name, checksum);
+            }
+        })
+        .map_err(PdbError::Pdb)?;
+        Ok(checksums)
+    }
+
+    pub(crate) fn insert(&mut self, path: &str, checksum: SourceChecksum) {
+        self.checksums.insert(path.to_ascii_lowercase(), checksum);
+    }
+
+    /// The checksum of the file at `original_file_path`. The path is compared
+    /// ASCII case-insensitively, like the paths of srcsrv file entries.
+    pub fn get(&self, original_file_path: &str) -> Option<&SourceChecksum> {
+        self.checksums.get(&original_file_path.to_ascii_lowercase())
+    }
+}
+
+impl<'a> SrcSrvStream<'a> {
+    /// Like [`SrcSrvStream::source_for_path`], but additionally returns the
+    /// checksum that the file needs to have, if the PDB file has one.
+    ///
+    /// ```
+    /// use srcsrv::{PdbSourceChecksums, SourceRetrievalMethod, SrcSrvStream};
+    ///
+    /// # fn download(url: &str) -> Vec<u8> { unimplemented!() }
+    /// # fn wrapper<'s, S: pdb::Source<'s> + 's>(pdb: &mut pdb::PDB<'s, S>, path: &str) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    /// let checksums = PdbSourceChecksums::from_pdb(pdb)?;
+    /// if let Some(stream) = SrcSrvStream::from_pdb(pdb)? {
+    ///     if let Some((SourceRetrievalMethod::Download { url }, checksum)) = stream
+    ///         .stream()
+    ///         .source_and_checksum_for_path(path, "", &checksums)?
+    ///     {
+    ///         let contents = download(&url);
+    ///         if checksum.is_some_and(|checksum| !checksum.verify(&contents)) {
+    ///             println!("{url} does not have the contents that were compiled");
+    ///         }
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn source_and_checksum_for_path(
+        &self,
+        original_file_path: &str,
+        extraction_base_path: &str,
+        checksums: &PdbSourceChecksums,
+    ) -> Result<Option<(SourceRetrievalMethod, Option<SourceChecksum>)>, EvalError> {
+        let method = self.source_for_path(original_file_path, extraction_base_path)?;
+        Ok(method.map(|method| (method, checksums.get(original_file_path).cloned())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PdbSourceChecksums, SourceChecksum};
+    use crate::{SourceRetrievalMethod, SrcSrvStream, SrcSrvStreamBuilder};
+
+    #[test]
+    fn verify() {
+        let checksum = SourceChecksum::Md5([
+            0x90, 0x01, 0x50, 0x98, 0x3c, 0xd2, 0x4f, 0xb0, 0xd6, 0x96, 0x3f, 0x7d, 0x28, 0xe1,
+            0x7f, 0x72,
+        ]);
+        assert!(checksum.verify(b"abc"));
+        assert!(!checksum.verify(b"abd"));
+        assert_eq!(
+            SourceChecksum::from_pdb(&pdb::FileChecksum::Md5(checksum.as_bytes())),
+            Some(checksum)
+        );
+        assert_eq!(
+            SourceChecksum::from_pdb(&pdb::FileChecksum::Sha1(&[0; 16])),
+            None
+        );
+    }
+
+    #[test]
+    fn source_and_checksum() {
+        let bytes = SrcSrvStreamBuilder::new()
+            .set_var("SRCSRVTRG", "https://example.com/%var2%")
+            .add_source_file_entry(&[r#"C:\build\main.cpp"#, "main.cpp"])
+            .add_source_file_entry(&[r#"C:\build\util.h"#, "util.h"])
+            .to_bytes()
+            .unwrap();
+        let stream = SrcSrvStream::parse(&bytes).unwrap();
+        let mut checksums = PdbSourceChecksums::default();
+        checksums.insert(r#"C:\build\Main.cpp"#, SourceChecksum::Sha256([1; 32]));
+
+        assert_eq!(
+            stream
+                .source_and_checksum_for_path(r#"C:\build\main.cpp"#, "", &checksums)
+                .unwrap(),
+            Some((
+                SourceRetrievalMethod::Download {
+                    url: "https://example.com/main.cpp".to_string()
+                },
+                Some(SourceChecksum::Sha256([1; 32]))
+            ))
+        );
+        assert_eq!(
+            stream
+                .source_and_checksum_for_path(r#"C:\build\util.h"#, "", &checksums)
+                .unwrap()
+                .unwrap()
+                .1,
+            None
+        );
+    }
+}
@@ -0,0 +1,145 @@
+use crate::recognize::{is_operator, tokenize_command};
+
+/// Restricts the programs which the commands of
+/// [`SourceRetrievalMethod::ExecuteCommand`](crate::SourceRetrievalMethod::ExecuteCommand)
+/// may run, like the trusted commands in the debugger's `srcsrv.ini`.
+///
+/// Set it with [`EvalOptions::command_policy`](crate::EvalOptions::command_policy)
+/// to reject commands during lookups, or with `ExecOptions::command_policy`
+/// (with the `exec` feature) to reject them right before they are run.
+///
+/// The default policy allows all commands. Once a program is allowed, a
+/// command is only allowed if it starts with an allowed program, and if all
+/// commands which it chains with `&`, `&&`, `||` or `|` start with an allowed
+/// program as well. Programs are compared by their file name, ASCII
+/// case-insensitively and with or without the `.exe` extension, so allowing
+/// `git.exe` allows `git`, `GIT.EXE` and `C:\Program Files\Git\bin\git.exe`.
+///
+/// Commands which are recognized as a more specific retrieval method, such as
+/// [`SourceRetrievalMethod::GitFile`](crate::SourceRetrievalMethod::GitFile),
+/// are not checked, because they can be used without running the command.
+///
+/// ```
+/// use srcsrv::CommandPolicy;
+///
+/// let policy = CommandPolicy::new()
+///     .allow_program("tf.exe")
+///     .allow_program("git.exe");
+/// assert!(policy.is_allowed(r#"tf.exe view /version:42 "$/Project/main.cpp""#));
+/// assert!(policy.is_allowed(r#""C:\Program Files\Git\bin\git.exe" show abc:main.cpp > main.cpp"#));
+/// assert!(!policy.is_allowed("cmd /c python -c \"print(1)\""));
+/// assert!(!policy.is_allowed("git.exe show abc:main.cpp & calc.exe"));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct CommandPolicy {
+    /// lowercase program names without .exe
+    allowed_programs: Vec<String>,
+}
+
+impl CommandPolicy {
+    /// Create a policy which allows all commands.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow commands which run `program`, e.g. `p4.exe`. Once a program is
+    /// allowed, commands which run other programs are rejected.
+    pub fn allow_program(mut self, program: &str) -> Self {
+        self.allowed_programs.push(program_name(program));
+        self
+    }
+
+    /// Whether the policy allows `command`.
+    pub fn is_allowed(&self, command: &str) -> bool {
+        self.disallowed_program(command).is_none()
+    }
+
+    /// The first program in `command` which the policy doesn't allow, as it
+    /// appears in the command.
+    pub(crate) fn disallowed_program(&self, command: &str) -> Option<String> {
+        if self.allowed_programs.is_empty() {
+            return None;
+        }
+        let tokens = tokenize_command(command);
+        if tokens.is_empty() {
+            return Some(String::new());
+        }
+        let mut expect_program = true;
+        let mut after_redirection = false;
+        for token in tokens {
+            if is_operator(&token) {
+                after_redirection = matches!(token.as_str(), ">" | ">>" | "<");
+                expect_program |= !after_redirection;
+                continue;
+            }
+            if after_redirection {
+                // The path after a redirection operator.
+                after_redirection = false;
+                continue;
+            }
+            if expect_program {
+                if !self.allowed_programs.contains(&program_name(&token)) {
+                    return Some(token);
+                }
+                expect_program = false;
+            }
+        }
+        None
+    }
+}
+
+/// The lowercase file name of `program`, without the `.exe` extension.
+fn program_name(program: &str) -> String {
+    let file_name = program.rsplit(['\\', '/']).next().unwrap_or(program);
+    let file_name = file_name.to_ascii_lowercase();
+    match file_name.strip_suffix(".exe") {
+        Some(name) => name.to_string(),
+        None => file_name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CommandPolicy;
+    use crate::{EvalError, EvalOptions, SrcSrvStream};
+
+    #[test]
+    fn disallowed_program() {
+        let policy = CommandPolicy::new()
+            .allow_program("P4.EXE")
+            .allow_program("tar");
+        assert_eq!(policy.disallowed_program("p4 print -q //a.cpp#1"), None);
+        assert_eq!(
+            policy.disallowed_program(r#"p4 print -q //a.cpp#1 > "C:\a b.cpp" | tar -xO"#),
+            None
+        );
+        assert_eq!(
+            policy.disallowed_program(r#"p4 print -q //a.cpp#1 > C:\a.cpp && del C:\a.cpp"#),
+            Some("del".to_string())
+        );
+        assert_eq!(policy.disallowed_program(""), Some(String::new()));
+        assert_eq!(CommandPolicy::new().disallowed_program("anything"), None);
+    }
+
+    #[test]
+    fn lookup_with_command_policy() {
+        let stream = r#"SRCSRV: ini ------------------------------------------------
+VERSION=1
+SRCSRV: variables ------------------------------------------
+SRCSRVTRG=%targ%\%var2%
+SRCSRVCMD=%var3% get %var2% > %srcsrvtrg%
+SRCSRV: source files ---------------------------------------
+c:\build\a.cpp*a.cpp*tool.exe
+c:\build\b.cpp*b.cpp*evil.exe
+SRCSRV: end ------------------------------------------------"#;
+        let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
+        let options = EvalOptions::new().command_policy(CommandPolicy::new().allow_program("tool"));
+        assert!(stream
+            .source_for_path_with_vars(r#"c:\build\a.cpp"#, "", &options)
+            .is_ok());
+        assert_eq!(
+            stream.source_for_path_with_vars(r#"c:\build\b.cpp"#, "", &options),
+            Err(EvalError::CommandNotAllowed("evil.exe".to_string()))
+        );
+    }
+}
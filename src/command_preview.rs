@@ -0,0 +1,179 @@
+use crate::recognize::{is_operator, tokenize_command};
+use crate::{parse_env, EvalError, SourceRetrievalMethod, SrcSrvStream};
+use std::collections::HashMap;
+use std::result::Result;
+
+/// What would run to obtain a source file, without running it, so that the
+/// command can be shown to the user for approval.
+///
+/// The command line is split into tokens roughly like cmd.exe would do it:
+/// whitespace separates tokens, double quotes group characters and are
+/// removed, and the operators `>`, `>>`, `<`, `|`, `||`, `&` and `&&` are
+/// separate tokens. This is a best-effort decomposition for display purposes;
+/// [`CommandPreview::command`] is what would actually be run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CommandPreview {
+    /// The fully substituted command line.
+    pub command: String,
+    /// The first token of the command line, e.g. `tf.exe` or `cmd`.
+    pub program: String,
+    /// The tokens after the program, up to the first operator.
+    pub args: Vec<String>,
+    /// The tokens from the first operator on, e.g. `>` and a path for a
+    /// redirection, or `&` and another command. Empty if the command line
+    /// just runs the program.
+    pub trailing_tokens: Vec<String>,
+    /// The environment variables from `SRCSRVENV` which would be set.
+    pub env: HashMap<String, String>,
+    /// The path at which the command would create the source file.
+    pub target_path: String,
+}
+
+impl CommandPreview {
+    fn new(command: String, env: HashMap<String, String>, target_path: String) -> Self {
+        let mut tokens = tokenize_command(&command).into_iter();
+        let program = tokens.next().unwrap_or_default();
+        let mut args = Vec::new();
+        let mut trailing_tokens = Vec::new();
+        for token in tokens {
+            if !trailing_tokens.is_empty() || is_operator(&token) {
+                trailing_tokens.push(token);
+            } else {
+                args.push(token);
+            }
+        }
+        Self {
+            command,
+            program,
+            args,
+            trailing_tokens,
+            env,
+            target_path,
+        }
+    }
+}
+
+impl SourceRetrievalMethod {
+    /// A preview of the command of a [`SourceRetrievalMethod::ExecuteCommand`].
+    /// Returns `None` for all other variants; use
+    /// [`SrcSrvStream::command_preview_for_path`] to preview the commands of
+    /// recognized variants like [`SourceRetrievalMethod::GitFile`] as well.
+    pub fn command_preview(&self) -> Option<CommandPreview> {
+        match self {
+            SourceRetrievalMethod::ExecuteCommand {
+                command,
+                env,
+                target_path,
+                ..
+            } => Some(CommandPreview::new(
+                command.clone(),
+                env.clone(),
+                target_path.clone(),
+            )),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> SrcSrvStream<'a> {
+    /// Evaluate the command which the debugger would run to obtain the source
+    /// for `original_file_path`, without running it.
+    ///
+    /// Unlike [`SourceRetrievalMethod::command_preview`], this also works for
+    /// commands which were recognized as a more specific retrieval method, such
+    /// as [`SourceRetrievalMethod::GitFile`].
+    ///
+    /// `extraction_base_path` is used as the value of the special `%targ%` variable
+    /// and should not include a trailing backslash.
+    ///
+    /// Returns `Ok(None)` if the file path was not found in the list of file
+    /// entries, or if the stream has no `SRCSRVCMD` variable.
+    ///
+    /// ```
+    /// use srcsrv::SrcSrvStream;
+    ///
+    /// # fn wrapper(stream: &SrcSrvStream) -> std::result::Result<(), srcsrv::EvalError> {
+    /// let path = r#"C:\build\renderdoc\renderdoc\data\glsl\gl_texsample.h"#;
+    /// if let Some(preview) = stream.command_preview_for_path(path, r#"C:\Debugger\Cached Sources"#)? {
+    ///     println!("Run {} with arguments {:?}?", preview.program, preview.args);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn command_preview_for_path(
+        &self,
+        original_file_path: &str,
+        extraction_base_path: &str,
+    ) -> Result<Option<CommandPreview>, EvalError> {
+        let vars = match self
+            .source_and_raw_var_values_for_path(original_file_path, extraction_base_path)?
+        {
+            Some((_, vars)) => vars,
+            None => return Ok(None),
+        };
+        let command = match vars.get("srcsrvcmd") {
+            Some(command) => command.clone(),
+            None => return Ok(None),
+        };
+        let env = vars
+            .get("srcsrvenv")
+            .map(|env| parse_env(env))
+            .unwrap_or_default();
+        let target_path = vars.get("srcsrvtrg").cloned().unwrap_or_default();
+        Ok(Some(CommandPreview::new(command, env, target_path)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{EvalOptions, SrcSrvStream};
+    use std::collections::HashMap;
+
+    #[test]
+    fn command_preview() {
+        let stream = r#"SRCSRV: ini ------------------------------------------------
+VERSION=1
+SRCSRV: variables ------------------------------------------
+SRCSRVENV=GIT_DIR=C:\repo.git<BS>GIT_PAGER=cat
+SRCSRVTRG=%targ%\%var2%\%fnfile%(%var1%)
+SRCSRVCMD=git.exe -C "C:\repo" show %var2%:%var3% > "%srcsrvtrg%"
+SRCSRV: source files ---------------------------------------
+c:\build\my file.cpp*abc123*src/my file.cpp
+SRCSRV: end ------------------------------------------------"#
+            .replace("<BS>", "\x08");
+        let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
+        let preview = stream
+            .command_preview_for_path(r#"c:\build\my file.cpp"#, r#"C:\Cache"#)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            preview.command,
+            r#"git.exe -C "C:\repo" show abc123:src/my file.cpp > "C:\Cache\abc123\my file.cpp""#
+        );
+        assert_eq!(preview.program, "git.exe");
+        assert_eq!(
+            preview.args,
+            ["-C", r#"C:\repo"#, "show", "abc123:src/my", "file.cpp"]
+        );
+        assert_eq!(
+            preview.trailing_tokens,
+            [">", r#"C:\Cache\abc123\my file.cpp"#]
+        );
+        assert_eq!(
+            preview.env,
+            HashMap::from([
+                ("GIT_DIR".to_string(), r#"C:\repo.git"#.to_string()),
+                ("GIT_PAGER".to_string(), "cat".to_string()),
+            ])
+        );
+        assert_eq!(preview.target_path, r#"C:\Cache\abc123\my file.cpp"#);
+
+        let options = EvalOptions::new().recognize_commands(true);
+        let method = stream
+            .source_for_path_with_vars(r#"c:\build\my file.cpp"#, r#"C:\Cache"#, &options)
+            .unwrap()
+            .unwrap();
+        assert_eq!(method.command_preview(), None);
+    }
+}
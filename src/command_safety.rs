@@ -0,0 +1,434 @@
+use crate::recognize::{is_operator, tokenize_command};
+use crate::{EvalError, EvalVarMap, SourceRetrievalMethod, SrcSrvStream};
+use std::result::Result;
+
+/// Programs which only read from a version control server or download a file,
+/// as long as they are not given one of the [`UNSAFE_OPTIONS`].
+const KNOWN_SAFE_PROGRAMS: &[&str] = &[
+    "git", "hg", "svn", "p4", "sd", "tf", "cvs", "tar", "curl", "wget",
+];
+
+/// Programs which run code that is passed to them or downloaded by them.
+const CODE_RUNNING_PROGRAMS: &[&str] = &[
+    "python",
+    "python3",
+    "py",
+    "pythonw",
+    "powershell",
+    "pwsh",
+    "cmd",
+    "sh",
+    "bash",
+    "wscript",
+    "cscript",
+    "mshta",
+    "rundll32",
+    "regsvr32",
+    "certutil",
+    "bitsadmin",
+    "msiexec",
+    "perl",
+    "ruby",
+    "node",
+    "start",
+    "call",
+];
+
+/// Options of the known-safe programs which make them run other programs or
+/// read a configuration that can do so. Options which end in `=` or `::`
+/// match by prefix.
+const UNSAFE_OPTIONS: &[(&str, &[&str])] = &[
+    (
+        "git",
+        &[
+            "-c",
+            "--config-env=",
+            "--upload-pack=",
+            "--upload-pack",
+            "--receive-pack=",
+            "--receive-pack",
+            "--exec=",
+            "--exec",
+            "ext::",
+        ],
+    ),
+    ("hg", &["--config"]),
+    ("svn", &["--config-option", "--config-dir"]),
+    (
+        "tar",
+        &[
+            "-I",
+            "-F",
+            "--to-command=",
+            "--use-compress-program=",
+            "--checkpoint-action=",
+            "--info-script=",
+            "--new-volume-script=",
+        ],
+    ),
+    ("curl", &["-K", "--config"]),
+    ("wget", &["-e", "--execute", "--config="]),
+];
+
+/// Characters which have a special meaning for cmd.exe.
+const CMD_METACHARACTERS: &[char] = &['&', '|', '<', '>', '^', '"', '%'];
+
+/// The result of [`SrcSrvStream::analyze_command_for_path`] or
+/// [`SourceRetrievalMethod::analyze_command`]: what a command does, and what
+/// is suspicious about it.
+///
+/// Commands come from the PDB file. Tools which process PDB files from
+/// untrusted sources, such as crash report pipelines, should only run commands
+/// which are [known to be safe](CommandAnalysis::is_known_safe).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CommandAnalysis {
+    /// The fully substituted command line.
+    pub command: String,
+    /// The programs which the command runs, in order, as lowercase file names
+    /// without the `.exe` extension. A leading `cmd /c` is not included.
+    pub programs: Vec<String>,
+    /// The suspicious properties of the command. Empty if the command only runs
+    /// known version control and download tools with safe options, and only
+    /// writes below the extraction base path.
+    pub concerns: Vec<CommandConcern>,
+}
+
+/// A suspicious property of a command, see [`CommandAnalysis`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum CommandConcern {
+    /// The command runs a program which is not a known version control or
+    /// download tool.
+    UnknownProgram(String),
+    /// The command runs a program which runs code, such as `python -c` or
+    /// `powershell`. This is also reported for Chrome's download one-liners,
+    /// which are recognized as
+    /// [`SourceRetrievalMethod::DownloadWithDecode`] and can be fetched
+    /// without running the command.
+    RunsCode(String),
+    /// A known-safe program is given an option which can make it run other
+    /// programs, such as `git -c core.sshCommand=...`.
+    UnsafeOption { program: String, option: String },
+    /// The command runs several commands with `&`, `&&` or `||`.
+    ChainsCommands(String),
+    /// The command writes to a path outside of the extraction base path
+    /// (`%targ%`).
+    WritesOutsideTarget(String),
+    /// The value of a file entry variable, such as `var3`, contains characters
+    /// which are special to cmd.exe and appears in the command, so the entry
+    /// can inject commands.
+    VariableInjection { variable: String, value: String },
+}
+
+impl CommandAnalysis {
+    fn new(command: &str, target_path: &str, extraction_base_path: &str) -> Self {
+        let tokens = tokenize_command(command);
+        let mut programs = Vec::new();
+        let mut concerns = Vec::new();
+        if !is_below(target_path, extraction_base_path) {
+            concerns.push(CommandConcern::WritesOutsideTarget(target_path.to_string()));
+        }
+
+        for operator in tokens.iter().filter(|token| is_chain_operator(token)) {
+            concerns.push(CommandConcern::ChainsCommands(operator.clone()));
+        }
+        for mut segment in tokens.split(|token| is_operator(token) && !is_redirection(token)) {
+            // cmd /c only runs the rest of the command line.
+            while let [program, flag, rest @ ..] = segment {
+                if program_name(program) != "cmd" || !flag.eq_ignore_ascii_case("/c") {
+                    break;
+                }
+                segment = rest;
+            }
+            let (program, args) = match segment.split_first() {
+                Some((program, args)) => (program_name(program), args),
+                None => continue,
+            };
+            if CODE_RUNNING_PROGRAMS.contains(&program.as_str()) {
+                concerns.push(CommandConcern::RunsCode(program.clone()));
+            } else if !KNOWN_SAFE_PROGRAMS.contains(&program.as_str()) {
+                concerns.push(CommandConcern::UnknownProgram(program.clone()));
+            }
+            concerns.extend(unsafe_options(&program, args));
+            for path in written_paths(args) {
+                if !is_below(path, extraction_base_path) {
+                    concerns.push(CommandConcern::WritesOutsideTarget(path.to_string()));
+                }
+            }
+            programs.push(program);
+        }
+        concerns.dedup();
+
+        Self {
+            command: command.to_string(),
+            programs,
+            concerns,
+        }
+    }
+
+    /// Whether the command has no [concerns](CommandAnalysis::concerns).
+    pub fn is_known_safe(&self) -> bool {
+        self.concerns.is_empty()
+    }
+
+    /// Report the file entry variables (`var1`, `var2`, ...) whose values
+    /// contain cmd.exe metacharacters and appear in the command.
+    fn check_variables(&mut self, vars: &EvalVarMap) {
+        let mut injections: Vec<(usize, &str)> = vars
+            .iter()
+            .filter_map(|(name, value)| {
+                let index = name.strip_prefix("var")?.parse().ok()?;
+                Some((index, value.as_str()))
+            })
+            .filter(|(_, value)| value.contains(CMD_METACHARACTERS))
+            .filter(|(_, value)| self.command.contains(value))
+            .collect();
+        injections.sort();
+        self.concerns
+            .extend(injections.into_iter().map(|(index, value)| {
+                CommandConcern::VariableInjection {
+                    variable: format!("var{}", index),
+                    value: value.to_string(),
+                }
+            }));
+    }
+}
+
+impl SourceRetrievalMethod {
+    /// Analyze the command of a [`SourceRetrievalMethod::ExecuteCommand`].
+    /// Returns `None` for all other variants; use
+    /// [`SrcSrvStream::analyze_command_for_path`] to analyze the commands of
+    /// recognized variants as well, and to check the file entry variables for
+    /// injected commands.
+    ///
+    /// `extraction_base_path` should be the value which was used for `%targ%`.
+    pub fn analyze_command(&self, extraction_base_path: &str) -> Option<CommandAnalysis> {
+        match self {
+            SourceRetrievalMethod::ExecuteCommand {
+                command,
+                target_path,
+                ..
+            } => Some(CommandAnalysis::new(
+                command,
+                target_path,
+                extraction_base_path,
+            )),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> SrcSrvStream<'a> {
+    /// Analyze the command which the debugger would run to obtain the source
+    /// for `original_file_path`, without running it.
+    ///
+    /// `extraction_base_path` is used as the value of the special `%targ%` variable
+    /// and should not include a trailing backslash.
+    ///
+    /// Returns `Ok(None)` if the file path was not found in the list of file
+    /// entries, or if the stream has no `SRCSRVCMD` variable.
+    ///
+    /// ```
+    /// use srcsrv::SrcSrvStream;
+    ///
+    /// # fn wrapper(stream: &SrcSrvStream) -> std::result::Result<(), srcsrv::EvalError> {
+    /// let path = r#"C:\build\renderdoc\renderdoc\data\glsl\gl_texsample.h"#;
+    /// if let Some(analysis) = stream.analyze_command_for_path(path, r#"C:\Debugger\Cached Sources"#)? {
+    ///     if !analysis.is_known_safe() {
+    ///         eprintln!("Refusing to run {}: {:?}", analysis.command, analysis.concerns);
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn analyze_command_for_path(
+        &self,
+        original_file_path: &str,
+        extraction_base_path: &str,
+    ) -> Result<Option<CommandAnalysis>, EvalError> {
+        let vars = match self
+            .source_and_raw_var_values_for_path(original_file_path, extraction_base_path)?
+        {
+            Some((_, vars)) => vars,
+            None => return Ok(None),
+        };
+        let command = match vars.get("srcsrvcmd") {
+            Some(command) => command,
+            None => return Ok(None),
+        };
+        let target_path = vars.get("srcsrvtrg").map(String::as_str).unwrap_or("");
+        let mut analysis = CommandAnalysis::new(command, target_path, extraction_base_path);
+        analysis.check_variables(&vars);
+        Ok(Some(analysis))
+    }
+}
+
+/// The lowercase file name of `token`, without the `.exe` extension.
+fn program_name(token: &str) -> String {
+    let file_name = token.rsplit(['\\', '/']).next().unwrap_or(token);
+    let file_name = file_name.to_ascii_lowercase();
+    match file_name.strip_suffix(".exe") {
+        Some(name) => name.to_string(),
+        None => file_name,
+    }
+}
+
+fn is_redirection(token: &str) -> bool {
+    matches!(token, ">" | ">>" | "<")
+}
+
+fn is_chain_operator(token: &str) -> bool {
+    matches!(token, "&" | "&&" | "||")
+}
+
+fn unsafe_options(program: &str, args: &[String]) -> Vec<CommandConcern> {
+    let options = match UNSAFE_OPTIONS.iter().find(|(name, _)| *name == program) {
+        Some((_, options)) => *options,
+        None => return Vec::new(),
+    };
+    args.iter()
+        .filter(|arg| {
+            options.iter().any(|option| {
+                if option.ends_with('=') || option.ends_with("::") {
+                    arg.starts_with(option)
+                } else {
+                    arg.as_str() == *option
+                }
+            })
+        })
+        .map(|arg| CommandConcern::UnsafeOption {
+            program: program.to_string(),
+            option: arg.clone(),
+        })
+        .collect()
+}
+
+/// The paths which the arguments of a command segment write to: redirection
+/// targets and the values of output options like `-o` and `/output:`.
+fn written_paths(args: &[String]) -> Vec<&str> {
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            ">" | ">>" | "-o" | "-O" | "--output" | "--output-document" => {
+                paths.extend(args.next().map(String::as_str));
+            }
+            arg => {
+                if let Some(path) = arg.strip_prefix("--output=") {
+                    paths.push(path);
+                } else if arg.len() > 8 && arg[..8].eq_ignore_ascii_case("/output:") {
+                    paths.push(&arg[8..]);
+                }
+            }
+        }
+    }
+    paths
+}
+
+/// Whether `path` is `base` or below it, and doesn't leave it with `..`
+/// components. Paths are compared case-insensitively, with `/` and `\` as
+/// separators. If `base` is empty, only `..` components are checked.
+fn is_below(path: &str, base: &str) -> bool {
+    let normalize = |path: &str| path.replace('/', "\\").to_ascii_lowercase();
+    let path = normalize(path);
+    if path.split('\\').any(|component| component == "..") {
+        return false;
+    }
+    let base = normalize(base);
+    let base = base.trim_end_matches('\\');
+    if base.is_empty() {
+        return true;
+    }
+    match path.strip_prefix(base) {
+        Some(rest) => rest.is_empty() || rest.starts_with('\\'),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CommandConcern;
+    use crate::SrcSrvStream;
+
+    fn analyze(cmd: &str, var3: &str) -> Vec<CommandConcern> {
+        let stream = format!(
+            r#"SRCSRV: ini ------------------------------------------------
+VERSION=1
+SRCSRV: variables ------------------------------------------
+SRCSRVTRG=%targ%\%var2%\%fnfile%(%var1%)
+SRCSRVCMD={}
+SRCSRV: source files ---------------------------------------
+c:\build\a.cpp*abc123*{}
+SRCSRV: end ------------------------------------------------"#,
+            cmd, var3
+        );
+        let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
+        stream
+            .analyze_command_for_path(r#"c:\build\a.cpp"#, r#"C:\Cache"#)
+            .unwrap()
+            .unwrap()
+            .concerns
+    }
+
+    #[test]
+    fn safe_commands() {
+        assert_eq!(
+            analyze(
+                r#"cmd /c git.exe -C C:\repo show %var2%:%var3% > "%srcsrvtrg%""#,
+                "src/a.cpp"
+            ),
+            []
+        );
+        assert_eq!(
+            analyze(
+                r#"p4.exe print -o "%srcsrvtrg%" -q "//depot/%var3%#12""#,
+                "a.cpp"
+            ),
+            []
+        );
+    }
+
+    #[test]
+    fn suspicious_commands() {
+        assert_eq!(
+            analyze(
+                r#"cmd /c python -c "import urllib" %var3% > "%srcsrvtrg%""#,
+                "a.cpp"
+            ),
+            [CommandConcern::RunsCode("python".to_string())]
+        );
+        assert_eq!(
+            analyze(
+                r#"git -c core.sshCommand=calc show %var2%:%var3% > "%srcsrvtrg%""#,
+                "a.cpp"
+            ),
+            [CommandConcern::UnsafeOption {
+                program: "git".to_string(),
+                option: "-c".to_string()
+            }]
+        );
+        assert_eq!(
+            analyze(
+                r#"svn cat %var3% > C:\Windows\evil.dll & tool.exe"#,
+                "a.cpp"
+            ),
+            [
+                CommandConcern::ChainsCommands("&".to_string()),
+                CommandConcern::WritesOutsideTarget(r#"C:\Windows\evil.dll"#.to_string()),
+                CommandConcern::UnknownProgram("tool".to_string()),
+            ]
+        );
+        assert_eq!(
+            analyze(r#"svn cat %var3% > "%srcsrvtrg%""#, "a.cpp > ..\\..\\a.cpp"),
+            [
+                CommandConcern::WritesOutsideTarget(r#"..\..\a.cpp"#.to_string()),
+                CommandConcern::VariableInjection {
+                    variable: "var3".to_string(),
+                    value: r#"a.cpp > ..\..\a.cpp"#.to_string()
+                },
+            ]
+        );
+    }
+}
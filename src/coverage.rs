@@ -0,0 +1,107 @@
+use crate::from_pdb::for_each_pdb_source_file;
+use crate::{LookupOptions, PdbError, SrcSrvStream};
+use std::collections::BTreeSet;
+use std::result::Result;
+
+/// Which of the source files referenced by a PDB's modules have an entry in
+/// the srcsrv stream. Returned by [`SrcSrvStream::pdb_coverage`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PdbCoverage {
+    /// The source files which have a matching entry in the srcsrv stream.
+    pub indexed_files: BTreeSet<String>,
+    /// The source files which have no matching entry in the srcsrv stream.
+    pub unindexed_files: BTreeSet<String>,
+}
+
+impl PdbCoverage {
+    /// Whether every source file has an entry in the srcsrv stream.
+    pub fn is_complete(&self) -> bool {
+        self.unindexed_files.is_empty()
+    }
+
+    pub(crate) fn new(
+        stream: &SrcSrvStream<'_>,
+        files: impl IntoIterator<Item = String>,
+        options: &LookupOptions,
+    ) -> Self {
+        let mut coverage = PdbCoverage::default();
+        for file in files {
+            if stream.find_entry(&file, options).is_some() {
+                coverage.indexed_files.insert(file);
+            } else {
+                coverage.unindexed_files.insert(file);
+            }
+        }
+        coverage
+    }
+}
+
+impl<'a> SrcSrvStream<'a> {
+    /// Cross-reference the source files of all modules in `pdb` with the file
+    /// entries of this stream, to find the files which the source indexing
+    /// step missed.
+    ///
+    /// The file paths from the PDB are looked up like in
+    /// [`SrcSrvStream::source_for_path_with_options`]. Modules with line
+    /// information in the old C11 format are skipped.
+    ///
+    /// ```
+    /// use srcsrv::{LookupOptions, SrcSrvStream};
+    ///
+    /// # fn wrapper<'s, S: pdb::Source<'s> + 's>(pdb: &mut pdb::PDB<'s, S>) -> std::result::Result<(), srcsrv::PdbError> {
+    /// if let Some(stream) = SrcSrvStream::from_pdb(pdb)? {
+    ///     let coverage = stream.stream().pdb_coverage(pdb, &LookupOptions::default())?;
+    ///     for file in &coverage.unindexed_files {
+    ///         println!("Not source-indexed: {file}");
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn pdb_coverage<'s, S: pdb::Source<'s> + 's>(
+        &self,
+        pdb: &mut pdb::PDB<'s, S>,
+        options: &LookupOptions,
+    ) -> Result<PdbCoverage, PdbError> {
+        let mut files = BTreeSet::new();
+        for_each_pdb_source_file(pdb, |name, _| {
+            files.insert(name);
+        })
+        .map_err(PdbError::Pdb)?;
+        Ok(PdbCoverage::new(self, files, options))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PdbCoverage;
+    use crate::{LookupOptions, SrcSrvStream, SrcSrvStreamBuilder};
+
+    #[test]
+    fn coverage() {
+        let bytes = SrcSrvStreamBuilder::new()
+            .set_var("SRCSRVTRG", "https://example.com/%var2%")
+            .add_source_file_entry(&[r#"C:\build\src\main.cpp"#, "src/main.cpp"])
+            .add_source_file_entry(&[r#"C:\build\src\util.h"#, "src/util.h"])
+            .to_bytes()
+            .unwrap();
+        let stream = SrcSrvStream::parse(&bytes).unwrap();
+        let files = [
+            r#"c:\build\src\MAIN.cpp"#,
+            r#"C:\build\src\util.h"#,
+            r#"C:\Program Files\Microsoft Visual Studio\VC\include\vector"#,
+        ];
+        let coverage =
+            PdbCoverage::new(&stream, files.map(String::from), &LookupOptions::default());
+        assert!(!coverage.is_complete());
+        assert_eq!(
+            coverage.indexed_files.iter().collect::<Vec<_>>(),
+            [r#"C:\build\src\util.h"#, r#"c:\build\src\MAIN.cpp"#]
+        );
+        assert_eq!(
+            coverage.unindexed_files.iter().collect::<Vec<_>>(),
+            [r#"C:\Program Files\Microsoft Visual Studio\VC\include\vector"#]
+        );
+    }
+}
@@ -0,0 +1,234 @@
+use crate::{entry_path, split_entry, SrcSrvStream};
+use std::collections::HashSet;
+
+/// The differences between two streams, see [`diff`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StreamDiff {
+    /// The ini fields which were added, removed or changed.
+    pub ini_fields: Vec<FieldChange>,
+    /// The variables which were added, removed or changed.
+    pub variables: Vec<FieldChange>,
+    /// The values of var1, ..., varN of the entries for paths which only the
+    /// new stream has, in the order of the new stream.
+    pub added_entries: Vec<Vec<String>>,
+    /// The values of var1, ..., varN of the entries for paths which only the
+    /// old stream has, in the order of the old stream.
+    pub removed_entries: Vec<Vec<String>>,
+    /// The entries for paths which both streams have, but with different
+    /// values, in the order of the old stream.
+    pub changed_entries: Vec<EntryChange>,
+}
+
+impl StreamDiff {
+    /// Whether the streams have the same ini fields, variables and entries.
+    pub fn is_empty(&self) -> bool {
+        self.ini_fields.is_empty()
+            && self.variables.is_empty()
+            && self.added_entries.is_empty()
+            && self.removed_entries.is_empty()
+            && self.changed_entries.is_empty()
+    }
+}
+
+/// An ini field or variable which differs between two streams.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FieldChange {
+    /// The name of the field, as it appears in the old stream if it has it.
+    pub name: String,
+    /// The raw value in the old stream, `None` if the field was added.
+    pub old_value: Option<String>,
+    /// The raw value in the new stream, `None` if the field was removed.
+    pub new_value: Option<String>,
+}
+
+/// A file entry whose values differ between two streams.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EntryChange {
+    /// The values of var1, ..., varN in the old stream.
+    pub old_vars: Vec<String>,
+    /// The values of var1, ..., varN in the new stream.
+    pub new_vars: Vec<String>,
+}
+
+/// Compare the ini fields, variables and file entries of two streams, for
+/// example of two builds, to find out how the source indexing changed.
+///
+/// Field names and entry paths are compared ASCII case-insensitively, and
+/// values are compared exactly. If a stream has multiple entries for a path,
+/// the entry which lookups use is compared.
+///
+/// ```
+/// use srcsrv::SrcSrvStream;
+///
+/// # fn wrapper(old_bytes: &[u8], new_bytes: &[u8]) -> std::result::Result<(), srcsrv::ParseError> {
+/// let old = SrcSrvStream::parse(old_bytes)?;
+/// let new = SrcSrvStream::parse(new_bytes)?;
+/// let diff = srcsrv::diff(&old, &new);
+/// for vars in &diff.removed_entries {
+///     eprintln!("No longer indexed: {}", vars[0]);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn diff(old: &SrcSrvStream<'_>, new: &SrcSrvStream<'_>) -> StreamDiff {
+    let ini_fields = diff_fields(&old.ini_lines, &new.ini_lines, |name| {
+        (old.get_ini_field(name), new.get_ini_field(name))
+    });
+    let variables = diff_fields(&old.var_lines, &new.var_lines, |name| {
+        (old.get_raw_var(name), new.get_raw_var(name))
+    });
+
+    let mut removed_entries = Vec::new();
+    let mut changed_entries = Vec::new();
+    for old_vars in selected_entries(old) {
+        match new.source_file_index.get(old_vars[0]) {
+            Some(index) => {
+                let new_vars = split_entry(new.source_file_entries[index]);
+                if new_vars != old_vars {
+                    changed_entries.push(EntryChange {
+                        old_vars: to_strings(&old_vars),
+                        new_vars: to_strings(&new_vars),
+                    });
+                }
+            }
+            None => removed_entries.push(to_strings(&old_vars)),
+        }
+    }
+    let added_entries = selected_entries(new)
+        .into_iter()
+        .filter(|new_vars| old.source_file_index.get(new_vars[0]).is_none())
+        .map(|new_vars| to_strings(&new_vars))
+        .collect();
+
+    StreamDiff {
+        ini_fields,
+        variables,
+        added_entries,
+        removed_entries,
+        changed_entries,
+    }
+}
+
+/// The changes between the fields with the names in `old_lines` and
+/// `new_lines`, with `values` returning the (old, new) value of a name.
+fn diff_fields<'a>(
+    old_lines: &[(&str, &str)],
+    new_lines: &[(&str, &str)],
+    values: impl Fn(&str) -> (Option<&'a str>, Option<&'a str>),
+) -> Vec<FieldChange> {
+    let mut seen_names = HashSet::new();
+    let mut changes = Vec::new();
+    for (name, _) in old_lines.iter().chain(new_lines) {
+        if !seen_names.insert(name.to_ascii_lowercase()) {
+            continue;
+        }
+        let (old_value, new_value) = values(name);
+        if old_value != new_value {
+            changes.push(FieldChange {
+                name: name.to_string(),
+                old_value: old_value.map(str::to_string),
+                new_value: new_value.map(str::to_string),
+            });
+        }
+    }
+    changes
+}
+
+/// The values of the entry which lookups use for each path, in stream order.
+fn selected_entries<'a>(stream: &SrcSrvStream<'a>) -> Vec<Vec<&'a str>> {
+    let mut seen_paths = HashSet::new();
+    stream
+        .source_file_entries
+        .iter()
+        .filter_map(|line| {
+            let path = entry_path(line);
+            if !seen_paths.insert(path.to_ascii_lowercase()) {
+                return None;
+            }
+            let index = stream.source_file_index.get(path)?;
+            Some(split_entry(stream.source_file_entries[index]))
+        })
+        .collect()
+}
+
+fn to_strings(vars: &[&str]) -> Vec<String> {
+    vars.iter().map(|var| var.to_string()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff, EntryChange, FieldChange};
+    use crate::SrcSrvStream;
+
+    #[test]
+    fn diff_streams() {
+        let old = r#"SRCSRV: ini ------------------------------------------------
+VERSION=2
+DATETIME=Mon Jan 01 00:00:00 2024
+SRCSRV: variables ------------------------------------------
+HTTP_ALIAS=https://example.com/abc/
+SRCSRVTRG=%HTTP_ALIAS%%var2%
+SRCSRV: source files ---------------------------------------
+c:\build\a.cpp*a.cpp
+c:\build\b.cpp*b.cpp
+c:\build\c.cpp*c.cpp
+SRCSRV: end ------------------------------------------------"#;
+        let new = r#"SRCSRV: ini ------------------------------------------------
+VERSION=2
+SRCSRV: variables ------------------------------------------
+HTTP_ALIAS=https://example.com/def/
+SRCSRVTRG=%HTTP_ALIAS%%var2%
+SRCSRVERRVAR=var2
+SRCSRV: source files ---------------------------------------
+C:\BUILD\A.CPP*a.cpp
+c:\build\b.cpp*src/b.cpp
+c:\build\d.cpp*d.cpp
+SRCSRV: end ------------------------------------------------"#;
+        let old = SrcSrvStream::parse(old.as_bytes()).unwrap();
+        let new = SrcSrvStream::parse(new.as_bytes()).unwrap();
+        let diff = diff(&old, &new);
+        assert_eq!(
+            diff.ini_fields,
+            [FieldChange {
+                name: "DATETIME".to_string(),
+                old_value: Some("Mon Jan 01 00:00:00 2024".to_string()),
+                new_value: None,
+            }]
+        );
+        assert_eq!(
+            diff.variables,
+            [
+                FieldChange {
+                    name: "HTTP_ALIAS".to_string(),
+                    old_value: Some("https://example.com/abc/".to_string()),
+                    new_value: Some("https://example.com/def/".to_string()),
+                },
+                FieldChange {
+                    name: "SRCSRVERRVAR".to_string(),
+                    old_value: None,
+                    new_value: Some("var2".to_string()),
+                },
+            ]
+        );
+        assert_eq!(diff.added_entries, [[r#"c:\build\d.cpp"#, "d.cpp"]]);
+        assert_eq!(diff.removed_entries, [[r#"c:\build\c.cpp"#, "c.cpp"]]);
+        assert_eq!(
+            diff.changed_entries,
+            [
+                EntryChange {
+                    old_vars: vec![r#"c:\build\a.cpp"#.to_string(), "a.cpp".to_string()],
+                    new_vars: vec![r#"C:\BUILD\A.CPP"#.to_string(), "a.cpp".to_string()],
+                },
+                EntryChange {
+                    old_vars: vec![r#"c:\build\b.cpp"#.to_string(), "b.cpp".to_string()],
+                    new_vars: vec![r#"c:\build\b.cpp"#.to_string(), "src/b.cpp".to_string()],
+                },
+            ]
+        );
+        assert!(!diff.is_empty());
+        assert!(super::diff(&old, &old).is_empty());
+    }
+}
@@ -0,0 +1,48 @@
+use crate::case_insensitive::CaseInsensitiveStr;
+use rustc_hash::FxHashMap;
+
+/// A variable or file entry which is defined more than once in the stream,
+/// see [`SrcSrvStream::duplicate_variables`](crate::SrcSrvStream::duplicate_variables)
+/// and [`SrcSrvStream::duplicate_entries`](crate::SrcSrvStream::duplicate_entries).
+///
+/// Which definition is used is determined by the
+/// [`DuplicatePolicy`](crate::DuplicatePolicy) in the [`ParseOptions`](crate::ParseOptions).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Duplicate<'a> {
+    /// The variable name or original file path, as written in the first definition.
+    pub name: &'a str,
+    /// The 1-based line numbers of all definitions, in stream order.
+    pub line_numbers: Vec<usize>,
+}
+
+/// Collects the duplicates of one section during parsing.
+#[derive(Default)]
+pub(crate) struct DuplicateTracker<'a> {
+    pub(crate) duplicates: Vec<Duplicate<'a>>,
+    /// name -> index into duplicates
+    index: FxHashMap<&'a CaseInsensitiveStr, usize>,
+}
+
+impl<'a> DuplicateTracker<'a> {
+    /// Record another definition of `name`. The name and line number of the
+    /// first definition are only used the first time a name is recorded.
+    pub(crate) fn record(
+        &mut self,
+        first_name: &'a str,
+        first_line_number: usize,
+        line_number: usize,
+    ) {
+        let duplicates = &mut self.duplicates;
+        let index = *self
+            .index
+            .entry(CaseInsensitiveStr::new(first_name))
+            .or_insert_with(|| {
+                duplicates.push(Duplicate {
+                    name: first_name,
+                    line_numbers: vec![first_line_number],
+                });
+                duplicates.len() - 1
+            });
+        duplicates[index].line_numbers.push(line_number);
+    }
+}
@@ -0,0 +1,54 @@
+use crate::case_insensitive::CaseInsensitiveStr;
+use rustc_hash::{FxHashMap, FxSeededState};
+use std::hash::BuildHasher;
+
+/// Maps the original path of each file entry to its index into the stream's
+/// file entries. The paths are compared ASCII case-insensitively.
+///
+/// When the source files section is parsed in parallel, the map is split into
+/// shards by the hash of the path, so that each shard can be built on its own
+/// thread.
+pub(crate) struct EntryIndex<'a> {
+    shards: Vec<FxHashMap<&'a CaseInsensitiveStr, usize>>,
+}
+
+/// Choose shards with a different hash than the one used within the shards,
+/// so that all keys of a shard don't end up with similar hashes.
+const SHARD_HASHER: FxSeededState = FxSeededState::with_seed(0x5eed);
+
+impl<'a> EntryIndex<'a> {
+    /// Create an empty index which is not split into shards.
+    pub(crate) fn new() -> Self {
+        Self::with_shards(vec![FxHashMap::default()])
+    }
+
+    pub(crate) fn with_shards(shards: Vec<FxHashMap<&'a CaseInsensitiveStr, usize>>) -> Self {
+        EntryIndex { shards }
+    }
+
+    /// The index of the entry for `path`.
+    pub(crate) fn get(&self, path: &str) -> Option<usize> {
+        let key = CaseInsensitiveStr::new(path);
+        self.shards[shard_of(key, self.shards.len())]
+            .get(key)
+            .copied()
+    }
+
+    /// The shard that `path` belongs to, which is the whole map if the index
+    /// is not split into shards.
+    pub(crate) fn shard_mut(
+        &mut self,
+        path: &str,
+    ) -> &mut FxHashMap<&'a CaseInsensitiveStr, usize> {
+        let shard = shard_of(CaseInsensitiveStr::new(path), self.shards.len());
+        &mut self.shards[shard]
+    }
+}
+
+/// The shard for `key` in an index with `shard_count` shards.
+pub(crate) fn shard_of(key: &CaseInsensitiveStr, shard_count: usize) -> usize {
+    if shard_count == 1 {
+        return 0;
+    }
+    (SHARD_HASHER.hash_one(key) % shard_count as u64) as usize
+}
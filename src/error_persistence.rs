@@ -0,0 +1,238 @@
+use crate::{SourceRetrievalMethod, SrcSrvStream};
+use std::collections::HashSet;
+
+/// Keeps track of the version control systems for which commands should no
+/// longer be executed, as described in
+/// [the srcsrv documentation](https://docs.microsoft.com/en-us/windows-hardware/drivers/debugger/language-specification-1#handling-server-errors).
+///
+/// A stream can list error messages in its `SRCSRVERRDESC` variables, see
+/// [`SrcSrvStream::error_persistence_command_output_strings`]. If the output
+/// of a command contains one of these messages, the error is "persisted":
+/// commands of all further entries with the same
+/// `error_persistence_version_control` value (the value of the variable named
+/// by `SRCSRVERRVAR`, usually the server) are skipped, so that an unreachable
+/// server isn't contacted once per file.
+///
+/// ```
+/// use srcsrv::{ErrorPersistenceTracker, SourceRetrievalMethod, SrcSrvStream};
+///
+/// # fn run(command: &str) -> Vec<u8> { unimplemented!() }
+/// # fn wrapper(stream: &SrcSrvStream, paths: &[&str]) -> std::result::Result<(), srcsrv::EvalError> {
+/// let mut tracker = ErrorPersistenceTracker::new(stream);
+/// for path in paths {
+///     let method = match stream.source_for_path(path, r#"C:\Debugger\Cached Sources"#)? {
+///         Some(method) => method,
+///         None => continue,
+///     };
+///     if tracker.should_skip(&method) {
+///         continue;
+///     }
+///     if let SourceRetrievalMethod::ExecuteCommand { command, .. } = &method {
+///         let output = run(command);
+///         tracker.record_output(&method, &output);
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ErrorPersistenceTracker {
+    error_strings: Vec<String>,
+    failed_version_controls: HashSet<String>,
+}
+
+impl ErrorPersistenceTracker {
+    /// Create a tracker for the error messages of `stream`, with no persisted
+    /// errors.
+    pub fn new(stream: &SrcSrvStream) -> Self {
+        let mut error_strings: Vec<String> = stream
+            .error_persistence_command_output_strings()
+            .into_iter()
+            .filter(|s| !s.is_empty())
+            .map(str::to_string)
+            .collect();
+        error_strings.sort();
+        Self {
+            error_strings,
+            failed_version_controls: HashSet::new(),
+        }
+    }
+
+    /// Whether the command of `method` should not be executed, because an
+    /// error was persisted for its `error_persistence_version_control` value.
+    ///
+    /// Always `false` for methods which don't run a command, like downloads,
+    /// and for commands without an `error_persistence_version_control` value.
+    pub fn should_skip(&self, method: &SourceRetrievalMethod) -> bool {
+        match version_control(method) {
+            Some(version_control) => self.is_persisted(version_control),
+            None => false,
+        }
+    }
+
+    /// Check the output of the command of `method` for the error messages of
+    /// the stream. If it contains one, the error is persisted for the
+    /// `error_persistence_version_control` value of `method`.
+    ///
+    /// Returns `true` if the error was persisted.
+    pub fn record_output(&mut self, method: &SourceRetrievalMethod, output: &[u8]) -> bool {
+        let version_control = match version_control(method) {
+            Some(version_control) => version_control,
+            None => return false,
+        };
+        let output = String::from_utf8_lossy(output);
+        if !self
+            .error_strings
+            .iter()
+            .any(|error_string| output.contains(error_string.as_str()))
+        {
+            return false;
+        }
+        self.failed_version_controls
+            .insert(version_control.to_string());
+        true
+    }
+
+    /// Whether an error was persisted for `version_control`.
+    pub fn is_persisted(&self, version_control: &str) -> bool {
+        self.failed_version_controls.contains(version_control)
+    }
+
+    /// Forget all persisted errors, for example when the user asks to retry.
+    pub fn reset(&mut self) {
+        self.failed_version_controls.clear();
+    }
+}
+
+/// The `error_persistence_version_control` value of `method`, for raw
+/// commands as well as for the commands which
+/// [`EvalOptions::recognize_commands`](crate::EvalOptions::recognize_commands)
+/// recognized.
+fn version_control(method: &SourceRetrievalMethod) -> Option<&str> {
+    match method {
+        SourceRetrievalMethod::ExecuteCommand {
+            error_persistence_version_control,
+            ..
+        }
+        | SourceRetrievalMethod::GitFile {
+            error_persistence_version_control,
+            ..
+        }
+        | SourceRetrievalMethod::TfsItem {
+            error_persistence_version_control,
+            ..
+        }
+        | SourceRetrievalMethod::Perforce {
+            error_persistence_version_control,
+            ..
+        }
+        | SourceRetrievalMethod::SourceDepot {
+            error_persistence_version_control,
+            ..
+        }
+        | SourceRetrievalMethod::Svn {
+            error_persistence_version_control,
+            ..
+        }
+        | SourceRetrievalMethod::Cvs {
+            error_persistence_version_control,
+            ..
+        }
+        | SourceRetrievalMethod::CabExtract {
+            error_persistence_version_control,
+            ..
+        } => error_persistence_version_control.as_deref(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ErrorPersistenceTracker;
+    use crate::{EvalOptions, SourceRetrievalMethod, SrcSrvStream};
+
+    #[test]
+    fn error_persistence() {
+        let stream = r#"SRCSRV: ini ------------------------------------------------
+VERSION=1
+SRCSRV: variables ------------------------------------------
+SRCSRVERRDESC=Connect to server failed
+SRCSRVERRDESC2=
+SRCSRVERRVAR=var2
+SRCSRVTRG=%targ%\%var3%
+SRCSRVCMD=tool.exe get %var2% %var3% %srcsrvtrg%
+SRCSRV: source files ---------------------------------------
+c:\build\a.cpp*server1*a.cpp
+c:\build\b.cpp*server1*b.cpp
+c:\build\c.cpp*server2*c.cpp
+SRCSRV: end ------------------------------------------------"#;
+        let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
+        let method = |path| stream.source_for_path(path, "").unwrap().unwrap();
+        let (a, b, c) = (
+            method(r#"c:\build\a.cpp"#),
+            method(r#"c:\build\b.cpp"#),
+            method(r#"c:\build\c.cpp"#),
+        );
+
+        let mut tracker = ErrorPersistenceTracker::new(&stream);
+        assert!(!tracker.should_skip(&a));
+        // Unrelated errors and the empty SRCSRVERRDESC2 don't persist errors.
+        assert!(!tracker.record_output(&a, b"File not found."));
+        assert!(!tracker.should_skip(&b));
+        assert!(tracker.record_output(&a, b"Error: Connect to server failed (10061)"));
+        assert!(tracker.should_skip(&b));
+        assert!(tracker.is_persisted("server1"));
+        assert!(!tracker.should_skip(&c));
+
+        tracker.reset();
+        assert!(!tracker.should_skip(&b));
+    }
+    #[test]
+    fn error_persistence_tfs() {
+        let stream = r#"SRCSRV: ini ------------------------------------------------
+VERSION=3
+INDEXVERSION=2
+VERCTRL=Team Foundation Server
+SRCSRV: variables ------------------------------------------
+SRCSRVVERCTRL=tfs
+SRCSRVERRDESC=access
+SRCSRVERRVAR=var2
+VSTFDEVDIV_DEVDIV2=http://vstfdevdiv.redmond.corp.microsoft.com:8080/DevDiv2
+VSTFDEVDIV_DEVDIV3=http://vstfdevdiv.redmond.corp.microsoft.com:8080/DevDiv3
+TFS_EXTRACT_CMD=tf.exe view /version:%var4% /noprompt "$%var3%" /server:%fnvar%(%var2%) /output:%srcsrvtrg%
+TFS_EXTRACT_TARGET=%targ%\%var2%%fnbksl%(%var3%)\%var4%\%fnfile%(%var1%)
+SRCSRVTRG=%TFS_extract_target%
+SRCSRVCMD=%TFS_extract_cmd%
+SRCSRV: source files ---------------------------------------
+f:\dd\inc\cvinfo.h*VSTFDEVDIV_DEVDIV2*/DevDiv/inc/cvinfo.h*1363200
+f:\dd\inc\ammintrin.h*VSTFDEVDIV_DEVDIV2*/DevDiv/inc/ammintrin.h*1363200
+f:\dd\inc\intrin.h*VSTFDEVDIV_DEVDIV3*/DevDiv/inc/intrin.h*1363200
+SRCSRV: end ------------------------------------------------"#;
+        let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
+        for recognize_commands in [false, true] {
+            let options = EvalOptions::new().recognize_commands(recognize_commands);
+            let method = |path| {
+                stream
+                    .source_for_path_with_vars(path, r#"C:\Cache"#, &options)
+                    .unwrap()
+                    .unwrap()
+            };
+            let cvinfo = method(r#"f:\dd\inc\cvinfo.h"#);
+            let ammintrin = method(r#"f:\dd\inc\ammintrin.h"#);
+            let intrin = method(r#"f:\dd\inc\intrin.h"#);
+            assert_eq!(
+                matches!(cvinfo, SourceRetrievalMethod::TfsItem { .. }),
+                recognize_commands
+            );
+
+            let mut tracker = ErrorPersistenceTracker::new(&stream);
+            assert!(tracker.record_output(
+                &cvinfo,
+                b"TF30063: You are not authorized to access vstfdevdiv."
+            ));
+            assert!(tracker.is_persisted("VSTFDEVDIV_DEVDIV2"));
+            assert!(tracker.should_skip(&ammintrin));
+            assert!(!tracker.should_skip(&intrin));
+        }
+    }
+}
@@ -2,6 +2,9 @@
 #[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
 #[non_exhaustive]
 pub enum ParseError {
+    /// The stream is not valid UTF-8. With the `encoding_rs` feature, streams
+    /// in other encodings can be parsed with
+    /// [`OwnedSrcSrvStream::parse_with_encoding`](crate::OwnedSrcSrvStream::parse_with_encoding).
     #[error("The srcsrv stream is not valid utf-8.")]
     InvalidUtf8,
 
@@ -14,24 +17,264 @@ pub enum ParseError {
     #[error("The VERSION ini variable is missing.")]
     MissingVersion,
 
-    #[error("Could not find the ini section in the srcsrv stream.")]
-    MissingIniSection,
+    #[error("Could not find the ini section in the srcsrv stream, found {line:?} on line {line_number} instead.")]
+    MissingIniSection {
+        /// The 1-based line number.
+        line_number: usize,
+        /// The contents of the line.
+        line: String,
+    },
 
-    #[error("Could not find the variables section in the srcsrv stream.")]
-    MissingVariablesSection,
+    #[error("Could not find the variables section in the srcsrv stream, found {line:?} on line {line_number} instead.")]
+    MissingVariablesSection {
+        /// The 1-based line number.
+        line_number: usize,
+        /// The contents of the line.
+        line: String,
+    },
 
     #[error("The SRCSRVTRG field was missing. This is a required field.")]
     MissingSrcSrvTrgField,
 
-    #[error("Could not find the source files section in the srcsrv stream.")]
-    MissingSourceFilesSection,
+    #[error("Could not find the source files section in the srcsrv stream, found {line:?} on line {line_number} instead.")]
+    MissingSourceFilesSection {
+        /// The 1-based line number.
+        line_number: usize,
+        /// The contents of the line.
+        line: String,
+    },
 
-    #[error("Could not find the end marker line in theh srcsrv stream.")]
-    MissingTerminationLine,
+    #[error("Could not find the end marker line in the srcsrv stream, found {line:?} on line {line_number} instead.")]
+    MissingTerminationLine {
+        /// The 1-based line number.
+        line_number: usize,
+        /// The contents of the line.
+        line: String,
+    },
 
-    #[error("Missing = in a variable line in the srcsrv stream.")]
-    MissingEquals,
+    #[error("The variable {name} in line {line_number} of the srcsrv stream was already defined.")]
+    DuplicateVariable {
+        /// The 1-based line number.
+        line_number: usize,
+        /// The name of the variable.
+        name: String,
+    },
 
+    #[error("The file entry for {path} in line {line_number} of the srcsrv stream duplicates an earlier entry.")]
+    DuplicateEntry {
+        /// The 1-based line number.
+        line_number: usize,
+        /// The original file path of the entry.
+        path: String,
+    },
+
+    #[error("Missing = in line {line_number} of the srcsrv stream: {line:?}")]
+    MissingEquals {
+        /// The 1-based line number.
+        line_number: usize,
+        /// The contents of the line.
+        line: String,
+    },
+
+    #[error("Invalid srcsrv variable value in line {line_number} of the srcsrv stream: {error}")]
+    InvalidTemplate {
+        /// The 1-based line number.
+        line_number: usize,
+        /// The contents of the line.
+        line: String,
+        /// The reason why the variable value could not be parsed.
+        #[source]
+        error: TemplateError,
+    },
+}
+
+/// An enum for errors that occur when reading the `srcsrv` stream from a PDB
+/// file, see [`SrcSrvStream::from_pdb`](crate::SrcSrvStream::from_pdb).
+#[cfg(feature = "pdb")]
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum PdbError {
+    #[error("Could not access the PDB file: {0}")]
+    Io(#[source] std::io::Error),
+
+    #[error("Could not read the srcsrv stream from the PDB file: {0}")]
+    Pdb(#[source] pdb::Error),
+
+    #[error(transparent)]
+    Parse(#[from] ParseError),
+
+    #[error("Could not write the srcsrv stream into the malformed PDB file: {0}")]
+    MalformedPdb(String),
+}
+
+/// An enum for errors that can occur when fetching a source file with
+/// [`fetch_source`](crate::fetch_source).
+#[cfg(feature = "fetch")]
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum FetchError {
+    /// The download failed with an error which won't go away by retrying,
+    /// such as a 404 response.
+    #[error("Downloading the source file failed: {0}")]
+    Http(#[source] Box<dyn std::error::Error + Send + Sync>),
+
+    /// The download failed with an error which is usually temporary, such as
+    /// a 5xx response or a timeout, on every attempt. `error` is the error of
+    /// the last attempt.
+    #[error("Downloading the source file failed after {attempts} attempt(s): {error}")]
+    Transient {
+        attempts: u32,
+        #[source]
+        error: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    #[error("Could not decode the downloaded source file: {0}")]
+    Decode(String),
+
+    #[error("Could not store the source file: {0}")]
+    Io(#[source] std::io::Error),
+
+    #[error("Source files with retrieval kind {0:?} can't be fetched.")]
+    UnsupportedMethod(crate::RetrievalKind),
+
+    /// The [`UrlPolicy`](crate::UrlPolicy) of the
+    /// [`FetchOptions`](crate::FetchOptions) doesn't allow the URL.
+    #[error("The URL {0} is not allowed by the URL policy.")]
+    UrlNotAllowed(String),
+
+    /// The [`CancellationToken`](crate::CancellationToken) was cancelled.
+    #[error("Fetching the source file was cancelled.")]
+    Cancelled,
+}
+
+#[cfg(feature = "fetch")]
+impl FetchError {
+    /// Whether fetching the file again later might succeed.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, FetchError::Transient { .. })
+    }
+}
+
+/// An unsuccessful HTTP response. [`SourceFetcher`](crate::SourceFetcher)
+/// implementations should return this error for responses with an error
+/// status, so that retryable and permanent failures can be told apart.
+#[cfg(feature = "fetch")]
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("The server responded with HTTP status {status}.")]
+pub struct HttpStatusError {
+    pub status: u16,
+}
+
+/// An enum for errors that can occur when running the command of a
+/// [`SourceRetrievalMethod::ExecuteCommand`](crate::SourceRetrievalMethod::ExecuteCommand)
+/// with [`execute_command`](crate::execute_command).
+#[cfg(feature = "exec")]
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum ExecError {
+    #[error("Source files with retrieval kind {0:?} are not obtained by running a command.")]
+    UnsupportedMethod(crate::RetrievalKind),
+
+    #[error("Could not run the command: {0}")]
+    Io(#[source] std::io::Error),
+
+    /// The command runs a program which the
+    /// [`CommandPolicy`](crate::CommandPolicy) doesn't allow.
+    #[error("The program {0} is not allowed by the command policy.")]
+    CommandNotAllowed(String),
+
+    #[error("The command did not finish within {0:?}.")]
+    TimedOut(std::time::Duration),
+
+    /// The [`CancellationToken`](crate::CancellationToken) was cancelled.
+    #[error("Running the command was cancelled.")]
+    Cancelled,
+
+    /// The command finished, but the target file doesn't exist.
+    #[error("The command did not create the target file {target_path}.")]
+    TargetMissing {
+        target_path: String,
+        output: crate::CommandOutput,
+    },
+}
+
+/// An enum for errors that can occur when translating a
+/// [`SourceRetrievalMethod`](crate::SourceRetrievalMethod) into a
+/// [`PortableCommand`](crate::PortableCommand).
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TranslateError {
+    #[error("Source files with retrieval kind {0:?} can't be obtained with a portable command.")]
+    UnsupportedMethod(crate::RetrievalKind),
+
+    /// The command runs a program which is only available on Windows, or
+    /// which is not known to be available on all platforms.
+    #[error("The program {0} is not available on all platforms.")]
+    UnsupportedProgram(String),
+
+    /// The command uses cmd.exe syntax which can't be translated, such as
+    /// command chaining with `&` or an unknown `%VAR%` reference.
+    #[error("The command uses {0}, which has no portable equivalent.")]
+    UnsupportedSyntax(String),
+
+    /// The command writes the file itself, instead of redirecting its output
+    /// to the target path.
+    #[error("The command doesn't write the file to its standard output.")]
+    OutputNotRedirected,
+}
+
+/// An enum for errors that can occur when inspecting a git checkout with
+/// [`GitCheckout::inspect`](crate::GitCheckout::inspect).
+#[cfg(feature = "git2")]
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum GitCheckoutError {
+    #[error("Could not open the git repository: {0}")]
+    Open(#[source] git2::Error),
+
+    #[error("The git repository has no working directory.")]
+    Bare,
+
+    #[error("Could not find the checked out commit: {0}")]
+    NoCommit(#[source] git2::Error),
+
+    #[error("Could not get the status of a file: {0}")]
+    Status(#[source] git2::Error),
+}
+
+/// An enum for errors that can occur when merging streams with
+/// [`merge`](crate::merge).
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum MergeError {
+    #[error("The streams define the variable {0} with different values.")]
+    VariableConflict(String),
+
+    /// An entry would need an eleventh value to select the variables of its
+    /// stream, see [`MergeConflictPolicy::Rename`](crate::MergeConflictPolicy::Rename).
+    #[error("The source file entry for {0} already uses all 10 values.")]
+    TooManyEntryValues(String),
+
+    #[error("Invalid srcsrv template: {0}")]
+    InvalidTemplate(#[from] TemplateError),
+}
+
+/// An enum for errors that can occur when reading Source Link information.
+#[cfg(feature = "sourcelink")]
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SourceLinkError {
+    #[error("Invalid Source Link JSON: {0}")]
+    InvalidJson(String),
+
+    #[error("Could not read the malformed portable PDB file: {0}")]
+    MalformedPortablePdb(String),
+}
+
+/// An enum for errors that occur when parsing the value of a srcsrv variable.
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TemplateError {
     #[error("Missing closing % in srcsrv variable use.")]
     MissingPercent,
 
@@ -52,4 +295,71 @@ pub enum EvalError {
 
     #[error("Could not resolve srcsrv variable name {0}.")]
     UnknownVariable(String),
+
+    #[error("Unknown srcsrv function {0}.")]
+    UnknownFunction(String),
+
+    #[error("Invalid srcsrv template: {0}")]
+    InvalidTemplate(#[from] TemplateError),
+
+    /// The entry evaluated to a download from a URL which the
+    /// [`UrlPolicy`](crate::UrlPolicy) doesn't allow.
+    #[error("The URL {0} is not allowed by the URL policy.")]
+    UrlNotAllowed(String),
+
+    /// The entry evaluated to a command which runs a program that the
+    /// [`CommandPolicy`](crate::CommandPolicy) doesn't allow.
+    #[error("The program {0} is not allowed by the command policy.")]
+    CommandNotAllowed(String),
+}
+
+/// An enum for errors that can occur when serializing a srcsrv stream.
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum WriteError {
+    #[error("The VERSION ini variable is missing.")]
+    MissingVersion,
+
+    #[error("The SRCSRVTRG field was missing. This is a required field.")]
+    MissingSrcSrvTrgField,
+
+    #[error("The name {0:?} is not a valid srcsrv field name.")]
+    InvalidFieldName(String),
+
+    #[error("The value {0:?} contains a line break.")]
+    LineBreakInValue(String),
+
+    #[error("A source file entry needs at least one value.")]
+    EmptySourceFileEntry,
+
+    #[error("A source file entry has more than 10 values.")]
+    TooManyEntryValues,
+
+    #[error("The source file entry value {0:?} contains a * character.")]
+    AsteriskInEntryValue(String),
+}
+
+/// A problem that was encountered while parsing the stream, but which did not
+/// cause the parse to fail.
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ParseWarning {
+    #[error("Skipped line {line_number} of the srcsrv stream: {reason}")]
+    SkippedLine {
+        /// The 1-based line number.
+        line_number: usize,
+        /// The contents of the skipped line.
+        line: String,
+        /// The error that would have been returned in strict mode.
+        reason: ParseError,
+    },
+
+    #[error("Ignored data after the end marker line, starting at line {line_number}.")]
+    TrailingData {
+        /// The 1-based line number of the first non-empty line after the end marker.
+        line_number: usize,
+    },
+
+    #[error("The srcsrv stream ended without an end marker line.")]
+    MissingEndMarker,
 }
@@ -0,0 +1,625 @@
+use crate::portable_command::{command_env, print_invocation};
+use crate::source_cache::write_atomically;
+use crate::{
+    CancellationToken, CommandPolicy, ErrorPersistenceTracker, ExecError, ExtractionResult,
+    SourceRetrievalMethod,
+};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::result::Result;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// The exit status and the output of a command which was run by
+/// [`execute_command`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandOutput {
+    pub status: ExitStatus,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+/// Options for [`execute_command`].
+///
+/// ```
+/// use srcsrv::ExecOptions;
+/// use std::time::Duration;
+///
+/// let options = ExecOptions::new().timeout(Some(Duration::from_secs(30)));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecOptions {
+    timeout: Option<Duration>,
+    cancellation: Option<CancellationToken>,
+    command_policy: CommandPolicy,
+}
+
+impl Default for ExecOptions {
+    fn default() -> Self {
+        Self {
+            timeout: Some(Duration::from_secs(300)),
+            cancellation: None,
+            command_policy: CommandPolicy::new(),
+        }
+    }
+}
+
+impl ExecOptions {
+    /// Create the default options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Kill the command and fail with [`ExecError::TimedOut`] if it runs
+    /// longer than `timeout`. `None` waits forever.
+    ///
+    /// Defaults to 5 minutes.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Kill the command and fail with [`ExecError::Cancelled`] once `token` is
+    /// cancelled.
+    pub fn cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    /// Fail with [`ExecError::CommandNotAllowed`], without running the
+    /// command, if it runs a program which `policy` doesn't allow.
+    ///
+    /// Defaults to [`CommandPolicy::new`], which allows all commands.
+    pub fn command_policy(mut self, policy: CommandPolicy) -> Self {
+        self.command_policy = policy;
+        self
+    }
+}
+
+/// Run the command of a [`SourceRetrievalMethod::ExecuteCommand`], which
+/// should create the source file at its `target_path`.
+///
+/// The command is run by `cmd /C`, with the variables of the `env` map added
+/// to the environment, after creating the directory of the target path. On
+/// other platforms than Windows, the command is run by `sh -c`, which is only
+/// useful for commands that were written for it.
+///
+/// Fails with [`ExecError::TargetMissing`] if the target path doesn't exist
+/// once the command has finished, regardless of its exit status. The output of
+/// the command can be matched against
+/// [`SrcSrvStream::error_persistence_command_output_strings`](crate::SrcSrvStream::error_persistence_command_output_strings)
+/// in that case.
+///
+/// Commands come from the PDB file, so only run them for PDB files which you
+/// trust.
+///
+/// ```no_run
+/// use srcsrv::{execute_command, ExecOptions, SrcSrvStream};
+///
+/// # fn wrapper(stream: &SrcSrvStream) -> Result<(), Box<dyn std::error::Error>> {
+/// let path = r#"C:\build\renderdoc\renderdoc\data\glsl\gl_texsample.h"#;
+/// if let Some(method) = stream.source_for_path(path, r#"C:\Debugger\Cached Sources"#)? {
+///     let output = execute_command(&method, &ExecOptions::new())?;
+///     println!("{}", String::from_utf8_lossy(&output.stdout));
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn execute_command(
+    method: &SourceRetrievalMethod,
+    options: &ExecOptions,
+) -> Result<CommandOutput, ExecError> {
+    let (command, env, target_path) = match method {
+        SourceRetrievalMethod::ExecuteCommand {
+            command,
+            env,
+            target_path,
+            ..
+        } => (command, env, target_path),
+        _ => return Err(ExecError::UnsupportedMethod(method.kind())),
+    };
+    if let Some(program) = options.command_policy.disallowed_program(command) {
+        return Err(ExecError::CommandNotAllowed(program));
+    }
+    if let Some(dir) = Path::new(target_path).parent() {
+        if !dir.as_os_str().is_empty() {
+            std::fs::create_dir_all(dir).map_err(ExecError::Io)?;
+        }
+    }
+
+    let mut command = shell_command(command);
+    command.envs(env);
+    let output = run(command, options)?;
+    if !Path::new(target_path).is_file() {
+        return Err(ExecError::TargetMissing {
+            target_path: target_path.clone(),
+            output,
+        });
+    }
+    Ok(output)
+}
+
+/// Obtain the source file of a retrieval method which was recognized with
+/// [`EvalOptions::recognize_commands`](crate::EvalOptions::recognize_commands)
+/// by running the version control tool directly, with an argument array
+/// instead of a command line, and write it to the method's `target_path`.
+///
+/// This avoids running `cmd.exe`, so the values of the file entry can't inject
+/// commands or break the quoting, no matter which characters they contain.
+/// Supported are [`SourceRetrievalMethod::GitFile`] (with `git show`, or with
+/// `git archive --remote` for repository URLs), [`SourceRetrievalMethod::Svn`],
+/// [`SourceRetrievalMethod::Cvs`], [`SourceRetrievalMethod::Perforce`],
+/// [`SourceRetrievalMethod::SourceDepot`] and [`SourceRetrievalMethod::TfsItem`].
+/// All other methods, including unrecognized commands, fail with
+/// [`ExecError::UnsupportedMethod`]. The program has to be found in the `PATH`.
+///
+/// The tool prints the file, and the printed file is written to the target
+/// path if the tool exits successfully. Otherwise this fails with
+/// [`ExecError::TargetMissing`], whose output contains the error messages of
+/// the tool.
+///
+/// ```no_run
+/// use srcsrv::{execute_without_shell, EvalOptions, ExecOptions, SrcSrvStream};
+///
+/// # fn wrapper(stream: &SrcSrvStream) -> Result<(), Box<dyn std::error::Error>> {
+/// let path = r#"C:\build\renderdoc\renderdoc\data\glsl\gl_texsample.h"#;
+/// let options = EvalOptions::new().recognize_commands(true);
+/// let base_path = r#"C:\Debugger\Cached Sources"#;
+/// if let Some(method) = stream.source_for_path_with_vars(path, base_path, &options)? {
+///     execute_without_shell(&method, &ExecOptions::new())?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn execute_without_shell(
+    method: &SourceRetrievalMethod,
+    options: &ExecOptions,
+) -> Result<CommandOutput, ExecError> {
+    let (target_path, (program, args, is_tar)) =
+        match (target_path(method), direct_invocation(method)) {
+            (Some(target_path), Some(invocation)) => (target_path, invocation),
+            _ => return Err(ExecError::UnsupportedMethod(method.kind())),
+        };
+    if let Some(program) = options.command_policy.disallowed_program(program) {
+        return Err(ExecError::CommandNotAllowed(program));
+    }
+
+    let mut command = Command::new(program);
+    command.args(&args);
+    if let Some(env) = command_env(method) {
+        command.envs(env);
+    }
+    let output = run(command, options)?;
+    let contents = match (output.status.success(), is_tar) {
+        (true, true) => first_tar_file(&output.stdout),
+        (true, false) => Some(output.stdout.as_slice()),
+        (false, _) => None,
+    };
+    match contents {
+        Some(contents) => {
+            write_atomically(Path::new(target_path), contents).map_err(ExecError::Io)?;
+            Ok(output)
+        }
+        None => Err(ExecError::TargetMissing {
+            target_path: target_path.clone(),
+            output,
+        }),
+    }
+}
+
+/// Run the command of `method` and check that it created the source file.
+///
+/// Commands of [`SourceRetrievalMethod::ExecuteCommand`] are run with
+/// [`execute_command`], and recognized version control commands with
+/// [`execute_without_shell`]. The output of the command is checked with
+/// `tracker` for the error messages of the stream, also if the command failed,
+/// so that the commands of further files can be skipped with
+/// [`ErrorPersistenceTracker::should_skip`].
+///
+/// ```no_run
+/// use srcsrv::{extract_source, ErrorPersistenceTracker, ExecOptions, SrcSrvStream};
+///
+/// # fn wrapper(stream: &SrcSrvStream, paths: &[&str]) -> Result<(), Box<dyn std::error::Error>> {
+/// let mut tracker = ErrorPersistenceTracker::new(stream);
+/// for path in paths {
+///     let method = match stream.source_for_path(path, r#"C:\Debugger\Cached Sources"#)? {
+///         Some(method) if !tracker.should_skip(&method) => method,
+///         _ => continue,
+///     };
+///     let result = extract_source(&method, &ExecOptions::new(), &mut tracker)?;
+///     println!(
+///         "{}: {} bytes in {:?}",
+///         result.local_path.display(),
+///         result.bytes_written,
+///         result.duration
+///     );
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn extract_source(
+    method: &SourceRetrievalMethod,
+    options: &ExecOptions,
+    tracker: &mut ErrorPersistenceTracker,
+) -> Result<ExtractionResult, ExecError> {
+    let start = Instant::now();
+    let result = match method {
+        SourceRetrievalMethod::ExecuteCommand { .. } => execute_command(method, options),
+        _ => execute_without_shell(method, options),
+    };
+    let output = match result {
+        Ok(output) => output,
+        Err(ExecError::TargetMissing {
+            target_path,
+            output,
+        }) => {
+            record_output(tracker, method, &output);
+            return Err(ExecError::TargetMissing {
+                target_path,
+                output,
+            });
+        }
+        Err(err) => return Err(err),
+    };
+    let error_persistence_triggered = record_output(tracker, method, &output);
+    let local_path = PathBuf::from(target_path(method).unwrap());
+    let bytes_written = std::fs::metadata(&local_path).map_err(ExecError::Io)?.len();
+    Ok(ExtractionResult {
+        local_path,
+        bytes_written,
+        duration: start.elapsed(),
+        exit_status: Some(output.status),
+        stdout: output.stdout,
+        stderr: output.stderr,
+        error_persistence_triggered,
+    })
+}
+
+fn record_output(
+    tracker: &mut ErrorPersistenceTracker,
+    method: &SourceRetrievalMethod,
+    output: &CommandOutput,
+) -> bool {
+    tracker.record_output(method, &output.stdout) || tracker.record_output(method, &output.stderr)
+}
+
+/// The path at which the command of `method` creates the source file.
+fn target_path(method: &SourceRetrievalMethod) -> Option<&String> {
+    match method {
+        SourceRetrievalMethod::ExecuteCommand { target_path, .. }
+        | SourceRetrievalMethod::GitFile { target_path, .. }
+        | SourceRetrievalMethod::Svn { target_path, .. }
+        | SourceRetrievalMethod::Cvs { target_path, .. }
+        | SourceRetrievalMethod::Perforce { target_path, .. }
+        | SourceRetrievalMethod::SourceDepot { target_path, .. }
+        | SourceRetrievalMethod::TfsItem { target_path, .. }
+        | SourceRetrievalMethod::CabExtract { target_path, .. } => Some(target_path),
+        _ => None,
+    }
+}
+
+/// The program and the arguments which print the file of `method`, and
+/// whether the output is a tar archive.
+fn direct_invocation(method: &SourceRetrievalMethod) -> Option<(&'static str, Vec<String>, bool)> {
+    let to_strings = |args: &[&str]| args.iter().map(ToString::to_string).collect();
+    match method {
+        SourceRetrievalMethod::GitFile {
+            repo,
+            revision,
+            path,
+            ..
+        } if repo.contains("://") => {
+            let remote = format!("--remote={}", repo);
+            Some((
+                "git",
+                to_strings(&["archive", &remote, revision, path]),
+                true,
+            ))
+        }
+        SourceRetrievalMethod::SourceDepot {
+            port,
+            depot_path,
+            revision,
+            ..
+        } => {
+            let file_spec = format!("{}#{}", depot_path, revision);
+            let args = match port {
+                Some(port) => to_strings(&["-p", port, "print", "-q", &file_spec]),
+                None => to_strings(&["print", "-q", &file_spec]),
+            };
+            Some(("sd", args, false))
+        }
+        SourceRetrievalMethod::TfsItem {
+            server,
+            item_path,
+            version,
+            ..
+        } => {
+            let version = format!("/version:{}", version);
+            let server = format!("/server:{}", server);
+            let args = [
+                "view",
+                &version,
+                "/noprompt",
+                "/console",
+                item_path,
+                &server,
+            ];
+            Some(("tf", to_strings(&args), false))
+        }
+        _ => {
+            let (program, args) = print_invocation(method)?;
+            Some((program, args, false))
+        }
+    }
+}
+
+/// The contents of the first regular file in the tar archive `archive`, such
+/// as the output of `git archive`.
+fn first_tar_file(archive: &[u8]) -> Option<&[u8]> {
+    const BLOCK_SIZE: usize = 512;
+
+    let mut offset = 0;
+    while let Some(header) = archive.get(offset..offset + BLOCK_SIZE) {
+        if header.iter().all(|&b| b == 0) {
+            return None;
+        }
+        // The size is an octal number, padded with NULs or spaces.
+        let size = std::str::from_utf8(&header[124..136]).ok()?;
+        let size = usize::from_str_radix(size.trim_matches(['\0', ' ']), 8).ok()?;
+        let data_start = offset + BLOCK_SIZE;
+        // git archive starts with a pax global header ('g') which contains the
+        // commit id; 'x' headers describe the next file.
+        if matches!(header[156], b'0' | b'\0') {
+            return archive.get(data_start..data_start + size);
+        }
+        offset = data_start + size.div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+    }
+    None
+}
+
+/// Run `command` with the output pipes captured, waiting for it according to
+/// `options`.
+fn run(mut command: Command, options: &ExecOptions) -> Result<CommandOutput, ExecError> {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(ExecError::Io)?;
+    // Read both pipes on their own threads, so that the command doesn't block
+    // on a full pipe.
+    let stdout = read_on_thread(child.stdout.take());
+    let stderr = read_on_thread(child.stderr.take());
+
+    let status = wait(&mut child, options)?;
+    Ok(CommandOutput {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+#[cfg(windows)]
+fn shell_command(command: &str) -> Command {
+    use std::os::windows::process::CommandExt;
+
+    let mut shell = Command::new("cmd");
+    // cmd has its own quoting rules, so the command must be passed unescaped.
+    shell.arg("/C").raw_arg(command);
+    shell
+}
+
+#[cfg(not(windows))]
+fn shell_command(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+fn read_on_thread(pipe: Option<impl Read + Send + 'static>) -> JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut output = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut output);
+        }
+        output
+    })
+}
+
+/// Wait for `child` to exit, and kill it if it times out or is cancelled.
+fn wait(child: &mut Child, options: &ExecOptions) -> Result<ExitStatus, ExecError> {
+    const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait().map_err(ExecError::Io)? {
+            return Ok(status);
+        }
+        let error = match (&options.cancellation, options.timeout) {
+            (Some(token), _) if token.is_cancelled() => ExecError::Cancelled,
+            (_, Some(timeout)) if start.elapsed() >= timeout => ExecError::TimedOut(timeout),
+            _ => {
+                match &options.cancellation {
+                    Some(token) => {
+                        token.sleep(POLL_INTERVAL);
+                    }
+                    None => std::thread::sleep(POLL_INTERVAL),
+                }
+                continue;
+            }
+        };
+        // Processes started by the command may keep the output pipes open, so
+        // the reader threads are not joined.
+        let _ = child.kill();
+        let _ = child.wait();
+        return Err(error);
+    }
+}
+
+#[cfg(all(test, not(windows)))]
+mod tests {
+    use super::{execute_command, execute_without_shell, extract_source, ExecOptions};
+    use crate::{
+        CancellationToken, CommandPolicy, ErrorPersistenceTracker, ExecError, RetrievalKind,
+        SourceRetrievalMethod, SrcSrvStream,
+    };
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    fn method(command: &str, target_path: &str) -> SourceRetrievalMethod {
+        SourceRetrievalMethod::ExecuteCommand {
+            command: command.to_string(),
+            env: HashMap::from([("SRCSRV_TEST".to_string(), "hello".to_string())]),
+            version_ctrl: None,
+            target_path: target_path.to_string(),
+            error_persistence_version_control: None,
+        }
+    }
+
+    #[test]
+    fn execute() {
+        let dir = std::env::temp_dir().join(format!("srcsrv-exec-{}", std::process::id()));
+        let target = dir.join("sub").join("main.cpp");
+        let target = target.to_str().unwrap();
+
+        let output = execute_command(
+            &method(
+                &format!("echo $SRCSRV_TEST; echo oops >&2; echo data > '{target}'"),
+                target,
+            ),
+            &ExecOptions::new(),
+        )
+        .unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"hello\n");
+        assert_eq!(output.stderr, b"oops\n");
+        assert_eq!(std::fs::read(target).unwrap(), b"data\n");
+
+        let missing = dir.join("missing.cpp");
+        let err = execute_command(
+            &method("echo not found; exit 1", missing.to_str().unwrap()),
+            &ExecOptions::new(),
+        )
+        .unwrap_err();
+        match err {
+            ExecError::TargetMissing { output, .. } => {
+                assert!(!output.status.success());
+                assert_eq!(output.stdout, b"not found\n");
+            }
+            err => panic!("Unexpected error {:?}", err),
+        }
+
+        let options = ExecOptions::new().timeout(Some(Duration::from_millis(50)));
+        assert!(matches!(
+            execute_command(&method("exec sleep 10", target), &options),
+            Err(ExecError::TimedOut(_))
+        ));
+
+        let token = CancellationToken::new();
+        token.cancel();
+        let options = ExecOptions::new().cancellation(token);
+        assert!(matches!(
+            execute_command(&method("exec sleep 10", target), &options),
+            Err(ExecError::Cancelled)
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let options = ExecOptions::new().command_policy(CommandPolicy::new().allow_program("echo"));
+        let command = format!("echo data > '{target}' && true srcsrv-policy-marker");
+        assert!(matches!(
+            execute_command(&method(&command, target), &options),
+            Err(ExecError::CommandNotAllowed(program)) if program == "true"
+        ));
+        assert!(!dir.exists());
+
+        assert!(matches!(
+            execute_command(
+                &SourceRetrievalMethod::Download {
+                    url: "https://example.com/main.cpp".to_string()
+                },
+                &ExecOptions::new()
+            ),
+            Err(ExecError::UnsupportedMethod(RetrievalKind::Download))
+        ));
+    }
+
+    #[test]
+    fn execute_git_without_shell() {
+        let dir = std::env::temp_dir().join(format!("srcsrv-exec-git-{}", std::process::id()));
+        let repo = dir.join("repo");
+        std::fs::create_dir_all(repo.join("src")).unwrap();
+        std::fs::write(repo.join("src").join("a; b.cpp"), b"int main() {}\n").unwrap();
+        let git = |args: &[&str]| {
+            let status = std::process::Command::new("git")
+                .args([
+                    "-c",
+                    "user.name=srcsrv",
+                    "-c",
+                    "user.email=srcsrv@example.com",
+                ])
+                .arg("-C")
+                .arg(&repo)
+                .args(args)
+                .output()
+                .unwrap()
+                .status;
+            assert!(status.success());
+        };
+        git(&["init", "-q"]);
+        git(&["add", "."]);
+        git(&["commit", "-q", "-m", "Initial commit"]);
+
+        let target = dir.join("cache").join("a.cpp");
+        let git_file = |repo: String, path: &str| SourceRetrievalMethod::GitFile {
+            repo,
+            revision: "HEAD".to_string(),
+            path: path.to_string(),
+            target_path: target.to_str().unwrap().to_string(),
+            env: HashMap::new(),
+            error_persistence_version_control: None,
+        };
+        let repo_path = repo.to_str().unwrap().to_string();
+        for repo in [repo_path.clone(), format!("file://{}", repo_path)] {
+            execute_without_shell(&git_file(repo, "src/a; b.cpp"), &ExecOptions::new()).unwrap();
+            assert_eq!(std::fs::read(&target).unwrap(), b"int main() {}\n");
+            std::fs::remove_file(&target).unwrap();
+        }
+
+        let err = execute_without_shell(&git_file(repo_path, "missing.cpp"), &ExecOptions::new())
+            .unwrap_err();
+        assert!(matches!(err, ExecError::TargetMissing { .. }));
+        assert!(!target.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn extract_with_error_persistence() {
+        let dir = std::env::temp_dir().join(format!("srcsrv-extract-{}", std::process::id()));
+        let stream = r#"SRCSRV: ini ------------------------------------------------
+VERSION=1
+SRCSRV: variables ------------------------------------------
+SRCSRVERRDESC=is unreachable
+SRCSRVERRVAR=var2
+SRCSRVTRG=%targ%/%var3%
+SRCSRVCMD=echo %var2% is unreachable && echo data > %srcsrvtrg%
+SRCSRV: source files ---------------------------------------
+c:\build\a.cpp*server1*a.cpp
+SRCSRV: end ------------------------------------------------"#;
+        let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
+        let method = stream
+            .source_for_path(r#"c:\build\a.cpp"#, dir.to_str().unwrap())
+            .unwrap()
+            .unwrap();
+        let mut tracker = ErrorPersistenceTracker::new(&stream);
+        let result = extract_source(&method, &ExecOptions::new(), &mut tracker).unwrap();
+        assert_eq!(result.local_path, dir.join("a.cpp"));
+        assert_eq!(result.bytes_written, 5);
+        assert!(result.exit_status.unwrap().success());
+        assert_eq!(result.stdout, b"server1 is unreachable\n");
+        assert!(result.error_persistence_triggered);
+        assert!(tracker.should_skip(&method));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
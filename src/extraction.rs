@@ -0,0 +1,30 @@
+use std::path::PathBuf;
+use std::process::ExitStatus;
+use std::time::Duration;
+
+/// What happened when a source file was obtained, so that the caller can log
+/// and cache all files in the same way, no matter how they were obtained.
+///
+/// This is returned by `fetch_source` (with the `fetch`
+/// feature) and by `extract_source` (with the `exec` feature).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtractionResult {
+    /// The path at which the source file was stored.
+    pub local_path: PathBuf,
+    /// The size of the stored file.
+    pub bytes_written: u64,
+    /// How long it took to obtain the file.
+    pub duration: Duration,
+    /// The exit status of the command. `None` for downloads.
+    pub exit_status: Option<ExitStatus>,
+    /// The standard output of the command. Empty for downloads.
+    pub stdout: Vec<u8>,
+    /// The standard error output of the command. Empty for downloads.
+    pub stderr: Vec<u8>,
+    /// Whether the output of the command contained one of the error messages
+    /// of the stream, so that the commands of further files with the same
+    /// `error_persistence_version_control` value should be skipped, see
+    /// [`ErrorPersistenceTracker`](crate::ErrorPersistenceTracker). Always
+    /// `false` for downloads.
+    pub error_persistence_triggered: bool,
+}
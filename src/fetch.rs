@@ -0,0 +1,785 @@
+use crate::insecure_urls::upgrade_http_url;
+use crate::source_cache::write_atomically;
+use crate::{
+    AuthProvider, CancellationToken, ContentEncoding, ExtractionResult, FetchError,
+    HttpStatusError, SourceRetrievalMethod, UrlPolicy,
+};
+use std::collections::hash_map::RandomState;
+use std::error::Error;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::path::Path;
+use std::result::Result;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// An HTTP client which [`fetch_source`] uses to download source files.
+///
+/// Implement this for the client of your choice, for example reqwest, ureq or
+/// an in-house client. Closures which take a URL and return the response body
+/// implement it as well. With the `blocking-fetch` feature, `UreqFetcher`
+/// implements it with ureq. For async code, the `reqwest` feature provides
+/// `ReqwestFetcher`, which has async versions of the fetch functions.
+///
+/// ```
+/// use srcsrv::{HttpStatusError, SourceFetcher};
+///
+/// struct MyClient;
+///
+/// impl SourceFetcher for MyClient {
+///     fn fetch(&self, url: &str) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+///         // Send a GET request to `url`, follow redirects, and return the body
+///         // of a successful response.
+///         # let status = 404;
+///         Err(HttpStatusError { status }.into())
+///     }
+/// }
+/// ```
+pub trait SourceFetcher {
+    /// Download `url` and return the response body. Redirects should be
+    /// followed, and unsuccessful responses should be returned as
+    /// [`HttpStatusError`]s.
+    fn fetch(&self, url: &str) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>>;
+
+    /// Download `url` like [`SourceFetcher::fetch`], with the additional
+    /// request `headers`, which are `(name, value)` pairs from the
+    /// [`AuthProvider`] in the [`FetchOptions`].
+    ///
+    /// Clients which support request headers should implement this. The
+    /// default implementation calls `fetch` if `headers` is empty, and fails
+    /// otherwise, so that requests are never sent without their credentials.
+    fn fetch_with_headers(
+        &self,
+        url: &str,
+        headers: &[(String, String)],
+    ) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        if !headers.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "This source fetcher does not support request headers.",
+            )
+            .into());
+        }
+        self.fetch(url)
+    }
+
+    /// Whether `error`, which was returned by [`SourceFetcher::fetch`], is
+    /// usually temporary, so that the download should be retried.
+    ///
+    /// The default implementation looks for an [`HttpStatusError`] or an
+    /// [`io::Error`] in the chain of error sources. 408, 429 and 5xx statuses
+    /// are retryable, all other statuses are not. I/O errors are retryable if
+    /// they are timeouts or if the connection was refused, reset or aborted.
+    /// Other errors are assumed to be network problems and are retryable.
+    fn is_retryable(&self, error: &(dyn Error + Send + Sync + 'static)) -> bool {
+        is_retryable_error(error)
+    }
+}
+
+/// The default implementation of [`SourceFetcher::is_retryable`].
+pub(crate) fn is_retryable_error(error: &(dyn Error + Send + Sync + 'static)) -> bool {
+    let mut error: Option<&(dyn Error + 'static)> = Some(error);
+    while let Some(e) = error {
+        if let Some(HttpStatusError { status }) = e.downcast_ref::<HttpStatusError>() {
+            return matches!(status, 408 | 429 | 500..=599);
+        }
+        if let Some(e) = e.downcast_ref::<io::Error>() {
+            return matches!(
+                e.kind(),
+                io::ErrorKind::TimedOut
+                    | io::ErrorKind::Interrupted
+                    | io::ErrorKind::ConnectionRefused
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::UnexpectedEof
+            );
+        }
+        error = e.source();
+    }
+    true
+}
+
+impl<F> SourceFetcher for F
+where
+    F: Fn(&str) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>>,
+{
+    fn fetch(&self, url: &str) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        self(url)
+    }
+}
+
+/// Options for [`fetch_source_with_options`] and
+/// [`fetch_source_contents_with_options`].
+///
+/// Failed downloads are retried if [`SourceFetcher::is_retryable`] returns
+/// `true` for the error, with an exponential backoff between the attempts.
+/// Private source servers can be accessed by setting an [`AuthProvider`].
+///
+/// ```
+/// use srcsrv::FetchOptions;
+/// use std::time::Duration;
+///
+/// let options = FetchOptions::new()
+///     .max_retries(3)
+///     .initial_backoff(Duration::from_secs(1));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FetchOptions {
+    pub(crate) max_retries: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    jitter: bool,
+    auth: Option<Auth>,
+    pub(crate) url_policy: UrlPolicy,
+    pub(crate) cancellation: Option<CancellationToken>,
+    pub(crate) upgrade_http: bool,
+    insecure_download_handler: Option<InsecureDownloadHandler>,
+    pub(crate) max_concurrent_downloads: usize,
+    pub(crate) max_downloads_per_host: usize,
+}
+
+/// An [`AuthProvider`] set with [`FetchOptions::auth`].
+#[derive(Clone)]
+struct Auth(Arc<dyn AuthProvider + Send + Sync>);
+
+impl fmt::Debug for Auth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Auth")
+    }
+}
+
+impl PartialEq for Auth {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Auth {}
+
+/// A handler set with [`FetchOptions::on_insecure_download`].
+#[derive(Clone)]
+struct InsecureDownloadHandler(Arc<dyn Fn(&str) + Send + Sync>);
+
+impl fmt::Debug for InsecureDownloadHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("InsecureDownloadHandler")
+    }
+}
+
+impl PartialEq for InsecureDownloadHandler {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for InsecureDownloadHandler {}
+
+impl Default for FetchOptions {
+    fn default() -> Self {
+        Self {
+            max_retries: 0,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            jitter: true,
+            auth: None,
+            url_policy: UrlPolicy::new(),
+            max_concurrent_downloads: 8,
+            max_downloads_per_host: 4,
+            cancellation: None,
+            upgrade_http: false,
+            insecure_download_handler: None,
+        }
+    }
+}
+
+impl FetchOptions {
+    /// Create the default options, which are the options used by
+    /// [`fetch_source`] and [`fetch_source_contents`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How often a download which failed with a retryable error is retried.
+    ///
+    /// Defaults to 0.
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// How long to wait before the first retry. The wait doubles with every
+    /// further retry, up to [`FetchOptions::max_backoff`].
+    ///
+    /// Defaults to 500 milliseconds.
+    pub fn initial_backoff(mut self, backoff: Duration) -> Self {
+        self.initial_backoff = backoff;
+        self
+    }
+
+    /// The longest wait between two attempts.
+    ///
+    /// Defaults to 30 seconds.
+    pub fn max_backoff(mut self, backoff: Duration) -> Self {
+        self.max_backoff = backoff;
+        self
+    }
+
+    /// Wait a random duration between half of the backoff and the full
+    /// backoff, so that many clients which failed at the same time don't retry
+    /// at the same time.
+    ///
+    /// Defaults to `true`.
+    pub fn jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Get the request headers for each download from `auth`, for example
+    /// to authenticate with a private source server.
+    ///
+    /// Defaults to no extra headers.
+    pub fn auth(mut self, auth: impl AuthProvider + Send + Sync + 'static) -> Self {
+        self.auth = Some(Auth(Arc::new(auth)));
+        self
+    }
+
+    /// Fail with [`FetchError::UrlNotAllowed`] instead of downloading from a
+    /// URL which `policy` doesn't allow. Redirects are followed by the
+    /// [`SourceFetcher`], so they need to be restricted there.
+    ///
+    /// Defaults to [`UrlPolicy::new`], which allows all URLs.
+    pub fn url_policy(mut self, policy: UrlPolicy) -> Self {
+        self.url_policy = policy;
+        self
+    }
+
+    /// Try to download `http://` URLs over `https://` first, and only fall
+    /// back to the `http` URL if that fails.
+    ///
+    /// Defaults to `false`.
+    pub fn upgrade_http(mut self, upgrade_http: bool) -> Self {
+        self.upgrade_http = upgrade_http;
+        self
+    }
+
+    /// Call `handler` with the URL whenever a file is about to be downloaded
+    /// over plain `http://`, including fallbacks after a failed upgrade with
+    /// [`FetchOptions::upgrade_http`], for example to log a warning.
+    ///
+    /// ```
+    /// use srcsrv::FetchOptions;
+    ///
+    /// let options = FetchOptions::new()
+    ///     .upgrade_http(true)
+    ///     .on_insecure_download(|url| eprintln!("Warning: downloading {} over http", url));
+    /// ```
+    pub fn on_insecure_download<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(&str) + Send + Sync + 'static,
+    {
+        self.insecure_download_handler = Some(InsecureDownloadHandler(Arc::new(handler)));
+        self
+    }
+
+    /// Stop with [`FetchError::Cancelled`] once `token` is cancelled.
+    ///
+    /// The token is checked before every attempt and while waiting between
+    /// attempts. A request which is in progress when the token is cancelled
+    /// is completed by the [`SourceFetcher`], but its response is discarded
+    /// and no file is written.
+    pub fn cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    /// Whether the cancellation token has been cancelled.
+    pub(crate) fn is_cancelled(&self) -> bool {
+        matches!(&self.cancellation, Some(token) if token.is_cancelled())
+    }
+
+    /// How many downloads [`fetch_sources`](crate::fetch_sources) runs at the
+    /// same time. Values below 1 are treated as 1.
+    ///
+    /// Defaults to 8.
+    pub fn max_concurrent_downloads(mut self, max: usize) -> Self {
+        self.max_concurrent_downloads = max.max(1);
+        self
+    }
+
+    /// How many downloads from the same host [`fetch_sources`](crate::fetch_sources)
+    /// runs at the same time. Values below 1 are treated as 1.
+    ///
+    /// Defaults to 4.
+    pub fn max_downloads_per_host(mut self, max: usize) -> Self {
+        self.max_downloads_per_host = max.max(1);
+        self
+    }
+
+    /// The request headers for `url` from the auth provider.
+    pub(crate) fn headers(&self, url: &str) -> Vec<(String, String)> {
+        match &self.auth {
+            Some(auth) => auth.0.headers(url),
+            None => Vec::new(),
+        }
+    }
+
+    /// Call the handler set with [`FetchOptions::on_insecure_download`].
+    pub(crate) fn report_insecure_download(&self, url: &str) {
+        if let Some(InsecureDownloadHandler(handler)) = &self.insecure_download_handler {
+            handler(url);
+        }
+    }
+
+    /// The wait before retry number `retry`, starting at 0.
+    pub(crate) fn backoff(&self, retry: u32) -> Duration {
+        let factor = 1u32.checked_shl(retry).unwrap_or(u32::MAX);
+        let backoff = self
+            .initial_backoff
+            .checked_mul(factor)
+            .map_or(self.max_backoff, |backoff| backoff.min(self.max_backoff));
+        if !self.jitter {
+            return backoff;
+        }
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u32(retry);
+        let random = hasher.finish() as f64 / u64::MAX as f64;
+        backoff.mul_f64(0.5 + random * 0.5)
+    }
+}
+
+/// Download the file for a [`SourceRetrievalMethod::Download`] or
+/// [`SourceRetrievalMethod::DownloadWithDecode`] and return its contents.
+/// Responses of `DownloadWithDecode` are decoded.
+///
+/// Returns [`FetchError::UnsupportedMethod`] for all other retrieval methods.
+pub fn fetch_source_contents(
+    fetcher: &(impl SourceFetcher + ?Sized),
+    method: &SourceRetrievalMethod,
+) -> Result<Vec<u8>, FetchError> {
+    fetch_source_contents_with_options(fetcher, method, &FetchOptions::new())
+}
+
+/// Like [`fetch_source_contents`], but with [`FetchOptions`].
+pub fn fetch_source_contents_with_options(
+    fetcher: &(impl SourceFetcher + ?Sized),
+    method: &SourceRetrievalMethod,
+    options: &FetchOptions,
+) -> Result<Vec<u8>, FetchError> {
+    match method {
+        SourceRetrievalMethod::Download { url } => fetch_url(fetcher, url, options),
+        SourceRetrievalMethod::DownloadWithDecode { url, encoding } => {
+            let response = fetch_url(fetcher, url, options)?;
+            match encoding {
+                ContentEncoding::Base64 => decode_base64(&response),
+            }
+        }
+        _ => Err(FetchError::UnsupportedMethod(method.kind())),
+    }
+}
+
+/// Download the file for a [`SourceRetrievalMethod::Download`] or
+/// [`SourceRetrievalMethod::DownloadWithDecode`] and store it at
+/// `target_path`, creating the parent directories if needed.
+///
+/// The file is written to a temporary file next to `target_path` first and
+/// then renamed, so `target_path` never contains a partial download.
+///
+/// Returns the size of the file and the time the download took.
+///
+/// ```
+/// use srcsrv::{fetch_source, SrcSrvStream};
+/// use std::path::Path;
+///
+/// # fn get(url: &str) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> { unimplemented!() }
+/// # fn wrapper(stream: &SrcSrvStream) -> Result<(), Box<dyn std::error::Error>> {
+/// let cache_dir = Path::new("/tmp/sources");
+/// let path = r#"C:\build\renderdoc\renderdoc\data\glsl\gl_texsample.h"#;
+/// if let Some(method) = stream.source_for_path(path, "")? {
+///     if let Some(cache_path) = stream.source_cache_path("6D1DFFC4DC524537962CCABC000820641", path)? {
+///         fetch_source(&get, &method, &cache_dir.join(cache_path))?;
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn fetch_source(
+    fetcher: &(impl SourceFetcher + ?Sized),
+    method: &SourceRetrievalMethod,
+    target_path: &Path,
+) -> Result<ExtractionResult, FetchError> {
+    fetch_source_with_options(fetcher, method, target_path, &FetchOptions::new())
+}
+
+/// Like [`fetch_source`], but with [`FetchOptions`].
+pub fn fetch_source_with_options(
+    fetcher: &(impl SourceFetcher + ?Sized),
+    method: &SourceRetrievalMethod,
+    target_path: &Path,
+    options: &FetchOptions,
+) -> Result<ExtractionResult, FetchError> {
+    let start = Instant::now();
+    let contents = fetch_source_contents_with_options(fetcher, method, options)?;
+    if options.is_cancelled() {
+        return Err(FetchError::Cancelled);
+    }
+    write_atomically(target_path, &contents).map_err(FetchError::Io)?;
+    Ok(ExtractionResult {
+        local_path: target_path.to_path_buf(),
+        bytes_written: contents.len() as u64,
+        duration: start.elapsed(),
+        exit_status: None,
+        stdout: Vec::new(),
+        stderr: Vec::new(),
+        error_persistence_triggered: false,
+    })
+}
+
+/// Fetch `url`, over https first if `options` asks for it.
+fn fetch_url(
+    fetcher: &(impl SourceFetcher + ?Sized),
+    url: &str,
+    options: &FetchOptions,
+) -> Result<Vec<u8>, FetchError> {
+    if let Some(https_url) = upgrade_http_url(url) {
+        if options.upgrade_http {
+            match fetch_with_retries(fetcher, &https_url, options) {
+                Err(FetchError::Cancelled) => return Err(FetchError::Cancelled),
+                Err(_) => {}
+                result => return result,
+            }
+        }
+        options.report_insecure_download(url);
+    }
+    fetch_with_retries(fetcher, url, options)
+}
+
+/// Fetch `url`, retrying retryable errors as configured in `options`.
+fn fetch_with_retries(
+    fetcher: &(impl SourceFetcher + ?Sized),
+    url: &str,
+    options: &FetchOptions,
+) -> Result<Vec<u8>, FetchError> {
+    if !options.url_policy.is_allowed(url) {
+        return Err(FetchError::UrlNotAllowed(url.to_string()));
+    }
+    let headers = options.headers(url);
+    let mut attempts = 0;
+    loop {
+        if options.is_cancelled() {
+            return Err(FetchError::Cancelled);
+        }
+        let error = match fetcher.fetch_with_headers(url, &headers) {
+            Ok(_) if options.is_cancelled() => return Err(FetchError::Cancelled),
+            Ok(response) => return Ok(response),
+            Err(error) => error,
+        };
+        attempts += 1;
+        if !fetcher.is_retryable(&*error) {
+            return Err(FetchError::Http(error));
+        }
+        if attempts > options.max_retries {
+            return Err(FetchError::Transient { attempts, error });
+        }
+        let backoff = options.backoff(attempts - 1);
+        match &options.cancellation {
+            Some(token) => {
+                if !token.sleep(backoff) {
+                    return Err(FetchError::Cancelled);
+                }
+            }
+            None => std::thread::sleep(backoff),
+        }
+    }
+}
+
+/// Decode standard base64, ignoring whitespace, as returned by gitiles for
+/// `?format=TEXT`.
+pub(crate) fn decode_base64(encoded: &[u8]) -> Result<Vec<u8>, FetchError> {
+    let mut decoded = Vec::with_capacity(encoded.len() / 4 * 3);
+    let mut bits = 0u32;
+    let mut bit_count = 0;
+    let mut padding = 0;
+    for &byte in encoded {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => {
+                padding += 1;
+                continue;
+            }
+            b' ' | b'\t' | b'\r' | b'\n' => continue,
+            _ => {
+                return Err(FetchError::Decode(format!(
+                    "Invalid base64 byte {byte:#04x}."
+                )))
+            }
+        };
+        if padding > 0 {
+            return Err(FetchError::Decode("Data after base64 padding.".to_string()));
+        }
+        bits = (bits << 6) | value as u32;
+        bit_count += 6;
+        if bit_count >= 8 {
+            bit_count -= 8;
+            decoded.push((bits >> bit_count) as u8);
+        }
+    }
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        decode_base64, fetch_source, fetch_source_contents, fetch_source_contents_with_options,
+        fetch_source_with_options, SourceFetcher,
+    };
+    use crate::{
+        CancellationToken, ContentEncoding, Credential, FetchError, FetchOptions, HostCredentials,
+        HttpStatusError, RetrievalKind, SourceRetrievalMethod, UrlPolicy,
+    };
+    use std::cell::Cell;
+    use std::error::Error;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    fn fetcher(url: &str) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        match url {
+            "https://example.com/main.cpp" => Ok(b"int main() {}\n".to_vec()),
+            "https://example.com/main.cpp?format=TEXT" => Ok(b"aW50IG1haW4oKSB7fQo=".to_vec()),
+            _ => Err(HttpStatusError { status: 404 }.into()),
+        }
+    }
+
+    #[test]
+    fn base64() {
+        assert_eq!(decode_base64(b"").unwrap(), b"");
+        assert_eq!(decode_base64(b"Zg==").unwrap(), b"f");
+        assert_eq!(decode_base64(b"Zm8=").unwrap(), b"fo");
+        assert_eq!(decode_base64(b"Zm9v\nYmFy").unwrap(), b"foobar");
+        assert!(decode_base64(b"Zm9v!").is_err());
+    }
+
+    #[test]
+    fn fetch() {
+        let download = SourceRetrievalMethod::Download {
+            url: "https://example.com/main.cpp".to_string(),
+        };
+        let decode = SourceRetrievalMethod::DownloadWithDecode {
+            url: "https://example.com/main.cpp?format=TEXT".to_string(),
+            encoding: ContentEncoding::Base64,
+        };
+        assert_eq!(
+            fetch_source_contents(&fetcher, &download).unwrap(),
+            b"int main() {}\n"
+        );
+        assert_eq!(
+            fetch_source_contents(&fetcher, &decode).unwrap(),
+            b"int main() {}\n"
+        );
+        assert!(matches!(
+            fetch_source_contents(
+                &fetcher,
+                &SourceRetrievalMethod::Download {
+                    url: "https://example.com/missing.cpp".to_string()
+                }
+            ),
+            Err(FetchError::Http(_))
+        ));
+        assert!(matches!(
+            fetch_source_contents(
+                &fetcher,
+                &SourceRetrievalMethod::CopyFile {
+                    source_path: r#"\\server\share\main.cpp"#.to_string(),
+                    target_path: r#"server\share\main.cpp"#.to_string(),
+                }
+            ),
+            Err(FetchError::UnsupportedMethod(RetrievalKind::CopyFile))
+        ));
+
+        let dir = std::env::temp_dir().join(format!("srcsrv-fetch-{}", std::process::id()));
+        let target_path = dir.join("abc").join("main.cpp");
+        let result = fetch_source(&fetcher, &decode, &target_path).unwrap();
+        assert_eq!(result.local_path, target_path);
+        assert_eq!(result.bytes_written, 14);
+        assert_eq!(result.exit_status, None);
+        let contents = std::fs::read(&target_path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(contents, b"int main() {}\n");
+    }
+
+    #[test]
+    fn retries() {
+        let download = SourceRetrievalMethod::Download {
+            url: "https://example.com/main.cpp".to_string(),
+        };
+        let options = FetchOptions::new()
+            .max_retries(2)
+            .initial_backoff(Duration::ZERO);
+        let attempts = Cell::new(0);
+        let flaky = |_url: &str| -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+            attempts.set(attempts.get() + 1);
+            match attempts.get() {
+                1 => Err(HttpStatusError { status: 503 }.into()),
+                2 => Err(io::Error::from(io::ErrorKind::TimedOut).into()),
+                _ => Ok(b"int main() {}\n".to_vec()),
+            }
+        };
+        assert_eq!(
+            fetch_source_contents_with_options(&flaky, &download, &options).unwrap(),
+            b"int main() {}\n"
+        );
+        assert_eq!(attempts.get(), 3);
+
+        attempts.set(0);
+        let unavailable = |_url: &str| -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+            attempts.set(attempts.get() + 1);
+            Err(HttpStatusError { status: 502 }.into())
+        };
+        let err =
+            fetch_source_contents_with_options(&unavailable, &download, &options).unwrap_err();
+        assert!(err.is_retryable());
+        assert!(matches!(err, FetchError::Transient { attempts: 3, .. }));
+        assert_eq!(attempts.get(), 3);
+
+        attempts.set(0);
+        let missing = |_url: &str| -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+            attempts.set(attempts.get() + 1);
+            Err(HttpStatusError { status: 404 }.into())
+        };
+        let err = fetch_source_contents_with_options(&missing, &download, &options).unwrap_err();
+        assert!(!err.is_retryable());
+        assert!(matches!(err, FetchError::Http(_)));
+        assert_eq!(attempts.get(), 1);
+
+        let error: Box<dyn Error + Send + Sync> = "connection closed".into();
+        assert!(missing.is_retryable(&*error));
+    }
+
+    #[test]
+    fn backoff() {
+        let options = FetchOptions::new()
+            .initial_backoff(Duration::from_secs(1))
+            .max_backoff(Duration::from_secs(5))
+            .jitter(false);
+        assert_eq!(options.backoff(0), Duration::from_secs(1));
+        assert_eq!(options.backoff(2), Duration::from_secs(4));
+        assert_eq!(options.backoff(3), Duration::from_secs(5));
+        assert_eq!(options.backoff(40), Duration::from_secs(5));
+        let jittered = options.jitter(true).backoff(1);
+        assert!(jittered >= Duration::from_secs(1) && jittered <= Duration::from_secs(2));
+    }
+
+    struct HeaderFetcher;
+
+    impl SourceFetcher for HeaderFetcher {
+        fn fetch(&self, url: &str) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+            self.fetch_with_headers(url, &[])
+        }
+
+        fn fetch_with_headers(
+            &self,
+            _url: &str,
+            headers: &[(String, String)],
+        ) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+            match headers {
+                [(name, value)] if name == "Authorization" && value == "Bearer secret" => {
+                    Ok(b"int main() {}\n".to_vec())
+                }
+                _ => Err(HttpStatusError { status: 401 }.into()),
+            }
+        }
+    }
+
+    #[test]
+    fn auth() {
+        let download = SourceRetrievalMethod::Download {
+            url: "https://example.com/main.cpp".to_string(),
+        };
+        let options = FetchOptions::new().auth(
+            HostCredentials::new().add("example.com", Credential::Bearer("secret".to_string())),
+        );
+        assert_eq!(
+            fetch_source_contents_with_options(&HeaderFetcher, &download, &options).unwrap(),
+            b"int main() {}\n"
+        );
+        assert!(matches!(
+            fetch_source_contents(&HeaderFetcher, &download),
+            Err(FetchError::Http(_))
+        ));
+
+        // Fetchers without header support fail instead of sending the request
+        // without credentials.
+        let err = fetch_source_contents_with_options(&fetcher, &download, &options).unwrap_err();
+        assert!(matches!(err, FetchError::Http(_)));
+    }
+
+    #[test]
+    fn url_policy() {
+        let options = FetchOptions::new().url_policy(UrlPolicy::new().allow_host("example.org"));
+        let download = SourceRetrievalMethod::Download {
+            url: "https://example.com/main.cpp".to_string(),
+        };
+        assert!(matches!(
+            fetch_source_contents_with_options(&fetcher, &download, &options),
+            Err(FetchError::UrlNotAllowed(url)) if url == "https://example.com/main.cpp"
+        ));
+    }
+
+    #[test]
+    fn cancellation() {
+        let download = SourceRetrievalMethod::Download {
+            url: "https://example.com/main.cpp".to_string(),
+        };
+        let token = CancellationToken::new();
+        let options = FetchOptions::new().cancellation(token.clone());
+        let cancelling_fetcher = |url: &str| {
+            token.cancel();
+            fetcher(url)
+        };
+        let dir = std::env::temp_dir().join(format!("srcsrv-cancel-{}", std::process::id()));
+        let target_path = dir.join("main.cpp");
+        assert!(matches!(
+            fetch_source_with_options(&cancelling_fetcher, &download, &target_path, &options),
+            Err(FetchError::Cancelled)
+        ));
+        assert!(!dir.exists());
+        assert!(matches!(
+            fetch_source_contents_with_options(&fetcher, &download, &options),
+            Err(FetchError::Cancelled)
+        ));
+    }
+
+    #[test]
+    fn upgrade_http() {
+        let download = SourceRetrievalMethod::Download {
+            url: "http://example.com/main.cpp".to_string(),
+        };
+        let insecure = Arc::new(Mutex::new(Vec::new()));
+        let insecure_clone = insecure.clone();
+        let options = FetchOptions::new()
+            .upgrade_http(true)
+            .on_insecure_download(move |url| insecure_clone.lock().unwrap().push(url.to_string()));
+        assert_eq!(
+            fetch_source_contents_with_options(&fetcher, &download, &options).unwrap(),
+            b"int main() {}\n"
+        );
+        assert!(insecure.lock().unwrap().is_empty());
+
+        let http_only = |url: &str| -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+            match url {
+                "http://example.com/main.cpp" => Ok(b"int main() {}\n".to_vec()),
+                _ => Err(io::Error::from(io::ErrorKind::ConnectionRefused).into()),
+            }
+        };
+        assert_eq!(
+            fetch_source_contents_with_options(&http_only, &download, &options).unwrap(),
+            b"int main() {}\n"
+        );
+        assert_eq!(*insecure.lock().unwrap(), ["http://example.com/main.cpp"]);
+    }
+}
@@ -0,0 +1,161 @@
+use crate::{OwnedSrcSrvStream, ParseOptions, PdbError, SrcSrvStream};
+use std::collections::HashSet;
+use std::path::Path;
+use std::result::Result;
+
+impl<'a> SrcSrvStream<'a> {
+    /// Read the `srcsrv` stream from a PDB file and parse it. Returns `Ok(None)`
+    /// if the PDB file has no `srcsrv` stream, i.e. if it is not source-indexed.
+    ///
+    /// The parsed stream is returned as an [`OwnedSrcSrvStream`], because the
+    /// stream bytes which are read from the PDB file don't outlive this call.
+    ///
+    /// ```
+    /// use srcsrv::SrcSrvStream;
+    ///
+    /// # fn wrapper<'s, S: pdb::Source<'s> + 's>(pdb: &mut pdb::PDB<'s, S>) -> std::result::Result<(), srcsrv::PdbError> {
+    /// if let Some(stream) = SrcSrvStream::from_pdb(pdb)? {
+    ///     println!("{:?}", stream.stream().version_control_description());
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn from_pdb<'s, S: pdb::Source<'s> + 's>(
+        pdb: &mut pdb::PDB<'s, S>,
+    ) -> Result<Option<OwnedSrcSrvStream>, PdbError> {
+        OwnedSrcSrvStream::from_pdb(pdb)
+    }
+
+    /// Open the PDB file at `path`, read its `srcsrv` stream and parse it.
+    /// Returns `Ok(None)` if the PDB file is not source-indexed.
+    ///
+    /// ```no_run
+    /// use srcsrv::SrcSrvStream;
+    ///
+    /// # fn wrapper() -> std::result::Result<(), srcsrv::PdbError> {
+    /// if let Some(stream) = SrcSrvStream::from_pdb_path("xul.pdb")? {
+    ///     println!("{:?}", stream.stream().vcs_kind());
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn from_pdb_path(path: impl AsRef<Path>) -> Result<Option<OwnedSrcSrvStream>, PdbError> {
+        OwnedSrcSrvStream::from_pdb_path(path)
+    }
+}
+
+impl OwnedSrcSrvStream {
+    /// Read the `srcsrv` stream from a PDB file and parse it. Returns `Ok(None)`
+    /// if the PDB file has no `srcsrv` stream, i.e. if it is not source-indexed.
+    ///
+    /// The stream bytes are copied out of the PDB file, so the returned stream
+    /// doesn't borrow from `pdb`.
+    pub fn from_pdb<'s, S: pdb::Source<'s> + 's>(
+        pdb: &mut pdb::PDB<'s, S>,
+    ) -> Result<Option<OwnedSrcSrvStream>, PdbError> {
+        Self::from_pdb_with_options(pdb, &ParseOptions::default())
+    }
+
+    /// Like [`OwnedSrcSrvStream::from_pdb`], with the given parse options.
+    pub fn from_pdb_with_options<'s, S: pdb::Source<'s> + 's>(
+        pdb: &mut pdb::PDB<'s, S>,
+        options: &ParseOptions,
+    ) -> Result<Option<OwnedSrcSrvStream>, PdbError> {
+        let stream = match pdb.named_stream(b"srcsrv") {
+            Ok(stream) => stream,
+            Err(pdb::Error::StreamNameNotFound) => return Ok(None),
+            Err(err) => return Err(PdbError::Pdb(err)),
+        };
+        let stream = OwnedSrcSrvStream::parse_with_options(stream.as_slice().to_vec(), options)?;
+        Ok(Some(stream))
+    }
+
+    /// Open the PDB file at `path`, read its `srcsrv` stream and parse it.
+    /// See [`SrcSrvStream::from_pdb_path`].
+    pub fn from_pdb_path(path: impl AsRef<Path>) -> Result<Option<OwnedSrcSrvStream>, PdbError> {
+        let file = std::fs::File::open(path).map_err(PdbError::Io)?;
+        let mut pdb = pdb::PDB::open(file).map_err(PdbError::Pdb)?;
+        Self::from_pdb(&mut pdb)
+    }
+}
+
+/// The paths of the source files which are referenced by the line
+/// information of the modules in `pdb`, without duplicates, in the order in
+/// which they first appear. These are the files which the srcsrv stream of
+/// the PDB file should have entries for.
+///
+/// ```
+/// use srcsrv::{pdb_source_files, SrcSrvStreamBuilder};
+///
+/// # fn wrapper<'s, S: pdb::Source<'s> + 's>(pdb: &mut pdb::PDB<'s, S>) -> std::result::Result<(), Box<dyn std::error::Error>> {
+/// let files = pdb_source_files(pdb)?;
+/// let bytes = SrcSrvStreamBuilder::github("example", "app", "0123abcd")
+///     .add_http_entries_below(r#"C:\build\app"#, &files)
+///     .to_bytes()?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn pdb_source_files<'s, S: pdb::Source<'s> + 's>(
+    pdb: &mut pdb::PDB<'s, S>,
+) -> Result<Vec<String>, PdbError> {
+    let mut files = Vec::new();
+    let mut seen = HashSet::new();
+    for_each_pdb_source_file(pdb, |name, _| {
+        if seen.insert(name.clone()) {
+            files.push(name);
+        }
+    })
+    .map_err(PdbError::Pdb)?;
+    Ok(files)
+}
+
+/// Call `f` with the path and checksum of each source file referenced by the
+/// line information of the modules in `pdb`. A file which is used by multiple
+/// modules is reported multiple times. Modules with line information in the
+/// old C11 format are skipped.
+pub(crate) fn for_each_pdb_source_file<'s, S: pdb::Source<'s> + 's>(
+    pdb: &mut pdb::PDB<'s, S>,
+    mut f: impl FnMut(String, &pdb::FileChecksum),
+) -> pdb::Result<()> {
+    use pdb::FallibleIterator;
+
+    let string_table = pdb.string_table()?;
+    let debug_info = pdb.debug_information()?;
+    let mut modules = debug_info.modules()?;
+    while let Some(module) = modules.next()? {
+        let Some(module_info) = pdb.module_info(&module)? else {
+            continue;
+        };
+        let line_program = match module_info.line_program() {
+            Ok(line_program) => line_program,
+            Err(pdb::Error::UnimplementedFeature(_)) => continue,
+            Err(err) => return Err(err),
+        };
+        let mut files = line_program.files();
+        while let Some(file) = files.next()? {
+            let name = file.name.to_string_lossy(&string_table)?;
+            f(name.into_owned(), &file.checksum);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{PdbError, SrcSrvStream};
+
+    #[test]
+    fn from_pdb_path_errors() {
+        let dir = std::env::temp_dir();
+        assert!(matches!(
+            SrcSrvStream::from_pdb_path(dir.join("srcsrv-missing-file.pdb")),
+            Err(PdbError::Io(_))
+        ));
+
+        let path = dir.join(format!("srcsrv-not-a-pdb-{}.pdb", std::process::id()));
+        std::fs::write(&path, b"SRCSRV: ini ---").unwrap();
+        let result = SrcSrvStream::from_pdb_path(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(PdbError::Pdb(_))));
+    }
+}
@@ -0,0 +1,335 @@
+use crate::{GitCheckoutError, SourceRepository, SrcSrvStreamBuilder};
+use git2::{ErrorCode, Repository, Status};
+use std::path::{Component, Path, PathBuf};
+
+/// The state of the source files of a build in a local git checkout: the
+/// commit which is checked out, the remote it came from, and which files can
+/// be indexed because they are committed and unmodified.
+///
+/// Create it with [`GitCheckout::inspect`] after the build, and add the
+/// entries to a stream whose URLs refer to the same commit.
+///
+/// ```
+/// use srcsrv::{GitCheckout, SrcSrvStreamBuilder};
+///
+/// # fn wrapper(compiled_files: &[&str]) -> std::result::Result<(), Box<dyn std::error::Error>> {
+/// let checkout = GitCheckout::inspect(r#"C:\build\app"#, compiled_files)?;
+/// for file in &checkout.unindexable_files {
+///     eprintln!("Not indexing {}: {:?}", file.local_path, file.reason);
+/// }
+/// if let Some(repository) = checkout.source_repository() {
+///     let mut builder = SrcSrvStreamBuilder::multi_repository();
+///     checkout.add_repository_entries(&mut builder, &repository);
+///     let bytes = builder.to_bytes()?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GitCheckout {
+    /// The hash of the checked out commit.
+    pub commit: String,
+    /// The URL of the `origin` remote, or of the first remote if there is no
+    /// `origin`. `None` if the repository has no remotes.
+    pub remote_url: Option<String>,
+    /// The files which are committed and unmodified, in the order in which
+    /// they were passed.
+    pub files: Vec<GitCheckoutFile>,
+    /// The files which can't be indexed, in the order in which they were
+    /// passed.
+    pub unindexable_files: Vec<UnindexableFile>,
+}
+
+/// A file of a [`GitCheckout`] which can be indexed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GitCheckoutFile {
+    /// The path as it was passed to [`GitCheckout::inspect`].
+    pub local_path: String,
+    /// The path in the repository, with `/` as the separator.
+    pub repo_relative_path: String,
+}
+
+/// A file of a [`GitCheckout`] which can't be indexed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnindexableFile {
+    /// The path as it was passed to [`GitCheckout::inspect`].
+    pub local_path: String,
+    /// Why the file can't be indexed.
+    pub reason: UnindexableReason,
+}
+
+/// Why a file of a [`GitCheckout`] can't be indexed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum UnindexableReason {
+    /// The file is not inside the working directory of the repository, e.g.
+    /// a header of the compiler or a generated file in a separate object
+    /// directory.
+    OutsideCheckout,
+    /// The file is not part of the checked out commit: it is untracked,
+    /// ignored, only staged, or doesn't exist.
+    NotCommitted,
+    /// The file differs from its version in the checked out commit.
+    Modified,
+}
+
+impl GitCheckout {
+    /// Open the git repository whose working directory is `workdir`, and
+    /// check the status of each of `local_paths`, which can be absolute or
+    /// relative to the current directory.
+    pub fn inspect<P: AsRef<Path>, S: AsRef<str>>(
+        workdir: P,
+        local_paths: &[S],
+    ) -> Result<Self, GitCheckoutError> {
+        let repo = Repository::open(workdir.as_ref()).map_err(GitCheckoutError::Open)?;
+        let repo_workdir = repo.workdir().ok_or(GitCheckoutError::Bare)?;
+        let repo_workdir = canonicalize_lenient(repo_workdir);
+        let commit = repo
+            .head()
+            .and_then(|head| head.peel_to_commit())
+            .map_err(GitCheckoutError::NoCommit)?
+            .id()
+            .to_string();
+        let remote_url = remote_url(&repo);
+
+        let mut files = Vec::new();
+        let mut unindexable_files = Vec::new();
+        for local_path in local_paths {
+            let local_path = local_path.as_ref();
+            let repo_relative_path =
+                match relative_path(&repo_workdir, &canonicalize_lenient(Path::new(local_path))) {
+                    Some(path) => path,
+                    None => {
+                        unindexable_files.push(UnindexableFile {
+                            local_path: local_path.to_string(),
+                            reason: UnindexableReason::OutsideCheckout,
+                        });
+                        continue;
+                    }
+                };
+            let reason = match repo.status_file(Path::new(&repo_relative_path)) {
+                Ok(status) if status.is_empty() => None,
+                Ok(status)
+                    if status.intersects(Status::WT_NEW | Status::INDEX_NEW | Status::IGNORED) =>
+                {
+                    Some(UnindexableReason::NotCommitted)
+                }
+                Ok(_) => Some(UnindexableReason::Modified),
+                Err(error) if error.code() == ErrorCode::NotFound => {
+                    Some(UnindexableReason::NotCommitted)
+                }
+                Err(error) => return Err(GitCheckoutError::Status(error)),
+            };
+            match reason {
+                None => files.push(GitCheckoutFile {
+                    local_path: local_path.to_string(),
+                    repo_relative_path,
+                }),
+                Some(reason) => unindexable_files.push(UnindexableFile {
+                    local_path: local_path.to_string(),
+                    reason,
+                }),
+            }
+        }
+
+        Ok(GitCheckout {
+            commit,
+            remote_url,
+            files,
+            unindexable_files,
+        })
+    }
+
+    /// The repository to download the files from, at the checked out commit,
+    /// if the remote is on GitHub. For other hosts, create the
+    /// [`SourceRepository`] or the [`SrcSrvStreamBuilder`] from
+    /// [`GitCheckout::remote_url`] and [`GitCheckout::commit`].
+    pub fn source_repository(&self) -> Option<SourceRepository> {
+        let (owner, repo) = github_owner_and_repo(self.remote_url.as_deref()?)?;
+        Some(SourceRepository::GitHub {
+            owner: owner.to_string(),
+            repo: repo.to_string(),
+            revision: self.commit.clone(),
+        })
+    }
+
+    /// Add an entry for each of the indexable files with
+    /// [`SrcSrvStreamBuilder::add_http_entry`], to a stream which was created
+    /// with a preset for this repository and commit, such as
+    /// [`SrcSrvStreamBuilder::github`].
+    pub fn add_http_entries<'b>(
+        &self,
+        builder: &'b mut SrcSrvStreamBuilder,
+    ) -> &'b mut SrcSrvStreamBuilder {
+        for file in &self.files {
+            builder.add_http_entry(&file.local_path, &file.repo_relative_path, None);
+        }
+        builder
+    }
+
+    /// Add an entry for each of the indexable files with
+    /// [`SrcSrvStreamBuilder::add_repository_entry`], to a stream which was
+    /// created with [`SrcSrvStreamBuilder::multi_repository`].
+    pub fn add_repository_entries<'b>(
+        &self,
+        builder: &'b mut SrcSrvStreamBuilder,
+        repository: &SourceRepository,
+    ) -> &'b mut SrcSrvStreamBuilder {
+        for file in &self.files {
+            builder.add_repository_entry(&file.local_path, repository, &file.repo_relative_path);
+        }
+        builder
+    }
+}
+
+/// The URL of the `origin` remote, or of the first remote.
+fn remote_url(repo: &Repository) -> Option<String> {
+    let remotes = repo.remotes().ok()?;
+    let name = if remotes.iter().any(|name| name == Some("origin")) {
+        "origin"
+    } else {
+        remotes.iter().flatten().next()?
+    };
+    let remote = repo.find_remote(name).ok()?;
+    remote.url().map(str::to_string)
+}
+
+/// The owner and repository name of a GitHub remote URL, such as
+/// `https://github.com/owner/repo.git` or `git@github.com:owner/repo.git`.
+fn github_owner_and_repo(remote_url: &str) -> Option<(&str, &str)> {
+    let path = [
+        "https://github.com/",
+        "ssh://git@github.com/",
+        "git@github.com:",
+    ]
+    .iter()
+    .find_map(|prefix| remote_url.strip_prefix(prefix))?;
+    let path = path.trim_end_matches('/');
+    let path = path.strip_suffix(".git").unwrap_or(path);
+    match path.split_once('/') {
+        Some((owner, repo)) if !owner.is_empty() && !repo.is_empty() && !repo.contains('/') => {
+            Some((owner, repo))
+        }
+        _ => None,
+    }
+}
+
+/// The canonical form of `path`, or `path` itself if it doesn't exist, so
+/// that files which were deleted after the build are reported as well.
+fn canonicalize_lenient(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// The path of `path` relative to `root`, with `/` as the separator.
+fn relative_path(root: &Path, path: &Path) -> Option<String> {
+    let relative_path = path.strip_prefix(root).ok()?;
+    let components: Option<Vec<&str>> = relative_path
+        .components()
+        .map(|component| match component {
+            Component::Normal(name) => name.to_str(),
+            _ => None,
+        })
+        .collect();
+    let components = components?;
+    if components.is_empty() {
+        return None;
+    }
+    Some(components.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{github_owner_and_repo, GitCheckout, UnindexableReason};
+    use crate::{SourceRepository, SrcSrvStreamBuilder};
+    use git2::{Repository, Signature};
+    use std::path::Path;
+
+    #[test]
+    fn github_remote_urls() {
+        for url in [
+            "https://github.com/mstange/srcsrv",
+            "https://github.com/mstange/srcsrv.git",
+            "git@github.com:mstange/srcsrv.git",
+            "ssh://git@github.com/mstange/srcsrv",
+        ] {
+            assert_eq!(github_owner_and_repo(url), Some(("mstange", "srcsrv")));
+        }
+        assert_eq!(
+            github_owner_and_repo("https://gitlab.com/mstange/srcsrv"),
+            None
+        );
+        assert_eq!(github_owner_and_repo("https://github.com/mstange"), None);
+    }
+
+    #[test]
+    fn inspect() {
+        let dir = std::env::temp_dir().join(format!("srcsrv-git-checkout-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::write(dir.join("src/main.cpp"), "int main() {}\n").unwrap();
+        std::fs::write(dir.join("src/util.cpp"), "void f() {}\n").unwrap();
+
+        let repo = Repository::init(&dir).unwrap();
+        repo.remote("origin", "git@github.com:example/app.git")
+            .unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("src/main.cpp")).unwrap();
+        index.add_path(Path::new("src/util.cpp")).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = Signature::now("Builder", "builder@example.com").unwrap();
+        let commit = repo
+            .commit(Some("HEAD"), &signature, &signature, "Initial", &tree, &[])
+            .unwrap();
+
+        std::fs::write(dir.join("src/util.cpp"), "void f() { g(); }\n").unwrap();
+        std::fs::write(dir.join("src/generated.h"), "#define X 1\n").unwrap();
+
+        let path = |relative: &str| dir.join(relative).to_str().unwrap().to_string();
+        let checkout = GitCheckout::inspect(
+            &dir,
+            &[
+                path("src/main.cpp"),
+                path("src/util.cpp"),
+                path("src/generated.h"),
+                "/usr/include/stdio.h".to_string(),
+            ],
+        )
+        .unwrap();
+        assert_eq!(checkout.commit, commit.to_string());
+        assert_eq!(
+            checkout.remote_url.as_deref(),
+            Some("git@github.com:example/app.git")
+        );
+        assert_eq!(checkout.files.len(), 1);
+        assert_eq!(checkout.files[0].local_path, path("src/main.cpp"));
+        assert_eq!(checkout.files[0].repo_relative_path, "src/main.cpp");
+        let reasons: Vec<_> = checkout
+            .unindexable_files
+            .iter()
+            .map(|file| file.reason)
+            .collect();
+        assert_eq!(
+            reasons,
+            [
+                UnindexableReason::Modified,
+                UnindexableReason::NotCommitted,
+                UnindexableReason::OutsideCheckout
+            ]
+        );
+
+        let repository = checkout.source_repository().unwrap();
+        assert_eq!(
+            repository,
+            SourceRepository::GitHub {
+                owner: "example".to_string(),
+                repo: "app".to_string(),
+                revision: commit.to_string(),
+            }
+        );
+        let mut builder = SrcSrvStreamBuilder::multi_repository();
+        checkout.add_repository_entries(&mut builder, &repository);
+        assert!(builder.to_bytes().is_ok());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
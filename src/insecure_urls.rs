@@ -0,0 +1,103 @@
+use crate::{split_entry, EvalOptions, SharedEvalCache, SourceRetrievalMethod, SrcSrvStream};
+
+impl SourceRetrievalMethod {
+    /// Whether this is a download from a plain `http://` URL, which can be
+    /// tampered with in transit.
+    ///
+    /// See [`EvalOptions::upgrade_http`](crate::EvalOptions::upgrade_http) for
+    /// downloading these files over `https` instead.
+    pub fn is_insecure(&self) -> bool {
+        match self {
+            SourceRetrievalMethod::Download { url }
+            | SourceRetrievalMethod::DownloadWithDecode { url, .. } => is_http_url(url),
+            _ => false,
+        }
+    }
+}
+
+impl<'a> SrcSrvStream<'a> {
+    /// The original paths of the file entries which are downloaded from plain
+    /// `http://` URLs, in stream order. Entries which can't be evaluated are
+    /// skipped.
+    ///
+    /// ```
+    /// use srcsrv::SrcSrvStream;
+    ///
+    /// # fn wrapper(stream: &SrcSrvStream) {
+    /// for path in stream.insecure_entries() {
+    ///     eprintln!("Warning: {} is downloaded over plain http", path);
+    /// }
+    /// # }
+    /// ```
+    pub fn insecure_entries(&self) -> Vec<&'a str> {
+        let mut cache = SharedEvalCache {
+            entry_independent_vars: self.entry_independent_vars(),
+            ..Default::default()
+        };
+        let options = EvalOptions::new().recognize_commands(true);
+        self.source_file_entries
+            .iter()
+            .filter_map(|line| {
+                let vars = split_entry(line);
+                let (method, _) = self
+                    .source_and_raw_var_values_for_entry(&vars, "", &options, &mut cache)
+                    .ok()?;
+                Some(vars[0]).filter(|_| method.is_insecure())
+            })
+            .collect()
+    }
+}
+
+/// Whether `url` starts with `http://`, ASCII case-insensitively.
+fn is_http_url(url: &str) -> bool {
+    matches!(url.get(..7), Some(scheme) if scheme.eq_ignore_ascii_case("http://"))
+}
+
+/// `url` with `https://` instead of `http://`, if it is an `http` URL.
+pub(crate) fn upgrade_http_url(url: &str) -> Option<String> {
+    if !is_http_url(url) {
+        return None;
+    }
+    Some(format!("https://{}", &url[7..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::upgrade_http_url;
+    use crate::{EvalOptions, SourceRetrievalMethod, SrcSrvStream, SrcSrvStreamBuilder};
+
+    #[test]
+    fn upgrade_url() {
+        assert_eq!(
+            upgrade_http_url("HTTP://example.com/a").as_deref(),
+            Some("https://example.com/a")
+        );
+        assert_eq!(upgrade_http_url("https://example.com/a"), None);
+        assert_eq!(upgrade_http_url("http:"), None);
+    }
+
+    #[test]
+    fn insecure_entries() {
+        let bytes = SrcSrvStreamBuilder::new()
+            .set_var("SRCSRVTRG", "%var2%")
+            .add_source_file_entry(&[r#"C:\build\a.cpp"#, "https://example.com/a.cpp"])
+            .add_source_file_entry(&[r#"C:\build\b.cpp"#, "http://example.com/b.cpp"])
+            .to_bytes()
+            .unwrap();
+        let stream = SrcSrvStream::parse(&bytes).unwrap();
+        assert_eq!(stream.insecure_entries(), [r#"C:\build\b.cpp"#]);
+
+        let method = stream.source_for_path(r#"C:\build\b.cpp"#, "").unwrap();
+        assert!(method.unwrap().is_insecure());
+        let options = EvalOptions::new().upgrade_http(true);
+        let method = stream
+            .source_for_path_with_vars(r#"C:\build\b.cpp"#, "", &options)
+            .unwrap();
+        assert_eq!(
+            method,
+            Some(SourceRetrievalMethod::Download {
+                url: "https://example.com/b.cpp".to_string()
+            })
+        );
+    }
+}
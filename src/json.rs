@@ -0,0 +1,301 @@
+use crate::{split_entry, EvalOptions, SharedEvalCache, SourceRetrievalMethod, SrcSrvStream};
+use serde_json::{json, Value};
+use std::io::{self, Write};
+
+impl<'a> SrcSrvStream<'a> {
+    /// Export the contents of the stream as a JSON document, for tools which
+    /// want to inspect the stream without parsing the srcsrv format.
+    ///
+    /// The document has the `version`, the `ini` fields and `variables` as
+    /// lists of `{"name": ..., "value": ...}` objects in stream order, and the
+    /// `entries` as objects with the original `path` and the values of var1,
+    /// ..., varN in `vars`. Use [`SrcSrvStream::to_json_with_sources`] to
+    /// also evaluate each entry.
+    ///
+    /// ```
+    /// use srcsrv::SrcSrvStream;
+    ///
+    /// # fn wrapper() -> std::result::Result<(), Box<dyn std::error::Error>> {
+    /// let stream = SrcSrvStream::parse(br#"SRCSRV: ini ------------------------------------------------
+    /// VERSION=2
+    /// SRCSRV: variables ------------------------------------------
+    /// SRCSRVTRG=https://example.com/%var2%
+    /// SRCSRV: source files ---------------------------------------
+    /// C:\build\main.cpp*main.cpp
+    /// SRCSRV: end ------------------------------------------------"#)?;
+    /// let json: serde_json::Value = serde_json::from_str(&stream.to_json())?;
+    /// assert_eq!(json["variables"][0]["value"], "https://example.com/%var2%");
+    /// assert_eq!(json["entries"][0]["vars"][1], "main.cpp");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn to_json(&self) -> String {
+        self.json_value(None).to_string()
+    }
+
+    /// Like [`SrcSrvStream::to_json`], but each entry also has a `source`
+    /// with the serialized [`SourceRetrievalMethod`](crate::SourceRetrievalMethod)
+    /// of the entry, or an `error` with the message of the
+    /// [`EvalError`](crate::EvalError) if its evaluation failed.
+    ///
+    /// `extraction_base_path` is used as the value of the special `%targ%`
+    /// variable, like in [`SrcSrvStream::source_for_path`].
+    pub fn to_json_with_sources(&self, extraction_base_path: &str) -> String {
+        self.json_value(Some(extraction_base_path)).to_string()
+    }
+
+    /// Write a JSON Lines manifest with the resolution of each entry to
+    /// `writer`, one line per entry in stream order, for pipeline stages which
+    /// aren't written in Rust. The lines are written while the entries are
+    /// evaluated, so the manifest is never held in memory; pass a
+    /// [`BufWriter`](std::io::BufWriter) for large streams.
+    ///
+    /// Each line is an object with the original `path` and the `kind` of the
+    /// [`SourceRetrievalMethod`](crate::SourceRetrievalMethod), i.e. the name of
+    /// the variant, such as `"Download"` or `"GitFile"`. Depending on the
+    /// method, it also has the `url`, the `command` of an `ExecuteCommand`,
+    /// and the `target_path`. Entries whose evaluation failed have an `error`
+    /// with the message of the [`EvalError`](crate::EvalError) instead of a
+    /// `kind`.
+    ///
+    /// ```
+    /// use srcsrv::SrcSrvStream;
+    ///
+    /// # fn wrapper() -> std::result::Result<(), Box<dyn std::error::Error>> {
+    /// let stream = SrcSrvStream::parse(br#"SRCSRV: ini ------------------------------------------------
+    /// VERSION=2
+    /// SRCSRV: variables ------------------------------------------
+    /// SRCSRVTRG=https://example.com/%var2%
+    /// SRCSRV: source files ---------------------------------------
+    /// C:\build\main.cpp*main.cpp
+    /// SRCSRV: end ------------------------------------------------"#)?;
+    /// let mut manifest = Vec::new();
+    /// stream.write_resolution_manifest(&mut manifest, "")?;
+    /// assert_eq!(
+    ///     String::from_utf8(manifest)?,
+    ///     concat!(
+    ///         r#"{"path":"C:\\build\\main.cpp","kind":"Download","url":"https://example.com/main.cpp"}"#,
+    ///         "\n"
+    ///     )
+    /// );
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn write_resolution_manifest(
+        &self,
+        mut writer: impl Write,
+        extraction_base_path: &str,
+    ) -> io::Result<()> {
+        let mut cache = SharedEvalCache {
+            entry_independent_vars: self.entry_independent_vars(),
+            ..Default::default()
+        };
+        for line in &self.source_file_entries {
+            let vars = split_entry(line);
+            match self.source_and_raw_var_values_for_entry(
+                &vars,
+                extraction_base_path,
+                &EvalOptions::default(),
+                &mut cache,
+            ) {
+                Ok((method, _)) => {
+                    let line = ManifestLine::for_method(vars[0], &method);
+                    serde_json::to_writer(&mut writer, &line)?;
+                }
+                Err(error) => {
+                    let line = ManifestLine {
+                        path: vars[0],
+                        error: Some(error.to_string()),
+                        ..Default::default()
+                    };
+                    serde_json::to_writer(&mut writer, &line)?;
+                }
+            }
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()
+    }
+
+    fn json_value(&self, extraction_base_path: Option<&str>) -> Value {
+        let fields = |lines: &[(&str, &str)]| -> Vec<Value> {
+            lines
+                .iter()
+                .map(|(name, value)| json!({ "name": name, "value": value }))
+                .collect()
+        };
+        let mut cache = SharedEvalCache {
+            entry_independent_vars: self.entry_independent_vars(),
+            ..Default::default()
+        };
+        let entries: Vec<Value> = self
+            .source_file_entries
+            .iter()
+            .map(|line| {
+                let vars = split_entry(line);
+                let mut entry = json!({ "path": vars[0], "vars": vars });
+                if let Some(extraction_base_path) = extraction_base_path {
+                    match self.source_and_raw_var_values_for_entry(
+                        &vars,
+                        extraction_base_path,
+                        &EvalOptions::default(),
+                        &mut cache,
+                    ) {
+                        Ok((method, _)) => entry["source"] = json!(method),
+                        Err(error) => entry["error"] = json!(error.to_string()),
+                    }
+                }
+                entry
+            })
+            .collect();
+        json!({
+            "version": self.version(),
+            "ini": fields(&self.ini_lines),
+            "variables": fields(&self.var_lines),
+            "entries": entries,
+        })
+    }
+}
+
+/// A line of [`SrcSrvStream::write_resolution_manifest`].
+#[derive(serde::Serialize, Default)]
+struct ManifestLine<'l> {
+    path: &'l str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kind: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<&'l str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    command: Option<&'l str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target_path: Option<&'l str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl<'l> ManifestLine<'l> {
+    fn for_method(path: &'l str, method: &'l SourceRetrievalMethod) -> Self {
+        let (kind, url, command, target_path) = match method {
+            SourceRetrievalMethod::Download { url } => ("Download", Some(url), None, None),
+            SourceRetrievalMethod::DownloadWithDecode { url, .. } => {
+                ("DownloadWithDecode", Some(url), None, None)
+            }
+            SourceRetrievalMethod::GitFile { target_path, .. } => {
+                ("GitFile", None, None, Some(target_path))
+            }
+            SourceRetrievalMethod::TfsItem { target_path, .. } => {
+                ("TfsItem", None, None, Some(target_path))
+            }
+            SourceRetrievalMethod::Perforce { target_path, .. } => {
+                ("Perforce", None, None, Some(target_path))
+            }
+            SourceRetrievalMethod::SourceDepot { target_path, .. } => {
+                ("SourceDepot", None, None, Some(target_path))
+            }
+            SourceRetrievalMethod::Svn {
+                url, target_path, ..
+            } => ("Svn", Some(url), None, Some(target_path)),
+            SourceRetrievalMethod::Cvs { target_path, .. } => {
+                ("Cvs", None, None, Some(target_path))
+            }
+            SourceRetrievalMethod::CabExtract { target_path, .. } => {
+                ("CabExtract", None, None, Some(target_path))
+            }
+            SourceRetrievalMethod::CopyFile { target_path, .. } => {
+                ("CopyFile", None, None, Some(target_path))
+            }
+            SourceRetrievalMethod::ExecuteCommand {
+                command,
+                target_path,
+                ..
+            } => ("ExecuteCommand", None, Some(command), Some(target_path)),
+            SourceRetrievalMethod::Other { .. } => ("Other", None, None, None),
+        };
+        ManifestLine {
+            path,
+            kind: Some(kind),
+            url: url.map(String::as_str),
+            command: command.map(String::as_str),
+            target_path: target_path.map(String::as_str),
+            error: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::SrcSrvStream;
+    use serde_json::json;
+
+    #[test]
+    fn to_json_with_sources() {
+        let stream = r#"SRCSRV: ini ------------------------------------------------
+VERSION=2
+VERCTRL=http
+SRCSRV: variables ------------------------------------------
+SRCSRVTRG=%fnvar%(%var2%)%var3%
+HTTP_ALIAS=https://example.com/
+SRCSRV: source files ---------------------------------------
+c:\build\a.cpp*HTTP_ALIAS*a.cpp
+c:\build\b.cpp*MISSING*b.cpp
+SRCSRV: end ------------------------------------------------"#;
+        let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(&stream.to_json_with_sources(r#"C:\Cache"#)).unwrap();
+        assert_eq!(json["version"], 2);
+        assert_eq!(
+            json["ini"][1],
+            json!({ "name": "VERCTRL", "value": "http" })
+        );
+        assert_eq!(json["entries"][0]["path"], r#"c:\build\a.cpp"#);
+        assert_eq!(
+            json["entries"][0]["source"],
+            json!({ "Download": { "url": "https://example.com/a.cpp" } })
+        );
+        assert_eq!(
+            json["entries"][1]["error"],
+            "Could not resolve srcsrv variable name missing."
+        );
+
+        let json: serde_json::Value = serde_json::from_str(&stream.to_json()).unwrap();
+        assert!(json["entries"][0].get("source").is_none());
+    }
+
+    #[test]
+    fn resolution_manifest() {
+        let stream = r#"SRCSRV: ini ------------------------------------------------
+VERSION=2
+SRCSRV: variables ------------------------------------------
+SRCSRVTRG=%targ%\%var2%
+SRCSRVCMD=tool.exe get %var2% %fnvar%(%var3%)
+REVISION=42
+SRCSRV: source files ---------------------------------------
+c:\build\a.cpp*a.cpp*REVISION
+c:\build\b.cpp*b.cpp*UNKNOWN
+SRCSRV: end ------------------------------------------------"#;
+        let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
+        let mut manifest = Vec::new();
+        stream
+            .write_resolution_manifest(&mut manifest, r#"C:\Cache"#)
+            .unwrap();
+        let lines: Vec<serde_json::Value> = std::str::from_utf8(&manifest)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                json!({
+                    "path": r#"c:\build\a.cpp"#,
+                    "kind": "ExecuteCommand",
+                    "command": "tool.exe get a.cpp 42",
+                    "target_path": r#"C:\Cache\a.cpp"#,
+                }),
+                json!({
+                    "path": r#"c:\build\b.cpp"#,
+                    "error": "Could not resolve srcsrv variable name unknown.",
+                }),
+            ]
+        );
+    }
+}
@@ -22,24 +22,345 @@
 //! # Ok(())
 //! # }
 //! ```
+//!
+//! New streams can be created with [`SrcSrvStreamBuilder`].
 
+use std::borrow::Cow;
+use std::collections::hash_map::Entry;
 use std::collections::{HashMap, HashSet};
 use std::result::Result;
+use std::sync::OnceLock;
+
+use case_insensitive::CaseInsensitiveStr;
+use duplicates::DuplicateTracker;
+use entry_index::EntryIndex;
+use rustc_hash::{FxHashMap, FxHashSet};
+use write::StreamLayout;
 
 mod ast;
+#[cfg(feature = "fetch")]
+mod auth;
+mod breakpad;
+#[cfg(feature = "fetch")]
+mod bulk_fetch;
+mod cache_path;
+#[cfg(any(feature = "exec", feature = "fetch"))]
+mod cancel;
+mod case_insensitive;
+#[cfg(feature = "pdb")]
+mod checksum;
+mod command_policy;
+mod command_preview;
+mod command_safety;
+#[cfg(feature = "pdb")]
+mod coverage;
+mod diff;
+#[cfg(feature = "pdb")]
+mod digest;
+mod duplicates;
+mod entry_index;
+mod error_persistence;
 mod errors;
+#[cfg(feature = "exec")]
+mod exec;
+#[cfg(any(feature = "exec", feature = "fetch"))]
+mod extraction;
+#[cfg(feature = "fetch")]
+mod fetch;
+#[cfg(feature = "pdb")]
+mod from_pdb;
+#[cfg(feature = "git2")]
+mod git_checkout;
+mod insecure_urls;
+#[cfg(feature = "json")]
+mod json;
+mod merge;
+#[cfg(feature = "fetch")]
+mod mirror;
+#[cfg(feature = "pdb")]
+mod msf;
+mod options;
+mod owned;
+mod peek;
+mod portable_command;
+mod reader;
+mod recognize;
+#[cfg(feature = "reqwest")]
+mod reqwest_fetcher;
+mod rewrite;
+mod snapshot;
+mod source_cache;
+mod source_index;
+#[cfg(feature = "sourcelink")]
+mod source_link;
+#[cfg(feature = "fetch")]
+mod source_pack;
+mod source_path;
+mod stats;
+mod suffix_match;
+#[cfg(feature = "pdb")]
+mod to_pdb;
+mod trace;
+#[cfg(feature = "blocking-fetch")]
+mod ureq_fetcher;
+mod url_policy;
+mod validate;
+mod vcs;
+mod write;
 
-use ast::AstNode;
-pub use errors::{EvalError, ParseError};
+pub use ast::AstNode;
+#[cfg(feature = "fetch")]
+pub use auth::{AuthProvider, Credential, HostCredentials};
+#[cfg(feature = "fetch")]
+pub use bulk_fetch::{fetch_sources, FetchProgress};
+#[cfg(any(feature = "exec", feature = "fetch"))]
+pub use cancel::CancellationToken;
+#[cfg(feature = "pdb")]
+pub use checksum::{PdbSourceChecksums, SourceChecksum};
+pub use command_policy::CommandPolicy;
+pub use command_preview::CommandPreview;
+pub use command_safety::{CommandAnalysis, CommandConcern};
+#[cfg(feature = "pdb")]
+pub use coverage::PdbCoverage;
+pub use diff::{diff, EntryChange, FieldChange, StreamDiff};
+pub use duplicates::Duplicate;
+pub use error_persistence::ErrorPersistenceTracker;
+#[cfg(feature = "exec")]
+pub use errors::ExecError;
+#[cfg(feature = "git2")]
+pub use errors::GitCheckoutError;
+#[cfg(feature = "pdb")]
+pub use errors::PdbError;
+#[cfg(feature = "sourcelink")]
+pub use errors::SourceLinkError;
+pub use errors::{
+    EvalError, MergeError, ParseError, ParseWarning, TemplateError, TranslateError, WriteError,
+};
+#[cfg(feature = "fetch")]
+pub use errors::{FetchError, HttpStatusError};
+#[cfg(feature = "exec")]
+pub use exec::{
+    execute_command, execute_without_shell, extract_source, CommandOutput, ExecOptions,
+};
+#[cfg(any(feature = "exec", feature = "fetch"))]
+pub use extraction::ExtractionResult;
+#[cfg(feature = "fetch")]
+pub use fetch::{
+    fetch_source, fetch_source_contents, fetch_source_contents_with_options,
+    fetch_source_with_options, FetchOptions, SourceFetcher,
+};
+#[cfg(feature = "pdb")]
+pub use from_pdb::pdb_source_files;
+#[cfg(feature = "git2")]
+pub use git_checkout::{GitCheckout, GitCheckoutFile, UnindexableFile, UnindexableReason};
+pub use merge::{merge, MergeConflictPolicy};
+#[cfg(feature = "fetch")]
+pub use mirror::{mirror_sources, MirroredStream, UnfetchedEntry};
+pub use options::{
+    DuplicatePolicy, EvalOptions, LookupOptions, ParseOptions, UnknownFunctionPolicy,
+    UnknownVariablePolicy,
+};
+pub use owned::OwnedSrcSrvStream;
+pub use peek::SrcSrvStreamVersion;
+pub use portable_command::PortableCommand;
+pub use reader::SrcSrvStreamReader;
+#[cfg(feature = "reqwest")]
+pub use reqwest_fetcher::ReqwestFetcher;
+pub use rewrite::StreamRewriter;
+pub use snapshot::SrcSrvStreamSnapshot;
+pub use source_cache::SourceCache;
+pub use source_index::SourceIndex;
+#[cfg(feature = "sourcelink")]
+pub use source_link::{SourceLink, SourceLinkConversion, UnconvertedEntry};
+#[cfg(feature = "fetch")]
+pub use source_pack::{write_source_pack, SourcePack, SourcePackMember, SOURCE_PACK_MANIFEST_PATH};
+pub use source_path::{DebuggerSourcePath, SourcePathElement};
+pub use stats::SrcSrvStreamStats;
+pub use suffix_match::SuffixMatchCandidate;
+pub use trace::{EvalTrace, EvalTraceSource, EvalTraceStep};
+#[cfg(feature = "blocking-fetch")]
+pub use ureq_fetcher::UreqFetcher;
+pub use url_policy::UrlPolicy;
+pub use validate::ValidationFinding;
+pub use vcs::VcsKind;
+pub use write::{GitilesLayout, SourceRepository, SrcSrvStreamBuilder};
+
+#[cfg(feature = "encoding_rs")]
+pub use encoding_rs;
 
 /// A map of variables with their evaluated values.
 pub type EvalVarMap = HashMap<String, String>;
 
 /// Describes how the source file can be obtained.
+///
+/// Entries with a command are returned as
+/// [`SourceRetrievalMethod::ExecuteCommand`]. With
+/// [`EvalOptions::recognize_commands`], commands of well-known shapes are
+/// returned as the variants which describe them instead, such as
+/// [`SourceRetrievalMethod::GitFile`] or [`SourceRetrievalMethod::TfsItem`].
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
 pub enum SourceRetrievalMethod {
     /// The source can be downloaded from the web, at the given URL.
     Download { url: String },
+    /// The source can be downloaded from the web, at the given URL, but the
+    /// response needs to be decoded with `encoding` to get the file contents.
+    ///
+    /// This is returned for streams which download and decode the file with a
+    /// Python one-liner, such as Chrome's streams which fetch base64-encoded
+    /// files from gitiles (`?format=TEXT`), if commands are recognized, so that
+    /// consumers can fetch the file without executing the command. It is also
+    /// returned for streams without a command whose `SRCSRVTRG` is a URL and
+    /// which have the variable `HTTP_CONTENT_ENCODING=base64`.
+    DownloadWithDecode {
+        /// The URL of the encoded file.
+        url: String,
+        /// The encoding of the response.
+        encoding: ContentEncoding,
+    },
+    /// The source file can be obtained from a git repository. This is returned
+    /// for commands which run `git show`, `git cat-file` or `git archive`, so
+    /// that consumers can get the file without running the command, for example
+    /// with libgit2 or with the REST API of the git hosting service.
+    GitFile {
+        /// The repository, as given to git: a URL or a local path.
+        repo: String,
+        /// The revision (commit hash, tag or branch name).
+        revision: String,
+        /// The path of the file, relative to the repository root.
+        path: String,
+        /// The path at which the command would have created the file.
+        target_path: String,
+        /// The environment variables from `SRCSRVENV` for the command.
+        env: HashMap<String, String>,
+        /// The value of the variable which `SRCSRVERRVAR` names, see
+        /// [`SourceRetrievalMethod::ExecuteCommand`].
+        error_persistence_version_control: Option<String>,
+    },
+    /// The source file can be obtained from a Team Foundation Server (or Azure
+    /// DevOps) version control server. This is returned for `tf.exe view`
+    /// commands, so that consumers can use the REST API instead of tf.exe.
+    TfsItem {
+        /// The URL of the server or project collection, from `/server:`.
+        server: String,
+        /// The server path of the item, e.g. `$/Project/src/main.cpp`.
+        item_path: String,
+        /// The version spec, from `/version:`. This is usually a changeset
+        /// number.
+        version: String,
+        /// The path at which the command would have created the file.
+        target_path: String,
+        /// The environment variables from `SRCSRVENV` for the command.
+        env: HashMap<String, String>,
+        /// The value of the variable which `SRCSRVERRVAR` names, see
+        /// [`SourceRetrievalMethod::ExecuteCommand`].
+        error_persistence_version_control: Option<String>,
+    },
+    /// The source file can be obtained from a Perforce server. This is
+    /// returned for `p4.exe print` commands.
+    Perforce {
+        /// The server address, from the `-p` option or from `P4PORT` in
+        /// `SRCSRVENV`. `None` if neither is present, in which case p4 would
+        /// use the user's Perforce configuration.
+        port: Option<String>,
+        /// The depot path of the file, e.g. `//depot/project/main.cpp`.
+        depot_path: String,
+        /// The revision (after `#`) or changelist (after `@`) of the file.
+        revision: String,
+        /// The path at which the command would have created the file.
+        target_path: String,
+        /// The environment variables from `SRCSRVENV` for the command.
+        env: HashMap<String, String>,
+        /// The value of the variable which `SRCSRVERRVAR` names, see
+        /// [`SourceRetrievalMethod::ExecuteCommand`].
+        error_persistence_version_control: Option<String>,
+    },
+    /// The source file can be obtained from a Source Depot server, which was
+    /// used for older Microsoft products. This is returned for `sd.exe print`
+    /// commands.
+    SourceDepot {
+        /// The server address, from the `-p` option or from `SDPORT` in
+        /// `SRCSRVENV`.
+        port: Option<String>,
+        /// The depot path of the file.
+        depot_path: String,
+        /// The revision (after `#`) or change number (after `@`) of the file.
+        revision: String,
+        /// The path at which the command would have created the file.
+        target_path: String,
+        /// The environment variables from `SRCSRVENV` for the command.
+        env: HashMap<String, String>,
+        /// The value of the variable which `SRCSRVERRVAR` names, see
+        /// [`SourceRetrievalMethod::ExecuteCommand`].
+        error_persistence_version_control: Option<String>,
+    },
+    /// The source file can be obtained from a Subversion repository. This is
+    /// returned for `svn cat` and `svn export` commands.
+    Svn {
+        /// The URL of the file in the repository, without a peg revision.
+        url: String,
+        /// The revision of the file, from `-r` or from the peg revision.
+        revision: String,
+        /// The path at which the command would have created the file.
+        target_path: String,
+        /// The environment variables from `SRCSRVENV` for the command.
+        env: HashMap<String, String>,
+        /// The value of the variable which `SRCSRVERRVAR` names, see
+        /// [`SourceRetrievalMethod::ExecuteCommand`].
+        error_persistence_version_control: Option<String>,
+    },
+    /// The source file can be obtained from a CVS repository. This is returned
+    /// for `cvs checkout` and `cvs update` commands.
+    Cvs {
+        /// The CVS root, from the `-d` option or from `CVSROOT` in `SRCSRVENV`,
+        /// e.g. `:pserver:anonymous@cvs.example.com:/cvsroot`.
+        root: String,
+        /// The path of the file in the repository, starting with the module name.
+        path: String,
+        /// The revision or tag of the file.
+        revision: String,
+        /// The path at which the command would have created the file.
+        target_path: String,
+        /// The environment variables from `SRCSRVENV` for the command.
+        env: HashMap<String, String>,
+        /// The value of the variable which `SRCSRVERRVAR` names, see
+        /// [`SourceRetrievalMethod::ExecuteCommand`].
+        error_persistence_version_control: Option<String>,
+    },
+    /// The source file can be extracted from a cabinet (`.cab`) file or from a
+    /// single compressed file, like `main.cp_`. This is returned for
+    /// `expand.exe` and `extract.exe` commands, so that consumers on other
+    /// platforms can extract the file without these tools.
+    CabExtract {
+        /// The path of the cabinet or compressed file, often on a network
+        /// share.
+        archive_path: String,
+        /// The name of the file in the cabinet, from the `-F:` option of
+        /// `expand.exe` or the file name argument of `extract.exe`. `None` if
+        /// the archive is a single compressed file, or if the command extracts
+        /// all files of the cabinet.
+        member: Option<String>,
+        /// The path at which the command would have created the file.
+        target_path: String,
+        /// The environment variables from `SRCSRVENV` for the command.
+        env: HashMap<String, String>,
+        /// The value of the variable which `SRCSRVERRVAR` names, see
+        /// [`SourceRetrievalMethod::ExecuteCommand`].
+        error_persistence_version_control: Option<String>,
+    },
+    /// The source is a file on a network share (a UNC path like
+    /// `\\server\share\file.cpp`) or on a local or mapped drive, and can
+    /// simply be copied. This is returned if `SRCSRVTRG` evaluates to such a
+    /// path and there is no command.
+    CopyFile {
+        /// The path of the file which should be copied.
+        source_path: String,
+        /// A path below the extraction base path (`%targ%`) to which the file
+        /// can be copied, made from `source_path` without the leading `\\`
+        /// or the `:` after the drive letter.
+        target_path: String,
+    },
     /// Evaluating the given command on the Windows Command shell with the given
     /// environment variables will create the source file at `target_path`.
     ExecuteCommand {
@@ -64,16 +385,108 @@ pub enum SourceRetrievalMethod {
     Other { raw_var_values: EvalVarMap },
 }
 
+impl SourceRetrievalMethod {
+    /// The coarse kind of this retrieval method. All variants which describe a
+    /// command, such as [`SourceRetrievalMethod::GitFile`], are of kind
+    /// [`RetrievalKind::ExecuteCommand`], and
+    /// [`SourceRetrievalMethod::DownloadWithDecode`] is of kind
+    /// [`RetrievalKind::Download`].
+    pub fn kind(&self) -> RetrievalKind {
+        match self {
+            SourceRetrievalMethod::Download { .. }
+            | SourceRetrievalMethod::DownloadWithDecode { .. } => RetrievalKind::Download,
+            SourceRetrievalMethod::ExecuteCommand { .. }
+            | SourceRetrievalMethod::GitFile { .. }
+            | SourceRetrievalMethod::TfsItem { .. }
+            | SourceRetrievalMethod::Perforce { .. }
+            | SourceRetrievalMethod::SourceDepot { .. }
+            | SourceRetrievalMethod::Svn { .. }
+            | SourceRetrievalMethod::Cvs { .. }
+            | SourceRetrievalMethod::CabExtract { .. } => RetrievalKind::ExecuteCommand,
+            SourceRetrievalMethod::CopyFile { .. } => RetrievalKind::CopyFile,
+            SourceRetrievalMethod::Other { .. } => RetrievalKind::Other,
+        }
+    }
+}
+
+/// The coarse kind of a [`SourceRetrievalMethod`], see
+/// [`SrcSrvStream::classify_path`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum RetrievalKind {
+    /// The file can be downloaded from an HTTP(S) URL, without running a command.
+    Download,
+    /// The stream has a command template (`SRCSRVCMD`). The command may be
+    /// recognized as something more specific, like a git checkout.
+    ExecuteCommand,
+    /// The file can be copied from a network share or a local drive.
+    CopyFile,
+    /// None of the above.
+    Other,
+}
+
+/// The encoding of a downloaded file, see [`SourceRetrievalMethod::DownloadWithDecode`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum ContentEncoding {
+    /// The response is the base64-encoded file contents.
+    Base64,
+}
+
 /// A parsed representation of the `srcsrv` stream from a PDB file.
 pub struct SrcSrvStream<'a> {
     /// 1, 2 or 3, based on the VERSION={} field
     version: u8,
-    /// lowercase field name -> field value
-    ini_fields: HashMap<String, &'a str>,
-    /// lowercase field name -> (raw field value, parsed field value ast node)
-    var_fields: HashMap<String, (&'a str, AstNode<'a>)>,
-    /// lowercase original path -> [var1, ..., var10]
-    source_file_entries: HashMap<String, Vec<&'a str>>,
+    /// field name -> field value
+    ini_fields: FxHashMap<&'a CaseInsensitiveStr, &'a str>,
+    /// (field name, field value) for each line of the ini section, in stream order
+    ini_lines: Vec<(&'a str, &'a str)>,
+    /// field name -> raw field value and its lazily parsed ast node
+    var_fields: FxHashMap<&'a CaseInsensitiveStr, VarField<'a>>,
+    /// (field name, raw field value) for each line of the variables section, in stream order
+    var_lines: Vec<(&'a str, &'a str)>,
+    /// the line of each file entry, in stream order; the values of var1, ...,
+    /// var10 are only split off when the entry is used
+    source_file_entries: Vec<&'a str>,
+    /// original path -> index into source_file_entries
+    source_file_index: EntryIndex<'a>,
+    /// normalized lowercase original path -> index into source_file_entries,
+    /// built on first use, one for each LookupOptions::normalization_kind()
+    normalized_source_file_indexes: [OnceLock<FxHashMap<String, usize>>; 4],
+    /// lowercase file name -> indexes into source_file_entries, built on first use
+    file_name_index: OnceLock<FxHashMap<String, Vec<usize>>>,
+    /// problems which were encountered during parsing but were not fatal
+    warnings: Vec<ParseWarning>,
+    /// variables which were defined more than once
+    duplicate_variables: Vec<Duplicate<'a>>,
+    /// original paths which have more than one file entry
+    duplicate_entries: Vec<Duplicate<'a>>,
+    /// the policy which was used for duplicate_variables during parsing
+    duplicate_variable_policy: DuplicatePolicy,
+    /// with DuplicatePolicy::CollectAll, original path -> indexes into
+    /// source_file_entries, for the paths which have more than one entry
+    collected_entry_indexes: FxHashMap<&'a CaseInsensitiveStr, Vec<usize>>,
+    /// the formatting of the stream, with ParseOptions::preserve_layout
+    layout: Option<StreamLayout>,
+}
+
+/// The value of a variable from the variables section. Most streams define
+/// many more variables than a lookup needs, so the template is only checked
+/// for errors during parsing, and the ast is built on first use.
+struct VarField<'a> {
+    raw: &'a str,
+    node: OnceLock<AstNode<'a>>,
+}
+
+impl<'a> VarField<'a> {
+    fn node(&self) -> &AstNode<'a> {
+        self.node.get_or_init(|| {
+            // The template was validated during parsing.
+            AstNode::parse(self.raw).unwrap_or(AstNode::LiteralString(self.raw))
+        })
+    }
 }
 
 impl<'a> SrcSrvStream<'a> {
@@ -91,82 +504,453 @@ impl<'a> SrcSrvStream<'a> {
     /// # }
     /// ```
     pub fn parse(stream: &'a [u8]) -> Result<SrcSrvStream<'a>, ParseError> {
-        let stream = std::str::from_utf8(stream).map_err(|_| ParseError::InvalidUtf8)?;
-        let mut lines = stream.lines();
+        Self::parse_with_options(stream, &ParseOptions::default())
+    }
+
+    /// Parse the `srcsrv` stream with the given options. See [`ParseOptions`].
+    ///
+    /// Any problems which did not cause the parse to fail are available from
+    /// [`SrcSrvStream::warnings`] afterwards.
+    pub fn parse_with_options(
+        stream: &'a [u8],
+        options: &ParseOptions,
+    ) -> Result<SrcSrvStream<'a>, ParseError> {
+        let (mut srcsrv, mut lines) = Self::parse_header(stream, options)?;
+        srcsrv.parse_source_files(&mut lines, options)?;
+        Ok(srcsrv)
+    }
+
+    /// Parse the ini and variables sections, and the header line of the source
+    /// files section. The returned stream has no file entries yet, and the
+    /// returned line reader is positioned at the first file entry.
+    pub(crate) fn parse_header(
+        stream: &'a [u8],
+        options: &ParseOptions,
+    ) -> Result<(SrcSrvStream<'a>, LineReader<'a>), ParseError> {
+        let mut lines = LineReader::new(stream, options)?;
+        let mut warnings = Vec::new();
+        let (ini, (line_number, line)) = parse_ini_section(&mut lines, options, &mut warnings)?;
 
-        // Parse section SRCSRV: ini ------------------------------------------------
-        let first_line = lines.next().ok_or(ParseError::UnexpectedEof)?;
-        if !first_line.starts_with("SRCSRV: ini --") {
-            return Err(ParseError::MissingIniSection);
+        // Parse section SRCSRV: variables ------------------------------------------
+        if !line.starts_with("SRCSRV: variables --") {
+            return Err(ParseError::MissingVariablesSection {
+                line_number,
+                line: line.to_string(),
+            });
         }
+        let variables_section_header = line;
 
-        let mut ini_fields = HashMap::new();
-        let next_section_start_line = loop {
-            let line = lines.next().ok_or(ParseError::UnexpectedEof)?;
+        let mut var_fields = FxHashMap::default();
+        let mut var_lines: Vec<(&str, &str)> = Vec::new();
+        // The line number of each item in var_lines.
+        let mut var_line_numbers = Vec::new();
+        let mut duplicate_variables = DuplicateTracker::default();
+        let (line_number, line) = loop {
+            let (line_number, line) = lines.next_line()?;
             if line.starts_with("SRCSRV:") {
-                break line;
+                break (line_number, line);
+            }
+
+            let (name, value) = match line.split_once('=') {
+                Some(name_and_value) => name_and_value,
+                None => {
+                    skip_line(
+                        options,
+                        &mut warnings,
+                        line_number,
+                        line,
+                        ParseError::MissingEquals {
+                            line_number,
+                            line: line.to_string(),
+                        },
+                    )?;
+                    continue;
+                }
+            };
+            match AstNode::validate(value) {
+                Ok(()) => {
+                    let key = CaseInsensitiveStr::new(name);
+                    let policy = options.duplicate_variable_policy;
+                    if var_fields.contains_key(key) {
+                        if policy == DuplicatePolicy::Error {
+                            skip_line(
+                                options,
+                                &mut warnings,
+                                line_number,
+                                line,
+                                ParseError::DuplicateVariable {
+                                    line_number,
+                                    name: name.to_string(),
+                                },
+                            )?;
+                            continue;
+                        }
+                        let first = var_lines
+                            .iter()
+                            .position(|(n, _)| n.eq_ignore_ascii_case(name))
+                            .unwrap();
+                        duplicate_variables.record(
+                            var_lines[first].0,
+                            var_line_numbers[first],
+                            line_number,
+                        );
+                    }
+                    if policy != DuplicatePolicy::FirstWins || !var_fields.contains_key(key) {
+                        let field = VarField {
+                            raw: value,
+                            node: OnceLock::new(),
+                        };
+                        var_fields.insert(key, field);
+                    }
+                    var_lines.push((name, value));
+                    var_line_numbers.push(line_number);
+                }
+                Err(error) => skip_line(
+                    options,
+                    &mut warnings,
+                    line_number,
+                    line,
+                    ParseError::InvalidTemplate {
+                        line_number,
+                        line: line.to_string(),
+                        error,
+                    },
+                )?,
             }
+        };
+
+        if !var_fields.contains_key(CaseInsensitiveStr::new("SRCSRVTRG")) {
+            return Err(ParseError::MissingSrcSrvTrgField);
+        }
+
+        // Parse section SRCSRV: source files ---------------------------------------
+        if !line.starts_with("SRCSRV: source files --") {
+            return Err(ParseError::MissingSourceFilesSection {
+                line_number,
+                line: line.to_string(),
+            });
+        }
 
-            let (name, value) = line.split_once('=').ok_or(ParseError::MissingEquals)?;
-            ini_fields.insert(name.to_ascii_lowercase(), value);
+        let layout = if options.preserve_layout {
+            Some(StreamLayout {
+                prefix: lines.prefix.to_string(),
+                line_ending: lines.line_ending_after(ini.header),
+                ini_section_header: ini.header.to_string(),
+                variables_section_header: variables_section_header.to_string(),
+                source_files_section_header: line.to_string(),
+                ..StreamLayout::default()
+            })
+        } else {
+            None
         };
 
-        let version = match ini_fields.get(&"VERSION".to_ascii_lowercase()) {
-            Some(&"1") => 1,
-            Some(&"2") => 2,
-            Some(&"3") => 3,
-            Some(v) => return Err(ParseError::UnrecognizedVersion(v.to_string())),
-            None => return Err(ParseError::MissingVersion),
+        let srcsrv = SrcSrvStream {
+            version: ini.version,
+            ini_fields: ini.fields,
+            ini_lines: ini.lines,
+            var_fields,
+            var_lines,
+            source_file_entries: Vec::new(),
+            source_file_index: EntryIndex::new(),
+            normalized_source_file_indexes: Default::default(),
+            file_name_index: OnceLock::new(),
+            warnings,
+            duplicate_variables: duplicate_variables.duplicates,
+            duplicate_entries: Vec::new(),
+            duplicate_variable_policy: options.duplicate_variable_policy,
+            collected_entry_indexes: FxHashMap::default(),
+            layout,
         };
+        Ok((srcsrv, lines))
+    }
 
-        // Parse section SRCSRV: variables ------------------------------------------
-        if !next_section_start_line.starts_with("SRCSRV: variables --") {
-            return Err(ParseError::MissingVariablesSection);
+    /// Parse the file entries of the source files section, and the end marker.
+    fn parse_source_files(
+        &mut self,
+        lines: &mut LineReader<'a>,
+        options: &ParseOptions,
+    ) -> Result<(), ParseError> {
+        #[cfg(feature = "rayon")]
+        if options.parallel && options.duplicate_entry_policy != DuplicatePolicy::Error {
+            return self.parse_source_files_parallel(lines, options);
         }
 
-        let mut var_fields = HashMap::new();
-        let next_section_start_line = loop {
-            let line = lines.next().ok_or(ParseError::UnexpectedEof)?;
+        let mut source_file_entries: Vec<&str> = Vec::new();
+        let mut source_file_index = EntryIndex::new();
+        // The line number of each item in source_file_entries.
+        let mut entry_line_numbers = Vec::new();
+        let mut duplicate_entries = DuplicateTracker::default();
+        let mut collected_entry_indexes = FxHashMap::default();
+        let policy = options.duplicate_entry_policy;
+        let end_line = loop {
+            let (line_number, line) = match lines.next_line() {
+                Ok(line) => line,
+                Err(ParseError::UnexpectedEof) if options.allow_missing_end_marker => break None,
+                Err(err) => return Err(err),
+            };
             if line.starts_with("SRCSRV:") {
-                break line;
+                break Some((line_number, line));
             }
 
-            let (name, value) = line.split_once('=').ok_or(ParseError::MissingEquals)?;
-            let node = AstNode::parse(value)?;
-            var_fields.insert(name.to_ascii_lowercase(), (value, node));
+            let path = entry_path(line);
+            let index = source_file_entries.len();
+            let index_shard = source_file_index.shard_mut(path);
+            if policy == DuplicatePolicy::Error
+                && index_shard.contains_key(CaseInsensitiveStr::new(path))
+            {
+                skip_line(
+                    options,
+                    &mut self.warnings,
+                    line_number,
+                    line,
+                    ParseError::DuplicateEntry {
+                        line_number,
+                        path: path.to_string(),
+                    },
+                )?;
+                continue;
+            }
+            let previous = index_entry(
+                index_shard,
+                &mut collected_entry_indexes,
+                path,
+                index,
+                policy,
+            );
+            if let Some(previous) = previous {
+                // Only the first time a path is duplicated is `previous`
+                // the first entry, but that's the only time it's needed.
+                duplicate_entries.record(
+                    entry_path(source_file_entries[previous]),
+                    entry_line_numbers[previous],
+                    line_number,
+                );
+            }
+            source_file_entries.push(line);
+            entry_line_numbers.push(line_number);
         };
 
-        if !var_fields.contains_key(&"SRCSRVTRG".to_ascii_lowercase()) {
-            return Err(ParseError::MissingSrcSrvTrgField);
-        }
+        self.record_end_layout(lines, end_line);
+        lines.check_end_marker(end_line, &mut self.warnings)?;
 
-        // Parse section SRCSRV: source files ---------------------------------------
-        if !next_section_start_line.starts_with("SRCSRV: source files --") {
-            return Err(ParseError::MissingSourceFilesSection);
-        }
+        self.source_file_entries = source_file_entries;
+        self.source_file_index = source_file_index;
+        self.duplicate_entries = duplicate_entries.duplicates;
+        self.collected_entry_indexes = collected_entry_indexes;
+        Ok(())
+    }
 
-        let mut source_file_entries = HashMap::new();
+    /// Like [`SrcSrvStream::parse_source_files`], but the index of the file
+    /// entries is built on rayon's thread pool, split into shards. This gives
+    /// the same result as the sequential parse.
+    #[cfg(feature = "rayon")]
+    fn parse_source_files_parallel(
+        &mut self,
+        lines: &mut LineReader<'a>,
+        options: &ParseOptions,
+    ) -> Result<(), ParseError> {
+        use rayon::prelude::*;
+
+        let mut source_file_entries: Vec<&str> = Vec::new();
+        // The line number of each item in source_file_entries.
+        let mut entry_line_numbers = Vec::new();
         let end_line = loop {
-            let line = lines.next().ok_or(ParseError::UnexpectedEof)?;
+            let (line_number, line) = match lines.next_line() {
+                Ok(line) => line,
+                Err(ParseError::UnexpectedEof) if options.allow_missing_end_marker => break None,
+                Err(err) => return Err(err),
+            };
             if line.starts_with("SRCSRV:") {
-                break line;
+                break Some((line_number, line));
             }
-
-            let vars: Vec<&str> = line.splitn(10, '*').collect();
-            source_file_entries.insert(vars[0].to_ascii_lowercase(), vars);
+            source_file_entries.push(line);
+            entry_line_numbers.push(line_number);
         };
 
-        // Stop at SRCSRV: end ------------------------------------------------
-        if !end_line.starts_with("SRCSRV: end --") {
-            return Err(ParseError::MissingTerminationLine);
+        self.record_end_layout(lines, end_line);
+        lines.check_end_marker(end_line, &mut self.warnings)?;
+
+        // Use more shards than threads, so that threads which finish early
+        // can pick up more work.
+        let shard_count = rayon::current_num_threads() * 4;
+        let entry_shards: Vec<usize> = source_file_entries
+            .par_iter()
+            .map(|line| {
+                entry_index::shard_of(CaseInsensitiveStr::new(entry_path(line)), shard_count)
+            })
+            .collect();
+        // The indexes of the entries of each shard, in stream order.
+        let mut shard_entries = vec![Vec::new(); shard_count];
+        for (index, &shard) in entry_shards.iter().enumerate() {
+            shard_entries[shard].push(index);
         }
+        let policy = options.duplicate_entry_policy;
+        let shards: Vec<_> = shard_entries
+            .into_par_iter()
+            .map(|entry_indexes| {
+                let mut index_shard = FxHashMap::default();
+                let mut collected_entry_indexes = FxHashMap::default();
+                // (previous index, index) for each entry whose path was already indexed
+                let mut duplicates = Vec::new();
+                for index in entry_indexes {
+                    let path = entry_path(source_file_entries[index]);
+                    let previous = index_entry(
+                        &mut index_shard,
+                        &mut collected_entry_indexes,
+                        path,
+                        index,
+                        policy,
+                    );
+                    if let Some(previous) = previous {
+                        duplicates.push((previous, index));
+                    }
+                }
+                (index_shard, collected_entry_indexes, duplicates)
+            })
+            .collect();
 
-        Ok(SrcSrvStream {
-            version,
-            ini_fields,
-            var_fields,
-            source_file_entries,
-        })
+        let mut index_shards = Vec::with_capacity(shard_count);
+        let mut collected_entry_indexes = FxHashMap::default();
+        let mut duplicates = Vec::new();
+        for (index_shard, shard_collected_entry_indexes, shard_duplicates) in shards {
+            index_shards.push(index_shard);
+            collected_entry_indexes.extend(shard_collected_entry_indexes);
+            duplicates.extend(shard_duplicates);
+        }
+        // Record the duplicates in the order in which the sequential parse would.
+        duplicates.sort_unstable_by_key(|&(_, index)| index);
+        let mut duplicate_entries = DuplicateTracker::default();
+        for (previous, index) in duplicates {
+            duplicate_entries.record(
+                entry_path(source_file_entries[previous]),
+                entry_line_numbers[previous],
+                entry_line_numbers[index],
+            );
+        }
+
+        self.source_file_entries = source_file_entries;
+        self.source_file_index = EntryIndex::with_shards(index_shards);
+        self.duplicate_entries = duplicate_entries.duplicates;
+        self.collected_entry_indexes = collected_entry_indexes;
+        Ok(())
+    }
+
+    /// Record the end line and what follows it in the layout, if the layout
+    /// is preserved. If the end line is missing, the layout keeps the default
+    /// end line.
+    fn record_end_layout(&mut self, lines: &LineReader<'a>, end_line: Option<(usize, &'a str)>) {
+        if let (Some(layout), Some((_, line))) = (self.layout.as_mut(), end_line) {
+            layout.end_line = line.to_string();
+            layout.suffix = lines.text_after(line).to_string();
+        }
+    }
+
+    /// Parse the `srcsrv` stream, taking ownership of the stream bytes. This is
+    /// useful if the parsed stream needs to outlive the buffer of the PDB file.
+    /// See [`OwnedSrcSrvStream`].
+    pub fn parse_owned(stream: Vec<u8>) -> Result<OwnedSrcSrvStream, ParseError> {
+        OwnedSrcSrvStream::parse(stream)
+    }
+
+    /// Parse only the ini section of the `srcsrv` stream, to find out its
+    /// version and version control system without the cost of parsing the
+    /// variables and the file entries. Only the ini section needs to be valid.
+    ///
+    /// ```
+    /// use srcsrv::SrcSrvStream;
+    ///
+    /// # fn wrapper<'s, S: pdb::Source<'s> + 's>(pdb: &mut pdb::PDB<'s, S>) {
+    /// if let Ok(srcsrv_stream) = pdb.named_stream(b"srcsrv") {
+    ///     if let Ok(version) = SrcSrvStream::peek_version(srcsrv_stream.as_slice()) {
+    ///         println!("source-indexed with {:?}", version.version_control_description);
+    ///     }
+    /// }
+    /// # }
+    /// ```
+    pub fn peek_version(stream: &'a [u8]) -> Result<SrcSrvStreamVersion<'a>, ParseError> {
+        SrcSrvStreamVersion::peek(stream)
+    }
+
+    /// Whether `stream` starts with an ini section with a supported version.
+    /// This is as cheap as [`SrcSrvStream::peek_version`], and doesn't mean
+    /// that [`SrcSrvStream::parse`] will succeed.
+    pub fn is_srcsrv_stream(stream: &[u8]) -> bool {
+        SrcSrvStreamVersion::peek(stream).is_ok()
+    }
+
+    /// Serialize the stream back into the `srcsrv` text format.
+    ///
+    /// All ini fields, variables and file entries are written out in their
+    /// original order and with their original casing, including any fields
+    /// which this crate does not understand. Lines which were skipped in
+    /// lenient parsing mode are not written. Lines are terminated with `\r\n`,
+    /// unless the stream was parsed with [`ParseOptions::preserve_layout`].
+    pub fn to_bytes(&self) -> Vec<u8> {
+        write::write_stream(
+            self.layout.as_ref().unwrap_or(&StreamLayout::default()),
+            self.ini_lines.iter().cloned(),
+            self.var_lines.iter().cloned(),
+            self.source_file_entries
+                .iter()
+                .map(|line| split_entry(line)),
+        )
+    }
+
+    /// Create a [`SrcSrvStreamBuilder`] with the contents of this stream, so
+    /// that a modified version of this stream can be written.
+    ///
+    /// ```
+    /// use srcsrv::SrcSrvStream;
+    ///
+    /// # fn wrapper(bytes: &[u8]) -> std::result::Result<Vec<u8>, Box<dyn std::error::Error>> {
+    /// let stream = SrcSrvStream::parse(bytes)?;
+    /// let mut builder = stream.to_builder();
+    /// builder.set_var("HGSERVER", "https://hg-mirror.example.com/mozilla-central");
+    /// let modified_bytes = builder.to_bytes()?;
+    /// # Ok(modified_bytes)
+    /// # }
+    /// ```
+    pub fn to_builder(&self) -> SrcSrvStreamBuilder {
+        let mut builder = match &self.layout {
+            // Keep fields which are defined more than once.
+            Some(layout) => {
+                SrcSrvStreamBuilder::with_layout(layout.clone(), &self.ini_lines, &self.var_lines)
+            }
+            None => {
+                let mut builder = SrcSrvStreamBuilder::new();
+                for (name, value) in &self.ini_lines {
+                    builder.set_ini_field(name, value);
+                }
+                for (name, value) in &self.var_lines {
+                    builder.set_var(name, value);
+                }
+                builder
+            }
+        };
+        for line in &self.source_file_entries {
+            builder.add_source_file_entry(&split_entry(line));
+        }
+        builder
+    }
+
+    /// Create an owned copy of the contents of this stream. See
+    /// [`SrcSrvStreamSnapshot`].
+    pub fn snapshot(&self) -> SrcSrvStreamSnapshot {
+        SrcSrvStreamSnapshot::new(self)
+    }
+
+    /// Compute a summary of this stream: the number of entries, the servers
+    /// and revisions they refer to, how many entries there are of each
+    /// retrieval kind, and which variables are unused. This evaluates every
+    /// entry. See [`SrcSrvStreamStats`].
+    pub fn stats(&self) -> SrcSrvStreamStats {
+        SrcSrvStreamStats::new(self)
+    }
+
+    /// Problems which were encountered during parsing but which did not cause
+    /// the parse to fail, for example lines which were skipped in lenient mode.
+    pub fn warnings(&self) -> &[ParseWarning] {
+        &self.warnings
     }
 
     /// The value of the VERSION field from the ini section.
@@ -176,17 +960,51 @@ impl<'a> SrcSrvStream<'a> {
 
     /// The value of the INDEXVERSION field from the ini section, if specified.
     pub fn index_version(&self) -> Option<&'a str> {
-        self.ini_fields.get("indexversion").cloned()
+        self.get_ini_field("INDEXVERSION")
     }
 
     /// The value of the DATETIME field from the ini section, if specified.
     pub fn datetime(&self) -> Option<&'a str> {
-        self.ini_fields.get("datetime").cloned()
+        self.get_ini_field("DATETIME")
     }
 
     /// The value of the VERCTRL field from the ini section, if specified.
     pub fn version_control_description(&self) -> Option<&'a str> {
-        self.ini_fields.get("verctrl").cloned()
+        self.get_ini_field("VERCTRL")
+    }
+
+    /// Find out which kind of version control system this stream was indexed
+    /// against, without evaluating any file entries.
+    ///
+    /// This looks at the `SRCSRVVERCTRL` variable and the `VERCTRL` ini field
+    /// first, then at the program invoked by the `SRCSRVCMD` template, and
+    /// finally at whether `SRCSRVTRG` is an HTTP(S) URL.
+    /// Returns `None` if none of these give any indication.
+    pub fn vcs_kind(&self) -> Option<VcsKind> {
+        let description_kind = self
+            .expand_template_without_entry("SRCSRVVERCTRL")
+            .filter(|version_ctrl| !version_ctrl.contains('%'))
+            .and_then(|version_ctrl| VcsKind::from_description(&version_ctrl))
+            .filter(|kind| *kind != VcsKind::Other)
+            .or_else(|| {
+                self.version_control_description()
+                    .and_then(VcsKind::from_description)
+            });
+        match description_kind {
+            Some(kind) if kind != VcsKind::Other => return Some(kind),
+            _ => {}
+        }
+
+        if let Some(command) = self.expand_template_without_entry("SRCSRVCMD") {
+            if let Some(kind) = VcsKind::from_command(&command) {
+                return Some(kind);
+            }
+        } else if let Some(target) = self.expand_template_without_entry("SRCSRVTRG") {
+            if target.starts_with("http://") || target.starts_with("https://") {
+                return Some(VcsKind::Http);
+            }
+        }
+        description_kind
     }
 
     /// Look up `original_file_path` in the file entries and find out how to obtain
@@ -218,7 +1036,26 @@ impl<'a> SrcSrvStream<'a> {
         original_file_path: &str,
         extraction_base_path: &str,
     ) -> Result<Option<SourceRetrievalMethod>, EvalError> {
-        match self.source_and_raw_var_values_for_path(original_file_path, extraction_base_path)? {
+        self.source_for_path_with_options(
+            original_file_path,
+            extraction_base_path,
+            &LookupOptions::default(),
+        )
+    }
+
+    /// Like [`SrcSrvStream::source_for_path`], but `original_file_path` is
+    /// matched against the file entries according to `options`.
+    pub fn source_for_path_with_options(
+        &self,
+        original_file_path: &str,
+        extraction_base_path: &str,
+        options: &LookupOptions,
+    ) -> Result<Option<SourceRetrievalMethod>, EvalError> {
+        match self.source_and_raw_var_values_for_path_with_options(
+            original_file_path,
+            extraction_base_path,
+            options,
+        )? {
             Some((method, _)) => Ok(Some(method)),
             None => Ok(None),
         }
@@ -243,120 +1080,801 @@ impl<'a> SrcSrvStream<'a> {
         original_file_path: &str,
         extraction_base_path: &str,
     ) -> Result<Option<(SourceRetrievalMethod, EvalVarMap)>, EvalError> {
-        let mut map = match self.vars_for_file(original_file_path)? {
-            Some(map) => map,
-            None => return Ok(None),
-        };
-
-        let error_persistence_version_control = self
-            .get_raw_var("SRCSRVERRVAR")
-            .and_then(|var| map.get(&var.to_ascii_lowercase()).cloned());
-
-        map.insert("targ".to_string(), extraction_base_path.to_string());
-
-        let target = self.evaluate_required_field("SRCSRVTRG", &mut map)?;
-        let command = self.evaluate_optional_field("SRCSRVCMD", &mut map)?;
-        let env = self.evaluate_optional_field("SRCSRVENV", &mut map)?;
-        let version_ctrl = self.evaluate_optional_field("SRCSRVVERCTRL", &mut map)?;
+        self.source_and_raw_var_values_for_path_with_options(
+            original_file_path,
+            extraction_base_path,
+            &LookupOptions::default(),
+        )
+    }
 
-        if let Some(command) = command {
-            let env = match env {
-                Some(env) => env
-                    .split('\x08')
-                    .filter_map(|s| s.split_once('='))
-                    .map(|(envname, envval)| (envname.to_owned(), envval.to_owned()))
-                    .collect(),
-                None => HashMap::new(),
-            };
-            return Ok(Some((
-                SourceRetrievalMethod::ExecuteCommand {
-                    command,
-                    env,
-                    target_path: target,
-                    version_ctrl,
-                    error_persistence_version_control,
-                },
-                map,
-            )));
-        }
+    /// Like [`SrcSrvStream::source_and_raw_var_values_for_path`], but
+    /// `original_file_path` is matched against the file entries according to
+    /// `options`.
+    pub fn source_and_raw_var_values_for_path_with_options(
+        &self,
+        original_file_path: &str,
+        extraction_base_path: &str,
+        options: &LookupOptions,
+    ) -> Result<Option<(SourceRetrievalMethod, EvalVarMap)>, EvalError> {
+        self.source_and_raw_var_values_for_path_impl(
+            original_file_path,
+            extraction_base_path,
+            options,
+            &EvalOptions::default(),
+            &mut SharedEvalCache::default(),
+        )
+    }
 
-        if target.starts_with("http://") || target.starts_with("https://") {
-            return Ok(Some((SourceRetrievalMethod::Download { url: target }, map)));
+    /// Like [`SrcSrvStream::source_for_path`], but with variable values from
+    /// `options` taking precedence over the variables in the stream, and with
+    /// the path matched according to the lookup options in `options`.
+    pub fn source_for_path_with_vars(
+        &self,
+        original_file_path: &str,
+        extraction_base_path: &str,
+        options: &EvalOptions,
+    ) -> Result<Option<SourceRetrievalMethod>, EvalError> {
+        match self.source_and_raw_var_values_for_path_with_vars(
+            original_file_path,
+            extraction_base_path,
+            options,
+        )? {
+            Some((method, _)) => Ok(Some(method)),
+            None => Ok(None),
         }
+    }
 
-        Ok(Some((
-            SourceRetrievalMethod::Other {
-                raw_var_values: map.clone(),
-            },
-            map,
-        )))
+    /// Like [`SrcSrvStream::source_and_raw_var_values_for_path`], but with
+    /// variable values from `options` taking precedence over the variables in
+    /// the stream, and with the path matched according to the lookup options
+    /// in `options`.
+    pub fn source_and_raw_var_values_for_path_with_vars(
+        &self,
+        original_file_path: &str,
+        extraction_base_path: &str,
+        options: &EvalOptions,
+    ) -> Result<Option<(SourceRetrievalMethod, EvalVarMap)>, EvalError> {
+        self.source_and_raw_var_values_for_path_impl(
+            original_file_path,
+            extraction_base_path,
+            &options.lookup,
+            options,
+            &mut SharedEvalCache::default(),
+        )
     }
 
-    /// A set of strings which can be substring-matched to the output of the
-    /// command that is executed when obtaining source files.
+    /// Look up each path in `original_file_paths` in the file entries and find
+    /// out how to obtain the source for these files. The results are returned in
+    /// the same order as the paths.
     ///
-    /// If any of the strings matches, it is recommended to "persist the error"
-    /// and refuse to execute further commands for other files with the same
-    /// `error_persistence_version_control` value.
-    pub fn error_persistence_command_output_strings(&self) -> HashSet<&'a str> {
-        self.var_fields
+    /// `extraction_base_path` is used as the value of the special `%targ%` variable
+    /// and should not include a trailing backslash.
+    ///
+    /// This gives the same results as calling [`SrcSrvStream::source_for_path`]
+    /// for each path, but it is faster when looking up many paths: The values of
+    /// variables which don't depend on the file entry, such as server URLs, are
+    /// only evaluated once and then shared across all entries.
+    ///
+    /// ```
+    /// use srcsrv::SrcSrvStream;
+    ///
+    /// # fn wrapper() -> std::result::Result<(), Box<dyn std::error::Error>> {
+    /// # let stream = SrcSrvStream::parse(&[])?;
+    /// let paths = [
+    ///     r#"C:\build\renderdoc\renderdoc\data\glsl\gl_texsample.h"#,
+    ///     r#"C:\build\renderdoc\renderdoc\maths\matrix.cpp"#,
+    /// ];
+    /// for (path, method) in paths
+    ///     .iter()
+    ///     .zip(stream.source_for_paths(&paths, r#"C:\Debugger\Cached Sources"#))
+    /// {
+    ///     println!("{}: {:?}", path, method?);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn source_for_paths(
+        &self,
+        original_file_paths: &[&str],
+        extraction_base_path: &str,
+    ) -> Vec<Result<Option<SourceRetrievalMethod>, EvalError>> {
+        self.source_for_paths_with_options(
+            original_file_paths,
+            extraction_base_path,
+            &LookupOptions::default(),
+        )
+    }
+
+    /// Like [`SrcSrvStream::source_for_paths`], but the paths are matched
+    /// against the file entries according to `options`.
+    pub fn source_for_paths_with_options(
+        &self,
+        original_file_paths: &[&str],
+        extraction_base_path: &str,
+        options: &LookupOptions,
+    ) -> Vec<Result<Option<SourceRetrievalMethod>, EvalError>> {
+        let mut cache = SharedEvalCache {
+            entry_independent_vars: self.entry_independent_vars(),
+            ..Default::default()
+        };
+        original_file_paths
             .iter()
-            .filter_map(|(var_name, (var_value, _))| {
-                if var_name.starts_with(&"SRCSRVERRDESC".to_ascii_lowercase()) {
-                    Some(*var_value)
-                } else {
-                    None
-                }
+            .map(|original_file_path| {
+                let result = self.source_and_raw_var_values_for_path_impl(
+                    original_file_path,
+                    extraction_base_path,
+                    options,
+                    &EvalOptions::default(),
+                    &mut cache,
+                )?;
+                Ok(result.map(|(method, _)| method))
             })
             .collect()
     }
 
-    /// Get the value of the specified field from the ini section.
-    /// The field name is case-insensitive.
-    pub fn get_ini_field(&self, field_name: &str) -> Option<&'a str> {
-        self.ini_fields
-            .get(&field_name.to_ascii_lowercase())
-            .cloned()
-    }
-
-    /// Get the raw, unevaluated value of the specified field from the
-    /// variables section.
-    /// The field name is case-insensitive.
-    pub fn get_raw_var(&self, var_name: &str) -> Option<&'a str> {
-        self.var_fields
-            .get(&var_name.to_ascii_lowercase())
-            .map(|(val, _)| *val)
-    }
-
-    /// Create a map with the values of var1, ..., var10 for the given file path.
-    /// Returns Ok(None) if the file was not found.
-    fn vars_for_file(&self, file_path: &str) -> Result<Option<EvalVarMap>, EvalError> {
-        let vars = match self
-            .source_file_entries
-            .get(&file_path.to_ascii_lowercase())
-        {
-            Some(vars) => vars,
-            None => return Ok(None),
+    /// Look up all file entries for the path and evaluate each of them, in
+    /// stream order. The path is matched ASCII case-insensitively.
+    ///
+    /// If the stream was parsed with [`DuplicatePolicy::CollectAll`] for
+    /// entries, this returns one result for each entry with this path.
+    /// Otherwise there is at most one result, for the entry that
+    /// [`SrcSrvStream::source_for_path`] would use.
+    pub fn source_for_path_all(
+        &self,
+        original_file_path: &str,
+        extraction_base_path: &str,
+    ) -> Vec<Result<SourceRetrievalMethod, EvalError>> {
+        let key = CaseInsensitiveStr::new(original_file_path);
+        let index = self.source_file_index.get(original_file_path);
+        let indexes = match self.collected_entry_indexes.get(key) {
+            Some(indexes) => indexes.as_slice(),
+            None => index.as_slice(),
         };
-
-        Ok(Some(
-            vars.iter()
-                .enumerate()
-                .map(|(i, var)| (format!("var{}", i + 1), var.to_string()))
-                .collect(),
-        ))
+        let mut cache = SharedEvalCache::default();
+        indexes
+            .iter()
+            .map(|&index| {
+                let vars = split_entry(self.source_file_entries[index]);
+                let (method, _) = self.source_and_raw_var_values_for_entry(
+                    &vars,
+                    extraction_base_path,
+                    &EvalOptions::default(),
+                    &mut cache,
+                )?;
+                Ok(method)
+            })
+            .collect()
     }
 
-    fn evaluate_optional_field(
+    /// Like [`SrcSrvStream::source_for_path_with_vars`], but also returns a
+    /// trace of all variable substitutions that were made, which is useful to
+    /// find out why a stream produces an unexpected result. The trace is
+    /// returned even if evaluation fails, and it then ends with the step that
+    /// failed.
+    ///
+    /// ```
+    /// use srcsrv::{EvalOptions, SrcSrvStream};
+    ///
+    /// # fn wrapper(stream: &SrcSrvStream) {
+    /// let (result, trace) = stream.source_for_path_with_trace(
+    ///     r#"C:\build\renderdoc\renderdoc\maths\matrix.cpp"#,
+    ///     r#"C:\Debugger\Cached Sources"#,
+    ///     &EvalOptions::new(),
+    /// );
+    /// eprint!("{}", trace);
+    /// # }
+    /// ```
+    pub fn source_for_path_with_trace(
         &self,
-        var_name: &str,
-        var_map: &mut EvalVarMap,
+        original_file_path: &str,
+        extraction_base_path: &str,
+        options: &EvalOptions,
+    ) -> (Result<Option<SourceRetrievalMethod>, EvalError>, EvalTrace) {
+        let mut cache = SharedEvalCache {
+            trace: Some(Vec::new()),
+            ..Default::default()
+        };
+        let result = self.source_and_raw_var_values_for_path_impl(
+            original_file_path,
+            extraction_base_path,
+            &options.lookup,
+            options,
+            &mut cache,
+        );
+        let trace = EvalTrace {
+            steps: cache.trace.unwrap_or_default(),
+        };
+        (result.map(|result| result.map(|(method, _)| method)), trace)
+    }
+
+    /// Evaluate `template` as if it were the value of a variable in this
+    /// stream, for a file entry with the values `entry` (var1, var2, ...).
+    /// This is useful for trying out templates while writing an indexing
+    /// script. The entry doesn't have to be one of the stream's entries, and
+    /// can be empty if the template doesn't reference var1, ..., var10.
+    ///
+    /// `extraction_base_path` is used as the value of the special `%targ%`
+    /// variable. Variables in `options` take precedence over the variables in
+    /// the stream, but not over the values of the entry.
+    ///
+    /// ```
+    /// use srcsrv::{EvalOptions, SrcSrvStream};
+    ///
+    /// # fn wrapper(stream: &SrcSrvStream) -> std::result::Result<(), srcsrv::EvalError> {
+    /// let entry = [r#"C:\build\src\main.cpp"#, "src/main.cpp", "0123abcd"];
+    /// let url = stream.evaluate_template_for_entry(
+    ///     "%HTTP_ALIAS%/%var3%/%var2%",
+    ///     &entry,
+    ///     r#"C:\Debugger\Cached Sources"#,
+    ///     &EvalOptions::new(),
+    /// )?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn evaluate_template_for_entry(
+        &self,
+        template: &str,
+        entry: &[&str],
+        extraction_base_path: &str,
+        options: &EvalOptions,
+    ) -> Result<String, EvalError> {
+        let node = AstNode::parse(template)?;
+        let mut map: EvalVarMap = options.vars.clone();
+        map.extend(
+            entry
+                .iter()
+                .enumerate()
+                .map(|(i, var)| (format!("var{}", i + 1), var.to_string())),
+        );
+        map.insert("targ".to_string(), extraction_base_path.to_string());
+        let mut cache = SharedEvalCache::default();
+        let mut get_var = |var_name: &str| {
+            self.eval_impl(
+                var_name.to_ascii_lowercase(),
+                &mut map,
+                options,
+                &mut cache,
+                &EvalStack::Empty,
+            )
+            .map(Cow::Owned)
+        };
+        let mut call_function =
+            |function_name: &str, arg: &str| call_function(function_name, arg, options);
+        node.eval(&mut get_var, &mut call_function)
+            .map(Cow::into_owned)
+    }
+
+    /// Find out how the source for `original_file_path` would be obtained,
+    /// without evaluating the command or the full target path. This is much
+    /// cheaper than [`SrcSrvStream::source_for_path`] when you only want to
+    /// know, for example, which files can be downloaded over HTTP.
+    ///
+    /// The result agrees with [`SourceRetrievalMethod::kind`] of the method
+    /// returned by `source_for_path`, which doesn't recognize commands.
+    /// Entries whose target path starts with `%targ%` and which have no
+    /// command are classified as [`RetrievalKind::Other`]. Entries whose evaluation would fail are also
+    /// classified by their templates, so `source_for_path` may still return an
+    /// error for them. `None` is returned if there is no entry for the path.
+    ///
+    /// ```
+    /// use srcsrv::{RetrievalKind, SrcSrvStream};
+    ///
+    /// # fn wrapper(stream: &SrcSrvStream, paths: &[&str]) {
+    /// let downloadable: Vec<&str> = paths
+    ///     .iter()
+    ///     .copied()
+    ///     .filter(|path| stream.classify_path(path) == Some(RetrievalKind::Download))
+    ///     .collect();
+    /// # }
+    /// ```
+    pub fn classify_path(&self, original_file_path: &str) -> Option<RetrievalKind> {
+        let vars = split_entry(self.find_entry(original_file_path, &LookupOptions::default())?);
+        if self
+            .var_fields
+            .contains_key(CaseInsensitiveStr::new("SRCSRVCMD"))
+        {
+            return Some(RetrievalKind::ExecuteCommand);
+        }
+
+        // Only the beginning of the target is needed to classify it.
+        let mut prefix = String::new();
+        if let Some(field) = self.var_fields.get(CaseInsensitiveStr::new("SRCSRVTRG")) {
+            let eval_stack = EvalStack::WithAddedVar("srcsrvtrg", &EvalStack::Empty);
+            self.append_target_prefix(field.node(), &vars, &mut prefix, &eval_stack);
+        }
+        let kind = if prefix.starts_with("http://") || prefix.starts_with("https://") {
+            RetrievalKind::Download
+        } else if copyable_file_relative_path(&prefix).is_some() {
+            RetrievalKind::CopyFile
+        } else {
+            RetrievalKind::Other
+        };
+        Some(kind)
+    }
+
+    /// Append the evaluated value of `node` to `prefix` until `prefix` is long
+    /// enough to classify the target. Returns false if evaluation stopped
+    /// before the end of `node`, because the prefix is long enough or because
+    /// the rest of the value cannot be determined cheaply.
+    fn append_target_prefix(
+        &self,
+        node: &AstNode,
+        entry: &[&str],
+        prefix: &mut String,
+        eval_stack: &EvalStack,
+    ) -> bool {
+        // Long enough for "https://" and for "\\server\" or "C:\x".
+        const PREFIX_LEN: usize = 8;
+        if prefix.len() >= PREFIX_LEN {
+            return false;
+        }
+        match node {
+            AstNode::Sequence(nodes) => nodes
+                .iter()
+                .all(|node| self.append_target_prefix(node, entry, prefix, eval_stack)),
+            AstNode::LiteralString(s) => {
+                prefix.push_str(s);
+                true
+            }
+            AstNode::Variable(var_name) => {
+                let var_name = var_name.to_ascii_lowercase();
+                if is_entry_var_name(&var_name) {
+                    match entry_var(entry, &var_name) {
+                        Some(value) => {
+                            prefix.push_str(value);
+                            true
+                        }
+                        None => false,
+                    }
+                } else {
+                    match self.var_fields.get(CaseInsensitiveStr::new(&var_name)) {
+                        Some(field) if !eval_stack.contains(&var_name) => {
+                            let eval_stack = EvalStack::WithAddedVar(&var_name, eval_stack);
+                            self.append_target_prefix(field.node(), entry, prefix, &eval_stack)
+                        }
+                        // %targ%, unknown variables and recursion
+                        _ => false,
+                    }
+                }
+            }
+            AstNode::FnBackslash(node) => {
+                let mut value = String::new();
+                let complete = self.append_target_prefix(node, entry, &mut value, eval_stack);
+                prefix.push_str(&value.replace('/', "\\"));
+                complete
+            }
+            AstNode::FnVar(node) => {
+                // Support the common %fnvar%(%var2%) without a full evaluation.
+                let var_name = match &**node {
+                    AstNode::LiteralString(s) => *s,
+                    AstNode::Variable(var_name) if is_entry_var_name(var_name) => {
+                        match entry_var(entry, var_name) {
+                            Some(value) => value,
+                            None => return false,
+                        }
+                    }
+                    _ => return false,
+                };
+                self.append_target_prefix(&AstNode::Variable(var_name), entry, prefix, eval_stack)
+            }
+            AstNode::FnFile(_) | AstNode::Function(..) => false,
+        }
+    }
+
+    fn source_and_raw_var_values_for_path_impl(
+        &self,
+        original_file_path: &str,
+        extraction_base_path: &str,
+        options: &LookupOptions,
+        eval_options: &EvalOptions,
+        cache: &mut SharedEvalCache,
+    ) -> Result<Option<(SourceRetrievalMethod, EvalVarMap)>, EvalError> {
+        match self.find_entry(original_file_path, options) {
+            Some(line) => self
+                .source_and_raw_var_values_for_entry(
+                    &split_entry(line),
+                    extraction_base_path,
+                    eval_options,
+                    cache,
+                )
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Evaluate the retrieval method for the file entry with the values `vars`.
+    fn source_and_raw_var_values_for_entry(
+        &self,
+        vars: &[&str],
+        extraction_base_path: &str,
+        eval_options: &EvalOptions,
+        cache: &mut SharedEvalCache,
+    ) -> Result<(SourceRetrievalMethod, EvalVarMap), EvalError> {
+        // Values in the map take precedence over the variables in the stream.
+        let mut map: EvalVarMap = eval_options.vars.clone();
+        map.extend(
+            vars.iter()
+                .enumerate()
+                .map(|(i, var)| (format!("var{}", i + 1), var.to_string())),
+        );
+
+        let error_persistence_version_control = self
+            .get_raw_var("SRCSRVERRVAR")
+            .and_then(|var| map.get(&var.to_ascii_lowercase()).cloned());
+
+        map.insert("targ".to_string(), extraction_base_path.to_string());
+
+        let target = self.evaluate_required_field("SRCSRVTRG", &mut map, eval_options, cache)?;
+        let command = self.evaluate_optional_field("SRCSRVCMD", &mut map, eval_options, cache)?;
+        let env = self.evaluate_optional_field("SRCSRVENV", &mut map, eval_options, cache)?;
+        let version_ctrl =
+            self.evaluate_optional_field("SRCSRVVERCTRL", &mut map, eval_options, cache)?;
+
+        if let Some(command) = command {
+            let env = env.map(|env| parse_env(&env)).unwrap_or_default();
+            let recognized = eval_options
+                .recognize_commands
+                .then(|| {
+                    recognize::recognize_command(&recognize::EvaluatedCommand {
+                        command: &command,
+                        target_path: &target,
+                        env: &env,
+                        error_persistence_version_control: error_persistence_version_control
+                            .as_deref(),
+                    })
+                })
+                .flatten();
+            if let Some(method) = recognized {
+                return Ok((eval_options.apply_url_options(method)?, map));
+            }
+            if let Some(program) = eval_options.command_policy.disallowed_program(&command) {
+                return Err(EvalError::CommandNotAllowed(program));
+            }
+            return Ok((
+                SourceRetrievalMethod::ExecuteCommand {
+                    command,
+                    env,
+                    target_path: target,
+                    version_ctrl,
+                    error_persistence_version_control,
+                },
+                map,
+            ));
+        }
+
+        if target.starts_with("http://") || target.starts_with("https://") {
+            let method = match self.get_raw_var("HTTP_CONTENT_ENCODING") {
+                Some(encoding) if encoding.eq_ignore_ascii_case("base64") => {
+                    SourceRetrievalMethod::DownloadWithDecode {
+                        url: target,
+                        encoding: ContentEncoding::Base64,
+                    }
+                }
+                _ => SourceRetrievalMethod::Download { url: target },
+            };
+            return Ok((eval_options.apply_url_options(method)?, map));
+        }
+
+        // A target below %targ% is where the file would go, not where it comes from.
+        if let Some(relative_path) = copyable_file_relative_path(&target)
+            .filter(|_| !is_path_below(&target, extraction_base_path))
+        {
+            let target_path = format!("{}\\{}", extraction_base_path, relative_path);
+            return Ok((
+                SourceRetrievalMethod::CopyFile {
+                    source_path: target,
+                    target_path,
+                },
+                map,
+            ));
+        }
+
+        Ok((
+            SourceRetrievalMethod::Other {
+                raw_var_values: map.clone(),
+            },
+            map,
+        ))
+    }
+
+    /// A set of strings which can be substring-matched to the output of the
+    /// command that is executed when obtaining source files.
+    ///
+    /// If any of the strings matches, it is recommended to "persist the error"
+    /// and refuse to execute further commands for other files with the same
+    /// `error_persistence_version_control` value.
+    pub fn error_persistence_command_output_strings(&self) -> HashSet<&'a str> {
+        self.var_fields
+            .iter()
+            .filter_map(|(var_name, field)| {
+                let prefix = var_name.as_str().get(.."SRCSRVERRDESC".len())?;
+                if prefix.eq_ignore_ascii_case("SRCSRVERRDESC") {
+                    Some(field.raw)
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Get the value of the specified field from the ini section.
+    /// The field name is case-insensitive.
+    pub fn get_ini_field(&self, field_name: &str) -> Option<&'a str> {
+        self.ini_fields
+            .get(CaseInsensitiveStr::new(field_name))
+            .cloned()
+    }
+
+    /// Get the parsed template of the specified variable from the variables
+    /// section. The variable name is case-insensitive.
+    pub fn get_var_template(&self, var_name: &str) -> Option<&AstNode<'a>> {
+        self.var_fields
+            .get(CaseInsensitiveStr::new(var_name))
+            .map(|field| field.node())
+    }
+
+    /// Get the raw, unevaluated value of the specified field from the
+    /// variables section.
+    /// The field name is case-insensitive.
+    pub fn get_raw_var(&self, var_name: &str) -> Option<&'a str> {
+        self.var_fields
+            .get(CaseInsensitiveStr::new(var_name))
+            .map(|field| field.raw)
+    }
+
+    /// Get the raw values of all definitions of the specified variable, in
+    /// stream order. This only returns more than one value if the stream was
+    /// parsed with [`DuplicatePolicy::CollectAll`] for variables, otherwise
+    /// it returns the value from [`SrcSrvStream::get_raw_var`].
+    /// The field name is case-insensitive.
+    pub fn get_raw_var_all(&self, var_name: &str) -> Vec<&'a str> {
+        if self.duplicate_variable_policy != DuplicatePolicy::CollectAll {
+            return self.get_raw_var(var_name).into_iter().collect();
+        }
+        self.var_lines
+            .iter()
+            .filter(|(name, _)| name.eq_ignore_ascii_case(var_name))
+            .map(|(_, value)| *value)
+            .collect()
+    }
+
+    /// The variables which are defined more than once in the variables
+    /// section, in the order of their first definition. See
+    /// [`ParseOptions::duplicate_variable_policy`].
+    pub fn duplicate_variables(&self) -> &[Duplicate<'a>] {
+        &self.duplicate_variables
+    }
+
+    /// The original paths which have more than one entry in the source files
+    /// section, in the order of their first entry. See
+    /// [`ParseOptions::duplicate_entry_policy`].
+    pub fn duplicate_entries(&self) -> &[Duplicate<'a>] {
+        &self.duplicate_entries
+    }
+
+    /// Iterate over all entries in the source files section, in the order in
+    /// which they appear in the stream.
+    ///
+    /// Each item is the original file path, with its original casing, and the
+    /// raw values of var1, ..., varN for that entry. The first value is always
+    /// the original file path itself, i.e. the value of var1.
+    ///
+    /// If the stream contains multiple entries for the same path, all of them
+    /// are returned. Lookups such as [`SrcSrvStream::source_for_path`] use the
+    /// one selected by [`ParseOptions::duplicate_entry_policy`], which is the
+    /// last one by default.
+    pub fn source_file_entries(&self) -> impl Iterator<Item = (&'a str, Vec<&'a str>)> + '_ {
+        self.source_file_entries
+            .iter()
+            .map(|line| (entry_path(line), split_entry(line)))
+    }
+
+    /// Find file entries whose paths end in the same path components as
+    /// `original_file_path`, for example because the build machine used a
+    /// different directory prefix than the path you have.
+    ///
+    /// Path components are compared ASCII case-insensitively, and both `/` and
+    /// `\` are treated as separators. Only entries with at least
+    /// `min_matching_components` matching trailing components are returned
+    /// (at least the file name needs to match in any case).
+    ///
+    /// The candidates are sorted by the number of matching components, best
+    /// candidate first, and entries with the same number of matching components
+    /// are kept in stream order.
+    ///
+    /// ```
+    /// use srcsrv::SrcSrvStream;
+    ///
+    /// # fn wrapper(stream: &SrcSrvStream) {
+    /// for candidate in stream.suffix_match_candidates("/home/me/renderdoc/maths/matrix.cpp", 2) {
+    ///     println!("{} ({} components)", candidate.original_path, candidate.matching_components);
+    /// }
+    /// # }
+    /// ```
+    pub fn suffix_match_candidates(
+        &self,
+        original_file_path: &str,
+        min_matching_components: usize,
+    ) -> Vec<SuffixMatchCandidate<'a>> {
+        self.suffix_match_candidate_indexes(original_file_path, min_matching_components)
+            .into_iter()
+            .map(|(index, matching_components)| SuffixMatchCandidate {
+                original_path: entry_path(self.source_file_entries[index]),
+                matching_components,
+            })
+            .collect()
+    }
+
+    /// Returns (index into source_file_entries, matching component count) for
+    /// each suffix match candidate, sorted by descending match count.
+    fn suffix_match_candidate_indexes(
+        &self,
+        file_path: &str,
+        min_matching_components: usize,
+    ) -> Vec<(usize, usize)> {
+        let file_name = match suffix_match::lowercase_file_name(file_path) {
+            Some(file_name) => file_name,
+            None => return Vec::new(),
+        };
+        let file_name_index = self.file_name_index.get_or_init(|| {
+            let mut file_name_index: FxHashMap<String, Vec<usize>> = FxHashMap::default();
+            for (index, line) in self.source_file_entries.iter().enumerate() {
+                // Only consider the entry that a lookup of the exact path would find.
+                let path = entry_path(line);
+                if self.source_file_index.get(path) != Some(index) {
+                    continue;
+                }
+                if let Some(file_name) = suffix_match::lowercase_file_name(path) {
+                    file_name_index.entry(file_name).or_default().push(index);
+                }
+            }
+            file_name_index
+        });
+        let mut candidates: Vec<(usize, usize)> = file_name_index
+            .get(&file_name)
+            .into_iter()
+            .flatten()
+            .map(|&index| {
+                let entry_path = entry_path(self.source_file_entries[index]);
+                let matching_components =
+                    suffix_match::matching_trailing_components(file_path, entry_path);
+                (index, matching_components)
+            })
+            .filter(|&(_, matching_components)| {
+                matching_components >= min_matching_components.max(1)
+            })
+            .collect();
+        candidates.sort_by(|(_, a), (_, b)| b.cmp(a));
+        candidates
+    }
+
+    /// Find the line of the file entry for the given file path.
+    fn find_entry(&self, file_path: &str, options: &LookupOptions) -> Option<&'a str> {
+        if let Some(index) = self.source_file_index.get(file_path) {
+            return Some(self.source_file_entries[index]);
+        }
+
+        let normalization_kind = options.normalization_kind();
+        if normalization_kind != 0 {
+            let index = self.normalized_source_file_indexes[normalization_kind].get_or_init(|| {
+                self.source_file_entries
+                    .iter()
+                    .enumerate()
+                    .map(|(index, line)| (index, entry_path(line)))
+                    .filter(|(index, path)| self.source_file_index.get(path) == Some(*index))
+                    .map(|(index, path)| (options.normalize_path(path), index))
+                    .collect()
+            });
+            if let Some(&index) = index.get(&options.normalize_path(file_path)) {
+                return Some(self.source_file_entries[index]);
+            }
+        }
+
+        let min_matching_components = options.suffix_match_min_components?;
+        match self
+            .suffix_match_candidate_indexes(file_path, min_matching_components)
+            .as_slice()
+        {
+            [(_, best), (_, next), ..] if best == next => None,
+            [(index, _), ..] => Some(self.source_file_entries[*index]),
+            [] => None,
+        }
+    }
+
+    /// Evaluate the variable `var_name` without a file entry: references to
+    /// var1, ..., var10, %targ%, unknown variables and recursive references are
+    /// left in the result as `%name%`. Returns `None` if the variable doesn't exist.
+    fn expand_template_without_entry(&self, var_name: &str) -> Option<String> {
+        if !self
+            .var_fields
+            .contains_key(CaseInsensitiveStr::new(var_name))
+        {
+            return None;
+        }
+        self.expand_without_entry_impl(&var_name.to_ascii_lowercase(), &EvalStack::Empty)
+            .ok()
+    }
+
+    fn expand_without_entry_impl(
+        &self,
+        var_name: &str,
+        eval_stack: &EvalStack,
+    ) -> Result<String, EvalError> {
+        let node = match self.var_fields.get(CaseInsensitiveStr::new(var_name)) {
+            Some(field) if !eval_stack.contains(var_name) => field.node(),
+            _ => return Ok(format!("%{}%", var_name)),
+        };
+        let eval_stack = EvalStack::WithAddedVar(var_name, eval_stack);
+        node.eval(
+            &mut |name: &str| {
+                self.expand_without_entry_impl(&name.to_ascii_lowercase(), &eval_stack)
+                    .map(Cow::Owned)
+            },
+            &mut |function_name: &str, arg: &str| Ok(Some(format!("%{}%({})", function_name, arg))),
+        )
+        .map(Cow::into_owned)
+    }
+
+    /// Compute the set of variables whose values are the same for every file
+    /// entry, i.e. which don't depend on var1, ..., var10 or on %targ%, directly
+    /// or indirectly. Variables which use %fnvar% are conservatively treated as
+    /// entry-dependent.
+    fn entry_independent_vars(&self) -> FxHashSet<String> {
+        let mut memo = FxHashMap::default();
+        for var_name in self.var_fields.keys() {
+            let var_name = var_name.as_str().to_ascii_lowercase();
+            self.is_entry_independent(&var_name, &mut memo, &mut FxHashSet::default());
+        }
+        memo.into_iter()
+            .filter_map(|(var_name, independent)| if independent { Some(var_name) } else { None })
+            .collect()
+    }
+
+    fn is_entry_independent(
+        &self,
+        var_name: &str,
+        memo: &mut FxHashMap<String, bool>,
+        in_progress: &mut FxHashSet<String>,
+    ) -> bool {
+        if let Some(&independent) = memo.get(var_name) {
+            return independent;
+        }
+        if var_name == "targ" || is_entry_var_name(var_name) || in_progress.contains(var_name) {
+            return false;
+        }
+        let node = match self.var_fields.get(CaseInsensitiveStr::new(var_name)) {
+            Some(field) => field.node(),
+            None => return false,
+        };
+
+        in_progress.insert(var_name.to_string());
+        let mut referenced_vars = Vec::new();
+        node.collect_variables(&mut referenced_vars);
+        let independent = !node.contains_fnvar()
+            && referenced_vars.iter().all(|referenced_var| {
+                self.is_entry_independent(&referenced_var.to_ascii_lowercase(), memo, in_progress)
+            });
+        in_progress.remove(var_name);
+
+        memo.insert(var_name.to_string(), independent);
+        independent
+    }
+
+    fn evaluate_optional_field(
+        &self,
+        var_name: &str,
+        var_map: &mut EvalVarMap,
+        options: &EvalOptions,
+        cache: &mut SharedEvalCache,
     ) -> Result<Option<String>, EvalError> {
-        let var_name = var_name.to_ascii_lowercase();
-        if !self.var_fields.contains_key(&var_name) {
+        if !self
+            .var_fields
+            .contains_key(CaseInsensitiveStr::new(var_name))
+        {
             return Ok(None);
         }
-        let val = self.eval_impl(var_name, var_map, &EvalStack::Empty)?;
+        let var_name = var_name.to_ascii_lowercase();
+        let val = self.eval_impl(var_name, var_map, options, cache, &EvalStack::Empty)?;
         Ok(Some(val))
     }
 
@@ -364,235 +1882,1671 @@ impl<'a> SrcSrvStream<'a> {
         &self,
         var_name: &str,
         var_map: &mut EvalVarMap,
+        options: &EvalOptions,
+        cache: &mut SharedEvalCache,
     ) -> Result<String, EvalError> {
         let var_name = var_name.to_ascii_lowercase();
-        self.eval_impl(var_name, var_map, &EvalStack::Empty)
+        self.eval_impl(var_name, var_map, options, cache, &EvalStack::Empty)
     }
 
     fn eval_impl(
         &self,
         var_name: String,
         var_map: &mut EvalVarMap,
+        options: &EvalOptions,
+        cache: &mut SharedEvalCache,
         eval_stack: &EvalStack,
     ) -> Result<String, EvalError> {
         if let Some(val) = var_map.get(&var_name) {
+            let source = if is_entry_var_name(&var_name) {
+                EvalTraceSource::Entry
+            } else if var_name == "targ" {
+                EvalTraceSource::ExtractionBasePath
+            } else if options.vars.contains_key(&var_name) {
+                EvalTraceSource::Override
+            } else {
+                EvalTraceSource::Reused
+            };
+            cache.trace(eval_stack, &var_name, source, None, Some(val));
             return Ok(val.clone());
         }
+        if let Some(val) = cache.values.get(&var_name).cloned() {
+            cache.trace(
+                eval_stack,
+                &var_name,
+                EvalTraceSource::Reused,
+                None,
+                Some(&val),
+            );
+            var_map.insert(var_name, val.clone());
+            return Ok(val);
+        }
         if eval_stack.contains(&var_name) {
+            cache.trace(
+                eval_stack,
+                &var_name,
+                EvalTraceSource::Recursion,
+                None,
+                None,
+            );
             return Err(EvalError::Recursion(var_name));
         }
 
-        let node = match self.var_fields.get(&var_name) {
-            Some((_, node)) => node,
-            None => return Err(EvalError::UnknownVariable(var_name)),
-        };
+        let (template, node) = match self.var_fields.get(CaseInsensitiveStr::new(&var_name)) {
+            Some(field) => (field.raw, field.node()),
+            None => {
+                let result = match options.unknown_variable_policy {
+                    UnknownVariablePolicy::Error => {
+                        Err(EvalError::UnknownVariable(var_name.clone()))
+                    }
+                    UnknownVariablePolicy::Empty => Ok(String::new()),
+                    UnknownVariablePolicy::KeepLiteral => Ok(format!("%{}%", var_name)),
+                };
+                let value = result.as_deref().ok();
+                cache.trace(eval_stack, &var_name, EvalTraceSource::Unknown, None, value);
+                return result;
+            }
+        };
+
+        let step_index = cache.trace(
+            eval_stack,
+            &var_name,
+            EvalTraceSource::Template,
+            Some(template),
+            None,
+        );
+        let eval_stack = EvalStack::WithAddedVar(&var_name, eval_stack);
+        let mut get_var = |var_name: &str| {
+            self.eval_impl(
+                var_name.to_ascii_lowercase(),
+                var_map,
+                options,
+                cache,
+                &eval_stack,
+            )
+            .map(Cow::Owned)
+        };
+        let mut call_function =
+            |function_name: &str, arg: &str| call_function(function_name, arg, options);
+        let eval_val = node.eval(&mut get_var, &mut call_function)?.into_owned();
+        cache.set_trace_value(step_index, &eval_val);
+        if cache.entry_independent_vars.contains(&var_name) {
+            cache.values.insert(var_name.clone(), eval_val.clone());
+        }
+        var_map.insert(var_name, eval_val.clone());
+
+        Ok(eval_val)
+    }
+}
+
+/// Parse the evaluated `SRCSRVENV` value: `name=value` pairs separated by
+/// backspace characters.
+pub(crate) fn parse_env(env: &str) -> HashMap<String, String> {
+    env.split('\x08')
+        .filter_map(|s| s.split_once('='))
+        .map(|(envname, envval)| (envname.to_owned(), envval.to_owned()))
+        .collect()
+}
+
+/// Evaluate a srcsrv template, such as a candidate `SRCSRVTRG` value, without
+/// a stream. `vars` supplies the values of the referenced variables, including
+/// `var1`, ..., `var10` and `targ`. Variable names are case-insensitive.
+///
+/// The values in `vars` are used as they are; they are not expanded as
+/// templates themselves. The built-in functions `%fnvar%`, `%fnbksl%` and
+/// `%fnfile%` are supported; other functions fail with
+/// [`EvalError::UnknownFunction`].
+///
+/// ```
+/// use srcsrv::{evaluate_template, EvalVarMap};
+///
+/// # fn wrapper() -> std::result::Result<(), srcsrv::EvalError> {
+/// let mut vars = EvalVarMap::new();
+/// vars.insert("hgserver".to_string(), "https://hg.mozilla.org/mozilla-central".to_string());
+/// vars.insert("var2".to_string(), "mozglue/build/SSE.cpp".to_string());
+/// vars.insert("var3".to_string(), "1706d4d54ec68fae1280305b70a02cb24c16ff68".to_string());
+/// assert_eq!(
+///     evaluate_template("%hgserver%/raw-file/%var3%/%var2%", &vars)?,
+///     "https://hg.mozilla.org/mozilla-central/raw-file/1706d4d54ec68fae1280305b70a02cb24c16ff68/mozglue/build/SSE.cpp"
+/// );
+/// # Ok(())
+/// # }
+/// ```
+pub fn evaluate_template(template: &str, vars: &EvalVarMap) -> Result<String, EvalError> {
+    let node = AstNode::parse(template)?;
+    let mut get_var = |var_name: &str| {
+        vars.get(&var_name.to_ascii_lowercase())
+            .or_else(|| {
+                vars.iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case(var_name))
+                    .map(|(_, value)| value)
+            })
+            .map(|value| Cow::Borrowed(value.as_str()))
+            .ok_or_else(|| EvalError::UnknownVariable(var_name.to_ascii_lowercase()))
+    };
+    node.eval(&mut get_var, &mut |function_name: &str, _arg: &str| {
+        Err(EvalError::UnknownFunction(function_name.to_string()))
+    })
+    .map(Cow::into_owned)
+}
+
+/// Call the function `function_name` which is not built in, with the evaluated
+/// argument `arg`. `Ok(None)` means that the call should be treated as a
+/// variable reference, see [`UnknownFunctionPolicy::Variable`].
+fn call_function(
+    function_name: &str,
+    arg: &str,
+    options: &EvalOptions,
+) -> Result<Option<String>, EvalError> {
+    if let Some(function) = options.functions.get(&function_name.to_ascii_lowercase()) {
+        return function.call(arg).map(Some);
+    }
+    match options.unknown_function_policy {
+        UnknownFunctionPolicy::Error => Err(EvalError::UnknownFunction(function_name.to_string())),
+        UnknownFunctionPolicy::Literal => Ok(Some(format!("%{}%({})", function_name, arg))),
+        UnknownFunctionPolicy::Variable => Ok(None),
+    }
+}
+
+/// If `path` is a UNC path (`\\server\share\file`) or an absolute path with
+/// a drive letter (`X:\dir\file`), return a relative version of it
+/// (`server\share\file` or `X\dir\file`).
+fn copyable_file_relative_path(path: &str) -> Option<String> {
+    if let Some(unc_path) = path.strip_prefix("\\\\") {
+        if !unc_path.is_empty() && !unc_path.starts_with('\\') {
+            return Some(unc_path.to_string());
+        }
+        return None;
+    }
+    let bytes = path.as_bytes();
+    if bytes.len() > 3 && bytes[0].is_ascii_alphabetic() && &bytes[1..3] == b":\\" {
+        return Some(format!("{}{}", &path[..1], &path[2..]));
+    }
+    None
+}
+
+/// Whether `path` is `base` or a path below it. Windows paths are case
+/// insensitive, and `C:\src2` is not below `C:\src`.
+fn is_path_below(path: &str, base: &str) -> bool {
+    let base = base.trim_end_matches(['\\', '/']);
+    if base.is_empty() || path.len() < base.len() {
+        return false;
+    }
+    let (prefix, rest) = path.as_bytes().split_at(base.len());
+    prefix.eq_ignore_ascii_case(base.as_bytes())
+        && matches!(rest.first(), None | Some(b'\\') | Some(b'/'))
+}
+
+/// Whether `var_name` is one of the per-entry variables var1, ..., var10.
+fn is_entry_var_name(var_name: &str) -> bool {
+    match var_name.strip_prefix("var") {
+        Some(index) => !index.is_empty() && index.bytes().all(|b| b.is_ascii_digit()),
+        None => false,
+    }
+}
+
+/// Add the entry `index` with the original path `path` to `index_shard`,
+/// following `policy` if the path already has an entry. Returns the index of
+/// the entry which the path had before, if any.
+fn index_entry<'a>(
+    index_shard: &mut FxHashMap<&'a CaseInsensitiveStr, usize>,
+    collected_entry_indexes: &mut FxHashMap<&'a CaseInsensitiveStr, Vec<usize>>,
+    path: &'a str,
+    index: usize,
+    policy: DuplicatePolicy,
+) -> Option<usize> {
+    match index_shard.entry(CaseInsensitiveStr::new(path)) {
+        Entry::Vacant(entry) => {
+            entry.insert(index);
+            None
+        }
+        Entry::Occupied(mut entry) => {
+            let previous = *entry.get();
+            match policy {
+                DuplicatePolicy::FirstWins => {}
+                DuplicatePolicy::CollectAll => {
+                    collected_entry_indexes
+                        .entry(*entry.key())
+                        .or_insert_with(|| vec![previous])
+                        .push(index);
+                    entry.insert(index);
+                }
+                _ => {
+                    entry.insert(index);
+                }
+            }
+            Some(previous)
+        }
+    }
+}
+
+/// Split the line of a file entry into the values of var1, ..., var10.
+pub(crate) fn split_entry(line: &str) -> Vec<&str> {
+    line.splitn(10, '*').collect()
+}
+
+/// The original path of a file entry, i.e. the value of var1.
+pub(crate) fn entry_path(line: &str) -> &str {
+    line.split_once('*').map_or(line, |(path, _)| path)
+}
+
+/// The value of the per-entry variable `var_name` (var1, ..., var10) of `entry`.
+fn entry_var<'e>(entry: &[&'e str], var_name: &str) -> Option<&'e str> {
+    let index: usize = var_name.get(3..)?.parse().ok()?;
+    entry.get(index.checked_sub(1)?).copied()
+}
+
+/// Evaluated values of variables which are the same for every file entry, so
+/// that they can be shared across lookups.
+#[derive(Default)]
+struct SharedEvalCache {
+    /// lowercase names of the variables which may be cached
+    entry_independent_vars: FxHashSet<String>,
+    /// lowercase variable name -> evaluated value
+    values: EvalVarMap,
+    /// The steps of the evaluation, if tracing is enabled
+    trace: Option<Vec<EvalTraceStep>>,
+}
+
+impl SharedEvalCache {
+    /// Record a trace step if tracing is enabled, and return its index.
+    fn trace(
+        &mut self,
+        eval_stack: &EvalStack,
+        var_name: &str,
+        source: EvalTraceSource,
+        template: Option<&str>,
+        value: Option<&str>,
+    ) -> Option<usize> {
+        let steps = self.trace.as_mut()?;
+        steps.push(EvalTraceStep {
+            depth: eval_stack.depth(),
+            var_name: var_name.to_string(),
+            source,
+            template: template.map(ToString::to_string),
+            value: value.map(ToString::to_string),
+        });
+        Some(steps.len() - 1)
+    }
+
+    /// Set the value of a trace step which was recorded before its value was known.
+    fn set_trace_value(&mut self, step_index: Option<usize>, value: &str) {
+        if let (Some(steps), Some(index)) = (self.trace.as_mut(), step_index) {
+            steps[index].value = Some(value.to_string());
+        }
+    }
+}
+
+/// An iterator over the lines of a stream. Lines can be terminated by "\r\n",
+/// "\n", or a bare "\r". The terminators are not included in the lines.
+#[derive(Clone)]
+struct Lines<'a>(&'a str);
+
+impl<'a> Iterator for Lines<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        if self.0.is_empty() {
+            return None;
+        }
+        let (line, rest) = match memchr::memchr2(b'\r', b'\n', self.0.as_bytes()) {
+            Some(pos) if self.0[pos..].starts_with("\r\n") => (&self.0[..pos], &self.0[pos + 2..]),
+            Some(pos) => (&self.0[..pos], &self.0[pos + 1..]),
+            None => (self.0, ""),
+        };
+        self.0 = rest;
+        Some(line)
+    }
+}
+
+/// Iterates over the lines of the stream and keeps track of line numbers.
+#[derive(Clone)]
+pub(crate) struct LineReader<'a> {
+    /// The text before the first line, i.e. a byte order mark or nothing.
+    prefix: &'a str,
+    /// The whole stream, without the prefix.
+    text: &'a str,
+    lines: Lines<'a>,
+    /// The 1-based line number of the line which was returned last.
+    line_number: usize,
+    skip_blank_lines: bool,
+}
+
+impl<'a> LineReader<'a> {
+    /// Create a reader for the lines of `stream`, which needs to be valid
+    /// UTF-8. A leading byte order mark is skipped.
+    pub(crate) fn new(stream: &'a [u8], options: &ParseOptions) -> Result<Self, ParseError> {
+        let stream = std::str::from_utf8(stream).map_err(|_| ParseError::InvalidUtf8)?;
+        // Some indexing scripts write a byte order mark.
+        let text = stream.strip_prefix('\u{feff}').unwrap_or(stream);
+        Ok(LineReader {
+            prefix: &stream[..stream.len() - text.len()],
+            text,
+            lines: Lines(text),
+            line_number: 0,
+            skip_blank_lines: options.lenient,
+        })
+    }
+
+    /// Returns the next line with its 1-based line number.
+    pub(crate) fn next_line(&mut self) -> Result<(usize, &'a str), ParseError> {
+        loop {
+            let line = self.lines.next().ok_or(ParseError::UnexpectedEof)?;
+            self.line_number += 1;
+            if self.skip_blank_lines && line.trim().is_empty() {
+                continue;
+            }
+            return Ok((self.line_number, line));
+        }
+    }
+
+    /// The rest of the stream after `line`, which must have been returned by
+    /// this reader, starting with the line terminator of `line`.
+    pub(crate) fn text_after(&self, line: &'a str) -> &'a str {
+        let end = line.as_ptr() as usize - self.text.as_ptr() as usize + line.len();
+        &self.text[end..]
+    }
+
+    /// The line terminator which follows `line`, or `\r\n` if `line` is the
+    /// last line.
+    pub(crate) fn line_ending_after(&self, line: &'a str) -> &'static str {
+        let rest = self.text_after(line);
+        if rest.starts_with("\r\n") || rest.is_empty() {
+            "\r\n"
+        } else if rest.starts_with('\r') {
+            "\r"
+        } else {
+            "\n"
+        }
+    }
+
+    /// Check the line which ended the source files section, or `None` if the
+    /// stream ended without an end marker and that's allowed. Any non-empty
+    /// lines after the end marker are recorded as a warning.
+    pub(crate) fn check_end_marker(
+        &mut self,
+        end_line: Option<(usize, &str)>,
+        warnings: &mut Vec<ParseWarning>,
+    ) -> Result<(), ParseError> {
+        // Stop at SRCSRV: end ------------------------------------------------
+        let (line_number, line) = match end_line {
+            Some(end_line) => end_line,
+            None => {
+                warnings.push(ParseWarning::MissingEndMarker);
+                return Ok(());
+            }
+        };
+        if !line.starts_with("SRCSRV: end --") {
+            return Err(ParseError::MissingTerminationLine {
+                line_number,
+                line: line.to_string(),
+            });
+        }
+
+        let trailing_line = self.lines.by_ref().position(|line| {
+            !line
+                .trim_matches(|c: char| c.is_whitespace() || c == '\0')
+                .is_empty()
+        });
+        if let Some(offset) = trailing_line {
+            warnings.push(ParseWarning::TrailingData {
+                line_number: line_number + offset + 1,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// The parsed ini section of a stream.
+pub(crate) struct IniSection<'a> {
+    /// the line which starts the ini section
+    pub(crate) header: &'a str,
+    /// 1, 2 or 3, based on the VERSION={} field
+    pub(crate) version: u8,
+    /// field name -> field value
+    pub(crate) fields: FxHashMap<&'a CaseInsensitiveStr, &'a str>,
+    /// (field name, field value) for each line of the ini section, in stream order
+    pub(crate) lines: Vec<(&'a str, &'a str)>,
+}
+
+/// Parse the ini section, and return it together with the line which ended
+/// it and that line's 1-based line number.
+pub(crate) fn parse_ini_section<'a>(
+    lines: &mut LineReader<'a>,
+    options: &ParseOptions,
+    warnings: &mut Vec<ParseWarning>,
+) -> Result<(IniSection<'a>, (usize, &'a str)), ParseError> {
+    // Parse section SRCSRV: ini ------------------------------------------------
+    let (line_number, header) = lines.next_line()?;
+    if !header.starts_with("SRCSRV: ini --") {
+        return Err(ParseError::MissingIniSection {
+            line_number,
+            line: header.to_string(),
+        });
+    }
+
+    let mut fields = FxHashMap::default();
+    let mut ini_lines = Vec::new();
+    let end_line = loop {
+        let (line_number, line) = lines.next_line()?;
+        if line.starts_with("SRCSRV:") {
+            break (line_number, line);
+        }
+
+        match line.split_once('=') {
+            Some((name, value)) => {
+                fields.insert(CaseInsensitiveStr::new(name), value);
+                ini_lines.push((name, value));
+            }
+            None => skip_line(
+                options,
+                warnings,
+                line_number,
+                line,
+                ParseError::MissingEquals {
+                    line_number,
+                    line: line.to_string(),
+                },
+            )?,
+        }
+    };
+
+    let version = match fields.get(CaseInsensitiveStr::new("VERSION")) {
+        Some(&"1") => 1,
+        Some(&"2") => 2,
+        Some(&"3") => 3,
+        Some(v) => return Err(ParseError::UnrecognizedVersion(v.to_string())),
+        None => return Err(ParseError::MissingVersion),
+    };
+    let ini = IniSection {
+        header,
+        version,
+        fields,
+        lines: ini_lines,
+    };
+    Ok((ini, end_line))
+}
+
+/// Skip a line which could not be parsed and record a warning if we're in
+/// lenient mode, otherwise fail with `reason`.
+fn skip_line(
+    options: &ParseOptions,
+    warnings: &mut Vec<ParseWarning>,
+    line_number: usize,
+    line: &str,
+    reason: ParseError,
+) -> Result<(), ParseError> {
+    if !options.lenient {
+        return Err(reason);
+    }
+    warnings.push(ParseWarning::SkippedLine {
+        line_number,
+        line: line.to_string(),
+        reason,
+    });
+    Ok(())
+}
+
+enum EvalStack<'a> {
+    Empty,
+    WithAddedVar(&'a str, &'a EvalStack<'a>),
+}
+
+impl<'a> EvalStack<'a> {
+    pub fn contains(&self, s: &str) -> bool {
+        match self {
+            EvalStack::Empty => false,
+            EvalStack::WithAddedVar(var_name, rest) => *var_name == s || rest.contains(s),
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        match self {
+            EvalStack::Empty => 0,
+            EvalStack::WithAddedVar(_, rest) => 1 + rest.depth(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{
+        copyable_file_relative_path, is_path_below, AstNode, ContentEncoding, Duplicate,
+        DuplicatePolicy, EvalError, EvalOptions, EvalVarMap, LookupOptions, ParseError,
+        ParseOptions, ParseWarning, RetrievalKind, SourceRetrievalMethod, SrcSrvStream,
+        SuffixMatchCandidate, TemplateError, UnknownFunctionPolicy, UnknownVariablePolicy, VcsKind,
+    };
+
+    #[test]
+    fn firefox() {
+        let stream = r#"SRCSRV: ini ------------------------------------------------
+VERSION=2
+INDEXVERSION=2
+VERCTRL=http
+SRCSRV: variables ------------------------------------------
+HGSERVER=https://hg.mozilla.org/mozilla-central
+SRCSRVVERCTRL=http
+HTTP_EXTRACT_TARGET=%hgserver%/raw-file/%var3%/%var2%
+SRCSRVTRG=%http_extract_target%
+SRCSRV: source files ---------------------------------------
+/builds/worker/checkouts/gecko/mozglue/build/SSE.cpp*mozglue/build/SSE.cpp*1706d4d54ec68fae1280305b70a02cb24c16ff68
+/builds/worker/checkouts/gecko/memory/build/mozjemalloc.cpp*memory/build/mozjemalloc.cpp*1706d4d54ec68fae1280305b70a02cb24c16ff68
+/builds/worker/checkouts/gecko/vs2017_15.8.4/VC/include/algorithm*vs2017_15.8.4/VC/include/algorithm*1706d4d54ec68fae1280305b70a02cb24c16ff68
+/builds/worker/checkouts/gecko/mozglue/baseprofiler/core/ProfilerBacktrace.cpp*mozglue/baseprofiler/core/ProfilerBacktrace.cpp*1706d4d54ec68fae1280305b70a02cb24c16ff68
+/builds/worker/workspace/obj-build/dist/include/mozilla/IntegerRange.h*mfbt/IntegerRange.h*1706d4d54ec68fae1280305b70a02cb24c16ff68
+SRCSRV: end ------------------------------------------------
+
+
+"#;
+        let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
+        assert_eq!(stream.version(), 2);
+        assert_eq!(stream.datetime(), None);
+        assert_eq!(stream.version_control_description(), Some("http"));
+        assert_eq!(
+            stream
+                .source_for_path(
+                    r#"/builds/worker/checkouts/gecko/mozglue/baseprofiler/core/ProfilerBacktrace.cpp"#,
+                    r#"C:\Debugger\Cached Sources"#
+                )
+                .unwrap().unwrap(),
+            SourceRetrievalMethod::Download {
+                url: "https://hg.mozilla.org/mozilla-central/raw-file/1706d4d54ec68fae1280305b70a02cb24c16ff68/mozglue/baseprofiler/core/ProfilerBacktrace.cpp".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn chrome() {
+        // From https://chromium-browser-symsrv.commondatastorage.googleapis.com/chrome.dll.pdb/5D664C4A228FA9804C4C44205044422E1/chrome.dll.pdb
+        let stream = r#"SRCSRV: ini ------------------------------------------------
+VERSION=1
+INDEXVERSION=2
+VERCTRL=Subversion
+DATETIME=Fri Jul 30 14:11:46 2021
+SRCSRV: variables ------------------------------------------
+SRC_EXTRACT_TARGET_DIR=%targ%\%fnbksl%(%var2%)\%var3%
+SRC_EXTRACT_TARGET=%SRC_EXTRACT_TARGET_DIR%\%fnfile%(%var1%)
+SRC_EXTRACT_CMD=cmd /c "mkdir "%SRC_EXTRACT_TARGET_DIR%" & python -c "import urllib2, base64;url = \"%var4%\";u = urllib2.urlopen(url);open(r\"%SRC_EXTRACT_TARGET%\", \"wb\").write(%var5%(u.read()))"
+SRCSRVTRG=%SRC_EXTRACT_TARGET%
+SRCSRVCMD=%SRC_EXTRACT_CMD%
+SRCSRV: source files ---------------------------------------
+c:\b\s\w\ir\cache\builder\src\third_party\pdfium\core\fdrm\fx_crypt.cpp*core/fdrm/fx_crypt.cpp*dab1161c861cc239e48a17e1a5d729aa12785a53*https://pdfium.googlesource.com/pdfium.git/+/dab1161c861cc239e48a17e1a5d729aa12785a53/core/fdrm/fx_crypt.cpp?format=TEXT*base64.b64decode
+c:\b\s\w\ir\cache\builder\src\third_party\pdfium\core\fdrm\fx_crypt_aes.cpp*core/fdrm/fx_crypt_aes.cpp*dab1161c861cc239e48a17e1a5d729aa12785a53*https://pdfium.googlesource.com/pdfium.git/+/dab1161c861cc239e48a17e1a5d729aa12785a53/core/fdrm/fx_crypt_aes.cpp?format=TEXT*base64.b64decode
+SRCSRV: end ------------------------------------------------"#;
+        let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
+        assert_eq!(stream.version(), 1);
+        assert_eq!(stream.datetime(), Some("Fri Jul 30 14:11:46 2021"));
+        assert_eq!(stream.version_control_description(), Some("Subversion"));
+        assert_eq!(stream.vcs_kind(), Some(VcsKind::Svn));
+        let (method, raw_var_values) = stream
+            .source_and_raw_var_values_for_path(
+                r#"c:\b\s\w\ir\cache\builder\src\third_party\pdfium\core\fdrm\fx_crypt.cpp"#,
+                r#"C:\Debugger\Cached Sources"#,
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            method,
+            SourceRetrievalMethod::ExecuteCommand {
+                command: r#"cmd /c "mkdir "C:\Debugger\Cached Sources\core\fdrm\fx_crypt.cpp\dab1161c861cc239e48a17e1a5d729aa12785a53" & python -c "import urllib2, base64;url = \"https://pdfium.googlesource.com/pdfium.git/+/dab1161c861cc239e48a17e1a5d729aa12785a53/core/fdrm/fx_crypt.cpp?format=TEXT\";u = urllib2.urlopen(url);open(r\"C:\Debugger\Cached Sources\core\fdrm\fx_crypt.cpp\dab1161c861cc239e48a17e1a5d729aa12785a53\fx_crypt.cpp\", \"wb\").write(base64.b64decode(u.read()))""#.to_string(),
+                env: HashMap::new(),
+                target_path: r#"C:\Debugger\Cached Sources\core\fdrm\fx_crypt.cpp\dab1161c861cc239e48a17e1a5d729aa12785a53\fx_crypt.cpp"#.to_string(),
+                version_ctrl: None,
+                error_persistence_version_control: None,
+            }
+        );
+        assert_eq!(
+            stream
+                .source_for_path_with_vars(
+                    r#"c:\b\s\w\ir\cache\builder\src\third_party\pdfium\core\fdrm\fx_crypt.cpp"#,
+                    r#"C:\Debugger\Cached Sources"#,
+                    &EvalOptions::new().recognize_commands(true),
+                )
+                .unwrap().unwrap(),
+            SourceRetrievalMethod::DownloadWithDecode {
+                url: "https://pdfium.googlesource.com/pdfium.git/+/dab1161c861cc239e48a17e1a5d729aa12785a53/core/fdrm/fx_crypt.cpp?format=TEXT".to_string(),
+                encoding: ContentEncoding::Base64,
+            }
+        );
+        assert_eq!(
+            raw_var_values.get("srcsrvcmd").map(String::as_str),
+            Some(
+                r#"cmd /c "mkdir "C:\Debugger\Cached Sources\core\fdrm\fx_crypt.cpp\dab1161c861cc239e48a17e1a5d729aa12785a53" & python -c "import urllib2, base64;url = \"https://pdfium.googlesource.com/pdfium.git/+/dab1161c861cc239e48a17e1a5d729aa12785a53/core/fdrm/fx_crypt.cpp?format=TEXT\";u = urllib2.urlopen(url);open(r\"C:\Debugger\Cached Sources\core\fdrm\fx_crypt.cpp\dab1161c861cc239e48a17e1a5d729aa12785a53\fx_crypt.cpp\", \"wb\").write(base64.b64decode(u.read()))""#
+            )
+        );
+        assert_eq!(
+            raw_var_values.get("srcsrvtrg").map(String::as_str),
+            Some(
+                r#"C:\Debugger\Cached Sources\core\fdrm\fx_crypt.cpp\dab1161c861cc239e48a17e1a5d729aa12785a53\fx_crypt.cpp"#
+            )
+        );
+    }
+
+    #[test]
+    fn team_foundation() {
+        // From https://github.com/microsoft/perfview/blob/5c9f6059f54db41b4ac5c4fc8f57261779634489/src/TraceEvent/Symbols/NativeSymbolModule.cs#L776
+        let stream = r#"SRCSRV: ini ------------------------------------------------
+VERSION=3
+INDEXVERSION=2
+VERCTRL=Team Foundation Server
+DATETIME=Thu Mar 10 16:15:55 2016
+SRCSRV: variables ------------------------------------------
+TFS_EXTRACT_CMD=tf.exe view /version:%var4% /noprompt "$%var3%" /server:%fnvar%(%var2%) /output:%srcsrvtrg%
+TFS_EXTRACT_TARGET=%targ%\%var2%%fnbksl%(%var3%)\%var4%\%fnfile%(%var1%)
+VSTFDEVDIV_DEVDIV2=http://vstfdevdiv.redmond.corp.microsoft.com:8080/DevDiv2
+SRCSRVVERCTRL=tfs
+SRCSRVERRDESC=access
+SRCSRVERRVAR=var2
+SRCSRVTRG=%TFS_extract_target%
+SRCSRVCMD=%TFS_extract_cmd%
+SRCSRV: source files ---------------------------------------
+f:\dd\externalapis\legacy\vctools\vc12\inc\cvconst.h*VSTFDEVDIV_DEVDIV2*/DevDiv/Fx/Rel/NetFxRel3Stage/externalapis/legacy/vctools/vc12/inc/cvconst.h*1363200
+f:\dd\externalapis\legacy\vctools\vc12\inc\cvinfo.h*VSTFDEVDIV_DEVDIV2*/DevDiv/Fx/Rel/NetFxRel3Stage/externalapis/legacy/vctools/vc12/inc/cvinfo.h*1363200
+f:\dd\externalapis\legacy\vctools\vc12\inc\vc\ammintrin.h*VSTFDEVDIV_DEVDIV2*/DevDiv/Fx/Rel/NetFxRel3Stage/externalapis/legacy/vctools/vc12/inc/vc/ammintrin.h*1363200
+SRCSRV: end ------------------------------------------------"#;
+        let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
+        assert_eq!(stream.version(), 3);
+        assert_eq!(stream.datetime(), Some("Thu Mar 10 16:15:55 2016"));
+        assert_eq!(
+            stream.version_control_description(),
+            Some("Team Foundation Server")
+        );
+        assert_eq!(stream.vcs_kind(), Some(VcsKind::Tfs));
+        assert_eq!(
+            stream
+                .source_for_path(
+                    r#"F:\dd\externalapis\legacy\vctools\vc12\inc\cvinfo.h"#,
+                    r#"C:\Debugger\Cached Sources"#,
+                )
+                .unwrap().unwrap(),
+                SourceRetrievalMethod::ExecuteCommand {
+                    command: r#"tf.exe view /version:1363200 /noprompt "$/DevDiv/Fx/Rel/NetFxRel3Stage/externalapis/legacy/vctools/vc12/inc/cvinfo.h" /server:http://vstfdevdiv.redmond.corp.microsoft.com:8080/DevDiv2 /output:C:\Debugger\Cached Sources\VSTFDEVDIV_DEVDIV2\DevDiv\Fx\Rel\NetFxRel3Stage\externalapis\legacy\vctools\vc12\inc\cvinfo.h\1363200\cvinfo.h"#.to_string(),
+                    env: HashMap::new(),
+                    version_ctrl: Some("tfs".to_string()),
+                    target_path: r#"C:\Debugger\Cached Sources\VSTFDEVDIV_DEVDIV2\DevDiv\Fx\Rel\NetFxRel3Stage\externalapis\legacy\vctools\vc12\inc\cvinfo.h\1363200\cvinfo.h"#.to_string(),
+                    error_persistence_version_control: Some("VSTFDEVDIV_DEVDIV2".to_string()),
+                }
+        );
+        assert_eq!(
+            stream
+                .source_for_path_with_vars(
+                    r#"F:\dd\externalapis\legacy\vctools\vc12\inc\cvinfo.h"#,
+                    r#"C:\Debugger\Cached Sources"#,
+                    &EvalOptions::new().recognize_commands(true),
+                )
+                .unwrap().unwrap(),
+                SourceRetrievalMethod::TfsItem {
+                    server: "http://vstfdevdiv.redmond.corp.microsoft.com:8080/DevDiv2".to_string(),
+                    item_path: "$/DevDiv/Fx/Rel/NetFxRel3Stage/externalapis/legacy/vctools/vc12/inc/cvinfo.h".to_string(),
+                    version: "1363200".to_string(),
+                    target_path: r#"C:\Debugger\Cached Sources\VSTFDEVDIV_DEVDIV2\DevDiv\Fx\Rel\NetFxRel3Stage\externalapis\legacy\vctools\vc12\inc\cvinfo.h\1363200\cvinfo.h"#.to_string(),
+                    env: HashMap::new(),
+                    error_persistence_version_control: Some("VSTFDEVDIV_DEVDIV2".to_string()),
+                }
+        );
+    }
+
+    #[test]
+    fn renderdoc() {
+        // From https://renderdoc.org/symbols/renderdoc.pdb/6D1DFFC4DC524537962CCABC000820641/renderdoc.pd_
+        let stream = r#"SRCSRV: ini ------------------------------------------------
+VERSION=2
+VERCTRL=http
+SRCSRV: variables ------------------------------------------
+HTTP_ALIAS=https://raw.githubusercontent.com/baldurk/renderdoc/v1.15/
+HTTP_EXTRACT_TARGET=%HTTP_ALIAS%%var2%
+SRCSRVTRG=%HTTP_EXTRACT_TARGET%
+SRCSRV: source files ---------------------------------------
+C:\build\renderdoc\qrenderdoc\Code\BufferFormatter.cpp*qrenderdoc/Code/BufferFormatter.cpp
+C:\build\renderdoc\qrenderdoc\Windows\Dialogs\AnalyticsConfirmDialog.cpp*qrenderdoc/Windows/Dialogs/AnalyticsConfirmDialog.cpp
+C:\build\renderdoc\renderdoc\data\glsl\gl_texsample.h*renderdoc/data/glsl/gl_texsample.h
+C:\build\renderdoc\renderdoc\driver\d3d12\d3d12_device.cpp*renderdoc/driver/d3d12/d3d12_device.cpp
+C:\build\renderdoc\renderdoc\maths\matrix.cpp*renderdoc/maths/matrix.cpp
+C:\build\renderdoc\util\test\demos\texture_zoo.cpp*util/test/demos/texture_zoo.cpp
+C:\build\renderdoc\Win32\Release\renderdoc_app.h*Win32/Release/renderdoc_app.h
+C:\build\renderdoc\x64\Release\renderdoc_app.h*x64/Release/renderdoc_app.h
+SRCSRV: end ------------------------------------------------"#;
+        let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
+        assert_eq!(stream.version(), 2);
+        assert_eq!(stream.datetime(), None);
+        assert_eq!(stream.version_control_description(), Some("http"));
+        assert_eq!(
+            stream
+                .source_for_path(
+                    r#"C:\build\renderdoc\renderdoc\data\glsl\gl_texsample.h"#,
+                    r#"C:\Debugger\Cached Sources"#,
+                )
+                .unwrap().unwrap(),
+                SourceRetrievalMethod::Download {
+                    url: "https://raw.githubusercontent.com/baldurk/renderdoc/v1.15/renderdoc/data/glsl/gl_texsample.h".to_string(),
+                }
+        );
+    }
+
+    #[test]
+    fn copy_file() {
+        let stream = r#"SRCSRV: ini ------------------------------------------------
+VERSION=1
+SRCSRV: variables ------------------------------------------
+SRCSRVTRG=\\%var2%\src\%var3%
+SRCSRV: source files ---------------------------------------
+d:\build\foo.cpp*buildserver*foo.cpp
+SRCSRV: end ------------------------------------------------"#;
+        let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
+        assert_eq!(
+            stream
+                .source_for_path(r#"d:\build\foo.cpp"#, r#"C:\Debugger\Cached Sources"#)
+                .unwrap(),
+            Some(SourceRetrievalMethod::CopyFile {
+                source_path: r#"\\buildserver\src\foo.cpp"#.to_string(),
+                target_path: r#"C:\Debugger\Cached Sources\buildserver\src\foo.cpp"#.to_string(),
+            })
+        );
+        assert_eq!(
+            copyable_file_relative_path(r#"X:\src\foo.cpp"#),
+            Some(r#"X\src\foo.cpp"#.to_string())
+        );
+        assert_eq!(copyable_file_relative_path("src/foo.cpp"), None);
+    }
+
+    #[test]
+    fn copy_file_below_base() {
+        let stream = r#"SRCSRV: ini ------------------------------------------------
+VERSION=1
+SRCSRV: variables ------------------------------------------
+SRCSRVTRG=%var2%
+SRCSRV: source files ---------------------------------------
+d:\build\x.cpp*c:\cache\x.cpp
+d:\build\y.cpp*C:\src2\y.cpp
+SRCSRV: end ------------------------------------------------"#;
+        let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
+        // The target is in the cache itself, whatever its case.
+        assert!(matches!(
+            stream.source_for_path(r#"d:\build\x.cpp"#, r#"C:\Cache"#),
+            Ok(Some(SourceRetrievalMethod::Other { .. }))
+        ));
+        // C:\src2 is not below C:\src.
+        assert_eq!(
+            stream
+                .source_for_path(r#"d:\build\y.cpp"#, r#"C:\src"#)
+                .unwrap(),
+            Some(SourceRetrievalMethod::CopyFile {
+                source_path: r#"C:\src2\y.cpp"#.to_string(),
+                target_path: r#"C:\src\C\src2\y.cpp"#.to_string(),
+            })
+        );
+        assert!(is_path_below(r#"C:\src"#, r#"c:\SRC\"#));
+        assert!(!is_path_below(r#"C:\src2"#, r#"C:\src"#));
+    }
+
+    #[test]
+    fn perforce() {
+        let stream = "SRCSRV: ini ------------------------------------------------\r\n\
+                      VERSION=1\r\n\
+                      SRCSRV: variables ------------------------------------------\r\n\
+                      P4_EXTRACT_CMD=p4.exe print -o %srcsrvtrg% -q \"//%var2%#%var3%\"\r\n\
+                      SRCSRVTRG=%targ%\\%var3%\\%fnfile%(%var1%)\r\n\
+                      SRCSRVCMD=%P4_EXTRACT_CMD%\r\n\
+                      SRCSRVENV=P4PORT=perforce:1666\x08P4CLIENT=build\r\n\
+                      SRCSRV: source files ---------------------------------------\r\n\
+                      d:\\game\\main.cpp*depot/game/main.cpp*7\r\n\
+                      SRCSRV: end ------------------------------------------------\r\n";
+        let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
+        assert_eq!(
+            stream
+                .source_for_path_with_vars(
+                    r#"D:\game\main.cpp"#,
+                    r#"C:\Cached Sources"#,
+                    &EvalOptions::new().recognize_commands(true),
+                )
+                .unwrap(),
+            Some(SourceRetrievalMethod::Perforce {
+                port: Some("perforce:1666".to_string()),
+                depot_path: "//depot/game/main.cpp".to_string(),
+                revision: "7".to_string(),
+                target_path: r#"C:\Cached Sources\7\main.cpp"#.to_string(),
+                env: [("P4PORT", "perforce:1666"), ("P4CLIENT", "build")]
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), value.to_string()))
+                    .collect(),
+                error_persistence_version_control: None,
+            })
+        );
+    }
+
+    #[test]
+    fn classify_path() {
+        let stream = r#"SRCSRV: ini ------------------------------------------------
+VERSION=2
+SRCSRV: variables ------------------------------------------
+HTTP_ALIAS=https://example.com/
+SERVER=\\%var3%
+SRCSRVTRG=%fnvar%(%var2%)%var4%
+SRCSRV: source files ---------------------------------------
+c:\build\http.cpp*HTTP_ALIAS*unused*http.cpp
+c:\build\unc.cpp*SERVER*buildserver*\src\unc.cpp
+c:\build\local.cpp*TARG*unused*\local.cpp
+SRCSRV: end ------------------------------------------------"#;
+        let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
+        let expected = [
+            (r#"C:\build\http.cpp"#, Some(RetrievalKind::Download)),
+            (r#"C:\build\unc.cpp"#, Some(RetrievalKind::CopyFile)),
+            (r#"C:\build\local.cpp"#, Some(RetrievalKind::Other)),
+            (r#"C:\build\missing.cpp"#, None),
+        ];
+        for (path, kind) in expected {
+            assert_eq!(stream.classify_path(path), kind);
+            assert_eq!(
+                stream
+                    .source_for_path(path, r#"C:\Debugger\Cached Sources"#)
+                    .unwrap()
+                    .map(|method| method.kind()),
+                kind
+            );
+        }
+
+        let stream = r#"SRCSRV: ini ------------------------------------------------
+VERSION=2
+SRCSRV: variables ------------------------------------------
+SRCSRVTRG=%targ%\%var2%
+SRCSRVCMD=git.exe -C %targ% show %var3%:%var2%
+SRCSRV: source files ---------------------------------------
+c:\build\git.cpp*git.cpp*0123abcd
+SRCSRV: end ------------------------------------------------"#;
+        let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
+        assert_eq!(
+            stream.classify_path(r#"c:\build\git.cpp"#),
+            Some(RetrievalKind::ExecuteCommand)
+        );
+
+        let stream = r#"SRCSRV: ini ------------------------------------------------
+VERSION=2
+SRCSRV: variables ------------------------------------------
+HTTP_CONTENT_ENCODING=base64
+GITILES=https://chromium.googlesource.com/chromium/src/+/%var3%/%var2%?format=TEXT
+SRCSRVTRG=%GITILES%
+SRCSRV: source files ---------------------------------------
+c:\b\s\w\ir\cache\builder\src\base\files\file.cc*base/files/file.cc*0123abcd
+SRCSRV: end ------------------------------------------------"#;
+        let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
+        let path = r#"c:\b\s\w\ir\cache\builder\src\base\files\file.cc"#;
+        let method = stream.source_for_path(path, "").unwrap().unwrap();
+        assert!(matches!(
+            method,
+            SourceRetrievalMethod::DownloadWithDecode { .. }
+        ));
+        assert_eq!(method.kind(), RetrievalKind::Download);
+        assert_eq!(stream.classify_path(path), Some(method.kind()));
+    }
+
+    #[test]
+    fn eval_options() {
+        let stream = r#"SRCSRV: ini ------------------------------------------------
+VERSION=2
+SRCSRV: variables ------------------------------------------
+HGSERVER=https://hg.mozilla.org/mozilla-central
+SRCSRVTRG=%fnvar%(%var4%)/raw-file/%var3%/%var2%
+SRCSRV: source files ---------------------------------------
+/builds/SSE.cpp*mozglue/build/SSE.cpp*1706d4d54ec68fae1280305b70a02cb24c16ff68*HGSERVER
+/builds/Other.cpp*other/Other.cpp*1706d4d54ec68fae1280305b70a02cb24c16ff68*UNDEFINED_SERVER
+SRCSRV: end ------------------------------------------------"#;
+        let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
+        assert_eq!(
+            stream.source_for_path("/builds/Other.cpp", ""),
+            Err(EvalError::UnknownVariable("undefined_server".to_string()))
+        );
+
+        let options = EvalOptions::new()
+            .var("hgserver", "https://mirror.example.com/m-c")
+            .var("Undefined_Server", "https://other.example.com")
+            .var("var2", "ignored");
+        assert_eq!(
+            stream
+                .source_for_path_with_vars("/builds/SSE.cpp", "", &options)
+                .unwrap(),
+            Some(SourceRetrievalMethod::Download {
+                url: "https://mirror.example.com/m-c/raw-file/1706d4d54ec68fae1280305b70a02cb24c16ff68/mozglue/build/SSE.cpp".to_string()
+            })
+        );
+        assert_eq!(
+            stream
+                .source_for_path_with_vars("/builds/Other.cpp", "", &options)
+                .unwrap(),
+            Some(SourceRetrievalMethod::Download {
+                url: "https://other.example.com/raw-file/1706d4d54ec68fae1280305b70a02cb24c16ff68/other/Other.cpp".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn custom_functions() {
+        let stream = r#"SRCSRV: ini ------------------------------------------------
+VERSION=2
+SRCSRV: variables ------------------------------------------
+SRCSRVTRG=https://example.com/%fnLower%(%var2%)
+SRCSRV: source files ---------------------------------------
+c:\build\Foo.cpp*Src/Foo.cpp
+SRCSRV: end ------------------------------------------------"#;
+        let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
+        assert_eq!(
+            stream.source_for_path(r#"c:\build\Foo.cpp"#, ""),
+            Err(EvalError::UnknownFunction("fnLower".to_string()))
+        );
+        let options = EvalOptions::new().function("FNLOWER", |arg| Ok(arg.to_lowercase()));
+        assert_eq!(
+            stream
+                .source_for_path_with_vars(r#"c:\build\Foo.cpp"#, "", &options)
+                .unwrap(),
+            Some(SourceRetrievalMethod::Download {
+                url: "https://example.com/src/foo.cpp".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn evaluate_template_for_entry() {
+        let stream = r#"SRCSRV: ini ------------------------------------------------
+VERSION=2
+SRCSRV: variables ------------------------------------------
+HGSERVER=https://hg.mozilla.org/mozilla-central
+HGURL=%hgserver%/raw-file/%var3%
+SRCSRVTRG=%hgurl%/%var2%
+SRCSRV: source files ---------------------------------------
+/builds/SSE.cpp*mozglue/build/SSE.cpp*1706d4d5
+SRCSRV: end ------------------------------------------------"#;
+        let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
+        let entry = ["/builds/SSE.cpp", "mozglue/build/SSE.cpp", "1706d4d5"];
+        let options = EvalOptions::new();
+        assert_eq!(
+            stream.evaluate_template_for_entry("%HGURL%/%fnbksl%(%var2%)", &entry, "", &options),
+            Ok(
+                r#"https://hg.mozilla.org/mozilla-central/raw-file/1706d4d5/mozglue\build\SSE.cpp"#
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            stream.evaluate_template_for_entry(r#"%targ%\%var3%"#, &entry, r#"C:\src"#, &options),
+            Ok(r#"C:\src\1706d4d5"#.to_string())
+        );
+        assert_eq!(
+            stream.evaluate_template_for_entry("%hgurl%", &[], "", &options),
+            Err(EvalError::UnknownVariable("var3".to_string()))
+        );
+        let options = EvalOptions::new().var("HGSERVER", "https://mirror.example.com");
+        assert_eq!(
+            stream.evaluate_template_for_entry("%hgurl%", &entry, "", &options),
+            Ok("https://mirror.example.com/raw-file/1706d4d5".to_string())
+        );
+        assert_eq!(
+            stream.evaluate_template_for_entry("%unclosed", &entry, "", &options),
+            Err(EvalError::InvalidTemplate(TemplateError::MissingPercent))
+        );
+    }
+
+    #[test]
+    fn unknown_policies() {
+        let stream = r#"SRCSRV: ini ------------------------------------------------
+VERSION=2
+SRCSRV: variables ------------------------------------------
+FNPREFIX=https://example.com/
+SRCSRVTRG=%fnprefix%(%var2%)%Unknown%
+SRCSRV: source files ---------------------------------------
+c:\build\foo.cpp*src/foo.cpp
+SRCSRV: end ------------------------------------------------"#;
+        let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
+        let target = |function_policy, variable_policy| {
+            let options = EvalOptions::new()
+                .unknown_function_policy(function_policy)
+                .unknown_variable_policy(variable_policy);
+            let (_, vars) = stream
+                .source_and_raw_var_values_for_path_with_vars(r#"c:\build\foo.cpp"#, "", &options)?
+                .unwrap();
+            Ok(vars["srcsrvtrg"].clone())
+        };
+        assert_eq!(
+            target(UnknownFunctionPolicy::Error, UnknownVariablePolicy::Empty),
+            Err(EvalError::UnknownFunction("fnprefix".to_string()))
+        );
+        assert_eq!(
+            target(UnknownFunctionPolicy::Literal, UnknownVariablePolicy::Error),
+            Err(EvalError::UnknownVariable("unknown".to_string()))
+        );
+        assert_eq!(
+            target(UnknownFunctionPolicy::Literal, UnknownVariablePolicy::Empty),
+            Ok("%fnprefix%(src/foo.cpp)".to_string())
+        );
+        assert_eq!(
+            target(
+                UnknownFunctionPolicy::Variable,
+                UnknownVariablePolicy::KeepLiteral
+            ),
+            Ok("https://example.com/(src/foo.cpp)%unknown%".to_string())
+        );
+    }
+
+    #[test]
+    fn percent_escape() {
+        let stream = r#"SRCSRV: ini ------------------------------------------------
+VERSION=2
+SRCSRV: variables ------------------------------------------
+SRCSRVTRG=https://example.com/My%%20Project/%var2%
+SRCSRV: source files ---------------------------------------
+c:\My Project\foo.cpp*foo.cpp
+SRCSRV: end ------------------------------------------------"#;
+        let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
+        let expected = Some(SourceRetrievalMethod::Download {
+            url: "https://example.com/My%20Project/foo.cpp".to_string(),
+        });
+        assert_eq!(
+            stream
+                .source_for_path(r#"c:\My Project\foo.cpp"#, "")
+                .unwrap(),
+            expected
+        );
+
+        // The escaped value is written back as it is.
+        let bytes = stream.to_bytes();
+        assert!(std::str::from_utf8(&bytes)
+            .unwrap()
+            .contains("SRCSRVTRG=https://example.com/My%%20Project/%var2%\r\n"));
+        let stream = SrcSrvStream::parse(&bytes).unwrap();
+        assert_eq!(
+            stream
+                .source_for_path(r#"c:\My Project\foo.cpp"#, "")
+                .unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn evaluate_template() {
+        let mut vars = EvalVarMap::new();
+        vars.insert("var1".to_string(), r#"c:\build\foo.cpp"#.to_string());
+        vars.insert("var2".to_string(), "SERVER".to_string());
+        vars.insert("Server".to_string(), "https://example.com".to_string());
+        assert_eq!(
+            crate::evaluate_template("%fnvar%(%var2%)/%fnfile%(%VAR1%)", &vars),
+            Ok("https://example.com/foo.cpp".to_string())
+        );
+        assert_eq!(
+            crate::evaluate_template("%var3%", &vars),
+            Err(EvalError::UnknownVariable("var3".to_string()))
+        );
+        assert_eq!(
+            crate::evaluate_template("%var3", &vars),
+            Err(EvalError::InvalidTemplate(TemplateError::MissingPercent))
+        );
+    }
+
+    #[test]
+    fn var_template() {
+        let stream = r#"SRCSRV: ini ------------------------------------------------
+VERSION=2
+SRCSRV: variables ------------------------------------------
+HTTP_ALIAS=https://example.com/
+SRCSRVTRG=%HTTP_ALIAS%%fnbksl%(%var2%)
+SRCSRV: source files ---------------------------------------
+SRCSRV: end ------------------------------------------------"#;
+        let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
+        let node = stream.get_var_template("srcsrvtrg").unwrap();
+        assert_eq!(node.variables(), vec!["HTTP_ALIAS", "var2"]);
+        assert_eq!(node.functions(), vec!["fnbksl"]);
+        assert_eq!(
+            stream.get_var_template("http_alias"),
+            Some(&AstNode::LiteralString("https://example.com/"))
+        );
+        assert_eq!(stream.get_var_template("missing"), None);
+    }
+
+    #[test]
+    fn bom_and_line_endings() {
+        let lines = [
+            "SRCSRV: ini ------------------------------------------------",
+            "VERSION=2",
+            "SRCSRV: variables ------------------------------------------",
+            "SRCSRVTRG=https://example.com/%var2%",
+            "SRCSRV: source files ---------------------------------------",
+            "C:\\build\\a.cpp*a.cpp",
+            "C:\\build\\b.cpp*b.cpp",
+            "SRCSRV: end ------------------------------------------------",
+        ];
+        for (prefix, separator) in [("\u{feff}", "\r\n"), ("", "\r"), ("\u{feff}", "\r")] {
+            let stream = format!("{}{}{}", prefix, lines.join(separator), separator);
+            let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
+            assert_eq!(stream.source_file_entries().count(), 2);
+            assert_eq!(
+                stream.source_for_path("C:\\build\\b.cpp", "").unwrap(),
+                Some(SourceRetrievalMethod::Download {
+                    url: "https://example.com/b.cpp".to_string()
+                })
+            );
+            assert!(stream.warnings().is_empty());
+        }
+
+        // Mixed line endings.
+        let stream = format!(
+            "{}\r{}\n{}\r\n{}",
+            lines[..2].join("\r\n"),
+            lines[2..4].join("\r"),
+            lines[4..7].join("\n"),
+            lines[7]
+        );
+        let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
+        assert_eq!(stream.source_file_entries().count(), 2);
+    }
+
+    #[test]
+    fn duplicates() {
+        let stream = r#"SRCSRV: ini ------------------------------------------------
+VERSION=2
+SRCSRV: variables ------------------------------------------
+SERVER=https://first.example.com
+SRCSRVTRG=%server%/%var2%
+server=https://second.example.com
+SRCSRV: source files ---------------------------------------
+C:\build\a.cpp*a1.cpp
+C:\build\b.cpp*b.cpp
+c:\BUILD\a.cpp*a2.cpp
+C:\build\a.cpp*a3.cpp
+SRCSRV: end ------------------------------------------------"#;
+        let url = |stream: &SrcSrvStream| match stream
+            .source_for_path_with_options(
+                "C:\\build\\a.cpp",
+                "",
+                &LookupOptions::new().normalize_separators(true),
+            )
+            .unwrap()
+        {
+            Some(SourceRetrievalMethod::Download { url }) => url,
+            other => panic!("unexpected {:?}", other),
+        };
+
+        let parsed = SrcSrvStream::parse(stream.as_bytes()).unwrap();
+        assert_eq!(url(&parsed), "https://second.example.com/a3.cpp");
+        assert_eq!(
+            parsed.duplicate_variables(),
+            &[Duplicate {
+                name: "SERVER",
+                line_numbers: vec![4, 6]
+            }]
+        );
+        assert_eq!(
+            parsed.duplicate_entries(),
+            &[Duplicate {
+                name: "C:\\build\\a.cpp",
+                line_numbers: vec![8, 10, 11]
+            }]
+        );
+        assert_eq!(parsed.source_for_path_all("C:\\build\\a.cpp", "").len(), 1);
+        assert_eq!(
+            parsed.get_raw_var_all("server"),
+            vec!["https://second.example.com"]
+        );
+
+        let options = ParseOptions::new()
+            .duplicate_variable_policy(DuplicatePolicy::FirstWins)
+            .duplicate_entry_policy(DuplicatePolicy::FirstWins);
+        let parsed = SrcSrvStream::parse_with_options(stream.as_bytes(), &options).unwrap();
+        assert_eq!(url(&parsed), "https://first.example.com/a1.cpp");
+        assert_eq!(parsed.duplicate_entries()[0].line_numbers, vec![8, 10, 11]);
 
-        let eval_stack = EvalStack::WithAddedVar(&var_name, eval_stack);
-        let mut get_var =
-            |var_name: &str| self.eval_impl(var_name.to_ascii_lowercase(), var_map, &eval_stack);
-        let eval_val = node.eval(&mut get_var)?;
-        var_map.insert(var_name, eval_val.clone());
+        let options = ParseOptions::new()
+            .duplicate_variable_policy(DuplicatePolicy::CollectAll)
+            .duplicate_entry_policy(DuplicatePolicy::CollectAll);
+        let parsed = SrcSrvStream::parse_with_options(stream.as_bytes(), &options).unwrap();
+        assert_eq!(url(&parsed), "https://second.example.com/a3.cpp");
+        assert_eq!(
+            parsed.get_raw_var_all("server"),
+            vec!["https://first.example.com", "https://second.example.com"]
+        );
+        let urls: Vec<_> = parsed
+            .source_for_path_all("c:\\build\\A.cpp", "")
+            .into_iter()
+            .map(|result| match result.unwrap() {
+                SourceRetrievalMethod::Download { url } => url,
+                other => panic!("unexpected {:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            urls,
+            vec![
+                "https://second.example.com/a1.cpp",
+                "https://second.example.com/a2.cpp",
+                "https://second.example.com/a3.cpp"
+            ]
+        );
 
-        Ok(eval_val)
+        let options = ParseOptions::new().duplicate_entry_policy(DuplicatePolicy::Error);
+        assert_eq!(
+            SrcSrvStream::parse_with_options(stream.as_bytes(), &options).err(),
+            Some(ParseError::DuplicateEntry {
+                line_number: 10,
+                path: "c:\\BUILD\\a.cpp".to_string()
+            })
+        );
+        let options = ParseOptions::new().duplicate_variable_policy(DuplicatePolicy::Error);
+        assert_eq!(
+            SrcSrvStream::parse_with_options(stream.as_bytes(), &options).err(),
+            Some(ParseError::DuplicateVariable {
+                line_number: 6,
+                name: "server".to_string()
+            })
+        );
+        let options = options.lenient(true);
+        let parsed = SrcSrvStream::parse_with_options(stream.as_bytes(), &options).unwrap();
+        assert_eq!(
+            parsed.get_raw_var("SERVER"),
+            Some("https://first.example.com")
+        );
+        assert_eq!(parsed.warnings().len(), 1);
     }
-}
 
-enum EvalStack<'a> {
-    Empty,
-    WithAddedVar(&'a str, &'a EvalStack<'a>),
-}
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn parallel() {
+        let mut stream = r#"SRCSRV: ini ------------------------------------------------
+VERSION=2
+SRCSRV: variables ------------------------------------------
+SRCSRVTRG=https://example.com/%var2%
+SRCSRV: source files ---------------------------------------
+"#
+        .to_string();
+        for i in 0..1000 {
+            stream.push_str(&format!(
+                "C:\\build\\file{}.cpp*file{}_{}.cpp\r\n",
+                i % 300,
+                i % 300,
+                i
+            ));
+        }
+        stream.push_str("SRCSRV: end ------------------------------------------------");
 
-impl<'a> EvalStack<'a> {
-    pub fn contains(&self, s: &str) -> bool {
-        match self {
-            EvalStack::Empty => false,
-            EvalStack::WithAddedVar(var_name, rest) => *var_name == s || rest.contains(s),
+        for policy in [
+            DuplicatePolicy::LastWins,
+            DuplicatePolicy::FirstWins,
+            DuplicatePolicy::CollectAll,
+        ] {
+            let options = ParseOptions::new().duplicate_entry_policy(policy);
+            let sequential = SrcSrvStream::parse_with_options(stream.as_bytes(), &options).unwrap();
+            let parallel =
+                SrcSrvStream::parse_with_options(stream.as_bytes(), &options.parallel(true))
+                    .unwrap();
+            assert_eq!(parallel.duplicate_entries(), sequential.duplicate_entries());
+            assert_eq!(parallel.duplicate_entries().len(), 300);
+            for i in 0..300 {
+                let path = format!("c:\\BUILD\\file{}.cpp", i);
+                assert_eq!(
+                    parallel.source_for_path_all(&path, ""),
+                    sequential.source_for_path_all(&path, "")
+                );
+            }
         }
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use std::collections::HashMap;
-
-    use crate::{EvalError, SourceRetrievalMethod, SrcSrvStream};
 
     #[test]
-    fn firefox() {
+    fn vcs_kind_from_templates() {
         let stream = r#"SRCSRV: ini ------------------------------------------------
 VERSION=2
-INDEXVERSION=2
-VERCTRL=http
 SRCSRV: variables ------------------------------------------
-HGSERVER=https://hg.mozilla.org/mozilla-central
-SRCSRVVERCTRL=http
-HTTP_EXTRACT_TARGET=%hgserver%/raw-file/%var3%/%var2%
-SRCSRVTRG=%http_extract_target%
+GIT_CMD=git.exe -C %targ% show %var3%:%var2%
+SRCSRVTRG=%targ%\%var2%
+SRCSRVCMD=%GIT_CMD%
 SRCSRV: source files ---------------------------------------
-/builds/worker/checkouts/gecko/mozglue/build/SSE.cpp*mozglue/build/SSE.cpp*1706d4d54ec68fae1280305b70a02cb24c16ff68
-/builds/worker/checkouts/gecko/memory/build/mozjemalloc.cpp*memory/build/mozjemalloc.cpp*1706d4d54ec68fae1280305b70a02cb24c16ff68
-/builds/worker/checkouts/gecko/vs2017_15.8.4/VC/include/algorithm*vs2017_15.8.4/VC/include/algorithm*1706d4d54ec68fae1280305b70a02cb24c16ff68
-/builds/worker/checkouts/gecko/mozglue/baseprofiler/core/ProfilerBacktrace.cpp*mozglue/baseprofiler/core/ProfilerBacktrace.cpp*1706d4d54ec68fae1280305b70a02cb24c16ff68
-/builds/worker/workspace/obj-build/dist/include/mozilla/IntegerRange.h*mfbt/IntegerRange.h*1706d4d54ec68fae1280305b70a02cb24c16ff68
-SRCSRV: end ------------------------------------------------
+SRCSRV: end ------------------------------------------------"#;
+        let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
+        assert_eq!(stream.vcs_kind(), Some(VcsKind::Git));
 
+        let stream = r#"SRCSRV: ini ------------------------------------------------
+VERSION=2
+SRCSRV: variables ------------------------------------------
+SRCSRVTRG=https://example.com/%var2%
+SRCSRV: source files ---------------------------------------
+SRCSRV: end ------------------------------------------------"#;
+        let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
+        assert_eq!(stream.vcs_kind(), Some(VcsKind::Http));
+    }
 
-"#;
+    #[test]
+    fn recursion() {
+        let stream = r#"SRCSRV: ini ------------------------------------------------
+VERSION=2
+SRCSRV: variables ------------------------------------------
+A=recurse into %b%
+B=recurse into %C%
+C=recurse into %a%
+SRCSRVTRG=%a%
+SRCSRV: source files ---------------------------------------
+test
+SRCSRV: end ------------------------------------------------"#;
         let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
-        assert_eq!(stream.version(), 2);
-        assert_eq!(stream.datetime(), None);
-        assert_eq!(stream.version_control_description(), Some("http"));
         assert_eq!(
-            stream
-                .source_for_path(
-                    r#"/builds/worker/checkouts/gecko/mozglue/baseprofiler/core/ProfilerBacktrace.cpp"#,
-                    r#"C:\Debugger\Cached Sources"#
-                )
-                .unwrap().unwrap(),
-            SourceRetrievalMethod::Download {
-                url: "https://hg.mozilla.org/mozilla-central/raw-file/1706d4d54ec68fae1280305b70a02cb24c16ff68/mozglue/baseprofiler/core/ProfilerBacktrace.cpp".to_string()
-            }
+            stream.source_for_path("test", ""),
+            Err(EvalError::Recursion("a".to_string()))
         );
     }
 
     #[test]
-    fn chrome() {
-        // From https://chromium-browser-symsrv.commondatastorage.googleapis.com/chrome.dll.pdb/5D664C4A228FA9804C4C44205044422E1/chrome.dll.pdb
+    fn error_line_numbers() {
         let stream = r#"SRCSRV: ini ------------------------------------------------
-VERSION=1
-INDEXVERSION=2
-VERCTRL=Subversion
-DATETIME=Fri Jul 30 14:11:46 2021
+VERSION=2
 SRCSRV: variables ------------------------------------------
-SRC_EXTRACT_TARGET_DIR=%targ%\%fnbksl%(%var2%)\%var3%
-SRC_EXTRACT_TARGET=%SRC_EXTRACT_TARGET_DIR%\%fnfile%(%var1%)
-SRC_EXTRACT_CMD=cmd /c "mkdir "%SRC_EXTRACT_TARGET_DIR%" & python -c "import urllib2, base64;url = \"%var4%\";u = urllib2.urlopen(url);open(r\"%SRC_EXTRACT_TARGET%\", \"wb\").write(%var5%(u.read()))"
-SRCSRVTRG=%SRC_EXTRACT_TARGET%
-SRCSRVCMD=%SRC_EXTRACT_CMD%
+SRCSRVTRG=%var2%
 SRCSRV: source files ---------------------------------------
-c:\b\s\w\ir\cache\builder\src\third_party\pdfium\core\fdrm\fx_crypt.cpp*core/fdrm/fx_crypt.cpp*dab1161c861cc239e48a17e1a5d729aa12785a53*https://pdfium.googlesource.com/pdfium.git/+/dab1161c861cc239e48a17e1a5d729aa12785a53/core/fdrm/fx_crypt.cpp?format=TEXT*base64.b64decode
-c:\b\s\w\ir\cache\builder\src\third_party\pdfium\core\fdrm\fx_crypt_aes.cpp*core/fdrm/fx_crypt_aes.cpp*dab1161c861cc239e48a17e1a5d729aa12785a53*https://pdfium.googlesource.com/pdfium.git/+/dab1161c861cc239e48a17e1a5d729aa12785a53/core/fdrm/fx_crypt_aes.cpp?format=TEXT*base64.b64decode
-SRCSRV: end ------------------------------------------------"#;
-        let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
-        assert_eq!(stream.version(), 1);
-        assert_eq!(stream.datetime(), Some("Fri Jul 30 14:11:46 2021"));
-        assert_eq!(stream.version_control_description(), Some("Subversion"));
+test*test
+SRCSRV: something else"#;
         assert_eq!(
-            stream
-                .source_for_path(
-                    r#"c:\b\s\w\ir\cache\builder\src\third_party\pdfium\core\fdrm\fx_crypt.cpp"#,
-                    r#"C:\Debugger\Cached Sources"#,
-                )
-                .unwrap().unwrap(),
-            SourceRetrievalMethod::ExecuteCommand {
-                command: r#"cmd /c "mkdir "C:\Debugger\Cached Sources\core\fdrm\fx_crypt.cpp\dab1161c861cc239e48a17e1a5d729aa12785a53" & python -c "import urllib2, base64;url = \"https://pdfium.googlesource.com/pdfium.git/+/dab1161c861cc239e48a17e1a5d729aa12785a53/core/fdrm/fx_crypt.cpp?format=TEXT\";u = urllib2.urlopen(url);open(r\"C:\Debugger\Cached Sources\core\fdrm\fx_crypt.cpp\dab1161c861cc239e48a17e1a5d729aa12785a53\fx_crypt.cpp\", \"wb\").write(base64.b64decode(u.read()))""#.to_string(),
-                env: HashMap::new(),
-                target_path: r#"C:\Debugger\Cached Sources\core\fdrm\fx_crypt.cpp\dab1161c861cc239e48a17e1a5d729aa12785a53\fx_crypt.cpp"#.to_string(),
-                version_ctrl: None,
-                error_persistence_version_control: None,
-            }
+            SrcSrvStream::parse(stream.as_bytes()).err(),
+            Some(ParseError::MissingTerminationLine {
+                line_number: 7,
+                line: "SRCSRV: something else".to_string()
+            })
         );
     }
 
     #[test]
-    fn team_foundation() {
-        // From https://github.com/microsoft/perfview/blob/5c9f6059f54db41b4ac5c4fc8f57261779634489/src/TraceEvent/Symbols/NativeSymbolModule.cs#L776
+    fn missing_end_marker() {
+        let stream = r#"SRCSRV: ini ------------------------------------------------
+VERSION=2
+SRCSRV: variables ------------------------------------------
+SRCSRVTRG=https://example.com/%var2%
+SRCSRV: source files ---------------------------------------
+C:\build\a.cpp*a.cpp
+C:\build\b.cpp*b.cpp
+"#;
+        assert_eq!(
+            SrcSrvStream::parse(stream.as_bytes()).err(),
+            Some(ParseError::UnexpectedEof)
+        );
+        let options = ParseOptions::new().allow_missing_end_marker(true);
+        let stream = SrcSrvStream::parse_with_options(stream.as_bytes(), &options).unwrap();
+        assert_eq!(stream.warnings(), &[ParseWarning::MissingEndMarker]);
+        assert_eq!(
+            stream.source_for_path("C:\\build\\b.cpp", "").unwrap(),
+            Some(SourceRetrievalMethod::Download {
+                url: "https://example.com/b.cpp".to_string()
+            })
+        );
+
+        // The end marker is only optional at the end of the source files section.
+        let truncated = "SRCSRV: ini ------------------------------------------------\nVERSION=2\n";
+        assert_eq!(
+            SrcSrvStream::parse_with_options(truncated.as_bytes(), &options).err(),
+            Some(ParseError::UnexpectedEof)
+        );
+    }
+
+    #[test]
+    fn source_for_paths() {
         let stream = r#"SRCSRV: ini ------------------------------------------------
 VERSION=3
-INDEXVERSION=2
-VERCTRL=Team Foundation Server
-DATETIME=Thu Mar 10 16:15:55 2016
 SRCSRV: variables ------------------------------------------
 TFS_EXTRACT_CMD=tf.exe view /version:%var4% /noprompt "$%var3%" /server:%fnvar%(%var2%) /output:%srcsrvtrg%
 TFS_EXTRACT_TARGET=%targ%\%var2%%fnbksl%(%var3%)\%var4%\%fnfile%(%var1%)
 VSTFDEVDIV_DEVDIV2=http://vstfdevdiv.redmond.corp.microsoft.com:8080/DevDiv2
+SERVER_ALIAS=%vstfdevdiv_devdiv2%
 SRCSRVVERCTRL=tfs
-SRCSRVERRDESC=access
-SRCSRVERRVAR=var2
 SRCSRVTRG=%TFS_extract_target%
 SRCSRVCMD=%TFS_extract_cmd%
 SRCSRV: source files ---------------------------------------
-f:\dd\externalapis\legacy\vctools\vc12\inc\cvconst.h*VSTFDEVDIV_DEVDIV2*/DevDiv/Fx/Rel/NetFxRel3Stage/externalapis/legacy/vctools/vc12/inc/cvconst.h*1363200
-f:\dd\externalapis\legacy\vctools\vc12\inc\cvinfo.h*VSTFDEVDIV_DEVDIV2*/DevDiv/Fx/Rel/NetFxRel3Stage/externalapis/legacy/vctools/vc12/inc/cvinfo.h*1363200
-f:\dd\externalapis\legacy\vctools\vc12\inc\vc\ammintrin.h*VSTFDEVDIV_DEVDIV2*/DevDiv/Fx/Rel/NetFxRel3Stage/externalapis/legacy/vctools/vc12/inc/vc/ammintrin.h*1363200
+f:\dd\cvconst.h*VSTFDEVDIV_DEVDIV2*/DevDiv/cvconst.h*1363200
+f:\dd\cvinfo.h*SERVER_ALIAS*/DevDiv/cvinfo.h*1363201
+f:\dd\broken.h*UNKNOWN_SERVER*/DevDiv/broken.h*1363202
 SRCSRV: end ------------------------------------------------"#;
         let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
-        assert_eq!(stream.version(), 3);
-        assert_eq!(stream.datetime(), Some("Thu Mar 10 16:15:55 2016"));
+        let paths = [
+            r#"f:\dd\cvconst.h"#,
+            r#"f:\dd\missing.h"#,
+            r#"f:\dd\broken.h"#,
+            r#"F:\DD\CVINFO.H"#,
+        ];
+        let results = stream.source_for_paths(&paths, r#"C:\Debugger\Cached Sources"#);
+        assert_eq!(results.len(), paths.len());
+        for (path, result) in paths.iter().zip(results) {
+            assert_eq!(
+                result,
+                stream.source_for_path(path, r#"C:\Debugger\Cached Sources"#)
+            );
+        }
+        assert!(stream
+            .entry_independent_vars()
+            .contains("vstfdevdiv_devdiv2"));
+        assert!(!stream.entry_independent_vars().contains("srcsrvtrg"));
+    }
+
+    #[test]
+    fn lenient() {
+        let stream = r#"
+SRCSRV: ini ------------------------------------------------
+VERSION=2
+
+garbage
+SRCSRV: variables ------------------------------------------
+HTTP_ALIAS=https://example.com/
+BROKEN=%unterminated
+SRCSRVTRG=%HTTP_ALIAS%%var2%
+SRCSRV: source files ---------------------------------------
+
+C:\build\matrix.cpp*maths/matrix.cpp
+SRCSRV: end ------------------------------------------------
+more garbage
+"#;
         assert_eq!(
-            stream.version_control_description(),
-            Some("Team Foundation Server")
+            SrcSrvStream::parse(stream.as_bytes()).err(),
+            Some(ParseError::MissingIniSection {
+                line_number: 1,
+                line: "".to_string()
+            })
+        );
+        let stream =
+            SrcSrvStream::parse_with_options(stream.as_bytes(), &ParseOptions::new().lenient(true))
+                .unwrap();
+        assert_eq!(
+            stream.warnings(),
+            &[
+                ParseWarning::SkippedLine {
+                    line_number: 5,
+                    line: "garbage".to_string(),
+                    reason: ParseError::MissingEquals {
+                        line_number: 5,
+                        line: "garbage".to_string(),
+                    },
+                },
+                ParseWarning::SkippedLine {
+                    line_number: 8,
+                    line: "BROKEN=%unterminated".to_string(),
+                    reason: ParseError::InvalidTemplate {
+                        line_number: 8,
+                        line: "BROKEN=%unterminated".to_string(),
+                        error: TemplateError::MissingPercent,
+                    },
+                },
+                ParseWarning::TrailingData { line_number: 14 },
+            ]
         );
+        assert_eq!(stream.get_raw_var("broken"), None);
         assert_eq!(
             stream
-                .source_for_path(
-                    r#"F:\dd\externalapis\legacy\vctools\vc12\inc\cvinfo.h"#,
-                    r#"C:\Debugger\Cached Sources"#,
-                )
-                .unwrap().unwrap(),
-                SourceRetrievalMethod::ExecuteCommand {
-                    command: r#"tf.exe view /version:1363200 /noprompt "$/DevDiv/Fx/Rel/NetFxRel3Stage/externalapis/legacy/vctools/vc12/inc/cvinfo.h" /server:http://vstfdevdiv.redmond.corp.microsoft.com:8080/DevDiv2 /output:C:\Debugger\Cached Sources\VSTFDEVDIV_DEVDIV2\DevDiv\Fx\Rel\NetFxRel3Stage\externalapis\legacy\vctools\vc12\inc\cvinfo.h\1363200\cvinfo.h"#.to_string(),
-                    env: HashMap::new(),
-                    version_ctrl: Some("tfs".to_string()),
-                    target_path: r#"C:\Debugger\Cached Sources\VSTFDEVDIV_DEVDIV2\DevDiv\Fx\Rel\NetFxRel3Stage\externalapis\legacy\vctools\vc12\inc\cvinfo.h\1363200\cvinfo.h"#.to_string(),
-                    error_persistence_version_control: Some("VSTFDEVDIV_DEVDIV2".to_string()),
-                }
+                .source_for_path(r#"C:\build\matrix.cpp"#, "")
+                .unwrap(),
+            Some(SourceRetrievalMethod::Download {
+                url: "https://example.com/maths/matrix.cpp".to_string()
+            })
         );
     }
 
     #[test]
-    fn renderdoc() {
-        // From https://renderdoc.org/symbols/renderdoc.pdb/6D1DFFC4DC524537962CCABC000820641/renderdoc.pd_
+    fn to_bytes() {
+        let stream = "SRCSRV: ini ------------------------------------------------\r\n\
+            VERSION=2\r\n\
+            VerCtrl=http\r\n\
+            Custom_Field=something\r\n\
+            SRCSRV: variables ------------------------------------------\r\n\
+            HTTP_ALIAS=https://example.com/\r\n\
+            SRCSRVTRG=%HTTP_ALIAS%%var2%\r\n\
+            SRCSRV: source files ---------------------------------------\r\n\
+            C:\\build\\b.cpp*b.cpp\r\n\
+            C:\\build\\a.cpp*a.cpp*with*more*fields\r\n\
+            SRCSRV: end ------------------------------------------------\r\n";
+        let parsed = SrcSrvStream::parse(stream.as_bytes()).unwrap();
+        assert_eq!(std::str::from_utf8(&parsed.to_bytes()).unwrap(), stream);
+
+        let mut builder = parsed.to_builder();
+        builder.set_var("HTTP_ALIAS", "https://mirror.example.com/");
+        let modified = builder.to_bytes().unwrap();
+        let modified = SrcSrvStream::parse(&modified).unwrap();
+        assert_eq!(modified.get_ini_field("custom_field"), Some("something"));
+        assert_eq!(
+            modified.source_for_path(r#"C:\build\b.cpp"#, "").unwrap(),
+            Some(SourceRetrievalMethod::Download {
+                url: "https://mirror.example.com/b.cpp".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn preserve_layout() {
+        let stream = "\u{feff}SRCSRV: ini -----\n\
+            VERSION=2\n\
+            SRCSRV: variables -----\n\
+            server=https://first.example.com/\n\
+            SRCSRVTRG=%SERVER%%var2%\n\
+            SERVER=https://example.com/\n\
+            SRCSRV: source files -----\n\
+            C:\\build\\b.cpp*b.cpp\n\
+            SRCSRV: end -----\n\0\0";
+        let parsed = SrcSrvStream::parse(stream.as_bytes()).unwrap();
+        assert_ne!(std::str::from_utf8(&parsed.to_bytes()).unwrap(), stream);
+
+        let options = ParseOptions::new().preserve_layout(true);
+        let parsed = SrcSrvStream::parse_with_options(stream.as_bytes(), &options).unwrap();
+        assert_eq!(std::str::from_utf8(&parsed.to_bytes()).unwrap(), stream);
+        let builder = parsed.to_builder();
+        assert_eq!(
+            std::str::from_utf8(&builder.to_bytes().unwrap()).unwrap(),
+            stream
+        );
+
+        let mut builder = parsed.to_builder();
+        builder.add_source_file_entry(&[r#"C:\build\a.cpp"#, "a.cpp"]);
+        assert_eq!(
+            std::str::from_utf8(&builder.to_bytes().unwrap()).unwrap(),
+            stream.replace("b.cpp\n", "b.cpp\nC:\\build\\a.cpp*a.cpp\n")
+        );
+    }
+
+    #[test]
+    fn lookup_options() {
         let stream = r#"SRCSRV: ini ------------------------------------------------
 VERSION=2
-VERCTRL=http
 SRCSRV: variables ------------------------------------------
-HTTP_ALIAS=https://raw.githubusercontent.com/baldurk/renderdoc/v1.15/
-HTTP_EXTRACT_TARGET=%HTTP_ALIAS%%var2%
-SRCSRVTRG=%HTTP_EXTRACT_TARGET%
+SRCSRVTRG=https://example.com/%var2%
 SRCSRV: source files ---------------------------------------
-C:\build\renderdoc\qrenderdoc\Code\BufferFormatter.cpp*qrenderdoc/Code/BufferFormatter.cpp
-C:\build\renderdoc\qrenderdoc\Windows\Dialogs\AnalyticsConfirmDialog.cpp*qrenderdoc/Windows/Dialogs/AnalyticsConfirmDialog.cpp
-C:\build\renderdoc\renderdoc\data\glsl\gl_texsample.h*renderdoc/data/glsl/gl_texsample.h
-C:\build\renderdoc\renderdoc\driver\d3d12\d3d12_device.cpp*renderdoc/driver/d3d12/d3d12_device.cpp
-C:\build\renderdoc\renderdoc\maths\matrix.cpp*renderdoc/maths/matrix.cpp
-C:\build\renderdoc\util\test\demos\texture_zoo.cpp*util/test/demos/texture_zoo.cpp
-C:\build\renderdoc\Win32\Release\renderdoc_app.h*Win32/Release/renderdoc_app.h
-C:\build\renderdoc\x64\Release\renderdoc_app.h*x64/Release/renderdoc_app.h
+C:\build\src\foo.cpp*src/foo.cpp
 SRCSRV: end ------------------------------------------------"#;
         let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
-        assert_eq!(stream.version(), 2);
-        assert_eq!(stream.datetime(), None);
-        assert_eq!(stream.version_control_description(), Some("http"));
+        let expected = Some(SourceRetrievalMethod::Download {
+            url: "https://example.com/src/foo.cpp".to_string(),
+        });
+        assert_eq!(stream.source_for_path("c:/build/src/foo.cpp", ""), Ok(None));
         assert_eq!(
-            stream
-                .source_for_path(
-                    r#"C:\build\renderdoc\renderdoc\data\glsl\gl_texsample.h"#,
-                    r#"C:\Debugger\Cached Sources"#,
-                )
-                .unwrap().unwrap(),
-                SourceRetrievalMethod::Download {
-                    url: "https://raw.githubusercontent.com/baldurk/renderdoc/v1.15/renderdoc/data/glsl/gl_texsample.h".to_string(),
-                }
+            stream.source_for_path_with_options(
+                "c:/build/src/foo.cpp",
+                "",
+                &LookupOptions::new().normalize_separators(true)
+            ),
+            Ok(expected.clone())
+        );
+        assert_eq!(
+            stream.source_for_path_with_options(
+                r#"C:\build\include\..\src\.\foo.cpp"#,
+                "",
+                &LookupOptions::new().resolve_dot_components(true)
+            ),
+            Ok(expected.clone())
+        );
+        assert_eq!(
+            stream.source_for_path_with_options(
+                "C:/build/include/../src/foo.cpp",
+                "",
+                &LookupOptions::new()
+                    .normalize_separators(true)
+                    .resolve_dot_components(true)
+            ),
+            Ok(expected)
         );
     }
 
     #[test]
-    fn recursion() {
+    fn suffix_match() {
         let stream = r#"SRCSRV: ini ------------------------------------------------
 VERSION=2
 SRCSRV: variables ------------------------------------------
-A=recurse into %b%
-B=recurse into %C%
-C=recurse into %a%
-SRCSRVTRG=%a%
+SRCSRVTRG=https://example.com/%var2%
 SRCSRV: source files ---------------------------------------
-test
+D:\a\1\s\src\foo.cpp*src/foo.cpp
+D:\a\1\s\test\foo.cpp*test/foo.cpp
+D:\a\1\s\src\bar.cpp*src/bar.cpp
 SRCSRV: end ------------------------------------------------"#;
         let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
         assert_eq!(
-            stream.source_for_path("test", ""),
-            Err(EvalError::Recursion("a".to_string()))
+            stream.suffix_match_candidates("/home/me/project/src/Foo.cpp", 1),
+            vec![
+                SuffixMatchCandidate {
+                    original_path: r#"D:\a\1\s\src\foo.cpp"#,
+                    matching_components: 2,
+                },
+                SuffixMatchCandidate {
+                    original_path: r#"D:\a\1\s\test\foo.cpp"#,
+                    matching_components: 1,
+                },
+            ]
+        );
+
+        let options = LookupOptions::new().suffix_match_fallback(Some(2));
+        assert_eq!(
+            stream.source_for_path_with_options("/home/me/project/src/foo.cpp", "", &options),
+            Ok(Some(SourceRetrievalMethod::Download {
+                url: "https://example.com/src/foo.cpp".to_string(),
+            }))
+        );
+        assert_eq!(
+            stream.source_for_path_with_options("/home/me/project/other/foo.cpp", "", &options),
+            Ok(None)
+        );
+        let options = LookupOptions::new().suffix_match_fallback(Some(1));
+        assert_eq!(
+            stream.source_for_path_with_options("/home/me/project/other/foo.cpp", "", &options),
+            Ok(None),
+            "ambiguous matches should not be used"
+        );
+    }
+
+    #[test]
+    fn source_file_entries() {
+        let stream = r#"SRCSRV: ini ------------------------------------------------
+VERSION=2
+SRCSRV: variables ------------------------------------------
+SRCSRVTRG=https://example.com/%var2%
+SRCSRV: source files ---------------------------------------
+C:\Build\Foo.cpp*src/Foo.cpp
+C:\Build\bar.h*include/bar.h*extra
+SRCSRV: end ------------------------------------------------"#;
+        let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
+        let entries: Vec<_> = stream.source_file_entries().collect();
+        assert_eq!(
+            entries,
+            vec![
+                (
+                    r#"C:\Build\Foo.cpp"#,
+                    vec![r#"C:\Build\Foo.cpp"#, "src/Foo.cpp"]
+                ),
+                (
+                    r#"C:\Build\bar.h"#,
+                    vec![r#"C:\Build\bar.h"#, "include/bar.h", "extra"]
+                ),
+            ]
         );
     }
 }
@@ -0,0 +1,535 @@
+use crate::{
+    is_entry_var_name, split_entry, AstNode, MergeError, SrcSrvStream, SrcSrvStreamBuilder,
+};
+use std::collections::HashMap;
+use std::result::Result;
+
+/// The variables which are evaluated for each file entry to find out how to
+/// get the file. If the streams of a [`merge`] disagree on them, each entry
+/// needs to select the variables of its stream.
+const PER_STREAM_VARS: [(&str, &str); 4] = [
+    ("SRCSRVTRG", "TRG"),
+    ("SRCSRVCMD", "CMD"),
+    ("SRCSRVENV", "ENV"),
+    ("SRCSRVVERCTRL", "VERCTRL"),
+];
+
+/// How [`merge`] handles a variable which several streams define with
+/// different values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum MergeConflictPolicy {
+    /// Fail with [`MergeError::VariableConflict`].
+    #[default]
+    Error,
+    /// Use the value of the first stream which defines the variable. The
+    /// entries of the other streams may then resolve differently than before.
+    KeepFirst,
+    /// Use the value of the last stream which defines the variable. The
+    /// entries of the other streams may then resolve differently than before.
+    KeepLast,
+    /// Rename the variable in each stream which defines it with a different
+    /// value than an earlier stream, e.g. `HTTP_ALIAS` to `HTTP_ALIAS_2` in
+    /// the second stream, and update the references to it, so that every
+    /// entry resolves like in its own stream.
+    ///
+    /// If the streams have different `SRCSRVTRG`, `SRCSRVCMD`, `SRCSRVENV` or
+    /// `SRCSRVVERCTRL` variables after the renames, the values of stream N
+    /// are stored in `STREAMN_TRG`, `STREAMN_CMD` etc., each entry gets
+    /// `STREAMN` as its tenth value, and `SRCSRVTRG=%fnvar%(%var10%_TRG)`
+    /// etc. select the variables of the entry's stream. This fails with
+    /// [`MergeError::TooManyEntryValues`] if an entry already uses ten
+    /// values, and with [`MergeError::VariableConflict`] if only some of the
+    /// streams define one of these variables, e.g. if one stream runs a
+    /// command and another one downloads its files. `SRCSRVERRVAR` keeps the
+    /// value of the first stream.
+    Rename,
+}
+
+/// Combine the ini fields, variables and file entries of several streams
+/// into one stream, for example the streams of the main binary and of
+/// statically linked libraries which were indexed separately.
+///
+/// Ini fields use the value of the first stream which has them, except for
+/// `VERSION`, which is the highest version. Variables which several streams
+/// define with the same value are kept once, and variables with different
+/// values are handled according to `policy`. The file entries are added in
+/// stream order.
+///
+/// ```
+/// use srcsrv::{MergeConflictPolicy, SourceRetrievalMethod, SrcSrvStream, SrcSrvStreamBuilder};
+///
+/// # fn wrapper() -> std::result::Result<(), Box<dyn std::error::Error>> {
+/// let app = SrcSrvStreamBuilder::github("org", "app", "0123abcd")
+///     .add_http_entry(r#"C:\build\app\main.cpp"#, "main.cpp", None)
+///     .to_bytes()?;
+/// let zlib = SrcSrvStreamBuilder::github("madler", "zlib", "v1.3.1")
+///     .add_http_entry(r#"C:\build\zlib\inflate.c"#, "inflate.c", None)
+///     .to_bytes()?;
+/// let (app, zlib) = (SrcSrvStream::parse(&app)?, SrcSrvStream::parse(&zlib)?);
+///
+/// let bytes = srcsrv::merge(&[&app, &zlib], MergeConflictPolicy::Rename)?.to_bytes()?;
+/// let merged = SrcSrvStream::parse(&bytes)?;
+/// assert_eq!(
+///     merged.source_for_path(r#"C:\build\zlib\inflate.c"#, "")?,
+///     Some(SourceRetrievalMethod::Download {
+///         url: "https://raw.githubusercontent.com/madler/zlib/v1.3.1/inflate.c".to_string()
+///     })
+/// );
+/// # Ok(())
+/// # }
+/// ```
+pub fn merge(
+    streams: &[&SrcSrvStream<'_>],
+    policy: MergeConflictPolicy,
+) -> Result<SrcSrvStreamBuilder, MergeError> {
+    let mut builder = SrcSrvStreamBuilder::new();
+    let version = streams.iter().map(|stream| stream.version()).max();
+    for (stream_index, stream) in streams.iter().enumerate() {
+        for (name, value) in &stream.ini_lines {
+            let earlier_streams = &streams[..stream_index];
+            if earlier_streams
+                .iter()
+                .all(|earlier| earlier.get_ini_field(name).is_none())
+            {
+                builder.set_ini_field(name, value);
+            }
+        }
+    }
+    if let Some(version) = version {
+        builder.set_ini_field("VERSION", &version.to_string());
+    }
+
+    // With the Rename policy, the per-stream variables of each stream are
+    // collected separately, and only merged if they are the same everywhere
+    // after the renames.
+    let rename = policy == MergeConflictPolicy::Rename;
+    let mut per_stream_values: Vec<Vec<Option<String>>> = Vec::new();
+    let mut entries: Vec<(usize, Vec<String>)> = Vec::new();
+
+    // (name, value) of the merged variables, in insertion order
+    let mut vars: Vec<(String, String)> = Vec::new();
+    for (stream_index, stream) in streams.iter().enumerate() {
+        let stream_vars: Vec<(&str, &str)> = stream
+            .var_lines
+            .iter()
+            .copied()
+            .filter(|(name, _)| !(rename && is_per_stream_var(name)))
+            .collect();
+
+        // Find the variables of this stream which need to be renamed. A
+        // rename can change the values of the variables which refer to it,
+        // so repeat until nothing changes.
+        let mut renames: HashMap<String, String> = HashMap::new();
+        if rename {
+            loop {
+                let mut renamed_any = false;
+                for (name, value) in &stream_vars {
+                    if renames.contains_key(&name.to_ascii_lowercase())
+                        || name.eq_ignore_ascii_case("SRCSRVERRVAR")
+                    {
+                        continue;
+                    }
+                    let value = rename_vars(value, &renames)?;
+                    if matches!(find_var(&mut vars, name), Some(existing) if *existing != value) {
+                        let new_name =
+                            unused_name(&vars, &stream_vars, &renames, name, stream_index + 1);
+                        renames.insert(name.to_ascii_lowercase(), new_name);
+                        renamed_any = true;
+                    }
+                }
+                if !renamed_any {
+                    break;
+                }
+            }
+        }
+
+        for (name, value) in &stream_vars {
+            let value = rename_vars(value, &renames)?;
+            let name = match renames.get(&name.to_ascii_lowercase()) {
+                Some(new_name) => new_name.as_str(),
+                None => name,
+            };
+            match find_var(&mut vars, name) {
+                None => vars.push((name.to_string(), value)),
+                Some(existing) if *existing == value => {}
+                Some(existing) => match policy {
+                    MergeConflictPolicy::Error => {
+                        return Err(MergeError::VariableConflict(name.to_string()))
+                    }
+                    MergeConflictPolicy::Rename if name.eq_ignore_ascii_case("SRCSRVERRVAR") => {}
+                    MergeConflictPolicy::KeepFirst => {}
+                    MergeConflictPolicy::KeepLast => *existing = value,
+                    // The renames above leave no other conflicts.
+                    MergeConflictPolicy::Rename => {
+                        return Err(MergeError::VariableConflict(name.to_string()))
+                    }
+                },
+            }
+        }
+
+        if rename {
+            let values = PER_STREAM_VARS
+                .iter()
+                .map(|(name, _)| {
+                    stream
+                        .get_raw_var(name)
+                        .map(|value| rename_vars(value, &renames))
+                        .transpose()
+                })
+                .collect::<Result<_, _>>()?;
+            per_stream_values.push(values);
+        }
+
+        let fnvar_entry_vars = fnvar_entry_var_indexes(&stream.var_lines)?;
+        for line in &stream.source_file_entries {
+            let entry = split_entry(line)
+                .into_iter()
+                .enumerate()
+                .map(
+                    |(index, value)| match renames.get(&value.to_ascii_lowercase()) {
+                        Some(new_name) if fnvar_entry_vars.contains(&index) => new_name.clone(),
+                        _ => value.to_string(),
+                    },
+                )
+                .collect();
+            entries.push((stream_index, entry));
+        }
+    }
+
+    // Whether the entries need to select the per-stream variables.
+    let per_stream = per_stream_values
+        .iter()
+        .any(|values| Some(values) != per_stream_values.first());
+    if per_stream {
+        // A variable which only some of the streams define can't be selected
+        // per stream: an empty value would still count as defined.
+        let mut selected = Vec::new();
+        for (var_index, (name, suffix)) in PER_STREAM_VARS.iter().enumerate() {
+            let defined_count = per_stream_values
+                .iter()
+                .filter(|values| values[var_index].is_some())
+                .count();
+            if defined_count == per_stream_values.len() {
+                selected.push((var_index, name, suffix));
+            } else if defined_count != 0 {
+                return Err(MergeError::VariableConflict(name.to_string()));
+            }
+        }
+        for (stream_index, values) in per_stream_values.iter().enumerate() {
+            for (var_index, _, suffix) in &selected {
+                let name = format!("STREAM{}_{}", stream_index + 1, suffix);
+                vars.push((name, values[*var_index].clone().unwrap_or_default()));
+            }
+        }
+        for (_, name, suffix) in &selected {
+            vars.push((name.to_string(), format!("%fnvar%(%var10%_{})", suffix)));
+        }
+    } else if let Some(values) = per_stream_values.first() {
+        for ((name, _), value) in PER_STREAM_VARS.iter().zip(values) {
+            if let Some(value) = value {
+                vars.push((name.to_string(), value.clone()));
+            }
+        }
+    }
+    for (name, value) in &vars {
+        builder.set_var(name, value);
+    }
+
+    for (stream_index, mut entry) in entries {
+        if per_stream {
+            if entry.len() >= 10 {
+                return Err(MergeError::TooManyEntryValues(entry.swap_remove(0)));
+            }
+            entry.resize(9, String::new());
+            entry.push(format!("STREAM{}", stream_index + 1));
+        }
+        builder.add_source_file_entry(&entry);
+    }
+    Ok(builder)
+}
+
+fn is_per_stream_var(name: &str) -> bool {
+    PER_STREAM_VARS
+        .iter()
+        .any(|(per_stream_name, _)| name.eq_ignore_ascii_case(per_stream_name))
+}
+
+fn find_var<'v>(vars: &'v mut [(String, String)], name: &str) -> Option<&'v mut String> {
+    vars.iter_mut()
+        .find(|(existing_name, _)| existing_name.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value)
+}
+
+/// `name` with a suffix for the stream with the 1-based `stream_number`,
+/// which isn't used by any of the merged `vars`, by any of the variables of
+/// the stream itself, and by none of the new names in `renames`.
+fn unused_name(
+    vars: &[(String, String)],
+    stream_vars: &[(&str, &str)],
+    renames: &HashMap<String, String>,
+    name: &str,
+    stream_number: usize,
+) -> String {
+    let mut new_name = format!("{}_{}", name, stream_number);
+    while vars
+        .iter()
+        .map(|(existing_name, _)| existing_name.as_str())
+        .chain(stream_vars.iter().map(|(existing_name, _)| *existing_name))
+        .chain(renames.values().map(String::as_str))
+        .any(|existing_name| existing_name.eq_ignore_ascii_case(&new_name))
+    {
+        new_name.push('_');
+    }
+    new_name
+}
+
+/// `template` with the references to the variables in `renames` (lowercase
+/// old name -> new name) replaced.
+fn rename_vars(template: &str, renames: &HashMap<String, String>) -> Result<String, MergeError> {
+    if renames.is_empty() {
+        return Ok(template.to_string());
+    }
+    let node = AstNode::parse(template)?;
+    Ok(rename_node(&node, renames).to_string())
+}
+
+fn rename_node<'a>(node: &AstNode<'a>, renames: &'a HashMap<String, String>) -> AstNode<'a> {
+    let rename_box = |node: &AstNode<'a>| Box::new(rename_node(node, renames));
+    match node {
+        AstNode::Sequence(nodes) => AstNode::Sequence(
+            nodes
+                .iter()
+                .map(|node| rename_node(node, renames))
+                .collect(),
+        ),
+        AstNode::LiteralString(s) => AstNode::LiteralString(s),
+        AstNode::Variable(name) => match renames.get(&name.to_ascii_lowercase()) {
+            Some(new_name) => AstNode::Variable(new_name),
+            None => AstNode::Variable(name),
+        },
+        AstNode::FnVar(node) => AstNode::FnVar(rename_box(node)),
+        AstNode::FnBackslash(node) => AstNode::FnBackslash(rename_box(node)),
+        AstNode::FnFile(node) => AstNode::FnFile(rename_box(node)),
+        AstNode::Function(name, node) => AstNode::Function(name, rename_box(node)),
+    }
+}
+
+/// The 0-based indexes of the entry values which the variables pass to
+/// `%fnvar%` directly, e.g. 1 for `%fnvar%(%var2%)`. These values are
+/// variable names, which need to be renamed along with the variables.
+fn fnvar_entry_var_indexes(vars: &[(&str, &str)]) -> Result<Vec<usize>, MergeError> {
+    fn visit(node: &AstNode<'_>, indexes: &mut Vec<usize>) {
+        match node {
+            AstNode::Sequence(nodes) => nodes.iter().for_each(|node| visit(node, indexes)),
+            AstNode::LiteralString(_) | AstNode::Variable(_) => {}
+            AstNode::FnVar(arg) => {
+                if let AstNode::Variable(name) = &**arg {
+                    let name = name.to_ascii_lowercase();
+                    if is_entry_var_name(&name) {
+                        if let Some(index) = name[3..]
+                            .parse::<usize>()
+                            .ok()
+                            .and_then(|i| i.checked_sub(1))
+                        {
+                            indexes.push(index);
+                        }
+                    }
+                }
+                visit(arg, indexes);
+            }
+            AstNode::FnBackslash(node) | AstNode::FnFile(node) | AstNode::Function(_, node) => {
+                visit(node, indexes)
+            }
+        }
+    }
+    let mut indexes = Vec::new();
+    for (_, value) in vars {
+        visit(&AstNode::parse(value)?, &mut indexes);
+    }
+    Ok(indexes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{merge, MergeConflictPolicy};
+    use crate::{MergeError, SourceRetrievalMethod, SrcSrvStream, SrcSrvStreamBuilder};
+
+    const APP: &str = r#"SRCSRV: ini ------------------------------------------------
+VERSION=1
+VERCTRL=http
+SRCSRV: variables ------------------------------------------
+HTTP_ALIAS=https://example.com/app/
+SRCSRVTRG=%fnvar%(%var2%)%var3%
+SRCSRV: source files ---------------------------------------
+c:\build\app\main.cpp*HTTP_ALIAS*main.cpp
+SRCSRV: end ------------------------------------------------"#;
+
+    const LIB: &str = r#"SRCSRV: ini ------------------------------------------------
+VERSION=2
+SRCSRV: variables ------------------------------------------
+HTTP_ALIAS=https://example.com/lib/
+SRCSRVTRG=%HTTP_ALIAS%%var2%
+SRCSRV: source files ---------------------------------------
+c:\build\lib\lib.c*lib.c
+SRCSRV: end ------------------------------------------------"#;
+
+    const TOOL: &str = r#"SRCSRV: ini ------------------------------------------------
+VERSION=2
+SRCSRV: variables ------------------------------------------
+SRCSRVTRG=%targ%\%var2%
+SRCSRVCMD=tool.exe get %var2%
+SRCSRV: source files ---------------------------------------
+c:\build\tool\tool.c*tool.c
+SRCSRV: end ------------------------------------------------"#;
+
+    fn source(stream: &SrcSrvStream, path: &str) -> SourceRetrievalMethod {
+        stream
+            .source_for_path(path, r#"C:\Cache"#)
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn merge_with_renames() {
+        let app = SrcSrvStream::parse(APP.as_bytes()).unwrap();
+        let lib = SrcSrvStream::parse(LIB.as_bytes()).unwrap();
+        let tool = SrcSrvStream::parse(TOOL.as_bytes()).unwrap();
+        let streams = [&app, &lib, &tool];
+
+        assert_eq!(
+            merge(&streams, MergeConflictPolicy::Error).unwrap_err(),
+            MergeError::VariableConflict("HTTP_ALIAS".to_string())
+        );
+
+        // Only TOOL runs a command, which its entries can't select.
+        assert_eq!(
+            merge(&streams, MergeConflictPolicy::Rename).unwrap_err(),
+            MergeError::VariableConflict("SRCSRVCMD".to_string())
+        );
+
+        let bytes = merge(&[&app, &lib], MergeConflictPolicy::Rename)
+            .unwrap()
+            .to_bytes()
+            .unwrap();
+        let merged = SrcSrvStream::parse(&bytes).unwrap();
+        assert_eq!(merged.version(), 2);
+        assert_eq!(merged.get_ini_field("VERCTRL"), Some("http"));
+        assert_eq!(
+            merged.get_raw_var("HTTP_ALIAS_2"),
+            Some("https://example.com/lib/")
+        );
+        assert_eq!(
+            merged.get_raw_var("STREAM2_TRG"),
+            Some("%HTTP_ALIAS_2%%var2%")
+        );
+        assert_eq!(
+            source(&merged, r#"c:\build\app\main.cpp"#),
+            SourceRetrievalMethod::Download {
+                url: "https://example.com/app/main.cpp".to_string()
+            }
+        );
+        assert_eq!(
+            source(&merged, r#"c:\build\lib\lib.c"#),
+            SourceRetrievalMethod::Download {
+                url: "https://example.com/lib/lib.c".to_string()
+            }
+        );
+        assert!(merged.get_raw_var("SRCSRVCMD").is_none());
+        assert!(merged.get_raw_var("STREAM1_CMD").is_none());
+
+        let bytes = merge(&[&app, &lib], MergeConflictPolicy::KeepFirst)
+            .unwrap()
+            .to_bytes()
+            .unwrap();
+        let merged = SrcSrvStream::parse(&bytes).unwrap();
+        assert_eq!(
+            merged.get_raw_var("SRCSRVTRG"),
+            Some("%fnvar%(%var2%)%var3%")
+        );
+    }
+
+    #[test]
+    fn merge_commands_per_stream() {
+        let other = TOOL
+            .replace("tool.exe", "other.exe")
+            .replace("tool.c", "other.c");
+        let tool = SrcSrvStream::parse(TOOL.as_bytes()).unwrap();
+        let other = SrcSrvStream::parse(other.as_bytes()).unwrap();
+        let bytes = merge(&[&tool, &other], MergeConflictPolicy::Rename)
+            .unwrap()
+            .to_bytes()
+            .unwrap();
+        let merged = SrcSrvStream::parse(&bytes).unwrap();
+        assert_eq!(
+            merged.get_raw_var("STREAM2_CMD"),
+            Some("other.exe get %var2%")
+        );
+        assert!(merged.get_raw_var("STREAM1_ENV").is_none());
+        assert!(merged.get_raw_var("SRCSRVENV").is_none());
+        assert_eq!(
+            source(&merged, r#"c:\build\tool\tool.c"#),
+            source(&tool, r#"c:\build\tool\tool.c"#)
+        );
+        assert_eq!(
+            source(&merged, r#"c:\build\tool\other.c"#),
+            source(&other, r#"c:\build\tool\other.c"#)
+        );
+    }
+
+    #[test]
+    fn rename_fnvar_entry_values() {
+        let other = APP.replace("/app/", "/other/");
+        let app = SrcSrvStream::parse(APP.as_bytes()).unwrap();
+        let other = SrcSrvStream::parse(other.as_bytes()).unwrap();
+        let bytes = merge(&[&app, &other], MergeConflictPolicy::Rename)
+            .unwrap()
+            .to_bytes()
+            .unwrap();
+        let merged = SrcSrvStream::parse(&bytes).unwrap();
+        assert!(merged.get_raw_var("STREAM1_TRG").is_none());
+        assert_eq!(
+            merged.source_file_entries[1],
+            r#"c:\build\app\main.cpp*HTTP_ALIAS_2*main.cpp"#
+        );
+    }
+
+    #[test]
+    fn rename_avoids_names_of_the_stream() {
+        let a = SrcSrvStreamBuilder::new()
+            .set_var("X", "https://a.com")
+            .set_var("SRCSRVTRG", "%X%/%var2%")
+            .add_source_file_entry(&[r#"c:\build\a.cpp"#, "a.cpp"])
+            .to_bytes()
+            .unwrap();
+        let b = SrcSrvStreamBuilder::new()
+            .set_var("X", "https://b.com")
+            .set_var("X_2", "sub")
+            .set_var("SRCSRVTRG", "%X%/%X_2%/%var2%")
+            .add_source_file_entry(&[r#"c:\build\b.cpp"#, "b.cpp"])
+            .to_bytes()
+            .unwrap();
+        let (a, b) = (
+            SrcSrvStream::parse(&a).unwrap(),
+            SrcSrvStream::parse(&b).unwrap(),
+        );
+        let bytes = merge(&[&a, &b], MergeConflictPolicy::Rename)
+            .unwrap()
+            .to_bytes()
+            .unwrap();
+        let merged = SrcSrvStream::parse(&bytes).unwrap();
+        assert_eq!(merged.get_raw_var("X_2"), Some("sub"));
+        assert_eq!(merged.get_raw_var("X_2_"), Some("https://b.com"));
+        assert_eq!(
+            source(&merged, r#"c:\build\a.cpp"#),
+            SourceRetrievalMethod::Download {
+                url: "https://a.com/a.cpp".to_string()
+            }
+        );
+        assert_eq!(
+            source(&merged, r#"c:\build\b.cpp"#),
+            SourceRetrievalMethod::Download {
+                url: "https://b.com/sub/b.cpp".to_string()
+            }
+        );
+    }
+}
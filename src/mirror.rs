@@ -0,0 +1,244 @@
+use crate::cache_path::relative_target_path;
+use crate::{
+    fetch_sources, EvalError, FetchError, FetchOptions, FetchProgress, SourceFetcher,
+    SourceRetrievalMethod, SrcSrvStream, SrcSrvStreamBuilder,
+};
+use std::collections::HashSet;
+use std::path::Path;
+
+/// The result of [`mirror_sources`].
+#[derive(Debug)]
+pub struct MirroredStream {
+    /// A stream which downloads the mirrored files from the mirror, created
+    /// with [`SrcSrvStreamBuilder::http`]. It only has entries for the files
+    /// which were mirrored.
+    pub builder: SrcSrvStreamBuilder,
+    /// The files which could not be mirrored, in stream order.
+    pub unmirrored: Vec<UnfetchedEntry>,
+}
+
+/// A file entry which [`mirror_sources`] or
+/// [`write_source_pack`](crate::write_source_pack) could not fetch.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum UnfetchedEntry {
+    /// The file is not downloaded from a URL, e.g. because it needs a command.
+    NotDownload {
+        original_path: String,
+        method: SourceRetrievalMethod,
+    },
+    /// Evaluating the entry failed.
+    EvalFailed {
+        original_path: String,
+        error: EvalError,
+    },
+    /// Downloading or storing the file failed.
+    FetchFailed {
+        original_path: String,
+        error: FetchError,
+    },
+}
+
+/// Download every file of `stream` which is downloaded from a URL, store the
+/// files below `mirror_dir`, and create a stream which downloads them from
+/// `mirror_base_url` instead, so that old builds can still be debugged when
+/// the original servers are gone.
+///
+/// Each file is stored at the host and path of its original URL below
+/// `mirror_dir`, like in [`SrcSrvStream::source_cache_path`] but without the
+/// debug ID, so that the mirrors of multiple streams can share a directory.
+/// `mirror_dir` should be served at `mirror_base_url`, e.g. by a static file
+/// server. Responses which need to be decoded are stored decoded.
+///
+/// The files are downloaded with [`fetch_sources`], which calls `progress`
+/// after every completed download.
+///
+/// ```
+/// use srcsrv::{mirror_sources, FetchOptions, SrcSrvStream};
+/// use std::path::Path;
+///
+/// # fn get(url: &str) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> { unimplemented!() }
+/// # fn wrapper(stream: &SrcSrvStream) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+/// let mirror = mirror_sources(
+///     &get,
+///     stream,
+///     Path::new("/srv/source-mirror"),
+///     "https://source-mirror.example.com/",
+///     &FetchOptions::new(),
+///     |_| {},
+/// );
+/// for entry in &mirror.unmirrored {
+///     eprintln!("Not mirrored: {:?}", entry);
+/// }
+/// let mirrored_stream_bytes = mirror.builder.to_bytes()?;
+/// # Ok(mirrored_stream_bytes)
+/// # }
+/// ```
+pub fn mirror_sources(
+    fetcher: &(impl SourceFetcher + Sync + ?Sized),
+    stream: &SrcSrvStream<'_>,
+    mirror_dir: &Path,
+    mirror_base_url: &str,
+    options: &FetchOptions,
+    progress: impl Fn(FetchProgress) + Sync,
+) -> MirroredStream {
+    let (downloadable, mut unmirrored) = downloadable_entries(stream);
+    let downloads: Vec<_> = downloadable
+        .iter()
+        .map(|entry| (entry.method.clone(), mirror_dir.join(&entry.relative_path)))
+        .collect();
+
+    let mut builder = SrcSrvStreamBuilder::http(mirror_base_url);
+    let results = fetch_sources(fetcher, &downloads, options, progress);
+    for (entry, result) in downloadable.into_iter().zip(results) {
+        match result {
+            Ok(()) => {
+                builder.add_http_entry(&entry.original_path, &entry.relative_path, None);
+            }
+            Err(error) => unmirrored.push((
+                entry.entry_index,
+                UnfetchedEntry::FetchFailed {
+                    original_path: entry.original_path,
+                    error,
+                },
+            )),
+        }
+    }
+    unmirrored.sort_by_key(|(entry_index, _)| *entry_index);
+
+    MirroredStream {
+        builder,
+        unmirrored: unmirrored.into_iter().map(|(_, entry)| entry).collect(),
+    }
+}
+
+/// A file entry which is downloaded from a URL.
+pub(crate) struct DownloadableEntry {
+    /// The index of the entry in the stream.
+    pub(crate) entry_index: usize,
+    pub(crate) original_path: String,
+    pub(crate) method: SourceRetrievalMethod,
+    /// The host and path of the URL, joined with `/`.
+    pub(crate) relative_path: String,
+}
+
+/// The entries of `stream` which are downloaded from a URL, and the entries
+/// which aren't, with their entry index. If a stream has multiple entries for
+/// a path, only the entry which lookups use is returned.
+pub(crate) fn downloadable_entries(
+    stream: &SrcSrvStream<'_>,
+) -> (Vec<DownloadableEntry>, Vec<(usize, UnfetchedEntry)>) {
+    let mut downloadable = Vec::new();
+    let mut other = Vec::new();
+    let mut seen_paths = HashSet::new();
+    for (entry_index, (path, _)) in stream.source_file_entries().enumerate() {
+        if !seen_paths.insert(path.to_ascii_lowercase()) {
+            continue;
+        }
+        let original_path = path.to_string();
+        let method = match stream.source_for_path(path, "") {
+            Ok(Some(method)) => method,
+            Ok(None) => continue,
+            Err(error) => {
+                other.push((
+                    entry_index,
+                    UnfetchedEntry::EvalFailed {
+                        original_path,
+                        error,
+                    },
+                ));
+                continue;
+            }
+        };
+        let relative_path = match &method {
+            SourceRetrievalMethod::Download { .. }
+            | SourceRetrievalMethod::DownloadWithDecode { .. } => relative_target_path(&method, ""),
+            _ => None,
+        };
+        match relative_path {
+            Some(relative_path) => downloadable.push(DownloadableEntry {
+                entry_index,
+                original_path,
+                method,
+                relative_path,
+            }),
+            None => other.push((
+                entry_index,
+                UnfetchedEntry::NotDownload {
+                    original_path,
+                    method,
+                },
+            )),
+        }
+    }
+    (downloadable, other)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{mirror_sources, UnfetchedEntry};
+    use crate::{FetchError, FetchOptions, HttpStatusError, SourceRetrievalMethod, SrcSrvStream};
+    use std::error::Error;
+
+    #[test]
+    fn mirror() {
+        let stream = r#"SRCSRV: ini ------------------------------------------------
+VERSION=2
+SRCSRV: variables ------------------------------------------
+HTTP_ALIAS=https://hg.example.com/repo/raw-file/abc/
+SHARE=\\server\share\
+SRCSRVTRG=%fnvar%(%var2%)%var3%
+SRCSRV: source files ---------------------------------------
+c:\build\a.cpp*HTTP_ALIAS*sub/a.cpp
+c:\build\share.cpp*SHARE*share.cpp
+c:\build\missing.cpp*HTTP_ALIAS*missing.cpp
+SRCSRV: end ------------------------------------------------"#;
+        let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
+        let fetcher = |url: &str| -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+            match url.strip_suffix("missing.cpp") {
+                Some(_) => Err(HttpStatusError { status: 404 }.into()),
+                None => Ok(url.as_bytes().to_vec()),
+            }
+        };
+
+        let dir = std::env::temp_dir().join(format!("srcsrv-mirror-{}", std::process::id()));
+        let mirror = mirror_sources(
+            &fetcher,
+            &stream,
+            &dir,
+            "https://mirror.example.com",
+            &FetchOptions::new(),
+            |_| {},
+        );
+        let contents = std::fs::read(dir.join("hg.example.com/repo/raw-file/abc/sub/a.cpp"));
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            contents.unwrap(),
+            b"https://hg.example.com/repo/raw-file/abc/sub/a.cpp"
+        );
+
+        let bytes = mirror.builder.to_bytes().unwrap();
+        let mirrored = SrcSrvStream::parse(&bytes).unwrap();
+        assert_eq!(
+            mirrored.source_for_path(r#"c:\build\a.cpp"#, "").unwrap(),
+            Some(SourceRetrievalMethod::Download {
+                url: "https://mirror.example.com/hg.example.com/repo/raw-file/abc/sub/a.cpp"
+                    .to_string()
+            })
+        );
+        assert_eq!(mirrored.source_file_entries().count(), 1);
+
+        assert_eq!(mirror.unmirrored.len(), 2);
+        assert!(matches!(
+            &mirror.unmirrored[0],
+            UnfetchedEntry::NotDownload { original_path, .. } if original_path == r#"c:\build\share.cpp"#
+        ));
+        assert!(matches!(
+            &mirror.unmirrored[1],
+            UnfetchedEntry::FetchFailed {
+                error: FetchError::Http(_),
+                ..
+            }
+        ));
+    }
+}
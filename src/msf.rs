@@ -0,0 +1,254 @@
+use crate::PdbError;
+use std::convert::TryInto;
+use std::result::Result;
+
+/// The magic bytes at the start of an MSF 7.0 file, the container format of
+/// PDB files.
+const MAGIC: &[u8; 32] = b"Microsoft C/C++ MSF 7.00\r\n\x1aDS\0\0\0";
+
+/// The size of the superblock, not including the list of block map blocks.
+const SUPERBLOCK_SIZE: usize = MAGIC.len() + 5 * 4;
+
+/// The streams of an MSF file, read into memory.
+///
+/// This is just enough of the format to add or replace a stream and write the
+/// file back out. Streams which are "nil" in the stream directory, i.e. which
+/// have a size of 0xffffffff, are `None`.
+pub(crate) struct Msf {
+    pub(crate) block_size: usize,
+    pub(crate) streams: Vec<Option<Vec<u8>>>,
+}
+
+impl Msf {
+    pub(crate) fn parse(data: &[u8]) -> Result<Msf, PdbError> {
+        let mut header = ByteReader::new(data);
+        if header.read_bytes(MAGIC.len())? != MAGIC {
+            return Err(malformed(
+                "The file does not start with the MSF 7.0 magic bytes.",
+            ));
+        }
+        let block_size = header.read_u32()? as usize;
+        let _free_block_map_block = header.read_u32()?;
+        let _block_count = header.read_u32()?;
+        let directory_size = header.read_u32()? as usize;
+        let _reserved = header.read_u32()?;
+        if !block_size.is_power_of_two() || block_size < 0x100 {
+            return Err(malformed(format!("Invalid block size {block_size}.")));
+        }
+
+        let blocks = Blocks { data, block_size };
+        let directory_block_count = directory_size.div_ceil(block_size);
+        let mut block_map = Vec::new();
+        for _ in 0..(directory_block_count * 4).div_ceil(block_size) {
+            block_map.extend_from_slice(blocks.get(header.read_u32()?)?);
+        }
+        let mut block_map = ByteReader::new(&block_map);
+        let mut directory = Vec::new();
+        for _ in 0..directory_block_count {
+            directory.extend_from_slice(blocks.get(block_map.read_u32()?)?);
+        }
+        directory.truncate(directory_size);
+
+        let mut directory = ByteReader::new(&directory);
+        let stream_count = directory.read_u32()?;
+        let stream_sizes = (0..stream_count)
+            .map(|_| directory.read_u32())
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut streams = Vec::with_capacity(stream_sizes.len());
+        for size in stream_sizes {
+            if size == u32::MAX {
+                streams.push(None);
+                continue;
+            }
+            let size = size as usize;
+            let mut stream = Vec::with_capacity(size);
+            for _ in 0..size.div_ceil(block_size) {
+                stream.extend_from_slice(blocks.get(directory.read_u32()?)?);
+            }
+            stream.truncate(size);
+            streams.push(Some(stream));
+        }
+        Ok(Msf {
+            block_size,
+            streams,
+        })
+    }
+
+    /// Lay out all streams from scratch and return the bytes of the file.
+    pub(crate) fn to_bytes(&self) -> Result<Vec<u8>, PdbError> {
+        let block_size = self.block_size;
+        let mut allocator = BlockAllocator {
+            block_size,
+            next: 3,
+        };
+        let stream_blocks: Vec<Vec<u32>> = self
+            .streams
+            .iter()
+            .map(|stream| {
+                let len = stream.as_ref().map_or(0, Vec::len);
+                allocator.allocate(len.div_ceil(block_size))
+            })
+            .collect();
+
+        let mut directory = Vec::new();
+        directory.extend_from_slice(&(self.streams.len() as u32).to_le_bytes());
+        for stream in &self.streams {
+            let size = stream
+                .as_ref()
+                .map_or(u32::MAX, |stream| stream.len() as u32);
+            directory.extend_from_slice(&size.to_le_bytes());
+        }
+        for block in stream_blocks.iter().flatten() {
+            directory.extend_from_slice(&block.to_le_bytes());
+        }
+        let directory_blocks = allocator.allocate(directory.len().div_ceil(block_size));
+        let block_map: Vec<u8> = directory_blocks
+            .iter()
+            .flat_map(|block| block.to_le_bytes())
+            .collect();
+        let block_map_blocks = allocator.allocate(block_map.len().div_ceil(block_size));
+        if SUPERBLOCK_SIZE + block_map_blocks.len() * 4 > block_size {
+            return Err(malformed("The stream directory is too large."));
+        }
+
+        let block_count = allocator.next;
+        let mut data = vec![0; block_count as usize * block_size];
+        let mut superblock = Vec::with_capacity(block_size);
+        superblock.extend_from_slice(MAGIC);
+        for value in [
+            block_size as u32,
+            1, // The free block map is in block 1.
+            block_count,
+            directory.len() as u32,
+            0,
+        ] {
+            superblock.extend_from_slice(&value.to_le_bytes());
+        }
+        for block in &block_map_blocks {
+            superblock.extend_from_slice(&block.to_le_bytes());
+        }
+        data[..superblock.len()].copy_from_slice(&superblock);
+
+        let mut write_blocks = |blocks: &[u32], bytes: &[u8]| {
+            for (block, chunk) in blocks.iter().zip(bytes.chunks(block_size)) {
+                let start = *block as usize * block_size;
+                data[start..start + chunk.len()].copy_from_slice(chunk);
+            }
+        };
+        for (blocks, stream) in stream_blocks.iter().zip(&self.streams) {
+            write_blocks(blocks, stream.as_deref().unwrap_or_default());
+        }
+        write_blocks(&directory_blocks, &directory);
+        write_blocks(&block_map_blocks, &block_map);
+
+        // Both free block maps are spread over the blocks at 1 and 2 modulo
+        // the block size. A set bit marks a free block. All blocks in the file
+        // are in use, and the bits past the end of the file are set.
+        let free_block_map_blocks: Vec<u32> = (0..block_count)
+            .filter(|block| *block as usize % block_size == 1)
+            .collect();
+        let mut free_block_map = vec![0xff; free_block_map_blocks.len() * block_size];
+        for block in 0..block_count as usize {
+            free_block_map[block / 8] &= !(1 << (block % 8));
+        }
+        let alternate_blocks: Vec<u32> = free_block_map_blocks.iter().map(|b| b + 1).collect();
+        write_blocks(&free_block_map_blocks, &free_block_map);
+        write_blocks(&alternate_blocks, &free_block_map);
+
+        Ok(data)
+    }
+}
+
+/// Hands out block numbers in increasing order, skipping the blocks of the
+/// free block maps and the superblock.
+struct BlockAllocator {
+    block_size: usize,
+    next: u32,
+}
+
+impl BlockAllocator {
+    fn allocate(&mut self, count: usize) -> Vec<u32> {
+        let mut blocks = Vec::with_capacity(count);
+        while blocks.len() < count {
+            if !matches!(self.next as usize % self.block_size, 1 | 2) {
+                blocks.push(self.next);
+            }
+            self.next += 1;
+        }
+        blocks
+    }
+}
+
+struct Blocks<'a> {
+    data: &'a [u8],
+    block_size: usize,
+}
+
+impl<'a> Blocks<'a> {
+    fn get(&self, block: u32) -> Result<&'a [u8], PdbError> {
+        let start = block as usize * self.block_size;
+        self.data
+            .get(start..start + self.block_size)
+            .ok_or_else(|| malformed(format!("Block {block} is out of range.")))
+    }
+}
+
+/// Reads little-endian values from a byte slice.
+pub(crate) struct ByteReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> ByteReader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        ByteReader { data }
+    }
+
+    pub(crate) fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], PdbError> {
+        if len > self.data.len() {
+            return Err(malformed("Unexpected end of data."));
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    pub(crate) fn read_u32(&mut self) -> Result<u32, PdbError> {
+        let bytes = self.read_bytes(4)?;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    /// The bytes which haven't been read yet.
+    pub(crate) fn rest(&self) -> &'a [u8] {
+        self.data
+    }
+}
+
+pub(crate) fn malformed(message: impl Into<String>) -> PdbError {
+    PdbError::MalformedPdb(message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Msf;
+
+    #[test]
+    fn round_trip() {
+        let msf = Msf {
+            block_size: 0x200,
+            streams: vec![
+                Some(vec![]),
+                Some(b"info".to_vec()),
+                None,
+                Some((0..0x60000).map(|i| i as u8).collect()),
+            ],
+        };
+        let bytes = msf.to_bytes().unwrap();
+        assert_eq!(bytes.len() % 0x200, 0);
+        let parsed = Msf::parse(&bytes).unwrap();
+        assert_eq!(parsed.block_size, 0x200);
+        assert_eq!(parsed.streams, msf.streams);
+
+        assert!(Msf::parse(&bytes[..0x200]).is_err());
+        assert!(Msf::parse(b"Microsoft C/C++ program database 2.00\r\n").is_err());
+    }
+}
@@ -0,0 +1,526 @@
+use crate::insecure_urls::upgrade_http_url;
+use crate::{CommandPolicy, EvalError, EvalVarMap, SourceRetrievalMethod, UrlPolicy};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// Options for [`SrcSrvStream::parse_with_options`](crate::SrcSrvStream::parse_with_options).
+///
+/// ```
+/// use srcsrv::{ParseOptions, SrcSrvStream};
+///
+/// # fn wrapper(bytes: &[u8]) -> std::result::Result<(), srcsrv::ParseError> {
+/// let stream = SrcSrvStream::parse_with_options(bytes, &ParseOptions::new().lenient(true))?;
+/// for warning in stream.warnings() {
+///     eprintln!("{}", warning);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ParseOptions {
+    pub(crate) lenient: bool,
+    pub(crate) allow_missing_end_marker: bool,
+    pub(crate) duplicate_variable_policy: DuplicatePolicy,
+    pub(crate) duplicate_entry_policy: DuplicatePolicy,
+    pub(crate) preserve_layout: bool,
+    #[cfg(feature = "rayon")]
+    pub(crate) parallel: bool,
+}
+
+impl ParseOptions {
+    /// Create the default options, which are the options used by
+    /// [`SrcSrvStream::parse`](crate::SrcSrvStream::parse).
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// In lenient mode, blank lines are ignored, and lines in the ini, variables
+    /// and source files sections which cannot be parsed are skipped and recorded
+    /// as [`ParseWarning`](crate::ParseWarning)s, instead of failing the parse.
+    ///
+    /// Defaults to `false`.
+    pub fn lenient(mut self, lenient: bool) -> Self {
+        self.lenient = lenient;
+        self
+    }
+
+    /// Accept streams which end in the source files section without a
+    /// `SRCSRV: end` line, such as truncated streams. The entries up to the end
+    /// of the stream are kept, and a [`ParseWarning::MissingEndMarker`](crate::ParseWarning::MissingEndMarker)
+    /// is recorded.
+    ///
+    /// Defaults to `false`, which fails with [`ParseError::UnexpectedEof`](crate::ParseError::UnexpectedEof).
+    pub fn allow_missing_end_marker(mut self, allow: bool) -> Self {
+        self.allow_missing_end_marker = allow;
+        self
+    }
+
+    /// What to do if the variables section defines the same variable more
+    /// than once (ASCII case-insensitively).
+    ///
+    /// Defaults to [`DuplicatePolicy::LastWins`].
+    pub fn duplicate_variable_policy(mut self, policy: DuplicatePolicy) -> Self {
+        self.duplicate_variable_policy = policy;
+        self
+    }
+
+    /// What to do if the source files section has more than one entry for the
+    /// same original path (ASCII case-insensitively).
+    ///
+    /// Defaults to [`DuplicatePolicy::LastWins`].
+    pub fn duplicate_entry_policy(mut self, policy: DuplicatePolicy) -> Self {
+        self.duplicate_entry_policy = policy;
+        self
+    }
+
+    /// Keep the formatting of the stream which doesn't affect its meaning:
+    /// the section header lines, the line terminators, a byte order mark and
+    /// anything after the end line. [`SrcSrvStream::to_bytes`](crate::SrcSrvStream::to_bytes)
+    /// and [`SrcSrvStream::to_builder`](crate::SrcSrvStream::to_builder) then
+    /// write the stream back unchanged, and `to_builder` also keeps variables
+    /// which are defined more than once.
+    ///
+    /// The output can still differ from the input if lines were skipped in
+    /// lenient mode, if the stream mixes different line terminators, or if
+    /// the end line is missing.
+    ///
+    /// Defaults to `false`.
+    pub fn preserve_layout(mut self, preserve: bool) -> Self {
+        self.preserve_layout = preserve;
+        self
+    }
+
+    /// Build the index of the file entries on multiple threads, using rayon's
+    /// global thread pool. On machines with several cores, this can make
+    /// parsing streams with many file entries faster. It gives the same result
+    /// as the sequential parse.
+    ///
+    /// This has no effect with [`DuplicatePolicy::Error`] for entries, because
+    /// duplicate entries then need to be found in stream order.
+    ///
+    /// Defaults to `false`.
+    #[cfg(feature = "rayon")]
+    pub fn parallel(mut self, parallel: bool) -> Self {
+        self.parallel = parallel;
+        self
+    }
+}
+
+/// How to handle variables or file entries which are defined more than once,
+/// see [`ParseOptions::duplicate_variable_policy`] and
+/// [`ParseOptions::duplicate_entry_policy`].
+///
+/// Except with `Error`, the duplicates are available from
+/// [`SrcSrvStream::duplicate_variables`](crate::SrcSrvStream::duplicate_variables)
+/// and [`SrcSrvStream::duplicate_entries`](crate::SrcSrvStream::duplicate_entries)
+/// after parsing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum DuplicatePolicy {
+    /// Use the last definition.
+    #[default]
+    LastWins,
+    /// Use the first definition.
+    FirstWins,
+    /// Fail with [`ParseError::DuplicateVariable`](crate::ParseError::DuplicateVariable)
+    /// or [`ParseError::DuplicateEntry`](crate::ParseError::DuplicateEntry). In
+    /// lenient mode, the duplicate line is skipped instead.
+    Error,
+    /// Use the last definition, but keep all definitions available from
+    /// [`SrcSrvStream::get_raw_var_all`](crate::SrcSrvStream::get_raw_var_all) and
+    /// [`SrcSrvStream::source_for_path_all`](crate::SrcSrvStream::source_for_path_all).
+    CollectAll,
+}
+
+/// Options for looking up file paths, for example with
+/// [`SrcSrvStream::source_for_path_with_options`](crate::SrcSrvStream::source_for_path_with_options).
+///
+/// Lookups are always ASCII case-insensitive, so paths which only differ in
+/// the case of their drive letter, or of any other character, always match.
+///
+/// If the exact (case-insensitive) path is not found, the path is looked up
+/// again with the enabled normalizations applied to both the requested path
+/// and the paths in the stream.
+///
+/// ```
+/// use srcsrv::{LookupOptions, SrcSrvStream};
+///
+/// # fn wrapper(stream: &SrcSrvStream) -> std::result::Result<(), srcsrv::EvalError> {
+/// let options = LookupOptions::new()
+///     .normalize_separators(true)
+///     .resolve_dot_components(true);
+/// let method = stream.source_for_path_with_options(
+///     "c:/build/renderdoc/renderdoc/maths/../maths/matrix.cpp",
+///     r#"C:\Debugger\Cached Sources"#,
+///     &options,
+/// )?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct LookupOptions {
+    pub(crate) normalize_separators: bool,
+    pub(crate) resolve_dot_components: bool,
+    pub(crate) suffix_match_min_components: Option<usize>,
+}
+
+impl LookupOptions {
+    /// Create the default options, which are the options used by
+    /// [`SrcSrvStream::source_for_path`](crate::SrcSrvStream::source_for_path).
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Treat forward slashes and backslashes as equivalent path separators.
+    ///
+    /// Defaults to `false`.
+    pub fn normalize_separators(mut self, normalize_separators: bool) -> Self {
+        self.normalize_separators = normalize_separators;
+        self
+    }
+
+    /// Remove `.` components and resolve `..` components against the preceding
+    /// component, so that `C:\build\src\..\include\foo.h` matches
+    /// `C:\build\include\foo.h`. Both `/` and `\` are treated as separators
+    /// when splitting the path into components.
+    ///
+    /// Defaults to `false`.
+    pub fn resolve_dot_components(mut self, resolve_dot_components: bool) -> Self {
+        self.resolve_dot_components = resolve_dot_components;
+        self
+    }
+
+    /// If the path is not found, fall back to matching the file entries by their
+    /// trailing path components, see [`SrcSrvStream::suffix_match_candidates`](crate::SrcSrvStream::suffix_match_candidates).
+    /// The best candidate is used if it has at least `min_matching_components`
+    /// matching components, and if no other candidate has as many matching
+    /// components as it.
+    ///
+    /// This is useful if the paths of the build machine had a different prefix,
+    /// for example `D:\a\1\s\src\foo.cpp` vs. `/home/me/project/src/foo.cpp`.
+    ///
+    /// Defaults to `None`, i.e. no suffix matching.
+    pub fn suffix_match_fallback(mut self, min_matching_components: Option<usize>) -> Self {
+        self.suffix_match_min_components = min_matching_components;
+        self
+    }
+
+    /// A number in 1..=3 which identifies the set of enabled normalizations,
+    /// if any normalization is enabled.
+    pub(crate) fn normalization_kind(&self) -> usize {
+        (self.normalize_separators as usize) | (self.resolve_dot_components as usize) << 1
+    }
+
+    /// Normalize `path` for lookups. The result is lowercase.
+    pub(crate) fn normalize_path(&self, path: &str) -> String {
+        let mut path = path.to_ascii_lowercase();
+        if self.normalize_separators {
+            path = path.replace('/', "\\");
+        }
+        if self.resolve_dot_components {
+            path = resolve_dot_components(&path);
+        }
+        path
+    }
+}
+
+/// Options for evaluating the variables of a file entry, for example with
+/// [`SrcSrvStream::source_for_path_with_vars`](crate::SrcSrvStream::source_for_path_with_vars).
+///
+/// ```
+/// use srcsrv::{EvalOptions, SrcSrvStream};
+///
+/// # fn wrapper(stream: &SrcSrvStream) -> std::result::Result<(), srcsrv::EvalError> {
+/// let options = EvalOptions::new().var("HGSERVER", "https://hg-mirror.example.com/mozilla-central");
+/// let method = stream.source_for_path_with_vars(
+///     "/builds/worker/checkouts/gecko/mozglue/build/SSE.cpp",
+///     r#"C:\Debugger\Cached Sources"#,
+///     &options,
+/// )?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EvalOptions {
+    /// lowercase variable name -> value
+    pub(crate) vars: EvalVarMap,
+    pub(crate) lookup: LookupOptions,
+    /// lowercase function name -> handler
+    pub(crate) functions: HashMap<String, CustomFunction>,
+    pub(crate) unknown_function_policy: UnknownFunctionPolicy,
+    pub(crate) unknown_variable_policy: UnknownVariablePolicy,
+    pub(crate) url_policy: UrlPolicy,
+    pub(crate) upgrade_http: bool,
+    pub(crate) command_policy: CommandPolicy,
+    pub(crate) recognize_commands: bool,
+}
+
+/// What to do when a template calls a function which is neither built in nor
+/// registered with [`EvalOptions::function`], see
+/// [`EvalOptions::unknown_function_policy`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum UnknownFunctionPolicy {
+    /// Fail with [`EvalError::UnknownFunction`].
+    #[default]
+    Error,
+    /// Keep the call in the output, with the evaluated argument:
+    /// `%fnfoo%(%var2%)` evaluates to `%fnfoo%(value of var2)`.
+    Literal,
+    /// Treat `%fnfoo%` as a variable reference which is followed by a
+    /// parenthesized literal: `%fnfoo%(%var2%)` evaluates to
+    /// `value of fnfoo(value of var2)`.
+    Variable,
+}
+
+/// What to do when a template references a variable which is not defined, see
+/// [`EvalOptions::unknown_variable_policy`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum UnknownVariablePolicy {
+    /// Fail with [`EvalError::UnknownVariable`].
+    #[default]
+    Error,
+    /// Substitute the empty string.
+    Empty,
+    /// Keep the reference in the output: `%Foo%` evaluates to `%foo%` (the
+    /// variable name is lowercased). This matches what srcsrv.dll does in some
+    /// cases.
+    KeepLiteral,
+}
+
+type CustomFunctionHandler = dyn Fn(&str) -> Result<String, EvalError> + Send + Sync;
+
+/// A function handler registered with [`EvalOptions::function`].
+#[derive(Clone)]
+pub(crate) struct CustomFunction(Arc<CustomFunctionHandler>);
+
+impl CustomFunction {
+    pub(crate) fn call(&self, arg: &str) -> Result<String, EvalError> {
+        (self.0)(arg)
+    }
+}
+
+impl fmt::Debug for CustomFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("CustomFunction")
+    }
+}
+
+impl PartialEq for CustomFunction {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for CustomFunction {}
+
+impl EvalOptions {
+    /// Create the default options, which evaluate the stream as it is.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use `value` as the value of the variable `var_name`, instead of the
+    /// value from the stream. This can also define variables that the stream
+    /// does not define, for example a server variable that is referenced via
+    /// `%fnvar%(%var2%)`. The variable name is case-insensitive.
+    ///
+    /// The value is used as it is; it is not expanded as a template. The
+    /// per-entry variables var1, ..., var10 and `%targ%` cannot be overridden.
+    pub fn var(mut self, var_name: &str, value: &str) -> Self {
+        self.vars
+            .insert(var_name.to_ascii_lowercase(), value.to_string());
+        self
+    }
+
+    /// Register a handler for the function `function_name`, so that
+    /// `%function_name%(arg)` in a template evaluates to `f(evaluated arg)`.
+    /// Function names start with `fn` and are case-insensitive. The built-in
+    /// functions `fnvar`, `fnbksl` and `fnfile` cannot be replaced.
+    ///
+    /// Calls to functions without a handler fail with
+    /// [`EvalError::UnknownFunction`].
+    ///
+    /// ```
+    /// use srcsrv::EvalOptions;
+    ///
+    /// let options = EvalOptions::new().function("fnlower", |arg| Ok(arg.to_lowercase()));
+    /// ```
+    pub fn function<F>(mut self, function_name: &str, f: F) -> Self
+    where
+        F: Fn(&str) -> Result<String, EvalError> + Send + Sync + 'static,
+    {
+        self.functions.insert(
+            function_name.to_ascii_lowercase(),
+            CustomFunction(Arc::new(f)),
+        );
+        self
+    }
+
+    /// How to evaluate calls to unknown functions.
+    ///
+    /// Defaults to [`UnknownFunctionPolicy::Error`].
+    pub fn unknown_function_policy(mut self, policy: UnknownFunctionPolicy) -> Self {
+        self.unknown_function_policy = policy;
+        self
+    }
+
+    /// How to evaluate references to unknown variables.
+    ///
+    /// Defaults to [`UnknownVariablePolicy::Error`].
+    pub fn unknown_variable_policy(mut self, policy: UnknownVariablePolicy) -> Self {
+        self.unknown_variable_policy = policy;
+        self
+    }
+
+    /// Fail with [`EvalError::UrlNotAllowed`] if the entry evaluates to a
+    /// download from a URL which `policy` doesn't allow.
+    ///
+    /// Defaults to [`UrlPolicy::new`], which allows all URLs.
+    pub fn url_policy(mut self, policy: UrlPolicy) -> Self {
+        self.url_policy = policy;
+        self
+    }
+
+    /// Replace `http://` with `https://` in download URLs. Most servers which
+    /// are referenced by old streams support https nowadays. Use
+    /// [`SourceRetrievalMethod::is_insecure`](crate::SourceRetrievalMethod::is_insecure)
+    /// without this option to find out which entries use plain http.
+    ///
+    /// The URL policy is applied to the upgraded URL.
+    ///
+    /// Defaults to `false`.
+    pub fn upgrade_http(mut self, upgrade_http: bool) -> Self {
+        self.upgrade_http = upgrade_http;
+        self
+    }
+
+    /// Fail with [`EvalError::CommandNotAllowed`] if the entry evaluates to a
+    /// [`SourceRetrievalMethod::ExecuteCommand`] whose command runs a program
+    /// which `policy` doesn't allow.
+    ///
+    /// Defaults to [`CommandPolicy::new`], which allows all commands.
+    pub fn command_policy(mut self, policy: CommandPolicy) -> Self {
+        self.command_policy = policy;
+        self
+    }
+
+    /// Return commands of well-known shapes as the retrieval methods which
+    /// describe them, instead of as [`SourceRetrievalMethod::ExecuteCommand`],
+    /// so that the file can be obtained without running the command: for
+    /// example [`SourceRetrievalMethod::GitFile`] for `git show` commands,
+    /// [`SourceRetrievalMethod::TfsItem`] for `tf.exe view` commands, or
+    /// [`SourceRetrievalMethod::DownloadWithDecode`] for the python download
+    /// one-liners in Chrome's streams. The [`EvalOptions::command_policy`] is
+    /// not applied to recognized commands.
+    ///
+    /// Defaults to `false`.
+    pub fn recognize_commands(mut self, recognize_commands: bool) -> Self {
+        self.recognize_commands = recognize_commands;
+        self
+    }
+
+    /// Apply [`EvalOptions::upgrade_http`] and [`EvalOptions::url_policy`] to
+    /// the evaluated `method`.
+    pub(crate) fn apply_url_options(
+        &self,
+        mut method: SourceRetrievalMethod,
+    ) -> Result<SourceRetrievalMethod, EvalError> {
+        if let SourceRetrievalMethod::Download { url }
+        | SourceRetrievalMethod::DownloadWithDecode { url, .. } = &mut method
+        {
+            if self.upgrade_http {
+                if let Some(https_url) = upgrade_http_url(url) {
+                    *url = https_url;
+                }
+            }
+            if !self.url_policy.is_allowed(url) {
+                return Err(EvalError::UrlNotAllowed(url.clone()));
+            }
+        }
+        Ok(method)
+    }
+
+    /// The options for matching the file path against the file entries.
+    ///
+    /// Defaults to [`LookupOptions::default()`].
+    pub fn lookup_options(mut self, lookup_options: LookupOptions) -> Self {
+        self.lookup = lookup_options;
+        self
+    }
+}
+
+/// Remove `.` components and resolve `..` components in `path`, keeping the
+/// original separators. Leading `..` components which cannot be resolved are
+/// kept.
+fn resolve_dot_components(path: &str) -> String {
+    // Each component is stored with the separator that precedes it, if any.
+    let mut components: Vec<(Option<char>, &str)> = Vec::new();
+    let mut separator = None;
+    let mut rest = path;
+    loop {
+        let (component, next) = match rest.find(['/', '\\']) {
+            Some(pos) => (
+                &rest[..pos],
+                Some((rest.as_bytes()[pos] as char, &rest[pos + 1..])),
+            ),
+            None => (rest, None),
+        };
+        match component {
+            "." => {}
+            ".." => match components.last() {
+                // The root (e.g. "" for "/foo", or "c:" for "c:\foo") has no parent.
+                Some((_, root)) if components.len() == 1 && is_root(root) => {}
+                Some((_, last)) if *last != ".." => {
+                    components.pop();
+                }
+                _ => components.push((separator, component)),
+            },
+            _ => components.push((separator, component)),
+        }
+        match next {
+            Some((next_separator, next_rest)) => {
+                separator = Some(next_separator);
+                rest = next_rest;
+            }
+            None => break,
+        }
+    }
+
+    let mut result = String::with_capacity(path.len());
+    for (i, (separator, component)) in components.into_iter().enumerate() {
+        if let (Some(separator), true) = (separator, i != 0) {
+            result.push(separator);
+        }
+        result.push_str(component);
+    }
+    result
+}
+
+fn is_root(component: &str) -> bool {
+    component.is_empty() || component.ends_with(':')
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::LookupOptions;
+
+    #[test]
+    fn normalize_path() {
+        let options = LookupOptions::new();
+        assert_eq!(options.normalize_path(r#"C:\Foo/Bar"#), r#"c:\foo/bar"#);
+
+        let options = LookupOptions::new().normalize_separators(true);
+        assert_eq!(options.normalize_path(r#"C:\Foo/Bar"#), r#"c:\foo\bar"#);
+
+        let options = LookupOptions::new().resolve_dot_components(true);
+        assert_eq!(
+            options.normalize_path(r#"C:\build\src\.\..\include/./foo.h"#),
+            r#"c:\build\include/foo.h"#
+        );
+        assert_eq!(options.normalize_path("/a/../../b"), "/b");
+        assert_eq!(options.normalize_path("../a/./b/.."), "../a");
+    }
+}
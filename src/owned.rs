@@ -0,0 +1,204 @@
+use crate::errors::ParseError;
+use crate::{ParseOptions, SrcSrvStream};
+use std::mem::ManuallyDrop;
+use std::ptr::NonNull;
+use std::result::Result;
+
+/// A parsed `srcsrv` stream which owns the stream bytes, so that it can be
+/// stored without borrowing from the PDB file's buffer.
+///
+/// Use [`OwnedSrcSrvStream::stream`] to query the stream.
+///
+/// ```
+/// use srcsrv::OwnedSrcSrvStream;
+///
+/// # fn wrapper<'s, S: pdb::Source<'s> + 's>(pdb: &mut pdb::PDB<'s, S>) -> std::result::Result<Option<OwnedSrcSrvStream>, srcsrv::ParseError> {
+/// let stream = match pdb.named_stream(b"srcsrv") {
+///     Ok(srcsrv_stream) => OwnedSrcSrvStream::parse(srcsrv_stream.as_slice().to_vec())?,
+///     Err(_) => return Ok(None),
+/// };
+/// println!("{:?}", stream.stream().version_control_description());
+/// # Ok(Some(stream))
+/// # }
+/// ```
+pub struct OwnedSrcSrvStream {
+    /// Borrows from `data`. The `'static` lifetime is a lie: the stream is
+    /// only handed out with a lifetime that is tied to `self`, and it is
+    /// dropped before `data` is freed.
+    stream: ManuallyDrop<SrcSrvStream<'static>>,
+    /// The stream bytes, which were allocated as a `Box<[u8]>`.
+    data: NonNull<[u8]>,
+}
+
+// SAFETY: The stream bytes are never mutated, and SrcSrvStream is Send + Sync.
+unsafe impl Send for OwnedSrcSrvStream {}
+unsafe impl Sync for OwnedSrcSrvStream {}
+
+impl OwnedSrcSrvStream {
+    /// Parse the `srcsrv` stream, taking ownership of the stream bytes.
+    /// See [`SrcSrvStream::parse`].
+    pub fn parse(data: Vec<u8>) -> Result<OwnedSrcSrvStream, ParseError> {
+        Self::parse_with_options(data, &ParseOptions::default())
+    }
+
+    /// Parse the `srcsrv` stream with the given options, taking ownership of
+    /// the stream bytes. See [`SrcSrvStream::parse_with_options`].
+    pub fn parse_with_options(
+        data: Vec<u8>,
+        options: &ParseOptions,
+    ) -> Result<OwnedSrcSrvStream, ParseError> {
+        let data = NonNull::from(Box::leak(data.into_boxed_slice()));
+        // SAFETY: `data` stays allocated and unmodified until `self` is dropped,
+        // and the stream which borrows from it is dropped first.
+        let bytes: &'static [u8] = unsafe { data.as_ref() };
+        match SrcSrvStream::parse_with_options(bytes, options) {
+            Ok(stream) => Ok(OwnedSrcSrvStream {
+                stream: ManuallyDrop::new(stream),
+                data,
+            }),
+            Err(err) => {
+                // SAFETY: The data came from a Box and nothing borrows from it anymore.
+                drop(unsafe { Box::from_raw(data.as_ptr()) });
+                Err(err)
+            }
+        }
+    }
+
+    /// Parse a `srcsrv` stream which is not encoded in UTF-8, such as a
+    /// stream with Windows-1252 paths from an older PDB. The bytes are
+    /// transcoded to UTF-8 first; malformed sequences are replaced with
+    /// U+FFFD. Pass [`encoding_rs::UTF_8`] to parse a stream with some invalid
+    /// UTF-8 in it instead of failing with [`ParseError::InvalidUtf8`].
+    ///
+    /// [`as_bytes`](OwnedSrcSrvStream::as_bytes) returns the transcoded bytes.
+    ///
+    /// ```
+    /// use srcsrv::{encoding_rs, OwnedSrcSrvStream, ParseOptions};
+    ///
+    /// # fn wrapper(bytes: Vec<u8>) -> std::result::Result<(), srcsrv::ParseError> {
+    /// let encoding = encoding_rs::Encoding::for_label(b"windows-1252").unwrap();
+    /// let stream = OwnedSrcSrvStream::parse_with_encoding(bytes, encoding, &ParseOptions::new())?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature = "encoding_rs")]
+    pub fn parse_with_encoding(
+        data: Vec<u8>,
+        encoding: &'static encoding_rs::Encoding,
+        options: &ParseOptions,
+    ) -> Result<OwnedSrcSrvStream, ParseError> {
+        let data = match encoding.decode_without_bom_handling(&data) {
+            (std::borrow::Cow::Borrowed(_), _) => data,
+            (std::borrow::Cow::Owned(decoded), _) => decoded.into_bytes(),
+        };
+        Self::parse_with_options(data, options)
+    }
+
+    /// The parsed stream.
+    pub fn stream(&self) -> &SrcSrvStream<'_> {
+        let stream: *const SrcSrvStream<'static> = &*self.stream;
+        // SAFETY: SrcSrvStream is invariant over its lifetime because of its
+        // lazily initialized caches, so the lifetime can't simply be shortened.
+        // This is still fine because the caches are only ever filled with
+        // data derived from the stream bytes, which outlive the returned reference.
+        unsafe { &*stream.cast::<SrcSrvStream<'_>>() }
+    }
+
+    /// The raw bytes of the stream.
+    pub fn as_bytes(&self) -> &[u8] {
+        // SAFETY: See `parse_with_options`.
+        unsafe { self.data.as_ref() }
+    }
+}
+
+impl Drop for OwnedSrcSrvStream {
+    fn drop(&mut self) {
+        // SAFETY: The stream is dropped before the data it borrows from, and
+        // neither is used afterwards.
+        unsafe {
+            ManuallyDrop::drop(&mut self.stream);
+            drop(Box::from_raw(self.data.as_ptr()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{OwnedSrcSrvStream, ParseError, SourceRetrievalMethod, SrcSrvStream};
+
+    fn make_owned_stream() -> OwnedSrcSrvStream {
+        let stream = r#"SRCSRV: ini ------------------------------------------------
+VERSION=2
+VERCTRL=http
+SRCSRV: variables ------------------------------------------
+HTTP_ALIAS=https://raw.githubusercontent.com/baldurk/renderdoc/v1.15/
+SRCSRVTRG=%HTTP_ALIAS%%var2%
+SRCSRV: source files ---------------------------------------
+C:\build\renderdoc\renderdoc\maths\matrix.cpp*renderdoc/maths/matrix.cpp
+SRCSRV: end ------------------------------------------------"#
+            .to_string();
+        SrcSrvStream::parse_owned(stream.into_bytes()).unwrap()
+    }
+
+    #[test]
+    fn owned() {
+        let stream = make_owned_stream();
+        assert_eq!(stream.stream().version_control_description(), Some("http"));
+        assert_eq!(
+            stream
+                .stream()
+                .source_for_path(r#"C:\build\renderdoc\renderdoc\maths\matrix.cpp"#, "")
+                .unwrap(),
+            Some(SourceRetrievalMethod::Download {
+                url: "https://raw.githubusercontent.com/baldurk/renderdoc/v1.15/renderdoc/maths/matrix.cpp".to_string()
+            })
+        );
+        assert!(stream.as_bytes().starts_with(b"SRCSRV: ini"));
+    }
+
+    #[cfg(feature = "encoding_rs")]
+    #[test]
+    fn owned_with_encoding() {
+        let stream = b"SRCSRV: ini ------------------------------------------------
+VERSION=2
+SRCSRV: variables ------------------------------------------
+SRCSRVTRG=https://example.com/%var2%
+SRCSRV: source files ---------------------------------------
+C:\\build\\caf\xe9.cpp*caf\xe9.cpp
+SRCSRV: end ------------------------------------------------"
+            .to_vec();
+        assert_eq!(
+            OwnedSrcSrvStream::parse(stream.clone()).err(),
+            Some(ParseError::InvalidUtf8)
+        );
+
+        let owned = OwnedSrcSrvStream::parse_with_encoding(
+            stream.clone(),
+            encoding_rs::WINDOWS_1252,
+            &Default::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            owned
+                .stream()
+                .source_for_path("C:\\build\\caf\u{e9}.cpp", "")
+                .unwrap(),
+            Some(SourceRetrievalMethod::Download {
+                url: "https://example.com/caf\u{e9}.cpp".to_string()
+            })
+        );
+
+        let owned =
+            OwnedSrcSrvStream::parse_with_encoding(stream, encoding_rs::UTF_8, &Default::default())
+                .unwrap();
+        assert_eq!(owned.stream().source_file_entries().count(), 1);
+    }
+
+    #[test]
+    fn owned_error() {
+        assert_eq!(
+            OwnedSrcSrvStream::parse(Vec::new()).err(),
+            Some(ParseError::UnexpectedEof)
+        );
+    }
+}
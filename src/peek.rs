@@ -0,0 +1,88 @@
+use crate::case_insensitive::CaseInsensitiveStr;
+use crate::{parse_ini_section, LineReader, ParseError, ParseOptions};
+use std::result::Result;
+
+/// The version information from the ini section of a `srcsrv` stream, see
+/// [`SrcSrvStream::peek_version`](crate::SrcSrvStream::peek_version).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SrcSrvStreamVersion<'a> {
+    /// The value of the VERSION field: 1, 2 or 3.
+    pub version: u8,
+    /// The value of the VERCTRL field, if specified.
+    pub version_control_description: Option<&'a str>,
+    /// The value of the INDEXVERSION field, if specified.
+    pub index_version: Option<&'a str>,
+    /// The value of the DATETIME field, if specified.
+    pub datetime: Option<&'a str>,
+}
+
+impl<'a> SrcSrvStreamVersion<'a> {
+    pub(crate) fn peek(stream: &'a [u8]) -> Result<Self, ParseError> {
+        let options = ParseOptions::default();
+        let mut lines = LineReader::new(ini_section_prefix(stream), &options)?;
+        let (ini, _) = parse_ini_section(&mut lines, &options, &mut Vec::new())?;
+        let field = |name| ini.fields.get(CaseInsensitiveStr::new(name)).copied();
+        Ok(SrcSrvStreamVersion {
+            version: ini.version,
+            version_control_description: field("VERCTRL"),
+            index_version: field("INDEXVERSION"),
+            datetime: field("DATETIME"),
+        })
+    }
+}
+
+/// The beginning of `stream`, up to the start of the line which ends the ini
+/// section, so that only this part needs to be checked for valid UTF-8. This
+/// is the whole stream if the ini section doesn't end.
+fn ini_section_prefix(stream: &[u8]) -> &[u8] {
+    const SECTION_START: &[u8] = b"SRCSRV:";
+    memchr::memmem::find_iter(stream, SECTION_START)
+        .find(|&pos| pos > 0 && matches!(stream[pos - 1], b'\r' | b'\n'))
+        .map_or(stream, |pos| &stream[..pos + SECTION_START.len()])
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ParseError, SrcSrvStream, SrcSrvStreamVersion};
+
+    #[test]
+    fn peek_version() {
+        let mut stream = b"SRCSRV: ini ------------------------------------------------
+VERSION=2
+INDEXVERSION=2
+VERCTRL=Team Foundation Server
+DATETIME=Thu Mar 10 16:15:55 2016
+SRCSRV: variables ------------------------------------------
+SRCSRVTRG=%targ%\\%var2%
+SRCSRV: source files ---------------------------------------
+C:\\build\\"
+            .to_vec();
+        // Everything after the ini section is ignored, even invalid UTF-8.
+        stream.extend_from_slice(b"caf\xe9.cpp*caf\xe9.cpp");
+        assert_eq!(
+            SrcSrvStream::peek_version(&stream),
+            Ok(SrcSrvStreamVersion {
+                version: 2,
+                version_control_description: Some("Team Foundation Server"),
+                index_version: Some("2"),
+                datetime: Some("Thu Mar 10 16:15:55 2016"),
+            })
+        );
+        assert!(SrcSrvStream::is_srcsrv_stream(&stream));
+        assert_eq!(
+            SrcSrvStream::parse(&stream).err(),
+            Some(ParseError::InvalidUtf8)
+        );
+
+        assert!(!SrcSrvStream::is_srcsrv_stream(b""));
+        assert!(!SrcSrvStream::is_srcsrv_stream(b"\x00\x01\x02"));
+        assert_eq!(
+            SrcSrvStream::peek_version(b"SRCSRV: ini ---\nVERSION=4\nSRCSRV: variables ---\n"),
+            Err(ParseError::UnrecognizedVersion("4".to_string()))
+        );
+        assert_eq!(
+            SrcSrvStream::peek_version(b"SRCSRV: ini ---\nVERSION=1\n"),
+            Err(ParseError::UnexpectedEof)
+        );
+    }
+}
@@ -0,0 +1,532 @@
+use crate::recognize::{is_cabinet, is_operator, is_program, tokenize_command};
+use crate::{ContentEncoding, RetrievalKind, SourceRetrievalMethod, TranslateError};
+use std::collections::HashMap;
+use std::process::Command;
+use std::result::Result;
+
+/// The programs which commands may run to be translated by
+/// [`SourceRetrievalMethod::to_portable_command`]. They are available, under
+/// the same name and with the same arguments, on all platforms.
+const PORTABLE_PROGRAMS: &[&str] = &["git", "hg", "svn", "p4", "cvs", "tar", "curl", "wget"];
+
+/// A way to obtain a source file which doesn't need the Windows Command shell,
+/// see [`SourceRetrievalMethod::to_portable_command`].
+///
+/// The commands of [`PortableCommand::Run`] and [`PortableCommand::Shell`]
+/// write the contents of the source file to their standard output, so that the
+/// caller can store the file wherever it wants, for example with
+/// [`SourceCache::store`](crate::SourceCache::store).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PortableCommand {
+    /// Download the file from `url`, and decode the response with `encoding`
+    /// if there is one.
+    Download {
+        url: String,
+        encoding: Option<ContentEncoding>,
+    },
+    /// Run `program` with `args`, without a shell.
+    Run {
+        /// The name of the program, without a directory or an `.exe` extension,
+        /// e.g. `git`.
+        program: String,
+        args: Vec<String>,
+        /// The environment variables to set.
+        env: HashMap<String, String>,
+    },
+    /// Run `script` with `sh -c`. This is used for pipelines, such as
+    /// `git archive ... | tar -xO`.
+    Shell {
+        script: String,
+        /// The environment variables to set.
+        env: HashMap<String, String>,
+    },
+}
+
+impl PortableCommand {
+    /// A [`Command`] which runs this command, or `None` for
+    /// [`PortableCommand::Download`].
+    pub fn command(&self) -> Option<Command> {
+        match self {
+            PortableCommand::Download { .. } => None,
+            PortableCommand::Run { program, args, env } => {
+                let mut command = Command::new(program);
+                command.args(args).envs(env);
+                Some(command)
+            }
+            PortableCommand::Shell { script, env } => {
+                let mut command = Command::new("sh");
+                command.arg("-c").arg(script).envs(env);
+                Some(command)
+            }
+        }
+    }
+}
+
+impl SourceRetrievalMethod {
+    /// Translate this retrieval method into a form which can be used on all
+    /// platforms, for example by symbol servers which run on Linux.
+    ///
+    /// Downloads, including the Python one-liners of
+    /// [`SourceRetrievalMethod::DownloadWithDecode`], become
+    /// [`PortableCommand::Download`]. The recognized version control methods
+    /// become `git`, `svn`, `p4` or `cvs` invocations which print the file, and
+    /// files from cabinets become `cabextract -p` invocations.
+    /// Other commands are translated if they only run programs which exist on
+    /// all platforms (`git`, `hg`, `svn`, `p4`, `cvs`, `tar`, `curl` and `wget`),
+    /// chained with `|`, and write the file by redirecting the output to the
+    /// target path. `cmd /c` prefixes are removed, and `%VAR%` references to the
+    /// variables in `SRCSRVENV` are expanded.
+    ///
+    /// Repository paths and URLs are used as they are. The revision of a
+    /// [`SourceRetrievalMethod::Perforce`] method is passed after `#`, like in
+    /// the commands of the Perforce indexing script.
+    ///
+    /// ```
+    /// use srcsrv::{PortableCommand, SourceRetrievalMethod};
+    /// use std::collections::HashMap;
+    ///
+    /// let method = SourceRetrievalMethod::Svn {
+    ///     url: "https://svn.example.com/repo/trunk/main.cpp".to_string(),
+    ///     revision: "1234".to_string(),
+    ///     target_path: r#"C:\Cache\main.cpp"#.to_string(),
+    ///     env: HashMap::new(),
+    ///     error_persistence_version_control: None,
+    /// };
+    /// match method.to_portable_command() {
+    ///     Ok(PortableCommand::Run { program, args, .. }) => {
+    ///         assert_eq!(program, "svn");
+    ///         assert_eq!(args, ["cat", "-r", "1234", "https://svn.example.com/repo/trunk/main.cpp"]);
+    ///     }
+    ///     other => panic!("{:?}", other),
+    /// }
+    /// ```
+    pub fn to_portable_command(&self) -> Result<PortableCommand, TranslateError> {
+        match self {
+            SourceRetrievalMethod::Download { url } => Ok(PortableCommand::Download {
+                url: url.clone(),
+                encoding: None,
+            }),
+            SourceRetrievalMethod::DownloadWithDecode { url, encoding } => {
+                Ok(PortableCommand::Download {
+                    url: url.clone(),
+                    encoding: Some(*encoding),
+                })
+            }
+            SourceRetrievalMethod::GitFile {
+                repo,
+                revision,
+                path,
+                ..
+            } if repo.contains("://") => {
+                // Remote repositories can't be read with git show, but most
+                // servers allow fetching single files with git archive.
+                Ok(PortableCommand::Shell {
+                    script: format!(
+                        "git archive --remote={} {} {} | tar -xO",
+                        sh_quote(repo),
+                        sh_quote(revision),
+                        sh_quote(path)
+                    ),
+                    env: command_env(self).cloned().unwrap_or_default(),
+                })
+            }
+            SourceRetrievalMethod::GitFile { .. }
+            | SourceRetrievalMethod::Svn { .. }
+            | SourceRetrievalMethod::Cvs { .. }
+            | SourceRetrievalMethod::Perforce { .. } => {
+                let (program, args) = print_invocation(self).unwrap();
+                Ok(PortableCommand::Run {
+                    program: program.to_string(),
+                    args,
+                    env: command_env(self).cloned().unwrap_or_default(),
+                })
+            }
+            SourceRetrievalMethod::CabExtract {
+                archive_path,
+                member,
+                ..
+            } if is_cabinet(archive_path) => {
+                let mut args = vec!["-p".to_string()];
+                if let Some(member) = member {
+                    args.extend(["-F".to_string(), member.clone()]);
+                }
+                args.push(archive_path.clone());
+                Ok(PortableCommand::Run {
+                    program: "cabextract".to_string(),
+                    args,
+                    env: command_env(self).cloned().unwrap_or_default(),
+                })
+            }
+            SourceRetrievalMethod::CabExtract { .. } => {
+                Err(TranslateError::UnsupportedProgram("expand.exe".to_string()))
+            }
+            SourceRetrievalMethod::TfsItem { .. } => {
+                Err(TranslateError::UnsupportedProgram("tf.exe".to_string()))
+            }
+            SourceRetrievalMethod::SourceDepot { .. } => {
+                Err(TranslateError::UnsupportedProgram("sd.exe".to_string()))
+            }
+            SourceRetrievalMethod::ExecuteCommand { command, env, .. } => {
+                translate_command(command, env)
+            }
+            SourceRetrievalMethod::CopyFile { .. } => {
+                Err(TranslateError::UnsupportedMethod(RetrievalKind::CopyFile))
+            }
+            SourceRetrievalMethod::Other { .. } => {
+                Err(TranslateError::UnsupportedMethod(RetrievalKind::Other))
+            }
+        }
+    }
+}
+
+/// The program and the arguments which print the file of a recognized
+/// `git` (with a local repository), `svn`, `cvs` or `p4` retrieval method to
+/// stdout.
+pub(crate) fn print_invocation(
+    method: &SourceRetrievalMethod,
+) -> Option<(&'static str, Vec<String>)> {
+    let (program, args) = match method {
+        SourceRetrievalMethod::GitFile {
+            repo,
+            revision,
+            path,
+            ..
+        } => (
+            "git",
+            to_strings(&["-C", repo, "show", &format!("{}:{}", revision, path)]),
+        ),
+        SourceRetrievalMethod::Svn { url, revision, .. } => {
+            ("svn", to_strings(&["cat", "-r", revision, url]))
+        }
+        SourceRetrievalMethod::Cvs {
+            root,
+            path,
+            revision,
+            ..
+        } => (
+            "cvs",
+            to_strings(&["-d", root, "checkout", "-p", "-r", revision, path]),
+        ),
+        SourceRetrievalMethod::Perforce {
+            port,
+            depot_path,
+            revision,
+            ..
+        } => {
+            let file_spec = format!("{}#{}", depot_path, revision);
+            match port {
+                Some(port) => ("p4", to_strings(&["-p", port, "print", "-q", &file_spec])),
+                None => ("p4", to_strings(&["print", "-q", &file_spec])),
+            }
+        }
+        _ => return None,
+    };
+    Some((program, args))
+}
+
+/// The environment variables from `SRCSRVENV` for the command of a
+/// recognized retrieval method.
+pub(crate) fn command_env(method: &SourceRetrievalMethod) -> Option<&HashMap<String, String>> {
+    match method {
+        SourceRetrievalMethod::GitFile { env, .. }
+        | SourceRetrievalMethod::TfsItem { env, .. }
+        | SourceRetrievalMethod::Perforce { env, .. }
+        | SourceRetrievalMethod::SourceDepot { env, .. }
+        | SourceRetrievalMethod::Svn { env, .. }
+        | SourceRetrievalMethod::Cvs { env, .. }
+        | SourceRetrievalMethod::CabExtract { env, .. } => Some(env),
+        _ => None,
+    }
+}
+
+fn to_strings(args: &[&str]) -> Vec<String> {
+    args.iter().map(ToString::to_string).collect()
+}
+
+/// Translate a command line of the form
+/// `[cmd /c] <program> <args> [| <program> <args>]... > <target>`.
+fn translate_command(
+    command: &str,
+    env: &HashMap<String, String>,
+) -> Result<PortableCommand, TranslateError> {
+    let mut tokens = tokenize_command(command);
+    if tokens.len() >= 2 && is_program(&tokens[0], "cmd") && tokens[1].eq_ignore_ascii_case("/c") {
+        tokens.drain(..2);
+    }
+    // The file has to be written by redirecting the output, so that the caller
+    // can get it from stdout instead.
+    match tokens.len().checked_sub(2).map(|pos| tokens[pos].as_str()) {
+        Some(">") => tokens.truncate(tokens.len() - 2),
+        _ => return Err(TranslateError::OutputNotRedirected),
+    }
+
+    let mut pipeline: Vec<Vec<String>> = vec![Vec::new()];
+    for token in tokens {
+        if token == "|" {
+            pipeline.push(Vec::new());
+        } else if is_operator(&token) {
+            return Err(TranslateError::UnsupportedSyntax(token));
+        } else {
+            let token = expand_env_vars(&token, env)?;
+            pipeline.last_mut().unwrap().push(token);
+        }
+    }
+
+    let mut programs = Vec::new();
+    for args in &mut pipeline {
+        if args.is_empty() {
+            return Err(TranslateError::UnsupportedSyntax("|".to_string()));
+        }
+        let program = args.remove(0);
+        let portable_program = PORTABLE_PROGRAMS
+            .iter()
+            .find(|name| is_program(&program, name))
+            .ok_or(TranslateError::UnsupportedProgram(program))?;
+        programs.push(portable_program.to_string());
+    }
+
+    if let ([program], [args]) = (programs.as_slice(), pipeline.as_slice()) {
+        return Ok(PortableCommand::Run {
+            program: program.clone(),
+            args: args.clone(),
+            env: env.clone(),
+        });
+    }
+    let script = programs
+        .iter()
+        .zip(&pipeline)
+        .map(|(program, args)| {
+            std::iter::once(program.clone())
+                .chain(args.iter().map(|arg| sh_quote(arg)))
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .collect::<Vec<_>>()
+        .join(" | ");
+    Ok(PortableCommand::Shell {
+        script,
+        env: env.clone(),
+    })
+}
+
+/// Expand the `%VAR%` references in `token` with the variables from
+/// `SRCSRVENV`, like cmd.exe would. Variable names are case-insensitive.
+fn expand_env_vars(token: &str, env: &HashMap<String, String>) -> Result<String, TranslateError> {
+    let mut expanded = String::new();
+    let mut rest = token;
+    while let Some(start) = rest.find('%') {
+        let end = match rest[start + 1..].find('%') {
+            Some(len) => start + 1 + len,
+            None => break,
+        };
+        let name = &rest[start + 1..end];
+        let value = env
+            .iter()
+            .find(|(var_name, _)| var_name.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+            .ok_or_else(|| TranslateError::UnsupportedSyntax(rest[start..=end].to_string()))?;
+        expanded.push_str(&rest[..start]);
+        expanded.push_str(value);
+        rest = &rest[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Quote `arg` for `sh`, unless it only consists of characters which don't
+/// need quoting.
+fn sh_quote(arg: &str) -> String {
+    let is_plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=@+,".contains(c));
+    if is_plain {
+        return arg.to_string();
+    }
+    format!("'{}'", arg.replace('\'', r#"'\''"#))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PortableCommand;
+    use crate::{ContentEncoding, RetrievalKind, SourceRetrievalMethod, TranslateError};
+    use std::collections::HashMap;
+
+    fn execute_command(command: &str, env: &[(&str, &str)]) -> SourceRetrievalMethod {
+        SourceRetrievalMethod::ExecuteCommand {
+            command: command.to_string(),
+            env: env
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            version_ctrl: None,
+            target_path: r#"C:\Cache\a.cpp"#.to_string(),
+            error_persistence_version_control: None,
+        }
+    }
+
+    #[test]
+    fn recognized_methods() {
+        let method = SourceRetrievalMethod::DownloadWithDecode {
+            url: "https://chromium.googlesource.com/a.cc?format=TEXT".to_string(),
+            encoding: ContentEncoding::Base64,
+        };
+        assert_eq!(
+            method.to_portable_command(),
+            Ok(PortableCommand::Download {
+                url: "https://chromium.googlesource.com/a.cc?format=TEXT".to_string(),
+                encoding: Some(ContentEncoding::Base64),
+            })
+        );
+
+        let git_file = |repo: &str| SourceRetrievalMethod::GitFile {
+            repo: repo.to_string(),
+            revision: "abc123".to_string(),
+            path: "src/my file.cpp".to_string(),
+            target_path: r#"C:\Cache\my file.cpp"#.to_string(),
+            env: HashMap::new(),
+            error_persistence_version_control: None,
+        };
+        assert_eq!(
+            git_file("/srv/repo").to_portable_command(),
+            Ok(PortableCommand::Run {
+                program: "git".to_string(),
+                args: vec![
+                    "-C".to_string(),
+                    "/srv/repo".to_string(),
+                    "show".to_string(),
+                    "abc123:src/my file.cpp".to_string(),
+                ],
+                env: HashMap::new(),
+            })
+        );
+        assert_eq!(
+            git_file("ssh://git.example.com/repo.git").to_portable_command(),
+            Ok(PortableCommand::Shell {
+                script: "git archive --remote=ssh://git.example.com/repo.git abc123 'src/my file.cpp' | tar -xO".to_string(),
+                env: HashMap::new(),
+            })
+        );
+
+        let perforce = SourceRetrievalMethod::Perforce {
+            port: Some("ssl:perforce:1666".to_string()),
+            depot_path: "//depot/game/main.cpp".to_string(),
+            revision: "12".to_string(),
+            target_path: r#"C:\Cache\main.cpp"#.to_string(),
+            env: [("P4CLIENT".to_string(), "build".to_string())]
+                .iter()
+                .cloned()
+                .collect(),
+            error_persistence_version_control: None,
+        };
+        match perforce.to_portable_command() {
+            Ok(PortableCommand::Run { program, args, env }) => {
+                assert_eq!(program, "p4");
+                assert_eq!(env["P4CLIENT"], "build");
+                assert_eq!(
+                    args,
+                    [
+                        "-p",
+                        "ssl:perforce:1666",
+                        "print",
+                        "-q",
+                        "//depot/game/main.cpp#12"
+                    ]
+                );
+            }
+            other => panic!("{:?}", other),
+        }
+
+        let tfs = SourceRetrievalMethod::TfsItem {
+            server: "https://tfs.example.com/tfs".to_string(),
+            item_path: "$/Project/main.cpp".to_string(),
+            version: "42".to_string(),
+            target_path: r#"C:\Cache\main.cpp"#.to_string(),
+            env: HashMap::new(),
+            error_persistence_version_control: None,
+        };
+        assert_eq!(
+            tfs.to_portable_command(),
+            Err(TranslateError::UnsupportedProgram("tf.exe".to_string()))
+        );
+        let cab = SourceRetrievalMethod::CabExtract {
+            archive_path: "/mnt/sources/build.CAB".to_string(),
+            member: Some("main.cpp".to_string()),
+            target_path: r#"C:\Cache\main.cpp"#.to_string(),
+            env: HashMap::new(),
+            error_persistence_version_control: None,
+        };
+        match cab.to_portable_command() {
+            Ok(PortableCommand::Run { program, args, .. }) => {
+                assert_eq!(program, "cabextract");
+                assert_eq!(args, ["-p", "-F", "main.cpp", "/mnt/sources/build.CAB"]);
+            }
+            other => panic!("{:?}", other),
+        }
+
+        let copy = SourceRetrievalMethod::CopyFile {
+            source_path: r#"\\server\share\a.cpp"#.to_string(),
+            target_path: r#"server\share\a.cpp"#.to_string(),
+        };
+        assert_eq!(
+            copy.to_portable_command(),
+            Err(TranslateError::UnsupportedMethod(RetrievalKind::CopyFile))
+        );
+    }
+
+    #[test]
+    fn commands() {
+        let method = execute_command(
+            r#"cmd /c hg.exe cat -R %HGREPO% -r 1a2b "src\a.cpp" > "C:\Cache\a.cpp""#,
+            &[("hgrepo", "https://hg.example.com/repo")],
+        );
+        match method.to_portable_command() {
+            Ok(PortableCommand::Run { program, args, env }) => {
+                assert_eq!(program, "hg");
+                assert_eq!(
+                    args,
+                    [
+                        "cat",
+                        "-R",
+                        "https://hg.example.com/repo",
+                        "-r",
+                        "1a2b",
+                        r#"src\a.cpp"#
+                    ]
+                );
+                assert_eq!(env["hgrepo"], "https://hg.example.com/repo");
+            }
+            other => panic!("{:?}", other),
+        }
+
+        let method = execute_command(
+            r#"curl.exe -s https://example.com/a.cpp.gz | "C:\Tools\tar.exe" -xzO > C:\Cache\a.cpp"#,
+            &[],
+        );
+        match method.to_portable_command() {
+            Ok(PortableCommand::Shell { script, .. }) => {
+                assert_eq!(script, "curl -s https://example.com/a.cpp.gz | tar -xzO");
+            }
+            other => panic!("{:?}", other),
+        }
+
+        let translate = |command| execute_command(command, &[]).to_portable_command();
+        assert_eq!(
+            translate(r#"tool.exe get a.cpp > C:\Cache\a.cpp"#),
+            Err(TranslateError::UnsupportedProgram("tool.exe".to_string()))
+        );
+        assert_eq!(
+            translate(r#"svn.exe export https://svn.example.com/a.cpp C:\Cache\a.cpp"#),
+            Err(TranslateError::OutputNotRedirected)
+        );
+        assert_eq!(
+            translate(r#"git show a:b && git show c:d > C:\Cache\a.cpp"#),
+            Err(TranslateError::UnsupportedSyntax("&&".to_string()))
+        );
+        assert_eq!(
+            translate(r#"p4 -p %P4PORT% print -q //depot/a.cpp#1 > C:\Cache\a.cpp"#),
+            Err(TranslateError::UnsupportedSyntax("%P4PORT%".to_string()))
+        );
+    }
+}
@@ -0,0 +1,229 @@
+use crate::{
+    entry_path, split_entry, DuplicatePolicy, EvalError, EvalOptions, LineReader, ParseError,
+    ParseOptions, ParseWarning, SharedEvalCache, SourceRetrievalMethod, SrcSrvStream, VcsKind,
+};
+use std::result::Result;
+
+/// A reader for very large `srcsrv` streams which parses the ini and variables
+/// sections up front, but doesn't build an index of the file entries.
+///
+/// Each lookup scans the source files section, which is much cheaper than
+/// parsing the whole stream if only a few paths are looked up, for example
+/// in a stream with hundreds of thousands of entries. Use [`SrcSrvStream`] to
+/// look up many paths in the same stream.
+///
+/// ```
+/// use srcsrv::SrcSrvStreamReader;
+///
+/// # fn wrapper(bytes: &[u8]) -> std::result::Result<(), Box<dyn std::error::Error>> {
+/// let reader = SrcSrvStreamReader::parse(bytes)?;
+/// let method = reader.source_for_path(
+///     r#"C:\build\renderdoc\renderdoc\maths\matrix.cpp"#,
+///     r#"C:\Debugger\Cached Sources"#,
+/// )?;
+/// for vars in reader.source_file_entries() {
+///     println!("{}", vars[0]);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct SrcSrvStreamReader<'a> {
+    /// The ini and variables sections, without file entries.
+    header: SrcSrvStream<'a>,
+    /// Positioned at the first file entry.
+    entry_lines: LineReader<'a>,
+    duplicate_entry_policy: DuplicatePolicy,
+}
+
+impl<'a> SrcSrvStreamReader<'a> {
+    /// Parse the ini and variables sections of the `srcsrv` stream.
+    ///
+    /// The source files section is only checked for its end marker, so that
+    /// lookups can't fail with parse errors later.
+    pub fn parse(stream: &'a [u8]) -> Result<SrcSrvStreamReader<'a>, ParseError> {
+        Self::parse_with_options(stream, &ParseOptions::default())
+    }
+
+    /// Like [`SrcSrvStreamReader::parse`], with the given options. The
+    /// [`ParseOptions::duplicate_entry_policy`] is only used to decide whether
+    /// the first or the last of several entries for a path is used; duplicate
+    /// entries are not reported.
+    pub fn parse_with_options(
+        stream: &'a [u8],
+        options: &ParseOptions,
+    ) -> Result<SrcSrvStreamReader<'a>, ParseError> {
+        let (mut header, entry_lines) = SrcSrvStream::parse_header(stream, options)?;
+        let mut lines = entry_lines.clone();
+        let end_line = loop {
+            match lines.next_line() {
+                Ok((line_number, line)) if line.starts_with("SRCSRV:") => {
+                    break Some((line_number, line))
+                }
+                Ok(_) => {}
+                Err(ParseError::UnexpectedEof) if options.allow_missing_end_marker => break None,
+                Err(err) => return Err(err),
+            }
+        };
+        lines.check_end_marker(end_line, &mut header.warnings)?;
+        Ok(SrcSrvStreamReader {
+            header,
+            entry_lines,
+            duplicate_entry_policy: options.duplicate_entry_policy,
+        })
+    }
+
+    /// Problems which were encountered during parsing but were not fatal.
+    pub fn warnings(&self) -> &[ParseWarning] {
+        self.header.warnings()
+    }
+
+    /// The value of the VERSION field from the ini section.
+    pub fn version(&self) -> u8 {
+        self.header.version()
+    }
+
+    /// The value of the VERCTRL field from the ini section, if specified.
+    pub fn version_control_description(&self) -> Option<&'a str> {
+        self.header.version_control_description()
+    }
+
+    /// The version control system, see [`SrcSrvStream::vcs_kind`].
+    pub fn vcs_kind(&self) -> Option<VcsKind> {
+        self.header.vcs_kind()
+    }
+
+    /// Get the value of the specified field from the ini section.
+    /// The field name is case-insensitive.
+    pub fn get_ini_field(&self, field_name: &str) -> Option<&'a str> {
+        self.header.get_ini_field(field_name)
+    }
+
+    /// Get the raw, unevaluated value of the specified field from the
+    /// variables section.
+    /// The field name is case-insensitive.
+    pub fn get_raw_var(&self, var_name: &str) -> Option<&'a str> {
+        self.header.get_raw_var(var_name)
+    }
+
+    /// Iterate over the entries in the source files section, in stream order.
+    /// Each item contains the raw values of var1, ..., varN for that entry; the
+    /// first value is the original file path.
+    pub fn source_file_entries(&self) -> impl Iterator<Item = Vec<&'a str>> + 'a {
+        self.lines().map(split_entry)
+    }
+
+    /// Look up the file entry for `original_file_path` and evaluate it, like
+    /// [`SrcSrvStream::source_for_path`]. The path is matched ASCII
+    /// case-insensitively, and the source files section is scanned from the
+    /// start for every call.
+    pub fn source_for_path(
+        &self,
+        original_file_path: &str,
+        extraction_base_path: &str,
+    ) -> Result<Option<SourceRetrievalMethod>, EvalError> {
+        let mut found = None;
+        for line in self.lines() {
+            if entry_path(line).eq_ignore_ascii_case(original_file_path) {
+                found = Some(line);
+                if self.duplicate_entry_policy == DuplicatePolicy::FirstWins {
+                    break;
+                }
+            }
+        }
+        let vars = match found {
+            Some(line) => split_entry(line),
+            None => return Ok(None),
+        };
+        let (method, _) = self.header.source_and_raw_var_values_for_entry(
+            &vars,
+            extraction_base_path,
+            &EvalOptions::default(),
+            &mut SharedEvalCache::default(),
+        )?;
+        Ok(Some(method))
+    }
+
+    /// The lines of the source files section.
+    fn lines(&self) -> impl Iterator<Item = &'a str> + 'a {
+        let mut lines = self.entry_lines.clone();
+        std::iter::from_fn(move || match lines.next_line() {
+            Ok((_, line)) if !line.starts_with("SRCSRV:") => Some(line),
+            _ => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        DuplicatePolicy, ParseError, ParseOptions, ParseWarning, SourceRetrievalMethod,
+        SrcSrvStream, SrcSrvStreamReader,
+    };
+
+    const STREAM: &str = r#"SRCSRV: ini ------------------------------------------------
+VERSION=2
+VERCTRL=http
+SRCSRV: variables ------------------------------------------
+HTTP_ALIAS=https://raw.githubusercontent.com/baldurk/renderdoc/v1.15/
+HTTP_EXTRACT_TARGET=%HTTP_ALIAS%%var2%
+SRCSRVTRG=%http_extract_target%
+SRCSRV: source files ---------------------------------------
+C:\build\renderdoc\renderdoc\data\glsl\gl_texsample.h*renderdoc/data/glsl/gl_texsample.h
+C:\build\renderdoc\renderdoc\maths\matrix.cpp*renderdoc/maths/matrix.cpp
+C:\build\renderdoc\renderdoc\maths\MATRIX.cpp*renderdoc/maths/matrix2.cpp
+SRCSRV: end ------------------------------------------------"#;
+
+    #[test]
+    fn reader() {
+        let reader = SrcSrvStreamReader::parse(STREAM.as_bytes()).unwrap();
+        let stream = SrcSrvStream::parse(STREAM.as_bytes()).unwrap();
+        assert_eq!(reader.version(), 2);
+        assert_eq!(reader.version_control_description(), Some("http"));
+        assert_eq!(
+            reader.get_raw_var("srcsrvtrg"),
+            Some("%http_extract_target%")
+        );
+        assert_eq!(
+            reader.source_file_entries().collect::<Vec<_>>(),
+            stream
+                .source_file_entries()
+                .map(|(_, vars)| vars.to_vec())
+                .collect::<Vec<_>>()
+        );
+        for path in [
+            r#"C:\build\renderdoc\renderdoc\data\glsl\gl_texsample.h"#,
+            r#"c:\BUILD\renderdoc\renderdoc\maths\matrix.cpp"#,
+            r#"C:\build\renderdoc\renderdoc\missing.cpp"#,
+        ] {
+            assert_eq!(
+                reader.source_for_path(path, "").unwrap(),
+                stream.source_for_path(path, "").unwrap()
+            );
+        }
+
+        let options = ParseOptions::new().duplicate_entry_policy(DuplicatePolicy::FirstWins);
+        let reader = SrcSrvStreamReader::parse_with_options(STREAM.as_bytes(), &options).unwrap();
+        assert_eq!(
+            reader
+                .source_for_path(r#"C:\build\renderdoc\renderdoc\maths\matrix.cpp"#, "")
+                .unwrap(),
+            Some(SourceRetrievalMethod::Download {
+                url: "https://raw.githubusercontent.com/baldurk/renderdoc/v1.15/renderdoc/maths/matrix.cpp".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn reader_end_marker() {
+        let truncated = STREAM.trim_end_matches(|c| c != '\n');
+        assert_eq!(
+            SrcSrvStreamReader::parse(truncated.as_bytes()).err(),
+            Some(ParseError::UnexpectedEof)
+        );
+        let options = ParseOptions::new().allow_missing_end_marker(true);
+        let reader =
+            SrcSrvStreamReader::parse_with_options(truncated.as_bytes(), &options).unwrap();
+        assert_eq!(reader.warnings(), &[ParseWarning::MissingEndMarker]);
+        assert_eq!(reader.source_file_entries().count(), 3);
+    }
+}
@@ -0,0 +1,963 @@
+use crate::{ContentEncoding, SourceRetrievalMethod};
+use std::collections::HashMap;
+
+/// The evaluated fields of a file entry whose source is obtained by executing
+/// a command.
+pub(crate) struct EvaluatedCommand<'s> {
+    pub command: &'s str,
+    pub target_path: &'s str,
+    /// The environment variables from `SRCSRVENV`.
+    pub env: &'s HashMap<String, String>,
+    /// The value of the variable which `SRCSRVERRVAR` names.
+    pub error_persistence_version_control: Option<&'s str>,
+}
+
+impl EvaluatedCommand<'_> {
+    /// Look up an environment variable from `SRCSRVENV`. Like on Windows,
+    /// environment variable names are case-insensitive.
+    fn env_var(&self, name: &str) -> Option<&str> {
+        self.env
+            .iter()
+            .find(|(var_name, _)| var_name.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn error_persistence_version_control(&self) -> Option<String> {
+        self.error_persistence_version_control.map(str::to_string)
+    }
+}
+
+/// Try to recognize a well-known command shape and return a more structured
+/// retrieval method for it, which can be used without executing the command.
+pub(crate) fn recognize_command(cmd: &EvaluatedCommand) -> Option<SourceRetrievalMethod> {
+    recognize_python_download(cmd.command)
+        .or_else(|| recognize_git(cmd))
+        .or_else(|| recognize_tfs(cmd))
+        .or_else(|| recognize_perforce(cmd))
+        .or_else(|| recognize_source_depot(cmd))
+        .or_else(|| recognize_svn(cmd))
+        .or_else(|| recognize_cvs(cmd))
+        .or_else(|| recognize_expand(cmd))
+        .or_else(|| recognize_extract(cmd))
+}
+
+/// Split a command line into tokens, roughly like cmd.exe would: Tokens are
+/// separated by whitespace, double quotes group characters (and are removed),
+/// and the redirection and command chaining operators `>`, `>>`, `<`, `|`,
+/// `||`, `&` and `&&` are separate tokens when they are not quoted.
+pub(crate) fn tokenize_command(command: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current: Option<String> = None;
+    let mut in_quotes = false;
+    let mut chars = command.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                current.get_or_insert_with(String::new);
+            }
+            c if in_quotes => current.get_or_insert_with(String::new).push(c),
+            c if c.is_whitespace() => tokens.extend(current.take()),
+            '>' | '<' | '|' | '&' => {
+                tokens.extend(current.take());
+                let mut operator = c.to_string();
+                if c != '<' && chars.peek() == Some(&c) {
+                    operator.push(c);
+                    chars.next();
+                }
+                tokens.push(operator);
+            }
+            c => current.get_or_insert_with(String::new).push(c),
+        }
+    }
+    tokens.extend(current);
+    tokens
+}
+
+/// Whether `token` invokes the program `name`, e.g. `git`, `git.exe` or
+/// `C:\Program Files\Git\bin\git.exe` for `git`.
+pub(crate) fn is_program(token: &str, name: &str) -> bool {
+    let file_name = token.rsplit(['\\', '/']).next().unwrap_or(token);
+    let file_name = file_name.to_ascii_lowercase();
+    let file_name = file_name.strip_suffix(".exe").unwrap_or(&file_name);
+    file_name == name
+}
+
+/// Whether `token` separates two commands or starts a redirection.
+pub(crate) fn is_operator(token: &str) -> bool {
+    matches!(token, ">" | ">>" | "<" | "|" | "||" | "&" | "&&")
+}
+
+/// Recognize commands which extract a single file from a git repository:
+///
+/// ```text
+/// git -C <repo> show <revision>:<path> > <target>
+/// git --git-dir=<repo> cat-file -p <revision>:<path> > <target>
+/// git archive --remote=<repo> <revision> <path> | tar -xO > <target>
+/// ```
+fn recognize_git(cmd: &EvaluatedCommand) -> Option<SourceRetrievalMethod> {
+    let tokens = tokenize_command(cmd.command);
+    let git_pos = tokens.iter().position(|token| is_program(token, "git"))?;
+    let mut args = tokens[git_pos + 1..]
+        .iter()
+        .take_while(|token| !is_operator(token))
+        .map(String::as_str);
+
+    let mut repo = None;
+    let subcommand = loop {
+        let arg = args.next()?;
+        if arg == "-C" || arg == "--git-dir" {
+            repo = Some(args.next()?);
+        } else if let Some(git_dir) = arg.strip_prefix("--git-dir=") {
+            repo = Some(git_dir);
+        } else if !arg.starts_with('-') {
+            break arg;
+        }
+    };
+
+    let (revision, path) = match subcommand {
+        "show" | "cat-file" => {
+            let object = args.find(|arg| !arg.starts_with('-') && *arg != "blob")?;
+            object.split_once(':')?
+        }
+        "archive" => {
+            let mut positional = Vec::new();
+            for arg in args {
+                if let Some(remote) = arg.strip_prefix("--remote=") {
+                    repo = Some(remote);
+                } else if !arg.starts_with('-') {
+                    positional.push(arg);
+                }
+            }
+            match positional.as_slice() {
+                [revision, path] => (*revision, *path),
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+    if revision.is_empty() || path.is_empty() {
+        return None;
+    }
+
+    Some(SourceRetrievalMethod::GitFile {
+        repo: repo?.to_string(),
+        revision: revision.to_string(),
+        path: path.to_string(),
+        target_path: cmd.target_path.to_string(),
+        env: cmd.env.clone(),
+        error_persistence_version_control: cmd.error_persistence_version_control(),
+    })
+}
+
+/// Recognize python one-liners which download a URL and write the (optionally
+/// decoded) response to the target path, as used by Chrome:
+///
+/// ```text
+/// cmd /c "mkdir "<dir>" & python -c "import urllib2, base64;url = \"<url>\";u = urllib2.urlopen(url);open(r\"<target>\", \"wb\").write(base64.b64decode(u.read()))"
+/// ```
+///
+/// Variations of this are recognized as well: Python 3's `urllib.request`,
+/// other variable names, the URL passed to `urlopen` directly, strings in
+/// single quotes, and the decoding functions `b64decode`, `standard_b64decode`,
+/// `decodestring` and `decodebytes` (with or without the `base64.` prefix) or
+/// Python 2's `.decode("base64")`.
+fn recognize_python_download(command: &str) -> Option<SourceRetrievalMethod> {
+    if !command.contains("python") {
+        return None;
+    }
+    let (_, urlopen_arg) = command.split_once("urlopen(")?;
+    let url = match python_string_literal(urlopen_arg) {
+        Some(url) => url,
+        None => {
+            let variable = urlopen_arg
+                .split(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .next()?;
+            python_assigned_string(command, variable)?
+        }
+    };
+    if !url.starts_with("http://") && !url.starts_with("https://") {
+        return None;
+    }
+
+    // The expression which is written: u.read(), base64.b64decode(u.read()),
+    // u.read().decode("base64") or base64.b64decode(urllib2.urlopen(url).read()).
+    let (_, written) = command.split_once(".write(")?;
+    let (before_read, after_read) = written.split_once(".read()")?;
+    let decode_function = match before_read.split_once('(') {
+        Some((function, _)) if !function.ends_with("urlopen") => Some(function),
+        _ => None,
+    };
+    let decodes_base64_string = after_read
+        .strip_prefix(".decode(")
+        .and_then(python_string_literal)
+        == Some("base64");
+    let url = url.to_string();
+    match (decode_function, decodes_base64_string) {
+        (None, false) => Some(SourceRetrievalMethod::Download { url }),
+        (None, true) => Some(SourceRetrievalMethod::DownloadWithDecode {
+            url,
+            encoding: ContentEncoding::Base64,
+        }),
+        (Some(function), false) => {
+            let function = function.strip_prefix("base64.").unwrap_or(function);
+            match function {
+                "b64decode" | "standard_b64decode" | "decodestring" | "decodebytes" => {
+                    Some(SourceRetrievalMethod::DownloadWithDecode {
+                        url,
+                        encoding: ContentEncoding::Base64,
+                    })
+                }
+                _ => None,
+            }
+        }
+        (Some(_), true) => None,
+    }
+}
+
+/// The contents of the python string literal at the start of `s`, which is
+/// quoted with `\"` (escaped for the command line), `"` or `'`.
+fn python_string_literal(s: &str) -> Option<&str> {
+    let s = s.trim_start();
+    let quote = ["\\\"", "\"", "'"]
+        .iter()
+        .find(|quote| s.starts_with(**quote))?;
+    let (value, _) = s[quote.len()..].split_once(quote)?;
+    Some(value)
+}
+
+/// The string literal which is assigned to `variable` in the python code in
+/// `command`, as in `url = "..."`.
+fn python_assigned_string<'s>(command: &'s str, variable: &str) -> Option<&'s str> {
+    if variable.is_empty() {
+        return None;
+    }
+    command.match_indices(variable).find_map(|(pos, _)| {
+        let preceded_by_identifier = matches!(
+            command[..pos].chars().next_back(),
+            Some(c) if c.is_ascii_alphanumeric() || c == '_' || c == '.'
+        );
+        if preceded_by_identifier {
+            return None;
+        }
+        let rest = command[pos + variable.len()..].trim_start();
+        let rest = rest.strip_prefix('=')?;
+        if rest.starts_with('=') {
+            return None;
+        }
+        python_string_literal(rest)
+    })
+}
+
+/// Recognize Team Foundation Server commands of the form
+///
+/// ```text
+/// tf.exe view /version:<version> /noprompt "$/<item path>" /server:<server> /output:<target>
+/// ```
+///
+/// The `/output:` argument is not parsed because it is often unquoted and
+/// contains spaces; the evaluated target path is used instead.
+fn recognize_tfs(cmd: &EvaluatedCommand) -> Option<SourceRetrievalMethod> {
+    let tokens = tokenize_command(cmd.command);
+    let tf_pos = tokens.iter().position(|token| is_program(token, "tf"))?;
+    let mut args = tokens[tf_pos + 1..]
+        .iter()
+        .take_while(|token| !is_operator(token))
+        .map(String::as_str);
+    if args.next()? != "view" {
+        return None;
+    }
+
+    let mut server = None;
+    let mut item_path = None;
+    let mut version = None;
+    for arg in args {
+        if let Some(option) = arg.strip_prefix(['/', '-']) {
+            let (name, value) = option.split_once(':').unwrap_or((option, ""));
+            match name.to_ascii_lowercase().as_str() {
+                "server" | "collection" => server = Some(value),
+                "version" => version = Some(value),
+                _ => {}
+            }
+        } else if arg.starts_with('$') && item_path.is_none() {
+            item_path = Some(arg);
+        }
+    }
+
+    let server = server.filter(|server| !server.is_empty())?;
+    let version = version.filter(|version| !version.is_empty())?;
+    Some(SourceRetrievalMethod::TfsItem {
+        server: server.to_string(),
+        item_path: item_path?.to_string(),
+        version: version.to_string(),
+        target_path: cmd.target_path.to_string(),
+        env: cmd.env.clone(),
+        error_persistence_version_control: cmd.error_persistence_version_control(),
+    })
+}
+
+/// Recognize Perforce commands of the form
+///
+/// ```text
+/// p4.exe [-p <port>] print -o <target> -q <depot path>#<revision>
+/// ```
+///
+/// If the port is not given on the command line, it is taken from the
+/// `P4PORT` variable in `SRCSRVENV`, if present.
+fn recognize_perforce(cmd: &EvaluatedCommand) -> Option<SourceRetrievalMethod> {
+    let file = recognize_print_command(cmd, "p4", "P4PORT")?;
+    Some(SourceRetrievalMethod::Perforce {
+        port: file.port,
+        depot_path: file.depot_path,
+        revision: file.revision,
+        target_path: cmd.target_path.to_string(),
+        env: cmd.env.clone(),
+        error_persistence_version_control: cmd.error_persistence_version_control(),
+    })
+}
+
+/// Recognize Source Depot commands. Source Depot is derived from Perforce and
+/// has the same command line syntax:
+///
+/// ```text
+/// sd.exe [-p <port>] print -o <target> -q <depot path>#<revision>
+/// ```
+///
+/// If the port is not given on the command line, it is taken from the
+/// `SDPORT` variable in `SRCSRVENV`, if present.
+fn recognize_source_depot(cmd: &EvaluatedCommand) -> Option<SourceRetrievalMethod> {
+    let file = recognize_print_command(cmd, "sd", "SDPORT")?;
+    Some(SourceRetrievalMethod::SourceDepot {
+        port: file.port,
+        depot_path: file.depot_path,
+        revision: file.revision,
+        target_path: cmd.target_path.to_string(),
+        env: cmd.env.clone(),
+        error_persistence_version_control: cmd.error_persistence_version_control(),
+    })
+}
+
+/// The file that is requested by a Perforce-style `print` command.
+struct DepotFile {
+    port: Option<String>,
+    depot_path: String,
+    revision: String,
+}
+
+/// Parse a Perforce-style `<program> [global options] print [options] <file spec>`
+/// command. `port_env_var` is the environment variable that supplies the
+/// server address if there is no `-p` option.
+fn recognize_print_command(
+    cmd: &EvaluatedCommand,
+    program: &str,
+    port_env_var: &str,
+) -> Option<DepotFile> {
+    let tokens = tokenize_command(cmd.command);
+    let program_pos = tokens.iter().position(|token| is_program(token, program))?;
+    let mut args = tokens[program_pos + 1..]
+        .iter()
+        .take_while(|token| !is_operator(token))
+        .map(String::as_str);
+
+    let mut port = None;
+    loop {
+        match args.next()? {
+            "-p" => port = Some(args.next()?),
+            "print" => break,
+            // Other global options with a value, such as -c <client>.
+            arg if arg.starts_with('-') && arg.len() == 2 && arg != "-s" && arg != "-q" => {
+                args.next()?;
+            }
+            arg if arg.starts_with('-') => {}
+            _ => return None,
+        }
+    }
+
+    let mut file_spec = None;
+    while let Some(arg) = args.next() {
+        match arg {
+            "-o" => {
+                args.next()?;
+            }
+            arg if arg.starts_with('-') => {}
+            arg => file_spec = Some(arg),
+        }
+    }
+    let (depot_path, revision) = file_spec?.rsplit_once(['#', '@'])?;
+    if depot_path.is_empty() || revision.is_empty() {
+        return None;
+    }
+
+    // cmd.exe expands environment variable references like %SDPORT% when it
+    // runs the command, with the variables from SRCSRVENV.
+    let port = match port {
+        Some(port) if port.len() > 2 && port.starts_with('%') && port.ends_with('%') => {
+            cmd.env_var(&port[1..port.len() - 1])
+        }
+        Some(port) => Some(port),
+        None => cmd.env_var(port_env_var),
+    };
+    Some(DepotFile {
+        port: port.map(ToString::to_string),
+        depot_path: depot_path.to_string(),
+        revision: revision.to_string(),
+    })
+}
+
+/// Recognize Subversion commands of the form
+///
+/// ```text
+/// svn.exe cat -r <revision> <url> > <target>
+/// svn.exe export <url>@<revision> <target>
+/// ```
+fn recognize_svn(cmd: &EvaluatedCommand) -> Option<SourceRetrievalMethod> {
+    let tokens = tokenize_command(cmd.command);
+    let svn_pos = tokens.iter().position(|token| is_program(token, "svn"))?;
+    let mut args = tokens[svn_pos + 1..]
+        .iter()
+        .take_while(|token| !is_operator(token))
+        .map(String::as_str);
+    if !matches!(args.next()?, "cat" | "export") {
+        return None;
+    }
+
+    let mut revision = None;
+    let mut url = None;
+    while let Some(arg) = args.next() {
+        if arg == "-r" || arg == "--revision" {
+            revision = Some(args.next()?);
+        } else if let Some(rev) = arg.strip_prefix("--revision=") {
+            revision = Some(rev);
+        } else if let Some(rev) = arg.strip_prefix("-r") {
+            revision = Some(rev);
+        } else if !arg.starts_with('-') && url.is_none() {
+            // The first positional argument is the URL. For `svn export`,
+            // the second one is the target path.
+            url = Some(arg);
+        }
+    }
+
+    let mut url = url.filter(|url| url.contains("://"))?;
+    if let Some((url_without_peg, peg_revision)) = url.rsplit_once('@') {
+        // Don't mistake the user name in svn+ssh://user@host/ for a revision.
+        if !peg_revision.contains('/') {
+            url = url_without_peg;
+            revision = revision.or(Some(peg_revision));
+        }
+    }
+    let revision = revision.filter(|revision| !revision.is_empty())?;
+    Some(SourceRetrievalMethod::Svn {
+        url: url.to_string(),
+        revision: revision.to_string(),
+        target_path: cmd.target_path.to_string(),
+        env: cmd.env.clone(),
+        error_persistence_version_control: cmd.error_persistence_version_control(),
+    })
+}
+
+/// Recognize CVS commands of the form
+///
+/// ```text
+/// cvs.exe -d <root> checkout -p -r <revision> <path> > <target>
+/// ```
+///
+/// If the root is not given on the command line, it is taken from the
+/// `CVSROOT` variable in `SRCSRVENV`, if present.
+fn recognize_cvs(cmd: &EvaluatedCommand) -> Option<SourceRetrievalMethod> {
+    let tokens = tokenize_command(cmd.command);
+    let cvs_pos = tokens.iter().position(|token| is_program(token, "cvs"))?;
+    let mut args = tokens[cvs_pos + 1..]
+        .iter()
+        .take_while(|token| !is_operator(token))
+        .map(String::as_str);
+
+    let mut root = None;
+    loop {
+        match args.next()? {
+            "-d" => root = Some(args.next()?),
+            "checkout" | "co" | "update" | "up" => break,
+            arg if arg.starts_with("-d") => root = Some(&arg[2..]),
+            arg if arg.starts_with('-') => {}
+            _ => return None,
+        }
+    }
+
+    let mut revision = None;
+    let mut path = None;
+    while let Some(arg) = args.next() {
+        match arg {
+            "-r" => revision = Some(args.next()?),
+            "-d" | "-j" | "-k" | "-D" => {
+                args.next()?;
+            }
+            arg if arg.starts_with("-r") => revision = Some(&arg[2..]),
+            arg if arg.starts_with('-') => {}
+            arg => path = Some(arg),
+        }
+    }
+
+    let root = root.or_else(|| cmd.env_var("CVSROOT"));
+    let revision = revision.filter(|revision| !revision.is_empty())?;
+    Some(SourceRetrievalMethod::Cvs {
+        root: root?.to_string(),
+        path: path?.to_string(),
+        revision: revision.to_string(),
+        target_path: cmd.target_path.to_string(),
+        env: cmd.env.clone(),
+        error_persistence_version_control: cmd.error_persistence_version_control(),
+    })
+}
+
+/// Recognize `expand.exe` commands which extract a file from a cabinet or
+/// decompress a single compressed file:
+///
+/// ```text
+/// expand.exe -r <archive> <destination>
+/// expand.exe <archive.cab> -F:<file name> <destination>
+/// ```
+fn recognize_expand(cmd: &EvaluatedCommand) -> Option<SourceRetrievalMethod> {
+    let tokens = tokenize_command(cmd.command);
+    let expand_pos = tokens
+        .iter()
+        .position(|token| is_program(token, "expand"))?;
+    let args = tokens[expand_pos + 1..]
+        .iter()
+        .take_while(|token| !is_operator(token));
+
+    let mut member = None;
+    let mut positional = Vec::new();
+    for arg in args {
+        match arg.strip_prefix(['-', '/']) {
+            Some(option) if option.len() > 2 && option[..2].eq_ignore_ascii_case("f:") => {
+                member = Some(&option[2..]);
+            }
+            Some(option) if option.eq_ignore_ascii_case("r") => {}
+            // -d only lists the files, -i renames files in a way we can't know.
+            Some(_) => return None,
+            None => positional.push(arg.as_str()),
+        }
+    }
+    let archive_path = match positional.as_slice() {
+        [archive_path] | [archive_path, _] => *archive_path,
+        _ => return None,
+    };
+    cab_extract(cmd, archive_path, member)
+}
+
+/// Recognize `extract.exe` commands which extract a file from a cabinet:
+///
+/// ```text
+/// extract.exe [/y] [/a] [/e] [/l <dir>] <archive.cab> <file name>
+/// extract.exe [/y] /c <compressed file> <destination>
+/// ```
+fn recognize_extract(cmd: &EvaluatedCommand) -> Option<SourceRetrievalMethod> {
+    let tokens = tokenize_command(cmd.command);
+    let extract_pos = tokens
+        .iter()
+        .position(|token| is_program(token, "extract"))?;
+    let mut args = tokens[extract_pos + 1..]
+        .iter()
+        .take_while(|token| !is_operator(token));
+
+    let mut is_copy = false;
+    let mut positional = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg
+            .strip_prefix('/')
+            .map(str::to_ascii_lowercase)
+            .as_deref()
+        {
+            Some("l") => {
+                args.next()?;
+            }
+            Some("c") => is_copy = true,
+            Some("y") | Some("a") | Some("e") => {}
+            // /d only lists the files.
+            Some(_) => return None,
+            None => positional.push(arg.as_str()),
+        }
+    }
+    let (archive_path, member) = match positional.as_slice() {
+        [archive_path] => (*archive_path, None),
+        // The second argument is the destination for /c and for compressed
+        // files, and the file name for cabinets.
+        [archive_path, _] if is_copy || !is_cabinet(archive_path) => (*archive_path, None),
+        [archive_path, member] => (*archive_path, Some(*member)),
+        _ => return None,
+    };
+    cab_extract(cmd, archive_path, member)
+}
+
+fn cab_extract(
+    cmd: &EvaluatedCommand,
+    archive_path: &str,
+    member: Option<&str>,
+) -> Option<SourceRetrievalMethod> {
+    let member = match member {
+        // Wildcards extract several files.
+        Some(member) if member.contains(['*', '?']) => return None,
+        member => member.map(ToString::to_string),
+    };
+    Some(SourceRetrievalMethod::CabExtract {
+        archive_path: archive_path.to_string(),
+        member,
+        target_path: cmd.target_path.to_string(),
+        env: cmd.env.clone(),
+        error_persistence_version_control: cmd.error_persistence_version_control(),
+    })
+}
+
+/// Whether `path` has the `.cab` extension, ASCII case-insensitively.
+pub(crate) fn is_cabinet(path: &str) -> bool {
+    let extension = path.len().checked_sub(4).and_then(|pos| path.get(pos..));
+    matches!(extension, Some(extension) if extension.eq_ignore_ascii_case(".cab"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        recognize_cvs, recognize_expand, recognize_extract, recognize_git, recognize_perforce,
+        recognize_python_download, recognize_source_depot, recognize_svn, recognize_tfs,
+        tokenize_command, EvaluatedCommand,
+    };
+    use crate::{ContentEncoding, SourceRetrievalMethod};
+    use std::collections::HashMap;
+
+    #[test]
+    fn python_download() {
+        let command = r#"cmd /c "mkdir "C:\src\core" & python -c "import urllib2, base64;url = \"https://pdfium.googlesource.com/pdfium.git/+/dab1161c/core/fdrm/fx_crypt.cpp?format=TEXT\";u = urllib2.urlopen(url);open(r\"C:\src\core\fx_crypt.cpp\", \"wb\").write(base64.b64decode(u.read()))""#;
+        assert_eq!(
+            recognize_python_download(command),
+            Some(SourceRetrievalMethod::DownloadWithDecode {
+                url: "https://pdfium.googlesource.com/pdfium.git/+/dab1161c/core/fdrm/fx_crypt.cpp?format=TEXT".to_string(),
+                encoding: ContentEncoding::Base64,
+            })
+        );
+
+        let command = r#"cmd /c "mkdir "C:\src\core" & python -c "import urllib2;url = \"https://example.com/fx_crypt.cpp\";u = urllib2.urlopen(url);open(r\"C:\src\core\fx_crypt.cpp\", \"wb\").write(u.read())""#;
+        assert_eq!(
+            recognize_python_download(command),
+            Some(SourceRetrievalMethod::Download {
+                url: "https://example.com/fx_crypt.cpp".to_string(),
+            })
+        );
+
+        let command = r#"cmd /c "python3 -c "import urllib.request, base64; r = urllib.request.urlopen('https://example.com/a.cpp?format=TEXT'); open(r'C:\src\a.cpp', 'wb').write(base64.standard_b64decode(r.read()))"""#;
+        assert_eq!(
+            recognize_python_download(command),
+            Some(SourceRetrievalMethod::DownloadWithDecode {
+                url: "https://example.com/a.cpp?format=TEXT".to_string(),
+                encoding: ContentEncoding::Base64,
+            })
+        );
+
+        let command = r#"python -c "import urllib2;src_url='https://example.com/a.cpp';open(r'C:\src\a.cpp','wb').write(urllib2.urlopen(src_url).read().decode('base64'))""#;
+        assert_eq!(
+            recognize_python_download(command),
+            Some(SourceRetrievalMethod::DownloadWithDecode {
+                url: "https://example.com/a.cpp".to_string(),
+                encoding: ContentEncoding::Base64,
+            })
+        );
+
+        let command = r#"python -c "import urllib2, zlib;url = \"https://example.com/a.cpp\";u = urllib2.urlopen(url);open(r\"C:\src\a.cpp\", \"wb\").write(zlib.decompress(u.read()))""#;
+        assert_eq!(recognize_python_download(command), None);
+
+        assert_eq!(
+            recognize_python_download("tf.exe view /version:1 foo"),
+            None
+        );
+    }
+
+    #[test]
+    fn tokenize() {
+        assert_eq!(
+            tokenize_command(r#"git.exe -C "C:\my repo" show abc:src/a.c>>"C:\out\a.c" && echo"#),
+            vec![
+                "git.exe",
+                "-C",
+                r#"C:\my repo"#,
+                "show",
+                "abc:src/a.c",
+                ">>",
+                r#"C:\out\a.c"#,
+                "&&",
+                "echo"
+            ]
+        );
+        assert_eq!(tokenize_command(r#"a "" b"#), vec!["a", "", "b"]);
+    }
+
+    #[test]
+    fn git() {
+        let recognize = |command| {
+            recognize_git(&EvaluatedCommand {
+                command,
+                target_path: r#"C:\src\a.c"#,
+                env: &HashMap::new(),
+                error_persistence_version_control: None,
+            })
+        };
+        let expected = Some(SourceRetrievalMethod::GitFile {
+            repo: "https://github.com/example/repo.git".to_string(),
+            revision: "0123abcd".to_string(),
+            path: "src/a.c".to_string(),
+            target_path: r#"C:\src\a.c"#.to_string(),
+            env: HashMap::new(),
+            error_persistence_version_control: None,
+        });
+        assert_eq!(
+            recognize(
+                r#"cmd /c git.exe -C "https://github.com/example/repo.git" show 0123abcd:src/a.c > "C:\src\a.c""#
+            ),
+            expected
+        );
+        assert_eq!(
+            recognize(
+                r#"git --git-dir=https://github.com/example/repo.git cat-file -p 0123abcd:src/a.c > C:\src\a.c"#
+            ),
+            expected
+        );
+        assert_eq!(
+            recognize(
+                r#"cmd /c git archive --remote=https://github.com/example/repo.git 0123abcd src/a.c | tar -xO > C:\src\a.c"#
+            ),
+            expected
+        );
+        assert_eq!(recognize("git show 0123abcd:src/a.c > a.c"), None);
+    }
+
+    #[test]
+    fn tfs() {
+        let recognize = |command| {
+            recognize_tfs(&EvaluatedCommand {
+                command,
+                target_path: r#"C:\Cached Sources\a.h"#,
+                env: &HashMap::new(),
+                error_persistence_version_control: Some("VSTFDEVDIV_DEVDIV2"),
+            })
+        };
+        assert_eq!(
+            recognize(
+                r#"tf.exe view /version:1363200 /noprompt "$/DevDiv/a.h" /server:http://tfs:8080/DevDiv2 /output:C:\Cached Sources\a.h"#
+            ),
+            Some(SourceRetrievalMethod::TfsItem {
+                server: "http://tfs:8080/DevDiv2".to_string(),
+                item_path: "$/DevDiv/a.h".to_string(),
+                version: "1363200".to_string(),
+                target_path: r#"C:\Cached Sources\a.h"#.to_string(),
+                env: HashMap::new(),
+                error_persistence_version_control: Some("VSTFDEVDIV_DEVDIV2".to_string()),
+            })
+        );
+        assert_eq!(
+            recognize(r#"tf.exe view /version:1363200 "$/DevDiv/a.h" /output:a.h"#),
+            None
+        );
+        assert_eq!(recognize(r#"tf.exe get "$/DevDiv/a.h""#), None);
+    }
+
+    #[test]
+    fn perforce() {
+        let mut env = HashMap::new();
+        let recognize = |command, env: &HashMap<String, String>| {
+            recognize_perforce(&EvaluatedCommand {
+                command,
+                target_path: r#"C:\src\a.cpp"#,
+                env,
+                error_persistence_version_control: None,
+            })
+        };
+        let expected = |port: Option<&str>, env: &HashMap<String, String>| {
+            Some(SourceRetrievalMethod::Perforce {
+                port: port.map(ToString::to_string),
+                depot_path: "//depot/game/src/a.cpp".to_string(),
+                revision: "12".to_string(),
+                target_path: r#"C:\src\a.cpp"#.to_string(),
+                env: env.clone(),
+                error_persistence_version_control: None,
+            })
+        };
+        let command = r#"p4.exe print -o "C:\src\a.cpp" -q "//depot/game/src/a.cpp#12""#;
+        assert_eq!(recognize(command, &env), expected(None, &env));
+        env.insert("p4port".to_string(), "ssl:perforce:1666".to_string());
+        assert_eq!(
+            recognize(command, &env),
+            expected(Some("ssl:perforce:1666"), &env)
+        );
+        assert_eq!(
+            recognize(
+                r#"p4 -p perforce:1666 -c client print -q -o C:\src\a.cpp //depot/game/src/a.cpp#12"#,
+                &env
+            ),
+            expected(Some("perforce:1666"), &env)
+        );
+        assert_eq!(recognize("p4.exe sync //depot/a.cpp#12", &env), None);
+    }
+
+    #[test]
+    fn source_depot() {
+        let mut env = HashMap::new();
+        env.insert("SDPORT".to_string(), "minkerneldepot:2020".to_string());
+        env.insert("SDCLIENT".to_string(), "srcsrv".to_string());
+        let cmd = EvaluatedCommand {
+            command: r#"sd.exe -p %SDPORT% print -o "C:\src\ntos\init.c" -q //depot/winmain/minkernel/ntos/init.c#3"#,
+            target_path: r#"C:\src\ntos\init.c"#,
+            env: &env,
+            error_persistence_version_control: None,
+        };
+        assert_eq!(
+            recognize_source_depot(&cmd),
+            Some(SourceRetrievalMethod::SourceDepot {
+                port: Some("minkerneldepot:2020".to_string()),
+                depot_path: "//depot/winmain/minkernel/ntos/init.c".to_string(),
+                revision: "3".to_string(),
+                target_path: r#"C:\src\ntos\init.c"#.to_string(),
+                env: env.clone(),
+                error_persistence_version_control: None,
+            })
+        );
+        let cmd = EvaluatedCommand {
+            command: r#"sd.exe print -o "C:\src\ntos\init.c" -q //depot/winmain/minkernel/ntos/init.c#3"#,
+            ..cmd
+        };
+        assert_eq!(
+            recognize_source_depot(&cmd),
+            Some(SourceRetrievalMethod::SourceDepot {
+                port: Some("minkerneldepot:2020".to_string()),
+                depot_path: "//depot/winmain/minkernel/ntos/init.c".to_string(),
+                revision: "3".to_string(),
+                target_path: r#"C:\src\ntos\init.c"#.to_string(),
+                env: env.clone(),
+                error_persistence_version_control: None,
+            })
+        );
+        assert_eq!(recognize_perforce(&cmd), None);
+    }
+
+    #[test]
+    fn svn() {
+        let env = HashMap::new();
+        let recognize = |command| {
+            recognize_svn(&EvaluatedCommand {
+                command,
+                target_path: r#"C:\src\main.c"#,
+                env: &env,
+                error_persistence_version_control: None,
+            })
+        };
+        let expected = Some(SourceRetrievalMethod::Svn {
+            url: "svn+ssh://user@svn.example.com/repo/trunk/main.c".to_string(),
+            revision: "1234".to_string(),
+            target_path: r#"C:\src\main.c"#.to_string(),
+            env: env.clone(),
+            error_persistence_version_control: None,
+        });
+        assert_eq!(
+            recognize(
+                r#"cmd /c svn.exe cat -r 1234 "svn+ssh://user@svn.example.com/repo/trunk/main.c" > "C:\src\main.c""#
+            ),
+            expected
+        );
+        assert_eq!(
+            recognize(
+                r#"svn export --non-interactive svn+ssh://user@svn.example.com/repo/trunk/main.c@1234 C:\src\main.c"#
+            ),
+            expected
+        );
+        assert_eq!(
+            recognize("svn cat svn+ssh://user@svn.example.com/repo/trunk/main.c"),
+            None
+        );
+    }
+
+    #[test]
+    fn cvs() {
+        let mut env = HashMap::new();
+        env.insert(
+            "CVSROOT".to_string(),
+            ":pserver:anonymous@cvs.example.com:/cvsroot".to_string(),
+        );
+        let recognize = |command| {
+            recognize_cvs(&EvaluatedCommand {
+                command,
+                target_path: r#"C:\src\main.c"#,
+                env: &env,
+                error_persistence_version_control: None,
+            })
+        };
+        let expected = Some(SourceRetrievalMethod::Cvs {
+            root: ":pserver:anonymous@cvs.example.com:/cvsroot".to_string(),
+            path: "project/src/main.c".to_string(),
+            revision: "1.42".to_string(),
+            target_path: r#"C:\src\main.c"#.to_string(),
+            env: env.clone(),
+            error_persistence_version_control: None,
+        });
+        assert_eq!(
+            recognize(
+                r#"cvs.exe -q -d :pserver:anonymous@cvs.example.com:/cvsroot checkout -p -r 1.42 project/src/main.c > "C:\src\main.c""#
+            ),
+            expected
+        );
+        assert_eq!(
+            recognize(r#"cvs co -p -r1.42 project/src/main.c > C:\src\main.c"#),
+            expected
+        );
+        assert_eq!(recognize("cvs co -p project/src/main.c"), None);
+    }
+
+    #[test]
+    fn cab_extract() {
+        let env = HashMap::new();
+        let cmd = |command| EvaluatedCommand {
+            command,
+            target_path: r#"C:\src\main.cpp"#,
+            env: &env,
+            error_persistence_version_control: None,
+        };
+        let expected = |archive_path: &str, member: Option<&str>| {
+            Some(SourceRetrievalMethod::CabExtract {
+                archive_path: archive_path.to_string(),
+                member: member.map(ToString::to_string),
+                target_path: r#"C:\src\main.cpp"#.to_string(),
+                env: HashMap::new(),
+                error_persistence_version_control: None,
+            })
+        };
+        assert_eq!(
+            recognize_expand(&cmd(
+                r#"expand.exe "\\server\sources\build 12.cab" -F:main.cpp "C:\src""#
+            )),
+            expected(r#"\\server\sources\build 12.cab"#, Some("main.cpp"))
+        );
+        assert_eq!(
+            recognize_expand(&cmd(r#"expand -r \\server\sources\main.cp_ C:\src"#)),
+            expected(r#"\\server\sources\main.cp_"#, None)
+        );
+        assert_eq!(
+            recognize_expand(&cmd(r#"expand -d \\server\sources\build.cab"#)),
+            None
+        );
+        assert_eq!(
+            recognize_expand(&cmd(r#"expand \\server\build.cab -F:*.cpp C:\src"#)),
+            None
+        );
+        assert_eq!(
+            recognize_extract(&cmd(
+                r#"extract.exe /y /l "C:\src" \\server\sources\build.cab main.cpp"#
+            )),
+            expected(r#"\\server\sources\build.cab"#, Some("main.cpp"))
+        );
+        assert_eq!(
+            recognize_extract(&cmd(
+                r#"extract.exe /y /c \\server\sources\main.cp_ C:\src\main.cpp"#
+            )),
+            expected(r#"\\server\sources\main.cp_"#, None)
+        );
+        assert_eq!(
+            recognize_extract(&cmd(r#"extract /d \\server\sources\build.cab"#)),
+            None
+        );
+    }
+}
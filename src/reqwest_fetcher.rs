@@ -0,0 +1,377 @@
+use crate::fetch::{decode_base64, is_retryable_error};
+use crate::insecure_urls::upgrade_http_url;
+use crate::{
+    ContentEncoding, ExtractionResult, FetchError, FetchOptions, HttpStatusError, SourceCache,
+    SourceRetrievalMethod,
+};
+use reqwest::header::LOCATION;
+use reqwest::redirect::Policy;
+use reqwest::{Client, Url};
+use std::io;
+use std::path::Path;
+use std::result::Result;
+use std::time::{Duration, Instant};
+
+/// The most redirects which are followed for one download.
+const MAX_REDIRECTS: usize = 10;
+
+/// An async fetcher which downloads source files with a [`reqwest::Client`],
+/// for services which run on tokio. This is the async counterpart of
+/// [`fetch_source_with_options`](crate::fetch_source_with_options) with a
+/// [`SourceFetcher`](crate::SourceFetcher).
+///
+/// All [`FetchOptions`] are supported. Redirects are followed by the fetcher
+/// itself, so every URL in a redirect chain is checked against the
+/// [`UrlPolicy`](crate::UrlPolicy), and the request headers from the
+/// [`AuthProvider`](crate::AuthProvider) are requested for each URL
+/// separately, so credentials are never sent to the host of a redirect target.
+///
+/// ```no_run
+/// use srcsrv::{FetchOptions, ReqwestFetcher, SourceCache, SrcSrvStream};
+///
+/// # async fn wrapper(stream: &SrcSrvStream<'_>) -> Result<(), Box<dyn std::error::Error>> {
+/// let fetcher = ReqwestFetcher::new();
+/// let cache = SourceCache::new("/tmp/sources");
+/// let path = r#"C:\build\renderdoc\renderdoc\data\glsl\gl_texsample.h"#;
+/// if let Some(method) = cache.source_for_path(stream, path)? {
+///     let result = fetcher
+///         .fetch_to_cache(&cache, &method, &FetchOptions::new().max_retries(2))
+///         .await?;
+///     println!("Stored at {}", result.local_path.display());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ReqwestFetcher {
+    client: Client,
+}
+
+impl ReqwestFetcher {
+    /// Create a fetcher with a client which waits at most 30 seconds for a
+    /// connection and 60 seconds for each read.
+    pub fn new() -> Self {
+        let client = Client::builder()
+            .redirect(Policy::none())
+            .connect_timeout(Duration::from_secs(30))
+            .read_timeout(Duration::from_secs(60))
+            .build()
+            .expect("the client configuration is valid");
+        Self::with_client(client)
+    }
+
+    /// Create a fetcher which downloads with `client`, for example to use a
+    /// proxy or different timeouts. Build the client with
+    /// [`Policy::none`](reqwest::redirect::Policy::none), otherwise it follows
+    /// redirects itself and the redirect targets are not checked against the
+    /// [`UrlPolicy`](crate::UrlPolicy).
+    pub fn with_client(client: Client) -> Self {
+        Self { client }
+    }
+
+    /// Download the file for a [`SourceRetrievalMethod::Download`] or
+    /// [`SourceRetrievalMethod::DownloadWithDecode`] and return its contents,
+    /// like [`fetch_source_contents_with_options`](crate::fetch_source_contents_with_options).
+    pub async fn fetch_source_contents(
+        &self,
+        method: &SourceRetrievalMethod,
+        options: &FetchOptions,
+    ) -> Result<Vec<u8>, FetchError> {
+        match method {
+            SourceRetrievalMethod::Download { url } => self.fetch_url(url, options).await,
+            SourceRetrievalMethod::DownloadWithDecode { url, encoding } => {
+                let response = self.fetch_url(url, options).await?;
+                match encoding {
+                    ContentEncoding::Base64 => decode_base64(&response),
+                }
+            }
+            _ => Err(FetchError::UnsupportedMethod(method.kind())),
+        }
+    }
+
+    /// Download the file for `method` and store it at `target_path`, creating
+    /// the parent directories if needed, like
+    /// [`fetch_source_with_options`](crate::fetch_source_with_options).
+    ///
+    /// The file is written to a temporary file next to `target_path` first and
+    /// then renamed, so `target_path` never contains a partial download.
+    pub async fn fetch_source(
+        &self,
+        method: &SourceRetrievalMethod,
+        target_path: &Path,
+        options: &FetchOptions,
+    ) -> Result<ExtractionResult, FetchError> {
+        let start = Instant::now();
+        let contents = self.fetch_source_contents(method, options).await?;
+        if options.is_cancelled() {
+            return Err(FetchError::Cancelled);
+        }
+        write_atomically(target_path, &contents)
+            .await
+            .map_err(FetchError::Io)?;
+        Ok(ExtractionResult {
+            local_path: target_path.to_path_buf(),
+            bytes_written: contents.len() as u64,
+            duration: start.elapsed(),
+            exit_status: None,
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+            error_persistence_triggered: false,
+        })
+    }
+
+    /// Download the file for `method` and store it in `cache`, at
+    /// [`SourceCache::target_path`].
+    pub async fn fetch_to_cache(
+        &self,
+        cache: &SourceCache,
+        method: &SourceRetrievalMethod,
+        options: &FetchOptions,
+    ) -> Result<ExtractionResult, FetchError> {
+        let target_path = cache
+            .target_path(method)
+            .ok_or_else(|| FetchError::UnsupportedMethod(method.kind()))?;
+        self.fetch_source(method, &target_path, options).await
+    }
+
+    /// Fetch `url`, over https first if `options` asks for it.
+    async fn fetch_url(&self, url: &str, options: &FetchOptions) -> Result<Vec<u8>, FetchError> {
+        if let Some(https_url) = upgrade_http_url(url) {
+            if options.upgrade_http {
+                match self.fetch_with_retries(&https_url, options).await {
+                    Err(FetchError::Cancelled) => return Err(FetchError::Cancelled),
+                    Err(_) => {}
+                    result => return result,
+                }
+            }
+            options.report_insecure_download(url);
+        }
+        self.fetch_with_retries(url, options).await
+    }
+
+    /// Fetch `url`, retrying retryable errors as configured in `options`.
+    async fn fetch_with_retries(
+        &self,
+        url: &str,
+        options: &FetchOptions,
+    ) -> Result<Vec<u8>, FetchError> {
+        let mut attempts = 0;
+        loop {
+            if options.is_cancelled() {
+                return Err(FetchError::Cancelled);
+            }
+            let error = match self.get(url, options).await {
+                Ok(_) if options.is_cancelled() => return Err(FetchError::Cancelled),
+                Ok(response) => return Ok(response),
+                Err(FetchError::Http(error)) => error,
+                Err(error) => return Err(error),
+            };
+            attempts += 1;
+            if !is_retryable_error(&*error) {
+                return Err(FetchError::Http(error));
+            }
+            if attempts > options.max_retries {
+                return Err(FetchError::Transient { attempts, error });
+            }
+            sleep(options.backoff(attempts - 1), options).await?;
+        }
+    }
+
+    /// Send one GET request for `url` and follow its redirects. Failed
+    /// requests are returned as [`FetchError::Http`]; invalid redirects and
+    /// too many redirects are not retryable.
+    async fn get(&self, url: &str, options: &FetchOptions) -> Result<Vec<u8>, FetchError> {
+        let mut url = url.to_string();
+        for _ in 0..=MAX_REDIRECTS {
+            if !options.url_policy.is_allowed(&url) {
+                return Err(FetchError::UrlNotAllowed(url));
+            }
+            let mut request = self.client.get(&url);
+            for (name, value) in options.headers(&url) {
+                request = request.header(name, value);
+            }
+            let response = request
+                .send()
+                .await
+                .map_err(|error| FetchError::Http(error.into()))?;
+            let status = response.status();
+            if status.is_redirection() {
+                if let Some(location) = response.headers().get(LOCATION) {
+                    url = redirect_target(&url, location.as_bytes())?;
+                    continue;
+                }
+            }
+            if !status.is_success() {
+                let status = status.as_u16();
+                return Err(FetchError::Http(HttpStatusError { status }.into()));
+            }
+            let body = response
+                .bytes()
+                .await
+                .map_err(|error| FetchError::Http(error.into()))?;
+            return Ok(body.to_vec());
+        }
+        let message = format!("More than {} redirects.", MAX_REDIRECTS);
+        Err(FetchError::Http(io::Error::other(message).into()))
+    }
+}
+
+impl Default for ReqwestFetcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The URL which the `Location` header `location` of a response for `url`
+/// points to.
+fn redirect_target(url: &str, location: &[u8]) -> Result<String, FetchError> {
+    let invalid = || {
+        let message = format!("Invalid redirect from {}.", url);
+        FetchError::Http(io::Error::other(message).into())
+    };
+    let location = std::str::from_utf8(location).map_err(|_| invalid())?;
+    let base = Url::parse(url).map_err(|_| invalid())?;
+    let target = base.join(location).map_err(|_| invalid())?;
+    Ok(target.into())
+}
+
+/// Wait for `duration`, or fail with [`FetchError::Cancelled`] once the
+/// cancellation token of `options` is cancelled.
+async fn sleep(duration: Duration, options: &FetchOptions) -> Result<(), FetchError> {
+    let token = match &options.cancellation {
+        Some(token) => token,
+        None => {
+            tokio::time::sleep(duration).await;
+            return Ok(());
+        }
+    };
+    // The token can't wake up a future, so check it periodically.
+    let deadline = Instant::now() + duration;
+    loop {
+        if token.is_cancelled() {
+            return Err(FetchError::Cancelled);
+        }
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Ok(());
+        }
+        tokio::time::sleep(remaining.min(Duration::from_millis(50))).await;
+    }
+}
+
+/// Write `contents` to a temporary file next to `path` and rename it to
+/// `path`, creating the parent directories if needed.
+async fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    let mut partial_path = path.as_os_str().to_owned();
+    partial_path.push(format!(".{}.partial", std::process::id()));
+    let mut result = tokio::fs::write(&partial_path, contents).await;
+    if result.is_ok() {
+        result = tokio::fs::rename(&partial_path, path).await;
+    }
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(&partial_path).await;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ReqwestFetcher;
+    use crate::{
+        ContentEncoding, FetchError, FetchOptions, SourceCache, SourceRetrievalMethod, UrlPolicy,
+    };
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Serve `responses` to one connection each, and return the base URL and
+    /// a thread which returns the request heads.
+    fn serve(responses: Vec<String>) -> (String, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request = String::new();
+                while !request.ends_with("\r\n\r\n") {
+                    reader.read_line(&mut request).unwrap();
+                }
+                stream.write_all(response.as_bytes()).unwrap();
+                requests.push(request);
+            }
+            requests
+        });
+        (url, server)
+    }
+
+    fn response(status: &str, headers: &str, body: &str) -> String {
+        format!(
+            "HTTP/1.1 {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            headers,
+            body.len(),
+            body
+        )
+    }
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    #[test]
+    fn fetch_to_cache() {
+        let (url, server) = serve(vec![
+            response("503 Service Unavailable", "", ""),
+            response("302 Found", "Location: /b.cpp?format=TEXT\r\n", ""),
+            response("200 OK", "", "aW50IGIoKTsK"),
+        ]);
+        let method = SourceRetrievalMethod::DownloadWithDecode {
+            url: format!("{}/src/a.cpp", url),
+            encoding: ContentEncoding::Base64,
+        };
+        let dir = std::env::temp_dir().join(format!("srcsrv-reqwest-{}", std::process::id()));
+        let cache = SourceCache::new(&dir);
+        let options = FetchOptions::new()
+            .max_retries(1)
+            .initial_backoff(std::time::Duration::from_millis(1))
+            .auth(|url: &str| vec![("X-Url".to_string(), url.to_string())]);
+        let result = block_on(ReqwestFetcher::new().fetch_to_cache(&cache, &method, &options));
+        let result = result.unwrap();
+        assert_eq!(result.local_path, cache.target_path(&method).unwrap());
+        assert_eq!(std::fs::read(&result.local_path).unwrap(), b"int b();\n");
+        let requests = server.join().unwrap();
+        assert!(requests[1].starts_with("GET /src/a.cpp HTTP/1.1\r\n"));
+        assert!(requests[2].starts_with("GET /b.cpp?format=TEXT HTTP/1.1\r\n"));
+        let expected_header = format!("x-url: {}/b.cpp?format=TEXT\r\n", url);
+        assert!(requests[2].contains(&expected_header));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn redirect_not_allowed() {
+        let (url, server) = serve(vec![response(
+            "301 Moved Permanently",
+            "Location: https://attacker.example/a.cpp\r\n",
+            "",
+        )]);
+        let method = SourceRetrievalMethod::Download {
+            url: format!("{}/a.cpp", url),
+        };
+        let options = FetchOptions::new().url_policy(UrlPolicy::new().allow_host("127.0.0.1"));
+        let result = block_on(ReqwestFetcher::new().fetch_source_contents(&method, &options));
+        assert!(matches!(
+            result,
+            Err(FetchError::UrlNotAllowed(url)) if url == "https://attacker.example/a.cpp"
+        ));
+        server.join().unwrap();
+    }
+}
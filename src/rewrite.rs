@@ -0,0 +1,230 @@
+use crate::{SrcSrvStream, SrcSrvStreamBuilder};
+
+/// Rewrites the variables and file entries of an existing stream, for
+/// example to point the streams of third-party PDB files at an internal
+/// source mirror without indexing the files again.
+///
+/// The rules are applied in the order in which they were added, to the raw
+/// value of each variable and to the values of var2, ..., varN of each
+/// entry. The original file paths in var1 are not changed, so lookups keep
+/// working. Replacements are inserted into the raw values as they are, so
+/// they can refer to variables, and a literal `%` needs to be written as
+/// `%%`.
+///
+/// ```
+/// use srcsrv::{SourceRetrievalMethod, SrcSrvStream, StreamRewriter};
+///
+/// # fn wrapper() -> std::result::Result<(), Box<dyn std::error::Error>> {
+/// let stream = SrcSrvStream::parse(br#"SRCSRV: ini ------------------------------------------------
+/// VERSION=2
+/// SRCSRV: variables ------------------------------------------
+/// HTTP_ALIAS=https://raw.githubusercontent.com/vendor/lib/3f2a9c1/
+/// SRCSRVTRG=%HTTP_ALIAS%%var2%
+/// SRCSRV: source files ---------------------------------------
+/// C:\vendor\lib\src\lib.c*src/lib.c
+/// SRCSRV: end ------------------------------------------------"#)?;
+///
+/// let rewriter = StreamRewriter::new()
+///     .replace_url_prefix("https://raw.githubusercontent.com/", "https://mirror.example.com/github/")
+///     .replace_revision("3f2a9c1", "release-1.4");
+/// let bytes = rewriter.rewrite(&stream).to_bytes()?;
+/// let rewritten = SrcSrvStream::parse(&bytes)?;
+/// assert_eq!(
+///     rewritten.source_for_path(r#"C:\vendor\lib\src\lib.c"#, "")?,
+///     Some(SourceRetrievalMethod::Download {
+///         url: "https://mirror.example.com/github/vendor/lib/release-1.4/src/lib.c".to_string()
+///     })
+/// );
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct StreamRewriter {
+    rules: Vec<RewriteRule>,
+    /// (variable name, raw value), set after the rules were applied
+    vars: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum RewriteRule {
+    UrlPrefix { from: String, to: String },
+    Revision { from: String, to: String },
+    Text { from: String, to: String },
+}
+
+impl StreamRewriter {
+    /// Create a rewriter without rules, which doesn't change the stream.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace URLs which start with `from`, e.g. `https://github.com/` or
+    /// just `https://`, so that they start with `to` instead. `from` is
+    /// compared ASCII case-insensitively, and only where a URL can start,
+    /// i.e. not in the middle of another URL or word.
+    pub fn replace_url_prefix(mut self, from: &str, to: &str) -> Self {
+        self.rules.push(RewriteRule::UrlPrefix {
+            from: from.to_string(),
+            to: to.to_string(),
+        });
+        self
+    }
+
+    /// Replace the revision `from`, such as a commit hash or a tag, with `to`.
+    /// Only whole occurrences are replaced, which aren't directly preceded or
+    /// followed by a letter, digit, `.`, `-` or `_`.
+    pub fn replace_revision(mut self, from: &str, to: &str) -> Self {
+        self.rules.push(RewriteRule::Revision {
+            from: from.to_string(),
+            to: to.to_string(),
+        });
+        self
+    }
+
+    /// Replace all occurrences of `from` with `to`.
+    pub fn replace_text(mut self, from: &str, to: &str) -> Self {
+        self.rules.push(RewriteRule::Text {
+            from: from.to_string(),
+            to: to.to_string(),
+        });
+        self
+    }
+
+    /// Set the raw value of the variable `var_name`, or add the variable if
+    /// the stream doesn't have it. This happens after the other rules were
+    /// applied.
+    pub fn set_var(mut self, var_name: &str, value: &str) -> Self {
+        self.vars.push((var_name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Create a [`SrcSrvStreamBuilder`] with the rewritten contents of
+    /// `stream`. Like with [`SrcSrvStream::to_builder`], streams which were
+    /// parsed with [`ParseOptions::preserve_layout`](crate::ParseOptions::preserve_layout)
+    /// keep their layout.
+    pub fn rewrite(&self, stream: &SrcSrvStream<'_>) -> SrcSrvStreamBuilder {
+        let mut builder = stream.to_builder();
+        builder.map_values(|value| self.rewrite_value(value));
+        for (name, value) in &self.vars {
+            builder.set_var(name, value);
+        }
+        builder
+    }
+
+    fn rewrite_value(&self, value: &str) -> String {
+        let mut value = value.to_string();
+        for rule in &self.rules {
+            value = match rule {
+                RewriteRule::UrlPrefix { from, to } => replace_matches(
+                    &value,
+                    from,
+                    to,
+                    true,
+                    |before, _| !matches!(before, Some(c) if is_url_char(c)),
+                ),
+                RewriteRule::Revision { from, to } => {
+                    replace_matches(&value, from, to, false, |before, after| {
+                        !matches!(before, Some(c) if is_word_char(c))
+                            && !matches!(after, Some(c) if is_word_char(c))
+                    })
+                }
+                RewriteRule::Text { from, to } => {
+                    replace_matches(&value, from, to, false, |_, _| true)
+                }
+            };
+        }
+        value
+    }
+}
+
+/// `value` with the non-overlapping occurrences of `from` replaced by `to`,
+/// if `is_match` accepts the characters before and after the occurrence.
+fn replace_matches(
+    value: &str,
+    from: &str,
+    to: &str,
+    ignore_ascii_case: bool,
+    is_match: impl Fn(Option<char>, Option<char>) -> bool,
+) -> String {
+    if from.is_empty() {
+        return value.to_string();
+    }
+    // ASCII lowercasing keeps the byte offsets the same.
+    let (haystack, needle) = if ignore_ascii_case {
+        (value.to_ascii_lowercase(), from.to_ascii_lowercase())
+    } else {
+        (value.to_string(), from.to_string())
+    };
+    let mut result = String::with_capacity(value.len());
+    let mut copied_until = 0;
+    for (start, _) in haystack.match_indices(&needle) {
+        let end = start + needle.len();
+        let before = value[..start].chars().next_back();
+        let after = value[end..].chars().next();
+        if start < copied_until || !is_match(before, after) {
+            continue;
+        }
+        result.push_str(&value[copied_until..start]);
+        result.push_str(to);
+        copied_until = end;
+    }
+    result.push_str(&value[copied_until..]);
+    result
+}
+
+/// Whether `c` can be part of a URL before the place where a URL prefix
+/// matches, so that the match is in the middle of a URL.
+fn is_url_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || ".-_/:+@".contains(c)
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || ".-_".contains(c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StreamRewriter;
+    use crate::{ParseOptions, SrcSrvStream};
+
+    #[test]
+    fn rewrite_rules() {
+        let stream = "SRCSRV: ini ------------------------------------------------\n\
+VERSION=2\n\
+SRCSRV: variables ------------------------------------------\n\
+SERVER=HTTPS://hg.example.com/repo\n\
+PROXY=https://proxy.example.com/?u=https://hg.example.com/repo\n\
+SRCSRVTRG=%SERVER%/raw-file/%var3%/%var2%\n\
+SRCSRV: source files ---------------------------------------\n\
+c:\\build\\https:\\a.cpp*a.cpp*abc123\n\
+c:\\build\\b.cpp*b.cpp*abc1234\n\
+SRCSRV: end ------------------------------------------------\n";
+        let stream = SrcSrvStream::parse_with_options(
+            stream.as_bytes(),
+            &ParseOptions::new().preserve_layout(true),
+        )
+        .unwrap();
+        let bytes = StreamRewriter::new()
+            .replace_url_prefix("https://", "https://mirror.internal/")
+            .replace_revision("abc123", "def456")
+            .replace_text("raw-file", "raw")
+            .set_var("EXTRA", "1")
+            .rewrite(&stream)
+            .to_bytes()
+            .unwrap();
+        assert_eq!(
+            std::str::from_utf8(&bytes).unwrap(),
+            "SRCSRV: ini ------------------------------------------------\n\
+VERSION=2\n\
+SRCSRV: variables ------------------------------------------\n\
+SERVER=https://mirror.internal/hg.example.com/repo\n\
+PROXY=https://mirror.internal/proxy.example.com/?u=https://mirror.internal/hg.example.com/repo\n\
+SRCSRVTRG=%SERVER%/raw/%var3%/%var2%\n\
+EXTRA=1\n\
+SRCSRV: source files ---------------------------------------\n\
+c:\\build\\https:\\a.cpp*a.cpp*def456\n\
+c:\\build\\b.cpp*b.cpp*abc1234\n\
+SRCSRV: end ------------------------------------------------\n"
+        );
+    }
+}
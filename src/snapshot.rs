@@ -0,0 +1,118 @@
+use crate::{SrcSrvStream, SrcSrvStreamBuilder};
+
+/// An owned copy of the contents of a [`SrcSrvStream`], which does not borrow
+/// from the stream bytes. With the `serde` feature, it can be serialized and
+/// deserialized, for example to cache it on disk.
+///
+/// Use [`SrcSrvStreamSnapshot::to_builder`] to turn the snapshot back into
+/// stream bytes, which can then be parsed again for lookups.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SrcSrvStreamSnapshot {
+    /// The value of the VERSION field.
+    pub version: u8,
+    /// (field name, field value) for each field in the ini section.
+    pub ini_fields: Vec<(String, String)>,
+    /// (variable name, raw variable value) for each variable in the variables section.
+    pub variables: Vec<(String, String)>,
+    /// [var1, ..., varN] for each entry in the source files section.
+    pub source_file_entries: Vec<Vec<String>>,
+}
+
+impl SrcSrvStreamSnapshot {
+    /// Create a snapshot of `stream`.
+    pub fn new(stream: &SrcSrvStream<'_>) -> Self {
+        let ini_fields = stream
+            .ini_lines
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        let variables = stream
+            .var_lines
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        let source_file_entries = stream
+            .source_file_entries()
+            .map(|(_, vars)| vars.iter().map(|var| var.to_string()).collect())
+            .collect();
+        SrcSrvStreamSnapshot {
+            version: stream.version(),
+            ini_fields,
+            variables,
+            source_file_entries,
+        }
+    }
+
+    /// Create a [`SrcSrvStreamBuilder`] with the contents of this snapshot.
+    pub fn to_builder(&self) -> SrcSrvStreamBuilder {
+        let mut builder = SrcSrvStreamBuilder::new();
+        for (name, value) in &self.ini_fields {
+            builder.set_ini_field(name, value);
+        }
+        for (name, value) in &self.variables {
+            builder.set_var(name, value);
+        }
+        for vars in &self.source_file_entries {
+            builder.add_source_file_entry(vars);
+        }
+        builder
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{SrcSrvStream, SrcSrvStreamSnapshot};
+
+    const STREAM: &str = r#"SRCSRV: ini ------------------------------------------------
+VERSION=2
+VERCTRL=http
+SRCSRV: variables ------------------------------------------
+HTTP_ALIAS=https://raw.githubusercontent.com/baldurk/renderdoc/v1.15/
+SRCSRVTRG=%HTTP_ALIAS%%var2%
+SRCSRV: source files ---------------------------------------
+C:\build\renderdoc\renderdoc\maths\matrix.cpp*renderdoc/maths/matrix.cpp
+SRCSRV: end ------------------------------------------------"#;
+
+    #[test]
+    fn snapshot_round_trip() {
+        let stream = SrcSrvStream::parse(STREAM.as_bytes()).unwrap();
+        let snapshot = stream.snapshot();
+        assert_eq!(snapshot.version, 2);
+        assert_eq!(
+            snapshot.source_file_entries,
+            vec![vec![
+                r#"C:\build\renderdoc\renderdoc\maths\matrix.cpp"#.to_string(),
+                "renderdoc/maths/matrix.cpp".to_string()
+            ]]
+        );
+
+        let bytes = snapshot.to_builder().to_bytes().unwrap();
+        let stream = SrcSrvStream::parse(&bytes).unwrap();
+        assert_eq!(SrcSrvStreamSnapshot::new(&stream), snapshot);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde() {
+        let stream = SrcSrvStream::parse(STREAM.as_bytes()).unwrap();
+        let snapshot = stream.snapshot();
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let deserialized: SrcSrvStreamSnapshot = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized, snapshot);
+
+        let method = stream
+            .source_for_path(r#"C:\build\renderdoc\renderdoc\maths\matrix.cpp"#, "")
+            .unwrap()
+            .unwrap();
+        let json = serde_json::to_string(&method).unwrap();
+        assert_eq!(
+            json,
+            r#"{"Download":{"url":"https://raw.githubusercontent.com/baldurk/renderdoc/v1.15/renderdoc/maths/matrix.cpp"}}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<crate::SourceRetrievalMethod>(&json).unwrap(),
+            method
+        );
+    }
+}
@@ -0,0 +1,191 @@
+use crate::cache_path::relative_target_path;
+use crate::{EvalError, EvalOptions, SourceRetrievalMethod, SrcSrvStream};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::result::Result;
+
+/// A directory where source files are extracted to, the way debuggers manage
+/// their source cache.
+///
+/// The directory is used as the value of `%targ%` when looking up files, so
+/// commands write their output below it. Downloaded files are stored below it
+/// at the host and path of their URL. Before fetching a file or executing a
+/// command, check [`SourceCache::cached_path`] to see if the file is already
+/// there.
+///
+/// ```
+/// use srcsrv::{SourceCache, SourceRetrievalMethod, SrcSrvStream};
+///
+/// # fn get(url: &str) -> Vec<u8> { unimplemented!() }
+/// # fn wrapper(stream: &SrcSrvStream) -> Result<(), Box<dyn std::error::Error>> {
+/// let cache = SourceCache::new(r#"C:\Debugger\Cached Sources"#);
+/// let path = r#"C:\build\renderdoc\renderdoc\data\glsl\gl_texsample.h"#;
+/// if let Some(method) = cache.source_for_path(stream, path)? {
+///     if let Some(cached_path) = cache.cached_path(&method) {
+///         println!("Already cached at {}", cached_path.display());
+///     } else if let SourceRetrievalMethod::Download { url } = &method {
+///         let stored_path = cache.store(&method, &get(url))?;
+///         println!("Downloaded to {}", stored_path.display());
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct SourceCache {
+    base_path: PathBuf,
+    /// `base_path` as the value of `%targ%`, without a trailing separator.
+    targ: String,
+}
+
+impl SourceCache {
+    /// Create a cache which stores files below `base_path`. The directory
+    /// is created when the first file is stored.
+    pub fn new(base_path: impl Into<PathBuf>) -> Self {
+        let base_path = base_path.into();
+        let targ = base_path
+            .to_string_lossy()
+            .trim_end_matches(['/', '\\'])
+            .to_string();
+        Self { base_path, targ }
+    }
+
+    /// The directory of the cache.
+    pub fn base_path(&self) -> &Path {
+        &self.base_path
+    }
+
+    /// Look up `original_file_path` in `stream`, with the cache directory as
+    /// the extraction base path.
+    ///
+    /// Returns `Ok(None)` if the file path was not found in the list of file
+    /// entries.
+    pub fn source_for_path(
+        &self,
+        stream: &SrcSrvStream,
+        original_file_path: &str,
+    ) -> Result<Option<SourceRetrievalMethod>, EvalError> {
+        stream.source_for_path(original_file_path, &self.targ)
+    }
+
+    /// Like [`SourceCache::source_for_path`], but evaluated with `options`,
+    /// see [`SrcSrvStream::source_for_path_with_vars`].
+    pub fn source_for_path_with_vars(
+        &self,
+        stream: &SrcSrvStream,
+        original_file_path: &str,
+        options: &EvalOptions,
+    ) -> Result<Option<SourceRetrievalMethod>, EvalError> {
+        stream.source_for_path_with_vars(original_file_path, &self.targ, options)
+    }
+
+    /// The path of the file for `method` relative to the cache directory, with
+    /// `/` as the separator.
+    ///
+    /// For methods with a target path, this is the target path without the
+    /// `%targ%` prefix. For downloads, it's the host and path of the URL.
+    /// Characters which are not allowed in Windows file names are replaced with
+    /// `_`, and `.` and `..` components are dropped, so the path always stays
+    /// within the cache directory.
+    ///
+    /// Returns `None` for [`SourceRetrievalMethod::Other`].
+    pub fn relative_target_path(&self, method: &SourceRetrievalMethod) -> Option<String> {
+        relative_target_path(method, &self.targ)
+    }
+
+    /// The path of the file for `method` in the cache directory. See
+    /// [`SourceCache::relative_target_path`].
+    pub fn target_path(&self, method: &SourceRetrievalMethod) -> Option<PathBuf> {
+        let relative_path = self.relative_target_path(method)?;
+        Some(self.base_path.join(relative_path))
+    }
+
+    /// The path of the file for `method` in the cache directory, if the file
+    /// exists.
+    pub fn cached_path(&self, method: &SourceRetrievalMethod) -> Option<PathBuf> {
+        self.target_path(method).filter(|path| path.is_file())
+    }
+
+    /// Store `contents` as the file for `method` and return its path, creating
+    /// the parent directories if needed.
+    ///
+    /// The contents are written to a temporary file next to the target path
+    /// first and then renamed, so the cache never contains a partial file.
+    ///
+    /// Fails with [`io::ErrorKind::InvalidInput`] for methods without a target
+    /// path.
+    pub fn store(&self, method: &SourceRetrievalMethod, contents: &[u8]) -> io::Result<PathBuf> {
+        let path = self.target_path(method).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The retrieval method has no target path.",
+            )
+        })?;
+        write_atomically(&path, contents)?;
+        Ok(path)
+    }
+}
+
+/// Write `contents` to a temporary file next to `path` and rename it to
+/// `path`, creating the parent directories if needed.
+pub(crate) fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut partial_path = path.as_os_str().to_owned();
+    partial_path.push(format!(".{}.partial", std::process::id()));
+    let result =
+        std::fs::write(&partial_path, contents).and_then(|()| std::fs::rename(&partial_path, path));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&partial_path);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SourceCache;
+    use crate::{SourceRetrievalMethod, SrcSrvStream};
+
+    #[test]
+    fn source_cache() {
+        let stream = r#"SRCSRV: ini ------------------------------------------------
+VERSION=1
+SRCSRV: variables ------------------------------------------
+SRCSRVTRG=%targ%\%var2%\%fnbksl%(%var3%)\%fnfile%(%var1%)
+SRCSRVCMD=p4.exe print -o %srcsrvtrg% -q "//depot/%var3%#%var2%"
+SRCSRV: source files ---------------------------------------
+d:\build\foo.cpp*4*src/foo.cpp
+SRCSRV: end ------------------------------------------------"#;
+        let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
+        let dir = std::env::temp_dir().join(format!("srcsrv-cache-{}", std::process::id()));
+        let cache = SourceCache::new(&dir);
+
+        let method = cache
+            .source_for_path(&stream, r#"d:\build\foo.cpp"#)
+            .unwrap()
+            .unwrap();
+        match &method {
+            SourceRetrievalMethod::ExecuteCommand { target_path, .. } => assert_eq!(
+                target_path,
+                &format!(r#"{}\4\src\foo.cpp\foo.cpp"#, dir.display())
+            ),
+            other => panic!("Unexpected method {:?}", other),
+        }
+        assert_eq!(
+            cache.relative_target_path(&method).as_deref(),
+            Some("4/src/foo.cpp/foo.cpp")
+        );
+        assert_eq!(cache.cached_path(&method), None);
+
+        let download = SourceRetrievalMethod::Download {
+            url: "https://example.com/src/../main.cpp".to_string(),
+        };
+        let path = cache.store(&download, b"int main() {}\n").unwrap();
+        assert_eq!(path, dir.join("example.com/src/main.cpp"));
+        assert_eq!(cache.cached_path(&download), Some(path.clone()));
+        let contents = std::fs::read(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(contents, b"int main() {}\n");
+    }
+}
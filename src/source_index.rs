@@ -0,0 +1,111 @@
+use crate::{OwnedSrcSrvStream, SourceRetrievalMethod, SrcSrvStream};
+
+/// A source index maps the original paths of source files, as they are stored
+/// in the debug information, to a way to obtain the file's contents.
+///
+/// [`SrcSrvStream`] is one such index. Consumers like profilers and debuggers
+/// can code against this trait, so that other source index formats can be
+/// used alongside srcsrv streams. A slice of indexes is an index as well,
+/// which returns the result of the first index that knows the path.
+///
+/// ```
+/// use srcsrv::{SourceIndex, SourceRetrievalMethod};
+///
+/// fn print_source(index: &dyn SourceIndex, path: &str) {
+///     match index.lookup(path) {
+///         Some(SourceRetrievalMethod::Download { url }) => println!("{path}: {url}"),
+///         Some(method) => println!("{path}: {:?}", method.kind()),
+///         None => println!("{path}: not indexed"),
+///     }
+/// }
+/// ```
+pub trait SourceIndex {
+    /// Find out how to obtain the source for the file at `original_path`.
+    /// Returns `None` if the index doesn't know the file.
+    fn lookup(&self, original_path: &str) -> Option<SourceRetrievalMethod>;
+}
+
+impl<'a> SourceIndex for SrcSrvStream<'a> {
+    /// Look up the file with [`SrcSrvStream::source_for_path`], with an
+    /// empty extraction base path, i.e. `%targ%` is empty and target paths
+    /// start with `\`. Entries whose variables can't be evaluated are treated
+    /// as unknown.
+    ///
+    /// Call `source_for_path` directly to pass an extraction base path or to
+    /// get the evaluation errors.
+    fn lookup(&self, original_path: &str) -> Option<SourceRetrievalMethod> {
+        self.source_for_path(original_path, "").ok().flatten()
+    }
+}
+
+impl SourceIndex for OwnedSrcSrvStream {
+    fn lookup(&self, original_path: &str) -> Option<SourceRetrievalMethod> {
+        self.stream().lookup(original_path)
+    }
+}
+
+impl<T: SourceIndex + ?Sized> SourceIndex for &T {
+    fn lookup(&self, original_path: &str) -> Option<SourceRetrievalMethod> {
+        (**self).lookup(original_path)
+    }
+}
+
+impl<T: SourceIndex + ?Sized> SourceIndex for Box<T> {
+    fn lookup(&self, original_path: &str) -> Option<SourceRetrievalMethod> {
+        (**self).lookup(original_path)
+    }
+}
+
+impl<T: SourceIndex> SourceIndex for [T] {
+    fn lookup(&self, original_path: &str) -> Option<SourceRetrievalMethod> {
+        self.iter().find_map(|index| index.lookup(original_path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SourceIndex;
+    use crate::{SourceRetrievalMethod, SrcSrvStream, SrcSrvStreamBuilder};
+    use std::collections::HashMap;
+
+    /// A source index in a different format.
+    struct MapIndex(HashMap<&'static str, &'static str>);
+
+    impl SourceIndex for MapIndex {
+        fn lookup(&self, original_path: &str) -> Option<SourceRetrievalMethod> {
+            let url = self.0.get(original_path)?;
+            Some(SourceRetrievalMethod::Download {
+                url: url.to_string(),
+            })
+        }
+    }
+
+    #[test]
+    fn lookup() {
+        let bytes = SrcSrvStreamBuilder::new()
+            .set_var("SRCSRVTRG", "https://example.com/%var2%")
+            .add_source_file_entry(&[r#"C:\build\main.cpp"#, "main.cpp"])
+            .to_bytes()
+            .unwrap();
+        let stream = SrcSrvStream::parse(&bytes).unwrap();
+        let map = MapIndex(HashMap::from([(
+            "/home/me/lib.rs",
+            "https://example.org/lib.rs",
+        )]));
+        let indexes: Vec<Box<dyn SourceIndex>> = vec![Box::new(stream), Box::new(map)];
+
+        let url = |path| match indexes.lookup(path) {
+            Some(SourceRetrievalMethod::Download { url }) => Some(url),
+            _ => None,
+        };
+        assert_eq!(
+            url(r#"c:\build\MAIN.cpp"#).as_deref(),
+            Some("https://example.com/main.cpp")
+        );
+        assert_eq!(
+            url("/home/me/lib.rs").as_deref(),
+            Some("https://example.org/lib.rs")
+        );
+        assert_eq!(url("/home/me/other.rs"), None);
+    }
+}
@@ -0,0 +1,725 @@
+use crate::{
+    EvalError, SourceIndex, SourceLinkError, SourceRetrievalMethod, SrcSrvStream,
+    SrcSrvStreamBuilder,
+};
+use std::collections::{BTreeMap, HashSet};
+use std::result::Result;
+
+/// The kind GUID of the custom debug information which contains the Source
+/// Link JSON, CC110556-A091-4D38-9FEC-25AB9A351A6A, in its on-disk layout.
+const SOURCE_LINK_KIND: [u8; 16] = [
+    0x56, 0x05, 0x11, 0xcc, 0x91, 0xa0, 0x38, 0x4d, 0x9f, 0xec, 0x25, 0xab, 0x9a, 0x35, 0x1a, 0x6a,
+];
+
+/// A Source Link document, which .NET portable PDBs carry instead of a srcsrv
+/// stream. It maps local paths, or local directories with a `*` wildcard, to
+/// URLs.
+///
+/// Lookups return [`SourceRetrievalMethod::Download`], so consumers can handle
+/// native and managed code the same way, also through [`SourceIndex`].
+///
+/// ```
+/// use srcsrv::{SourceLink, SourceRetrievalMethod};
+///
+/// # fn wrapper() -> std::result::Result<(), srcsrv::SourceLinkError> {
+/// let source_link = SourceLink::parse_json(br#"{
+///     "documents": {
+///         "C:\\src\\app\\*": "https://raw.githubusercontent.com/org/app/0123abcd/*"
+///     }
+/// }"#)?;
+/// assert_eq!(
+///     source_link.source_for_path(r#"C:\src\app\Program.cs"#),
+///     Some(SourceRetrievalMethod::Download {
+///         url: "https://raw.githubusercontent.com/org/app/0123abcd/Program.cs".to_string()
+///     })
+/// );
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLink {
+    /// The (path or path prefix, URL template) pairs, in document order.
+    documents: Vec<(String, String)>,
+}
+
+impl SourceLink {
+    /// Parse a Source Link JSON document, e.g. from a file next to the binary
+    /// or from [`SourceLink::from_portable_pdb`].
+    pub fn parse_json(json: &[u8]) -> Result<SourceLink, SourceLinkError> {
+        let json: serde_json::Value = serde_json::from_slice(json)
+            .map_err(|e| SourceLinkError::InvalidJson(e.to_string()))?;
+        let documents = json
+            .get("documents")
+            .and_then(serde_json::Value::as_object)
+            .ok_or_else(|| {
+                SourceLinkError::InvalidJson("Missing \"documents\" object.".to_string())
+            })?;
+        let documents = documents
+            .iter()
+            .map(|(path, url)| match url.as_str() {
+                Some(url) => Ok((path.clone(), url.to_string())),
+                None => Err(SourceLinkError::InvalidJson(format!(
+                    "The URL for {path:?} is not a string."
+                ))),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(SourceLink { documents })
+    }
+
+    /// Read the Source Link JSON from the custom debug information of a
+    /// portable PDB file. Returns `Ok(None)` if the PDB file has no Source
+    /// Link information.
+    pub fn from_portable_pdb(pdb: &[u8]) -> Result<Option<SourceLink>, SourceLinkError> {
+        match portable_pdb::source_link_json(pdb)? {
+            Some(json) => Ok(Some(Self::parse_json(json)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Find the URL for the file at `original_file_path`.
+    ///
+    /// Paths are compared ASCII case-insensitively. An exact entry takes
+    /// precedence, then the wildcard entry with the longest matching prefix.
+    /// The part of the path which matches the `*` is inserted into the URL
+    /// with `/` separators.
+    pub fn source_for_path(&self, original_file_path: &str) -> Option<SourceRetrievalMethod> {
+        let (index, rest) = self.matching_document(original_file_path)?;
+        let url = &self.documents[index].1;
+        let url = match rest {
+            Some(rest) => url.replacen('*', &rest.replace('\\', "/"), 1),
+            None => url.clone(),
+        };
+        Some(SourceRetrievalMethod::Download { url })
+    }
+
+    /// Create a builder for a srcsrv stream which downloads each of
+    /// `local_paths` from the same URL as this Source Link document, so that
+    /// both formats can be generated from the same input.
+    ///
+    /// A srcsrv stream lists every file, so the files need to be known, e.g.
+    /// from the PDB file. Paths which no document matches are skipped, as are
+    /// paths whose URLs contain a `%`, which the stream can't express.
+    ///
+    /// Each document becomes a variable `DOCUMENT1`, `DOCUMENT2`, etc., in
+    /// which a `*` is replaced with `%var3%`, and `SRCSRVTRG=%fnvar%(%var2%)`
+    /// looks up the variable of each file entry.
+    ///
+    /// ```
+    /// use srcsrv::{SourceLink, SrcSrvStream, SourceRetrievalMethod};
+    ///
+    /// # fn wrapper() -> std::result::Result<(), Box<dyn std::error::Error>> {
+    /// let source_link = SourceLink::parse_json(br#"{
+    ///     "documents": {
+    ///         "C:\\src\\app\\*": "https://raw.githubusercontent.com/org/app/0123abcd/*"
+    ///     }
+    /// }"#)?;
+    /// let bytes = source_link
+    ///     .to_srcsrv_stream_builder(&[r#"C:\src\app\Program.cs"#])
+    ///     .to_bytes()?;
+    ///
+    /// let stream = SrcSrvStream::parse(&bytes)?;
+    /// assert_eq!(
+    ///     stream.source_for_path(r#"C:\src\app\Program.cs"#, "")?,
+    ///     source_link.source_for_path(r#"C:\src\app\Program.cs"#)
+    /// );
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn to_srcsrv_stream_builder<S: AsRef<str>>(
+        &self,
+        local_paths: &[S],
+    ) -> SrcSrvStreamBuilder {
+        let mut builder = SrcSrvStreamBuilder::new();
+        builder
+            .set_ini_field("VERCTRL", "http")
+            .set_var("SRCSRVTRG", "%fnvar%(%var2%)");
+        let mut used_documents = vec![false; self.documents.len()];
+        for local_path in local_paths {
+            let local_path = local_path.as_ref();
+            let (index, rest) = match self.matching_document(local_path) {
+                Some(document) => document,
+                None => continue,
+            };
+            let url = &self.documents[index].1;
+            let rest = rest.map(|rest| rest.replace('\\', "/"));
+            if url.contains('%') || matches!(&rest, Some(rest) if rest.contains('%')) {
+                continue;
+            }
+            let var_name = format!("DOCUMENT{}", index + 1);
+            if !used_documents[index] {
+                builder.set_var(&var_name, &url.replacen('*', "%var3%", 1));
+                used_documents[index] = true;
+            }
+            match rest {
+                Some(rest) => builder.add_source_file_entry(&[local_path, &var_name, &rest]),
+                None => builder.add_source_file_entry(&[local_path, &var_name]),
+            };
+        }
+        builder
+    }
+
+    /// The index of the document for `original_file_path`, and the part of
+    /// the path which matches its `*`, if it is a wildcard document.
+    fn matching_document<'p>(
+        &self,
+        original_file_path: &'p str,
+    ) -> Option<(usize, Option<&'p str>)> {
+        let mut best: Option<(usize, usize, Option<&str>)> = None;
+        for (index, (pattern, _)) in self.documents.iter().enumerate() {
+            let (len, rest) = match pattern.strip_suffix('*') {
+                Some(prefix) => {
+                    let Some(rest) = strip_prefix_ignore_ascii_case(original_file_path, prefix)
+                    else {
+                        continue;
+                    };
+                    (prefix.len(), Some(rest))
+                }
+                None if pattern.eq_ignore_ascii_case(original_file_path) => (usize::MAX, None),
+                None => continue,
+            };
+            if !matches!(&best, Some((best_len, ..)) if *best_len >= len) {
+                best = Some((len, index, rest));
+            }
+        }
+        best.map(|(_, index, rest)| (index, rest))
+    }
+
+    /// Serialize the document as Source Link JSON.
+    pub fn to_json(&self) -> String {
+        let documents: serde_json::Map<String, serde_json::Value> = self
+            .documents
+            .iter()
+            .map(|(path, url)| (path.clone(), serde_json::Value::String(url.clone())))
+            .collect();
+        serde_json::json!({ "documents": documents }).to_string()
+    }
+}
+
+/// The result of [`SrcSrvStream::to_source_link`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLinkConversion {
+    /// The Source Link document for the files which could be converted.
+    pub source_link: SourceLink,
+    /// The files which could not be converted, in stream order.
+    pub unconverted: Vec<UnconvertedEntry>,
+}
+
+/// A file entry which [`SrcSrvStream::to_source_link`] could not express as
+/// Source Link.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum UnconvertedEntry {
+    /// The file is not obtained by a plain download, e.g. because it needs a
+    /// command or its response needs to be decoded.
+    NotDownload {
+        original_path: String,
+        method: SourceRetrievalMethod,
+    },
+    /// Evaluating the entry failed.
+    EvalFailed {
+        original_path: String,
+        error: EvalError,
+    },
+}
+
+impl<'a> SrcSrvStream<'a> {
+    /// Convert the file entries which are downloaded from a URL, such as the
+    /// entries of the streams written by [`SrcSrvStreamBuilder::http`], into
+    /// a Source Link document, for tools which only understand Source Link.
+    ///
+    /// Entries whose URL ends with the same path components as their local
+    /// path are combined into `*` wildcard documents per directory, all other
+    /// entries get a document for their exact path. Every converted entry
+    /// resolves to the same URL as in the stream. The wildcard documents can
+    /// also match files which are not in the stream.
+    ///
+    /// ```
+    /// use srcsrv::{SrcSrvStream, SrcSrvStreamBuilder};
+    ///
+    /// # fn wrapper() -> std::result::Result<(), Box<dyn std::error::Error>> {
+    /// let bytes = SrcSrvStreamBuilder::github("org", "app", "0123abcd")
+    ///     .add_http_entries_below(r#"C:\src\app"#, &[
+    ///         r#"C:\src\app\main.cpp"#,
+    ///         r#"C:\src\app\util\util.cpp"#,
+    ///     ])
+    ///     .to_bytes()?;
+    /// let stream = SrcSrvStream::parse(&bytes)?;
+    /// let conversion = stream.to_source_link();
+    /// assert!(conversion.unconverted.is_empty());
+    /// assert_eq!(
+    ///     conversion.source_link.to_json(),
+    ///     r#"{"documents":{"C:\\src\\app\\*":"https://raw.githubusercontent.com/org/app/0123abcd/*"}}"#
+    /// );
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn to_source_link(&self) -> SourceLinkConversion {
+        let mut urls = Vec::new();
+        let mut unconverted = Vec::new();
+        let mut seen_paths = HashSet::new();
+        for (original_path, _) in self.source_file_entries() {
+            if !seen_paths.insert(original_path.to_ascii_lowercase()) {
+                continue;
+            }
+            match self.source_for_path(original_path, "") {
+                Ok(Some(SourceRetrievalMethod::Download { url })) => {
+                    urls.push((original_path, url));
+                }
+                Ok(Some(method)) => unconverted.push(UnconvertedEntry::NotDownload {
+                    original_path: original_path.to_string(),
+                    method,
+                }),
+                Ok(None) => {}
+                Err(error) => unconverted.push(UnconvertedEntry::EvalFailed {
+                    original_path: original_path.to_string(),
+                    error,
+                }),
+            }
+        }
+
+        // For each wildcard pattern, count how many files agree on each URL
+        // template, and use the most common one.
+        let mut template_counts: BTreeMap<String, BTreeMap<String, usize>> = BTreeMap::new();
+        for (original_path, url) in &urls {
+            if let Some((pattern, template)) = wildcard_document(original_path, url) {
+                *template_counts
+                    .entry(pattern.to_ascii_lowercase())
+                    .or_default()
+                    .entry(format!("{}\0{}", pattern, template))
+                    .or_default() += 1;
+            }
+        }
+        let mut documents: Vec<(String, String)> = template_counts
+            .into_values()
+            .filter_map(|counts| {
+                let (document, _) = counts.into_iter().max_by_key(|(_, count)| *count)?;
+                let (pattern, template) = document.split_once('\0')?;
+                Some((pattern.to_string(), template.to_string()))
+            })
+            .collect();
+
+        // Files which the wildcards don't resolve correctly get an exact entry.
+        let wildcards = SourceLink {
+            documents: documents.clone(),
+        };
+        for (original_path, url) in urls {
+            let resolved = wildcards.source_for_path(original_path);
+            if !matches!(resolved, Some(SourceRetrievalMethod::Download { url: resolved }) if resolved == url)
+            {
+                documents.push((original_path.to_string(), url));
+            }
+        }
+
+        SourceLinkConversion {
+            source_link: SourceLink { documents },
+            unconverted,
+        }
+    }
+}
+
+/// The wildcard pattern and URL template which map `local_path` to `url`,
+/// based on the longest common trailing path, e.g. `C:\src\*` and
+/// `https://example.com/repo/*` for `C:\src\dir\a.cpp` and
+/// `https://example.com/repo/dir/a.cpp`.
+fn wildcard_document(local_path: &str, url: &str) -> Option<(String, String)> {
+    let local_components: Vec<&str> = local_path.split(['/', '\\']).collect();
+    let url_segments: Vec<&str> = url.split('/').collect();
+    // Keep the first local component and the scheme and host of the URL.
+    let common = local_components
+        .iter()
+        .rev()
+        .zip(url_segments.iter().rev())
+        .take_while(|(local, url)| local == url && !local.is_empty())
+        .count()
+        .min(local_components.len() - 1)
+        .min(url_segments.len().saturating_sub(3));
+    if common == 0 {
+        return None;
+    }
+    let tail_len = |components: &[&str]| {
+        components[components.len() - common..]
+            .iter()
+            .map(|c| c.len() + 1)
+            .sum::<usize>()
+            - 1
+    };
+    let pattern = &local_path[..local_path.len() - tail_len(&local_components)];
+    let template = &url[..url.len() - tail_len(&url_segments)];
+    Some((format!("{}*", pattern), format!("{}*", template)))
+}
+
+impl SourceIndex for SourceLink {
+    fn lookup(&self, original_path: &str) -> Option<SourceRetrievalMethod> {
+        self.source_for_path(original_path)
+    }
+}
+
+fn strip_prefix_ignore_ascii_case<'s>(s: &'s str, prefix: &str) -> Option<&'s str> {
+    let head = s.get(..prefix.len())?;
+    if head.eq_ignore_ascii_case(prefix) {
+        Some(&s[prefix.len()..])
+    } else {
+        None
+    }
+}
+
+/// Just enough of the ECMA-335 metadata format to find the custom debug
+/// information in a portable PDB file.
+mod portable_pdb {
+    use super::SOURCE_LINK_KIND;
+    use crate::SourceLinkError;
+    use std::convert::TryInto;
+    use std::result::Result;
+
+    const METADATA_SIGNATURE: u32 = 0x424a_5342;
+    const DOCUMENT: usize = 0x30;
+    const CUSTOM_DEBUG_INFORMATION: usize = 0x37;
+
+    /// The tables that the HasCustomDebugInformation coded index can refer to.
+    const HAS_CUSTOM_DEBUG_INFORMATION: [usize; 27] = [
+        0x06, 0x04, 0x01, 0x02, 0x08, 0x09, 0x0a, 0x00, 0x0e, 0x17, 0x14, 0x11, 0x1a, 0x1b, 0x20,
+        0x23, 0x26, 0x27, 0x28, 0x2a, 0x2c, 0x2b, 0x30, 0x32, 0x33, 0x34, 0x35,
+    ];
+
+    fn malformed(message: &str) -> SourceLinkError {
+        SourceLinkError::MalformedPortablePdb(message.to_string())
+    }
+
+    struct Reader<'a> {
+        data: &'a [u8],
+    }
+
+    impl<'a> Reader<'a> {
+        fn bytes(&mut self, len: usize) -> Result<&'a [u8], SourceLinkError> {
+            if len > self.data.len() {
+                return Err(malformed("Unexpected end of data."));
+            }
+            let (bytes, rest) = self.data.split_at(len);
+            self.data = rest;
+            Ok(bytes)
+        }
+
+        fn u16(&mut self) -> Result<u16, SourceLinkError> {
+            Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+        }
+
+        fn u32(&mut self) -> Result<u32, SourceLinkError> {
+            Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+        }
+
+        fn u64(&mut self) -> Result<u64, SourceLinkError> {
+            Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+        }
+
+        /// A heap or table index of 2 or 4 bytes.
+        fn index(&mut self, size: usize) -> Result<usize, SourceLinkError> {
+            if size == 2 {
+                Ok(self.u16()? as usize)
+            } else {
+                Ok(self.u32()? as usize)
+            }
+        }
+    }
+
+    /// The Source Link JSON of the module, if any.
+    pub(super) fn source_link_json(pdb: &[u8]) -> Result<Option<&[u8]>, SourceLinkError> {
+        let mut root = Reader { data: pdb };
+        if root.u32()? != METADATA_SIGNATURE {
+            return Err(malformed(
+                "The file does not start with the metadata signature.",
+            ));
+        }
+        root.bytes(8)?; // Major and minor version, reserved.
+        let version_len = root.u32()? as usize;
+        root.bytes(version_len)?;
+        root.u16()?; // Flags.
+        let stream_count = root.u16()?;
+
+        let (mut pdb_stream, mut tables, mut guids, mut blobs) = (None, None, None, None);
+        for _ in 0..stream_count {
+            let offset = root.u32()? as usize;
+            let size = root.u32()? as usize;
+            let name_len = root
+                .data
+                .iter()
+                .position(|b| *b == 0)
+                .ok_or_else(|| malformed("Unterminated stream name."))?;
+            let name = root.bytes(name_len)?;
+            root.bytes(4 - name_len % 4)?; // The nul terminator and padding.
+            let stream = pdb
+                .get(offset..offset.saturating_add(size))
+                .ok_or_else(|| malformed("Stream out of range."))?;
+            match name {
+                b"#Pdb" => pdb_stream = Some(stream),
+                b"#~" => tables = Some(stream),
+                b"#GUID" => guids = Some(stream),
+                b"#Blob" => blobs = Some(stream),
+                _ => {}
+            }
+        }
+        let (Some(pdb_stream), Some(tables)) = (pdb_stream, tables) else {
+            return Err(malformed("Missing #Pdb or #~ stream."));
+        };
+        let guids = guids.unwrap_or_default();
+        let blobs = blobs.unwrap_or_default();
+
+        // Row counts of all tables: the type system tables from #Pdb, the
+        // debug tables from #~.
+        let mut row_counts = [0usize; 64];
+        let mut pdb_stream = Reader { data: pdb_stream };
+        pdb_stream.bytes(24)?; // PDB id, entry point.
+        let referenced_tables = pdb_stream.u64()?;
+        for (table, rows) in row_counts.iter_mut().enumerate() {
+            if referenced_tables & (1 << table) != 0 {
+                *rows = pdb_stream.u32()? as usize;
+            }
+        }
+        let mut tables = Reader { data: tables };
+        tables.bytes(6)?; // Reserved, major and minor version.
+        let heap_sizes = tables.bytes(2)?[0];
+        let present_tables = tables.u64()?;
+        tables.u64()?; // Sorted tables.
+        if present_tables & ((1 << DOCUMENT) - 1) != 0 {
+            return Err(malformed("Type system tables in a portable PDB."));
+        }
+        for (table, rows) in row_counts.iter_mut().enumerate() {
+            if present_tables & (1 << table) != 0 {
+                *rows = tables.u32()? as usize;
+            }
+        }
+
+        let string_size = if heap_sizes & 1 != 0 { 4 } else { 2 };
+        let guid_size = if heap_sizes & 2 != 0 { 4 } else { 2 };
+        let blob_size = if heap_sizes & 4 != 0 { 4 } else { 2 };
+        let index_size = |table: usize| if row_counts[table] < 1 << 16 { 2 } else { 4 };
+        // The coded index uses 5 bits for the table.
+        let small_coded_index = HAS_CUSTOM_DEBUG_INFORMATION
+            .iter()
+            .all(|table| row_counts[*table] < 1 << (16 - 5));
+        let coded_index_size = if small_coded_index { 2 } else { 4 };
+        let row_sizes = [
+            blob_size + guid_size + blob_size + guid_size, // Document
+            index_size(DOCUMENT) + blob_size,              // MethodDebugInformation
+            index_size(0x06) + index_size(0x35) + index_size(0x33) + index_size(0x34) + 8, // LocalScope
+            4 + string_size,                          // LocalVariable
+            string_size + blob_size,                  // LocalConstant
+            index_size(0x35) + blob_size,             // ImportScope
+            index_size(0x06) * 2,                     // StateMachineMethod
+            coded_index_size + guid_size + blob_size, // CustomDebugInformation
+        ];
+        let preceding_size: usize = (DOCUMENT..CUSTOM_DEBUG_INFORMATION)
+            .map(|table| row_counts[table] * row_sizes[table - DOCUMENT])
+            .sum();
+        tables.bytes(preceding_size)?;
+
+        for _ in 0..row_counts[CUSTOM_DEBUG_INFORMATION] {
+            let _parent = tables.index(coded_index_size)?;
+            let kind = tables.index(guid_size)?;
+            let value = tables.index(blob_size)?;
+            if kind == 0 || guids.get((kind - 1) * 16..kind * 16) != Some(&SOURCE_LINK_KIND[..]) {
+                continue;
+            }
+            return blob(blobs, value).map(Some);
+        }
+        Ok(None)
+    }
+
+    /// The blob at `offset` in the blob heap, without its compressed length.
+    fn blob(blobs: &[u8], offset: usize) -> Result<&[u8], SourceLinkError> {
+        let data = blobs
+            .get(offset..)
+            .ok_or_else(|| malformed("Blob out of range."))?;
+        let (len, header_len) = match data {
+            [b0, ..] if b0 & 0x80 == 0 => (*b0 as usize, 1),
+            [b0, b1, ..] if b0 & 0xc0 == 0x80 => ((((*b0 & 0x3f) as usize) << 8) | *b1 as usize, 2),
+            [b0, b1, b2, b3, ..] if b0 & 0xe0 == 0xc0 => {
+                (u32::from_be_bytes([b0 & 0x1f, *b1, *b2, *b3]) as usize, 4)
+            }
+            _ => return Err(malformed("Invalid blob length.")),
+        };
+        data.get(header_len..header_len + len)
+            .ok_or_else(|| malformed("Blob out of range."))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SourceLink, UnconvertedEntry, SOURCE_LINK_KIND};
+    use crate::{SourceLinkError, SourceRetrievalMethod, SrcSrvStream};
+
+    const JSON: &str = r#"{
+        "documents": {
+            "C:\\src\\app\\*": "https://example.com/app/abc/*",
+            "C:\\src\\app\\vendor\\*": "https://example.com/vendor/def/*",
+            "C:\\src\\app\\Generated.cs": "https://example.com/generated.cs"
+        }
+    }"#;
+
+    fn url(source_link: &SourceLink, path: &str) -> Option<String> {
+        match source_link.source_for_path(path)? {
+            SourceRetrievalMethod::Download { url } => Some(url),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn lookup() {
+        let source_link = SourceLink::parse_json(JSON.as_bytes()).unwrap();
+        assert_eq!(
+            url(&source_link, r#"c:\SRC\app\dir\Program.cs"#).as_deref(),
+            Some("https://example.com/app/abc/dir/Program.cs")
+        );
+        assert_eq!(
+            url(&source_link, r#"C:\src\app\vendor\lib.cs"#).as_deref(),
+            Some("https://example.com/vendor/def/lib.cs")
+        );
+        assert_eq!(
+            url(&source_link, r#"C:\src\app\Generated.cs"#).as_deref(),
+            Some("https://example.com/generated.cs")
+        );
+        assert_eq!(url(&source_link, r#"C:\other\Program.cs"#), None);
+
+        assert!(matches!(
+            SourceLink::parse_json(br#"{"documents": {"a": 1}}"#),
+            Err(SourceLinkError::InvalidJson(_))
+        ));
+    }
+
+    #[test]
+    fn to_srcsrv_stream() {
+        let source_link = SourceLink::parse_json(JSON.as_bytes()).unwrap();
+        let paths = [
+            r#"C:\src\app\dir\Program.cs"#,
+            r#"C:\src\app\vendor\lib.cs"#,
+            r#"C:\src\app\Generated.cs"#,
+            r#"C:\src\app\100%.cs"#,
+            r#"C:\other\Program.cs"#,
+        ];
+        let bytes = source_link
+            .to_srcsrv_stream_builder(&paths)
+            .to_bytes()
+            .unwrap();
+        let stream = SrcSrvStream::parse(&bytes).unwrap();
+        assert_eq!(stream.source_file_entries().count(), 3);
+        for path in &paths[..3] {
+            assert_eq!(
+                stream.source_for_path(path, "").unwrap(),
+                source_link.source_for_path(path)
+            );
+        }
+        assert_eq!(stream.source_for_path(paths[3], "").unwrap(), None);
+    }
+
+    #[test]
+    fn from_srcsrv_stream() {
+        let stream = r#"SRCSRV: ini ------------------------------------------------
+VERSION=2
+SRCSRV: variables ------------------------------------------
+SRCSRVTRG=%fnvar%(%var2%)
+APP=https://example.com/app/abc/%var3%
+RENAMED=https://example.com/app/abc/renamed.cpp
+SHARE=\\server\share\%var3%
+SRCSRV: source files ---------------------------------------
+c:\src\a.cpp*APP*a.cpp
+c:\src\dir\b.cpp*APP*dir/b.cpp
+c:\src\c.cpp*RENAMED
+c:\src\d.cpp*MISSING
+c:\src\e.cpp*SHARE*e.cpp
+SRCSRV: end ------------------------------------------------"#;
+        let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
+        let conversion = stream.to_source_link();
+        assert_eq!(
+            conversion.source_link.documents,
+            [
+                (
+                    r#"c:\src\*"#.to_string(),
+                    "https://example.com/app/abc/*".to_string()
+                ),
+                (
+                    r#"c:\src\c.cpp"#.to_string(),
+                    "https://example.com/app/abc/renamed.cpp".to_string()
+                ),
+            ]
+        );
+        assert_eq!(conversion.unconverted.len(), 2);
+        assert!(matches!(
+            &conversion.unconverted[0],
+            UnconvertedEntry::EvalFailed { original_path, .. } if original_path == r#"c:\src\d.cpp"#
+        ));
+        assert!(matches!(
+            &conversion.unconverted[1],
+            UnconvertedEntry::NotDownload {
+                method: SourceRetrievalMethod::CopyFile { .. },
+                ..
+            }
+        ));
+    }
+
+    /// A portable PDB with a single CustomDebugInformation row.
+    fn portable_pdb(json: &[u8]) -> Vec<u8> {
+        let mut pdb_stream = vec![0; 24];
+        pdb_stream.extend_from_slice(&(1u64 << 0x06).to_le_bytes()); // MethodDef
+        pdb_stream.extend_from_slice(&3u32.to_le_bytes());
+
+        let mut tables = vec![0, 0, 0, 0, 2, 0, 0, 1];
+        let present: u64 = (1 << 0x30) | (1 << 0x37);
+        tables.extend_from_slice(&present.to_le_bytes());
+        tables.extend_from_slice(&0u64.to_le_bytes());
+        tables.extend_from_slice(&1u32.to_le_bytes()); // One Document.
+        tables.extend_from_slice(&1u32.to_le_bytes()); // One CustomDebugInformation.
+        tables.extend_from_slice(&[0; 8]); // The Document row.
+                                           // Parent: the Module (tag 7) row 1. Kind: GUID 1. Value: blob 1.
+        tables.extend_from_slice(&[0x27, 0x00, 0x01, 0x00, 0x01, 0x00]);
+
+        let guids = SOURCE_LINK_KIND.to_vec();
+        let mut blobs = vec![0, 0x80 | (json.len() >> 8) as u8, json.len() as u8];
+        blobs.extend_from_slice(json);
+        while blobs.len() % 4 != 0 {
+            blobs.push(0);
+        }
+
+        let version = b"PDB v1.0\0\0\0\0";
+        let streams: [(&[u8], &[u8]); 4] = [
+            (b"#Pdb\0\0\0\0", &pdb_stream),
+            (b"#~\0\0", &tables),
+            (b"#GUID\0\0\0", &guids),
+            (b"#Blob\0\0\0", &blobs),
+        ];
+        let header_len =
+            16 + version.len() + 4 + streams.iter().map(|(n, _)| 8 + n.len()).sum::<usize>();
+        let mut pdb = Vec::new();
+        pdb.extend_from_slice(&0x424a_5342u32.to_le_bytes());
+        pdb.extend_from_slice(&[1, 0, 1, 0, 0, 0, 0, 0]);
+        pdb.extend_from_slice(&(version.len() as u32).to_le_bytes());
+        pdb.extend_from_slice(version);
+        pdb.extend_from_slice(&[0, 0, streams.len() as u8, 0]);
+        let mut offset = header_len;
+        for (name, data) in &streams {
+            pdb.extend_from_slice(&(offset as u32).to_le_bytes());
+            pdb.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            pdb.extend_from_slice(name);
+            offset += data.len();
+        }
+        assert_eq!(pdb.len(), header_len);
+        for (_, data) in &streams {
+            pdb.extend_from_slice(data);
+        }
+        pdb
+    }
+
+    #[test]
+    fn from_portable_pdb() {
+        let source_link = SourceLink::from_portable_pdb(&portable_pdb(JSON.as_bytes()))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            source_link,
+            SourceLink::parse_json(JSON.as_bytes()).unwrap()
+        );
+
+        assert!(matches!(
+            SourceLink::from_portable_pdb(b"Microsoft C/C++ MSF 7.00\r\n"),
+            Err(SourceLinkError::MalformedPortablePdb(_))
+        ));
+    }
+}
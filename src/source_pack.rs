@@ -0,0 +1,286 @@
+use crate::bulk_fetch::fetch_all;
+use crate::mirror::downloadable_entries;
+use crate::{FetchOptions, FetchProgress, SourceFetcher, SrcSrvStream, UnfetchedEntry};
+use std::collections::HashSet;
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+/// The path of the manifest in the archives written by [`write_source_pack`].
+pub const SOURCE_PACK_MANIFEST_PATH: &str = "srcsrv-manifest.txt";
+
+const BLOCK_SIZE: usize = 512;
+
+/// The result of [`write_source_pack`].
+#[derive(Debug)]
+pub struct SourcePack {
+    /// The files in the archive, in stream order.
+    pub members: Vec<SourcePackMember>,
+    /// The files which could not be added to the archive, in stream order.
+    pub skipped: Vec<UnfetchedEntry>,
+}
+
+/// A file in the archive written by [`write_source_pack`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourcePackMember {
+    /// The original file path of the entry in the stream.
+    pub original_path: String,
+    /// The path of the file in the archive, with `/` as the separator.
+    pub member_path: String,
+}
+
+/// Download every file of `stream` which is downloaded from a URL, and write
+/// the files as a tar archive to `writer`, for debugging on machines without
+/// network access.
+///
+/// Each file is stored at the host and path of its URL, like in
+/// [`mirror_sources`](crate::mirror_sources). The archive also contains a
+/// manifest at [`SOURCE_PACK_MANIFEST_PATH`], with a line
+/// `<original path>\t<member path>` for each file. The files are downloaded
+/// with [`fetch_sources`](crate::fetch_sources), which calls `progress` after
+/// every completed download, and are kept in memory until all downloads are
+/// done, so that the archive has the same order as the stream.
+///
+/// Returns an error if writing to `writer` fails. Files which can't be
+/// downloaded are listed in [`SourcePack::skipped`].
+///
+/// ```
+/// use srcsrv::{write_source_pack, FetchOptions, SrcSrvStream};
+/// use std::fs::File;
+///
+/// # fn get(url: &str) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> { unimplemented!() }
+/// # fn wrapper(stream: &SrcSrvStream) -> Result<(), Box<dyn std::error::Error>> {
+/// let file = File::create("sources.tar")?;
+/// let pack = write_source_pack(&get, stream, file, &FetchOptions::new(), |_| {})?;
+/// eprintln!("Packed {} files, skipped {}", pack.members.len(), pack.skipped.len());
+/// # Ok(())
+/// # }
+/// ```
+pub fn write_source_pack(
+    fetcher: &(impl SourceFetcher + Sync + ?Sized),
+    stream: &SrcSrvStream<'_>,
+    mut writer: impl Write,
+    options: &FetchOptions,
+    progress: impl Fn(FetchProgress) + Sync,
+) -> io::Result<SourcePack> {
+    let (downloadable, mut skipped) = downloadable_entries(stream);
+    let methods: Vec<_> = downloadable.iter().map(|entry| &entry.method).collect();
+    let contents = Mutex::new(vec![None; downloadable.len()]);
+    let results = fetch_all(fetcher, &methods, options, progress, |index, data| {
+        contents.lock().unwrap()[index] = Some(data.to_vec());
+        Ok(())
+    });
+    let contents = contents.into_inner().unwrap();
+
+    let mut members = Vec::new();
+    let mut written_paths = HashSet::new();
+    let mut manifest = String::new();
+    for ((entry, result), data) in downloadable.into_iter().zip(results).zip(contents) {
+        match (result, data) {
+            (Ok(()), Some(data)) => {
+                if written_paths.insert(entry.relative_path.clone()) {
+                    write_tar_file(&mut writer, &entry.relative_path, &data)?;
+                }
+                manifest.push_str(&format!(
+                    "{}\t{}\n",
+                    entry.original_path, entry.relative_path
+                ));
+                members.push(SourcePackMember {
+                    original_path: entry.original_path,
+                    member_path: entry.relative_path,
+                });
+            }
+            (Err(error), _) => skipped.push((
+                entry.entry_index,
+                UnfetchedEntry::FetchFailed {
+                    original_path: entry.original_path,
+                    error,
+                },
+            )),
+            (Ok(()), None) => unreachable!("fetch_all stores the contents of successful downloads"),
+        }
+    }
+    write_tar_file(&mut writer, SOURCE_PACK_MANIFEST_PATH, manifest.as_bytes())?;
+    writer.write_all(&[0; 2 * BLOCK_SIZE])?;
+    writer.flush()?;
+    skipped.sort_by_key(|(entry_index, _)| *entry_index);
+
+    Ok(SourcePack {
+        members,
+        skipped: skipped.into_iter().map(|(_, entry)| entry).collect(),
+    })
+}
+
+/// Write a regular file to a tar archive, in the ustar format. Paths which
+/// don't fit into the ustar name fields use a GNU long name entry.
+fn write_tar_file(writer: &mut impl Write, path: &str, contents: &[u8]) -> io::Result<()> {
+    let (prefix, name) = match split_ustar_path(path) {
+        Some(split) => split,
+        None => {
+            let mut long_name = path.as_bytes().to_vec();
+            long_name.push(0);
+            write_tar_entry(writer, "", "././@LongLink", b'L', &long_name)?;
+            let mut end = 100;
+            while !path.is_char_boundary(end) {
+                end -= 1;
+            }
+            ("", &path[..end])
+        }
+    };
+    write_tar_entry(writer, prefix, name, b'0', contents)
+}
+
+fn write_tar_entry(
+    writer: &mut impl Write,
+    prefix: &str,
+    name: &str,
+    type_flag: u8,
+    contents: &[u8],
+) -> io::Result<()> {
+    let mut header = [0; BLOCK_SIZE];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    write_octal(&mut header[100..108], 0o644);
+    write_octal(&mut header[108..116], 0);
+    write_octal(&mut header[116..124], 0);
+    write_octal(&mut header[124..136], contents.len() as u64);
+    write_octal(&mut header[136..148], 0);
+    header[156] = type_flag;
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+    // The checksum is computed with the checksum field set to spaces.
+    header[148..156].copy_from_slice(b"        ");
+    let checksum: u32 = header.iter().map(|&b| u32::from(b)).sum();
+    write_octal(&mut header[148..155], u64::from(checksum));
+
+    writer.write_all(&header)?;
+    writer.write_all(contents)?;
+    let padding = (BLOCK_SIZE - contents.len() % BLOCK_SIZE) % BLOCK_SIZE;
+    writer.write_all(&[0; BLOCK_SIZE][..padding])
+}
+
+/// `value` as zero-padded octal digits followed by a NUL byte.
+fn write_octal(field: &mut [u8], value: u64) {
+    let digits = format!("{:0width$o}\0", value, width = field.len() - 1);
+    field.copy_from_slice(digits.as_bytes());
+}
+
+/// Split `path` into the ustar prefix of at most 155 bytes and the name of at
+/// most 100 bytes, at a `/`.
+fn split_ustar_path(path: &str) -> Option<(&str, &str)> {
+    if path.len() <= 100 {
+        return Some(("", path));
+    }
+    path.match_indices('/')
+        .map(|(index, _)| (&path[..index], &path[index + 1..]))
+        .find(|(prefix, name)| prefix.len() <= 155 && !name.is_empty() && name.len() <= 100)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write_source_pack, SourcePackMember, BLOCK_SIZE, SOURCE_PACK_MANIFEST_PATH};
+    use crate::{FetchOptions, HttpStatusError, SrcSrvStream, UnfetchedEntry};
+    use std::error::Error;
+
+    /// (name, contents) of the files in a tar archive.
+    fn read_tar(mut archive: &[u8]) -> Vec<(String, Vec<u8>)> {
+        let field = |header: &[u8]| {
+            let end = header.iter().position(|&b| b == 0).unwrap_or(header.len());
+            String::from_utf8(header[..end].to_vec()).unwrap()
+        };
+        let mut files = Vec::new();
+        let mut long_name = None;
+        while archive[..BLOCK_SIZE].iter().any(|&b| b != 0) {
+            let (header, rest) = archive.split_at(BLOCK_SIZE);
+            let size = usize::from_str_radix(&field(&header[124..136]), 8).unwrap();
+            let contents = rest[..size].to_vec();
+            archive = &rest[size.div_ceil(BLOCK_SIZE) * BLOCK_SIZE..];
+            if header[156] == b'L' {
+                long_name = Some(field(&contents));
+                continue;
+            }
+            let name = match (long_name.take(), field(&header[345..500])) {
+                (Some(long_name), _) => long_name,
+                (None, prefix) if prefix.is_empty() => field(&header[..100]),
+                (None, prefix) => format!("{}/{}", prefix, field(&header[..100])),
+            };
+            files.push((name, contents));
+        }
+        files
+    }
+
+    #[test]
+    fn source_pack() {
+        let long_dir = "d".repeat(120);
+        let stream = format!(
+            r#"SRCSRV: ini ------------------------------------------------
+VERSION=2
+SRCSRV: variables ------------------------------------------
+SRCSRVTRG=https://example.com/%var2%
+SRCSRV: source files ---------------------------------------
+c:\build\a.cpp*a.cpp
+c:\build\long.cpp*{long_dir}/long.cpp
+c:\build\missing.cpp*missing.cpp
+c:\build\copy\a.cpp*a.cpp
+SRCSRV: end ------------------------------------------------"#
+        );
+        let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
+        let fetcher = |url: &str| -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+            match url.strip_suffix("missing.cpp") {
+                Some(_) => Err(HttpStatusError { status: 404 }.into()),
+                None => Ok(url.as_bytes().to_vec()),
+            }
+        };
+
+        let mut archive = Vec::new();
+        let pack = write_source_pack(
+            &fetcher,
+            &stream,
+            &mut archive,
+            &FetchOptions::new(),
+            |_| {},
+        )
+        .unwrap();
+        let member = |original_path: &str, member_path: &str| SourcePackMember {
+            original_path: original_path.to_string(),
+            member_path: member_path.to_string(),
+        };
+        let long_path = format!("example.com/{long_dir}/long.cpp");
+        assert_eq!(
+            pack.members,
+            [
+                member(r#"c:\build\a.cpp"#, "example.com/a.cpp"),
+                member(r#"c:\build\long.cpp"#, &long_path),
+                member(r#"c:\build\copy\a.cpp"#, "example.com/a.cpp"),
+            ]
+        );
+        assert!(matches!(
+            &pack.skipped[..],
+            [UnfetchedEntry::FetchFailed { original_path, .. }] if original_path == r#"c:\build\missing.cpp"#
+        ));
+
+        assert_eq!(archive.len() % BLOCK_SIZE, 0);
+        let files = read_tar(&archive);
+        assert_eq!(
+            files[..2],
+            [
+                (
+                    "example.com/a.cpp".to_string(),
+                    b"https://example.com/a.cpp".to_vec()
+                ),
+                (
+                    long_path.clone(),
+                    format!("https://{long_path}").into_bytes()
+                ),
+            ]
+        );
+        assert_eq!(files[2].0, SOURCE_PACK_MANIFEST_PATH);
+        assert_eq!(
+            String::from_utf8(files[2].1.clone()).unwrap(),
+            format!(
+                "c:\\build\\a.cpp\texample.com/a.cpp\n\
+                 c:\\build\\long.cpp\t{long_path}\n\
+                 c:\\build\\copy\\a.cpp\texample.com/a.cpp\n"
+            )
+        );
+    }
+}
@@ -0,0 +1,195 @@
+use std::fmt;
+
+/// A source search path in the syntax of the `_NT_SOURCE_PATH` environment
+/// variable and the `.srcpath` command of the Windows debuggers, such as
+/// `srv*;cache*C:\SrcCache;C:\src\project`.
+///
+/// Tools which use this crate can honor the same configuration as WinDbg, for
+/// example by only using the srcsrv stream if the path has a `srv*` element,
+/// and by extracting files to [`DebuggerSourcePath::extraction_base_path`].
+///
+/// ```
+/// use srcsrv::{DebuggerSourcePath, SourcePathElement};
+///
+/// let path = DebuggerSourcePath::parse(r#"srv*C:\SrcCache;C:\src\project"#);
+/// assert!(path.uses_source_server());
+/// assert_eq!(path.extraction_base_path(), Some(r#"C:\SrcCache"#));
+/// assert_eq!(
+///     path.elements()[1],
+///     SourcePathElement::Directory(r#"C:\src\project"#.to_string())
+/// );
+/// assert_eq!(path.to_string(), r#"srv*C:\SrcCache;C:\src\project"#);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DebuggerSourcePath {
+    elements: Vec<SourcePathElement>,
+}
+
+/// An element of a [`DebuggerSourcePath`], separated from the other elements
+/// by `;`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum SourcePathElement {
+    /// `srv*` or `srv*<directory>`: Get files from the source server, i.e. with
+    /// the srcsrv stream of the PDB file, and store them below the directory,
+    /// or below the debugger's default source cache if there is none.
+    SourceServer { cache_path: Option<String> },
+    /// `cache*` or `cache*<directory>`: Cache the files of the following
+    /// elements, e.g. of directories on network shares, in the directory, or
+    /// in the debugger's default cache if there is none.
+    Cache { path: Option<String> },
+    /// A directory which is searched for source files.
+    Directory(String),
+}
+
+impl DebuggerSourcePath {
+    /// Parse a source path. The `srv*` and `cache*` prefixes are matched ASCII
+    /// case-insensitively, whitespace around the elements is ignored, and
+    /// empty elements are skipped.
+    pub fn parse(source_path: &str) -> Self {
+        let elements = source_path
+            .split(';')
+            .map(str::trim)
+            .filter(|element| !element.is_empty())
+            .map(|element| {
+                let optional_path = |path: &str| {
+                    Some(path.trim())
+                        .filter(|path| !path.is_empty())
+                        .map(str::to_string)
+                };
+                if let Some(cache_path) = strip_prefix_ignore_ascii_case(element, "srv*") {
+                    SourcePathElement::SourceServer {
+                        cache_path: optional_path(cache_path),
+                    }
+                } else if let Some(path) = strip_prefix_ignore_ascii_case(element, "cache*") {
+                    SourcePathElement::Cache {
+                        path: optional_path(path),
+                    }
+                } else {
+                    SourcePathElement::Directory(element.to_string())
+                }
+            })
+            .collect();
+        DebuggerSourcePath { elements }
+    }
+
+    /// Parse the value of the `_NT_SOURCE_PATH` environment variable, or
+    /// return `None` if it isn't set or isn't valid Unicode.
+    pub fn from_env() -> Option<Self> {
+        std::env::var("_NT_SOURCE_PATH")
+            .ok()
+            .map(|source_path| Self::parse(&source_path))
+    }
+
+    /// The elements of the path, in order.
+    pub fn elements(&self) -> &[SourcePathElement] {
+        &self.elements
+    }
+
+    /// Whether the path has a `srv*` element, so that files should be
+    /// obtained with the srcsrv stream.
+    pub fn uses_source_server(&self) -> bool {
+        self.elements
+            .iter()
+            .any(|element| matches!(element, SourcePathElement::SourceServer { .. }))
+    }
+
+    /// The directory which should be passed as the extraction base path
+    /// (`%targ%`) to lookups such as [`SrcSrvStream::source_for_path`](crate::SrcSrvStream::source_for_path):
+    /// the directory of the first `srv*` element which has one, or else the
+    /// directory of the first `cache*` element which has one.
+    ///
+    /// Returns `None` if no element names a directory, in which case the
+    /// debugger would use its default cache.
+    pub fn extraction_base_path(&self) -> Option<&str> {
+        let server_cache = self.elements.iter().find_map(|element| match element {
+            SourcePathElement::SourceServer { cache_path } => cache_path.as_deref(),
+            _ => None,
+        });
+        server_cache.or_else(|| {
+            self.elements.iter().find_map(|element| match element {
+                SourcePathElement::Cache { path } => path.as_deref(),
+                _ => None,
+            })
+        })
+    }
+
+    /// The directories which are searched for source files, in order.
+    pub fn directories(&self) -> impl Iterator<Item = &str> + '_ {
+        self.elements.iter().filter_map(|element| match element {
+            SourcePathElement::Directory(directory) => Some(directory.as_str()),
+            _ => None,
+        })
+    }
+}
+
+impl fmt::Display for DebuggerSourcePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, element) in self.elements.iter().enumerate() {
+            if index > 0 {
+                f.write_str(";")?;
+            }
+            element.fmt(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for SourcePathElement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SourcePathElement::SourceServer { cache_path } => {
+                write!(f, "srv*{}", cache_path.as_deref().unwrap_or(""))
+            }
+            SourcePathElement::Cache { path } => {
+                write!(f, "cache*{}", path.as_deref().unwrap_or(""))
+            }
+            SourcePathElement::Directory(directory) => f.write_str(directory),
+        }
+    }
+}
+
+fn strip_prefix_ignore_ascii_case<'s>(s: &'s str, prefix: &str) -> Option<&'s str> {
+    match s.get(..prefix.len()) {
+        Some(start) if start.eq_ignore_ascii_case(prefix) => Some(&s[prefix.len()..]),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DebuggerSourcePath, SourcePathElement};
+
+    #[test]
+    fn parse_source_path() {
+        let path = DebuggerSourcePath::parse(r#" SRV* ; Cache*C:\Cache ;;\\server\src;C:\src "#);
+        assert_eq!(
+            path.elements(),
+            [
+                SourcePathElement::SourceServer { cache_path: None },
+                SourcePathElement::Cache {
+                    path: Some(r#"C:\Cache"#.to_string())
+                },
+                SourcePathElement::Directory(r#"\\server\src"#.to_string()),
+                SourcePathElement::Directory(r#"C:\src"#.to_string()),
+            ]
+        );
+        assert!(path.uses_source_server());
+        assert_eq!(path.extraction_base_path(), Some(r#"C:\Cache"#));
+        assert_eq!(
+            path.directories().collect::<Vec<_>>(),
+            [r#"\\server\src"#, r#"C:\src"#]
+        );
+        assert_eq!(
+            path.to_string(),
+            r#"srv*;cache*C:\Cache;\\server\src;C:\src"#
+        );
+
+        let path = DebuggerSourcePath::parse(r#"C:\src;cache*;srv*D:\SrcSrv"#);
+        assert_eq!(path.extraction_base_path(), Some(r#"D:\SrcSrv"#));
+        assert!(!DebuggerSourcePath::parse(r#"C:\src"#).uses_source_server());
+        assert_eq!(DebuggerSourcePath::parse("").elements(), []);
+    }
+}
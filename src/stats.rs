@@ -0,0 +1,244 @@
+use crate::case_insensitive::CaseInsensitiveStr;
+use crate::{
+    split_entry, EvalOptions, RetrievalKind, SharedEvalCache, SourceRetrievalMethod, SrcSrvStream,
+};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+/// A summary of the contents of a [`SrcSrvStream`], see [`SrcSrvStream::stats`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SrcSrvStreamStats {
+    /// The number of entries in the source files section.
+    pub entry_count: usize,
+    /// The distinct servers that files are obtained from: URL hosts, Perforce
+    /// ports, CVS server names, or the server of a network share.
+    pub hosts: BTreeSet<String>,
+    /// The distinct revisions of the entries whose retrieval method has a
+    /// revision, such as [`SourceRetrievalMethod::GitFile`]. Revisions which
+    /// are only part of a download URL are not included.
+    pub revisions: BTreeSet<String>,
+    /// The number of entries of each retrieval kind. Entries which could not
+    /// be evaluated are not counted here.
+    pub kind_counts: BTreeMap<RetrievalKind, usize>,
+    /// The number of entries which could not be evaluated.
+    pub eval_error_count: usize,
+    /// The variables which are not referenced by any of the `SRCSRV*`
+    /// variables, directly or indirectly, in the order of the variables section.
+    pub unused_variables: Vec<String>,
+}
+
+impl SrcSrvStreamStats {
+    pub(crate) fn new(stream: &SrcSrvStream<'_>) -> Self {
+        let mut stats = SrcSrvStreamStats {
+            entry_count: stream.source_file_entries.len(),
+            unused_variables: unused_variables(stream),
+            ..Default::default()
+        };
+        let mut cache = SharedEvalCache {
+            entry_independent_vars: stream.entry_independent_vars(),
+            ..Default::default()
+        };
+        let options = EvalOptions::new().recognize_commands(true);
+        for line in &stream.source_file_entries {
+            let vars = split_entry(line);
+            let method =
+                match stream.source_and_raw_var_values_for_entry(&vars, "", &options, &mut cache) {
+                    Ok((method, _)) => method,
+                    Err(_) => {
+                        stats.eval_error_count += 1;
+                        continue;
+                    }
+                };
+            *stats.kind_counts.entry(method.kind()).or_insert(0) += 1;
+            if let Some(host) = host(&method) {
+                stats.hosts.insert(host.to_string());
+            }
+            if let Some(revision) = revision(&method) {
+                stats.revisions.insert(revision.to_string());
+            }
+        }
+        stats
+    }
+}
+
+/// Find the variables which can't influence the evaluation of any entry.
+fn unused_variables(stream: &SrcSrvStream<'_>) -> Vec<String> {
+    let used = used_variables(stream);
+    stream
+        .var_lines
+        .iter()
+        .filter(|(var_name, _)| !used.contains(&var_name.to_ascii_lowercase()))
+        .map(|(var_name, _)| var_name.to_string())
+        .collect()
+}
+
+/// The lowercase names of the variables which are referenced by the `SRCSRV*`
+/// variables, directly or indirectly, including the `SRCSRV*` variables
+/// themselves and referenced variables which are not defined, such as `var2`.
+pub(crate) fn used_variables(stream: &SrcSrvStream<'_>) -> HashSet<String> {
+    // The debugger reads the SRCSRV* variables, everything else is only used
+    // if it is referenced from them.
+    let mut used: HashSet<String> = HashSet::new();
+    let mut pending: Vec<String> = stream
+        .var_fields
+        .keys()
+        .map(|var_name| var_name.as_str().to_ascii_lowercase())
+        .filter(|var_name| var_name.starts_with("srcsrv"))
+        .collect();
+    let mut uses_fnvar = false;
+    while let Some(var_name) = pending.pop() {
+        if !used.insert(var_name.clone()) {
+            continue;
+        }
+        if let Some(field) = stream.var_fields.get(CaseInsensitiveStr::new(&var_name)) {
+            let node = field.node();
+            uses_fnvar |= node.contains_fnvar();
+            let mut referenced = Vec::new();
+            node.collect_variables(&mut referenced);
+            pending.extend(referenced.iter().map(|name| name.to_ascii_lowercase()));
+        }
+    }
+
+    // With %fnvar%, variable names usually come from the file entries, e.g.
+    // %fnvar%(%var2%). Treat every variable which is named by an entry value
+    // as used, along with the variables it references.
+    if uses_fnvar {
+        let mut named_by_entries: Vec<String> = stream
+            .source_file_entries
+            .iter()
+            .flat_map(|line| split_entry(line))
+            .map(|value| value.to_ascii_lowercase())
+            .filter(|value| {
+                stream
+                    .var_fields
+                    .contains_key(CaseInsensitiveStr::new(value))
+                    && !used.contains(value)
+            })
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        while let Some(var_name) = named_by_entries.pop() {
+            if !used.insert(var_name.clone()) {
+                continue;
+            }
+            if let Some(field) = stream.var_fields.get(CaseInsensitiveStr::new(&var_name)) {
+                let node = field.node();
+                let mut referenced = Vec::new();
+                node.collect_variables(&mut referenced);
+                named_by_entries.extend(referenced.iter().map(|name| name.to_ascii_lowercase()));
+            }
+        }
+    }
+    used
+}
+
+/// The server that the file is obtained from.
+fn host(method: &SourceRetrievalMethod) -> Option<&str> {
+    match method {
+        SourceRetrievalMethod::Download { url }
+        | SourceRetrievalMethod::DownloadWithDecode { url, .. }
+        | SourceRetrievalMethod::Svn { url, .. }
+        | SourceRetrievalMethod::GitFile { repo: url, .. }
+        | SourceRetrievalMethod::TfsItem { server: url, .. } => url_host(url),
+        SourceRetrievalMethod::Perforce { port, .. }
+        | SourceRetrievalMethod::SourceDepot { port, .. } => port.as_deref(),
+        SourceRetrievalMethod::Cvs { root, .. } => {
+            // [:method:][[user][:password]@]hostname[:[port]]/path
+            let after_user = root
+                .rsplit_once('@')
+                .map_or(root.as_str(), |(_, rest)| rest);
+            let after_method = match after_user.strip_prefix(':') {
+                Some(rest) => rest.split_once(':').map_or(rest, |(_, rest)| rest),
+                None => after_user,
+            };
+            let host = after_method.split([':', '/']).next()?;
+            Some(host).filter(|host| !host.is_empty())
+        }
+        SourceRetrievalMethod::CopyFile { source_path, .. }
+        | SourceRetrievalMethod::CabExtract {
+            archive_path: source_path,
+            ..
+        } => {
+            let unc_path = source_path.strip_prefix("\\\\")?;
+            unc_path.split('\\').next()
+        }
+        SourceRetrievalMethod::ExecuteCommand { .. } | SourceRetrievalMethod::Other { .. } => None,
+    }
+}
+
+/// The host of a URL like `https://user@host:port/path`.
+fn url_host(url: &str) -> Option<&str> {
+    let (_scheme, rest) = url.split_once("://")?;
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    Some(host).filter(|host| !host.is_empty())
+}
+
+/// The revision of the file, if the retrieval method has one.
+fn revision(method: &SourceRetrievalMethod) -> Option<&str> {
+    match method {
+        SourceRetrievalMethod::GitFile { revision, .. }
+        | SourceRetrievalMethod::TfsItem {
+            version: revision, ..
+        }
+        | SourceRetrievalMethod::Perforce { revision, .. }
+        | SourceRetrievalMethod::SourceDepot { revision, .. }
+        | SourceRetrievalMethod::Svn { revision, .. }
+        | SourceRetrievalMethod::Cvs { revision, .. } => Some(revision),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::url_host;
+    use crate::{RetrievalKind, SrcSrvStream};
+
+    #[test]
+    fn stats() {
+        let stream = r#"SRCSRV: ini ------------------------------------------------
+VERSION=3
+SRCSRV: variables ------------------------------------------
+TFS_EXTRACT_CMD=tf.exe view /version:%var4% /noprompt "$%var3%" /server:%fnvar%(%var2%) /output:%srcsrvtrg%
+TFS_EXTRACT_TARGET=%targ%\%var2%%fnbksl%(%var3%)\%var4%\%fnfile%(%var1%)
+VSTFDEVDIV_DEVDIV2=http://vstfdevdiv.redmond.corp.microsoft.com:8080/DevDiv2
+OTHER_SERVER=http://other:8080/Collection
+LEFTOVER=%OTHER_SERVER%
+SRCSRVTRG=%TFS_extract_target%
+SRCSRVCMD=%TFS_extract_cmd%
+SRCSRV: source files ---------------------------------------
+f:\dd\cvconst.h*VSTFDEVDIV_DEVDIV2*/DevDiv/cvconst.h*1363200
+f:\dd\cvinfo.h*VSTFDEVDIV_DEVDIV2*/DevDiv/cvinfo.h*1363201
+f:\dd\broken.h*UNKNOWN_SERVER*/DevDiv/broken.h*1363202
+SRCSRV: end ------------------------------------------------"#;
+        let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
+        let stats = stream.stats();
+        assert_eq!(stats.entry_count, 3);
+        assert_eq!(
+            stats.hosts.iter().collect::<Vec<_>>(),
+            vec!["vstfdevdiv.redmond.corp.microsoft.com:8080"]
+        );
+        assert_eq!(
+            stats.revisions.iter().collect::<Vec<_>>(),
+            vec!["1363200", "1363201"]
+        );
+        assert_eq!(
+            stats.kind_counts.into_iter().collect::<Vec<_>>(),
+            vec![(RetrievalKind::ExecuteCommand, 2)]
+        );
+        assert_eq!(stats.eval_error_count, 1);
+        assert_eq!(stats.unused_variables, vec!["OTHER_SERVER", "LEFTOVER"]);
+    }
+
+    #[test]
+    fn hosts() {
+        assert_eq!(
+            url_host("https://user@example.com:443/a?b"),
+            Some("example.com:443")
+        );
+        assert_eq!(url_host("svn://svn.example.com"), Some("svn.example.com"));
+        assert_eq!(url_host("C:\\repo"), None);
+    }
+}
@@ -0,0 +1,47 @@
+/// A file entry whose path ends in the same components as a requested path.
+/// Returned by [`SrcSrvStream::suffix_match_candidates`](crate::SrcSrvStream::suffix_match_candidates).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SuffixMatchCandidate<'a> {
+    /// The original path of the file entry, with its original casing.
+    pub original_path: &'a str,
+    /// The number of trailing path components, including the file name, which
+    /// match the requested path (ASCII case-insensitively).
+    pub matching_components: usize,
+}
+
+/// The non-empty components of `path`, split at both `/` and `\`.
+fn path_components(path: &str) -> impl DoubleEndedIterator<Item = &str> {
+    path.split(['/', '\\'])
+        .filter(|component| !component.is_empty())
+}
+
+/// The lowercase last component of `path`, if any.
+pub(crate) fn lowercase_file_name(path: &str) -> Option<String> {
+    path_components(path)
+        .next_back()
+        .map(|file_name| file_name.to_ascii_lowercase())
+}
+
+/// The number of trailing components that `a` and `b` have in common.
+pub(crate) fn matching_trailing_components(a: &str, b: &str) -> usize {
+    path_components(a)
+        .rev()
+        .zip(path_components(b).rev())
+        .take_while(|(a, b)| a.eq_ignore_ascii_case(b))
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::matching_trailing_components;
+
+    #[test]
+    fn trailing_components() {
+        assert_eq!(
+            matching_trailing_components(r#"D:\a\1\s\src\Foo.cpp"#, "/home/me/proj/src/foo.cpp"),
+            2
+        );
+        assert_eq!(matching_trailing_components("a/b/c", "x/b/d"), 0);
+        assert_eq!(matching_trailing_components(r#"C:\a\b"#, r#"c:\A\B\"#), 3);
+    }
+}
@@ -0,0 +1,326 @@
+use crate::msf::{malformed, ByteReader, Msf};
+use crate::{PdbError, SrcSrvStream};
+use std::convert::TryInto;
+use std::path::Path;
+use std::result::Result;
+
+/// The index of the PDB information stream, which contains the named stream map.
+const PDB_INFORMATION_STREAM: usize = 1;
+
+/// The version of the PDB information stream from which on it contains a GUID.
+const PDB_VERSION_VC70: u32 = 20000404;
+
+impl<'a> SrcSrvStream<'a> {
+    /// Store this stream as the `srcsrv` stream of the PDB file `pdb`, and
+    /// return the bytes of the modified PDB file. An existing `srcsrv` stream
+    /// is replaced. This is what `pdbstr -w -s:srcsrv` does.
+    ///
+    /// The stream is serialized with [`SrcSrvStream::to_bytes`]. All other
+    /// streams of the PDB file are kept as they are, but the file is laid out
+    /// from scratch, so it is usually not byte-identical to the original file
+    /// apart from the new stream.
+    ///
+    /// Only PDB files in the MSF 7.0 format are supported, which is the format
+    /// of all PDB files produced by toolchains of the last two decades.
+    pub fn write_to_pdb(&self, pdb: &[u8]) -> Result<Vec<u8>, PdbError> {
+        set_named_stream(pdb, "srcsrv", self.to_bytes())
+    }
+
+    /// Like [`SrcSrvStream::write_to_pdb`], but reads and overwrites the PDB
+    /// file at `path`.
+    ///
+    /// ```no_run
+    /// use srcsrv::{SrcSrvStream, SrcSrvStreamBuilder};
+    ///
+    /// # fn wrapper(builder: SrcSrvStreamBuilder) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    /// let bytes = builder.to_bytes()?;
+    /// SrcSrvStream::parse(&bytes)?.write_to_pdb_path("xul.pdb")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn write_to_pdb_path(&self, path: impl AsRef<Path>) -> Result<(), PdbError> {
+        let path = path.as_ref();
+        let pdb = std::fs::read(path).map_err(PdbError::Io)?;
+        let pdb = self.write_to_pdb(&pdb)?;
+        std::fs::write(path, pdb).map_err(PdbError::Io)
+    }
+}
+
+/// Add or replace the stream called `name` in the MSF file `pdb`.
+fn set_named_stream(pdb: &[u8], name: &str, data: Vec<u8>) -> Result<Vec<u8>, PdbError> {
+    let mut msf = Msf::parse(pdb)?;
+    let info = match msf.streams.get(PDB_INFORMATION_STREAM) {
+        Some(Some(info)) => info.clone(),
+        _ => return Err(malformed("The PDB information stream is missing.")),
+    };
+    let mut info = PdbInformation::parse(&info)?;
+    let stream_index = match info.named_streams.get(name) {
+        Some(stream_index) => stream_index as usize,
+        None => {
+            let stream_index = msf.streams.len();
+            info.named_streams.insert(name, stream_index as u32);
+            msf.streams.push(None);
+            stream_index
+        }
+    };
+    if stream_index >= msf.streams.len() {
+        return Err(malformed(format!(
+            "The {name} stream has the invalid index {stream_index}."
+        )));
+    }
+    msf.streams[stream_index] = Some(data);
+    msf.streams[PDB_INFORMATION_STREAM] = Some(info.to_bytes());
+    msf.to_bytes()
+}
+
+/// The PDB information stream, with its named stream map parsed.
+struct PdbInformation<'a> {
+    /// The version, signature, age and GUID.
+    header: &'a [u8],
+    named_streams: NamedStreamMap,
+    /// The feature codes after the named stream map.
+    features: &'a [u8],
+}
+
+impl<'a> PdbInformation<'a> {
+    fn parse(data: &'a [u8]) -> Result<Self, PdbError> {
+        let version = ByteReader::new(data).read_u32()?;
+        let header_len = if version >= PDB_VERSION_VC70 { 28 } else { 12 };
+        let mut reader = ByteReader::new(data);
+        let header = reader.read_bytes(header_len)?;
+        let named_streams = NamedStreamMap::parse(&mut reader)?;
+        Ok(PdbInformation {
+            header,
+            named_streams,
+            features: reader.rest(),
+        })
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.header.to_vec();
+        self.named_streams.write(&mut bytes);
+        bytes.extend_from_slice(self.features);
+        bytes
+    }
+}
+
+/// The map from stream names to stream indexes, a hash table with linear
+/// probing whose keys are offsets into a buffer of nul-terminated names.
+struct NamedStreamMap {
+    names: Vec<u8>,
+    /// (name offset, stream index) for each entry.
+    entries: Vec<(u32, u32)>,
+    capacity: u32,
+}
+
+impl NamedStreamMap {
+    fn parse(reader: &mut ByteReader<'_>) -> Result<Self, PdbError> {
+        let names_len = reader.read_u32()? as usize;
+        let names = reader.read_bytes(names_len)?.to_vec();
+        let size = reader.read_u32()?;
+        let capacity = reader.read_u32()?;
+        let present_word_count = reader.read_u32()? as usize;
+        reader.read_bytes(present_word_count * 4)?;
+        let deleted_word_count = reader.read_u32()? as usize;
+        reader.read_bytes(deleted_word_count * 4)?;
+        let entries = (0..size)
+            .map(|_| Ok((reader.read_u32()?, reader.read_u32()?)))
+            .collect::<Result<Vec<_>, PdbError>>()?;
+        let map = NamedStreamMap {
+            names,
+            entries,
+            capacity: capacity.max(1),
+        };
+        for (offset, _) in &map.entries {
+            map.name_at(*offset)?;
+        }
+        Ok(map)
+    }
+
+    fn name_at(&self, offset: u32) -> Result<&[u8], PdbError> {
+        self.names
+            .get(offset as usize..)
+            .and_then(|names| names.split(|b| *b == 0).next())
+            .ok_or_else(|| malformed(format!("Invalid stream name offset {offset}.")))
+    }
+
+    fn get(&self, name: &str) -> Option<u32> {
+        self.entries
+            .iter()
+            .find(|(offset, _)| self.name_at(*offset).ok() == Some(name.as_bytes()))
+            .map(|(_, stream_index)| *stream_index)
+    }
+
+    fn insert(&mut self, name: &str, stream_index: u32) {
+        let offset = self.names.len() as u32;
+        self.names.extend_from_slice(name.as_bytes());
+        self.names.push(0);
+        self.entries.push((offset, stream_index));
+    }
+
+    /// Write the map, with the entries placed in the buckets where readers
+    /// which look names up by hash expect them.
+    fn write(&self, bytes: &mut Vec<u8>) {
+        let mut capacity = self.capacity as usize;
+        while self.entries.len() > capacity * 2 / 3 {
+            capacity *= 2;
+        }
+        let mut buckets = vec![None; capacity];
+        for &(offset, stream_index) in &self.entries {
+            let name = self.name_at(offset).unwrap_or_default();
+            let mut bucket = name_hash(name) as usize % capacity;
+            while buckets[bucket].is_some() {
+                bucket = (bucket + 1) % capacity;
+            }
+            buckets[bucket] = Some((offset, stream_index));
+        }
+
+        let mut present_words = vec![0u32; capacity.div_ceil(32)];
+        for (bucket, entry) in buckets.iter().enumerate() {
+            if entry.is_some() {
+                present_words[bucket / 32] |= 1 << (bucket % 32);
+            }
+        }
+        bytes.extend_from_slice(&(self.names.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.names);
+        let mut values = vec![self.entries.len() as u32, capacity as u32];
+        values.push(present_words.len() as u32);
+        values.extend(&present_words);
+        values.push(0); // No deleted entries.
+        values.extend(buckets.iter().flatten().flat_map(|(k, v)| [*k, *v]));
+        for value in values {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+}
+
+/// The hash function of the named stream map, which is `hashStringV1`
+/// truncated to 16 bits.
+fn name_hash(name: &[u8]) -> u16 {
+    let mut chunks = name.chunks_exact(4);
+    let mut hash = 0u32;
+    for chunk in &mut chunks {
+        hash ^= u32::from_le_bytes(chunk.try_into().unwrap());
+    }
+    let mut remainder = chunks.remainder();
+    if remainder.len() >= 2 {
+        hash ^= u16::from_le_bytes([remainder[0], remainder[1]]) as u32;
+        remainder = &remainder[2..];
+    }
+    if let [byte] = remainder {
+        hash ^= *byte as u32;
+    }
+    hash |= 0x20202020;
+    hash ^= hash >> 11;
+    (hash ^ (hash >> 16)) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{name_hash, set_named_stream, PdbInformation};
+    use crate::msf::{ByteReader, Msf};
+    use crate::{SrcSrvStream, SrcSrvStreamBuilder};
+
+    /// A PDB file with no streams other than an empty PDB information stream.
+    fn empty_pdb() -> Vec<u8> {
+        let mut info = Vec::new();
+        for value in [20000404, 0x5eed, 1, 0, 0, 0, 0, 0, 0, 1, 1, 0, 0, 20140508] {
+            info.extend_from_slice(&u32::to_le_bytes(value));
+        }
+        Msf {
+            block_size: 0x1000,
+            streams: vec![Some(vec![]), Some(info), None, None],
+        }
+        .to_bytes()
+        .unwrap()
+    }
+
+    fn srcsrv_stream(revision: &str) -> Vec<u8> {
+        SrcSrvStreamBuilder::new()
+            .set_var("SRCSRVTRG", "https://example.com/%var2%/%var3%")
+            .add_source_file_entry(&[r#"C:\build\main.cpp"#, revision, "main.cpp"])
+            .to_bytes()
+            .unwrap()
+    }
+
+    fn read_srcsrv(pdb: &[u8]) -> Vec<u8> {
+        let mut pdb = pdb::PDB::open(std::io::Cursor::new(pdb)).unwrap();
+        pdb.named_stream(b"srcsrv").unwrap().as_slice().to_vec()
+    }
+
+    #[test]
+    fn write_to_pdb() {
+        let first = srcsrv_stream("abc");
+        let pdb = SrcSrvStream::parse(&first)
+            .unwrap()
+            .write_to_pdb(&empty_pdb())
+            .unwrap();
+        assert_eq!(read_srcsrv(&pdb), first);
+        assert_eq!(Msf::parse(&pdb).unwrap().streams.len(), 5);
+
+        // Replace the existing stream.
+        let second = srcsrv_stream("def");
+        let pdb = SrcSrvStream::parse(&second)
+            .unwrap()
+            .write_to_pdb(&pdb)
+            .unwrap();
+        assert_eq!(read_srcsrv(&pdb), second);
+        let msf = Msf::parse(&pdb).unwrap();
+        assert_eq!(msf.streams.len(), 5);
+        let info = msf.streams[1].as_ref().unwrap();
+        assert!(info.ends_with(&u32::to_le_bytes(20140508)));
+
+        let owned =
+            SrcSrvStream::from_pdb(&mut pdb::PDB::open(std::io::Cursor::new(&pdb)).unwrap())
+                .unwrap()
+                .unwrap();
+        assert_eq!(owned.stream().to_bytes(), second);
+    }
+
+    #[test]
+    fn named_stream_buckets() {
+        let mut pdb = empty_pdb();
+        let names: Vec<String> = (0..20).map(|i| format!("/stream{i}")).collect();
+        for name in &names {
+            pdb = set_named_stream(&pdb, name, name.as_bytes().to_vec()).unwrap();
+        }
+        let msf = Msf::parse(&pdb).unwrap();
+        let info = PdbInformation::parse(msf.streams[1].as_ref().unwrap()).unwrap();
+        let mut bytes = Vec::new();
+        info.named_streams.write(&mut bytes);
+        let mut reader = ByteReader::new(&bytes);
+        let names_len = reader.read_u32().unwrap() as usize;
+        reader.read_bytes(names_len).unwrap();
+        let size = reader.read_u32().unwrap() as usize;
+        let capacity = reader.read_u32().unwrap() as usize;
+        assert_eq!(size, 20);
+        assert!(size < capacity * 2 / 3 + 1);
+
+        // Every name must be found by probing from its hash bucket.
+        let present_word_count = reader.read_u32().unwrap() as usize;
+        let present: Vec<u32> = (0..present_word_count)
+            .map(|_| reader.read_u32().unwrap())
+            .collect();
+        reader.read_u32().unwrap();
+        let mut buckets = vec![None; capacity];
+        for (bucket, slot) in buckets.iter_mut().enumerate() {
+            if present[bucket / 32] & (1 << (bucket % 32)) != 0 {
+                *slot = Some(reader.read_u32().unwrap());
+                reader.read_u32().unwrap();
+            }
+        }
+        let map = &info.named_streams;
+        for name in &names {
+            let mut bucket = name_hash(name.as_bytes()) as usize % capacity;
+            loop {
+                let offset = buckets[bucket].expect("name not found");
+                if map.name_at(offset).unwrap() == name.as_bytes() {
+                    break;
+                }
+                bucket = (bucket + 1) % capacity;
+            }
+            let stream_index = map.get(name).unwrap() as usize;
+            assert_eq!(msf.streams[stream_index].as_deref(), Some(name.as_bytes()));
+        }
+    }
+}
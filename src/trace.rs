@@ -0,0 +1,139 @@
+use std::fmt;
+
+/// A record of the variable substitutions which were made while evaluating a
+/// file entry, see [`SrcSrvStream::source_for_path_with_trace`](crate::SrcSrvStream::source_for_path_with_trace).
+///
+/// The `Display` implementation prints one line per step, indented by depth.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EvalTrace {
+    /// The steps, in the order in which the variables were looked up. A
+    /// variable whose template references other variables comes before the
+    /// steps for those variables.
+    pub steps: Vec<EvalTraceStep>,
+}
+
+/// A single variable lookup in an [`EvalTrace`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EvalTraceStep {
+    /// The nesting level: 0 for the fields that are evaluated directly, such
+    /// as `SRCSRVTRG`, 1 for the variables they reference, and so on.
+    pub depth: usize,
+    /// The lowercase name of the variable.
+    pub var_name: String,
+    /// Where the value came from.
+    pub source: EvalTraceSource,
+    /// The raw template from the stream, if the value was evaluated from one.
+    pub template: Option<String>,
+    /// The resulting value, or `None` if evaluation failed.
+    pub value: Option<String>,
+}
+
+/// Where the value of a variable in an [`EvalTraceStep`] came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum EvalTraceSource {
+    /// One of var1, ..., var10 of the file entry.
+    Entry,
+    /// `%targ%`, i.e. the extraction base path.
+    ExtractionBasePath,
+    /// A value supplied with [`EvalOptions::var`](crate::EvalOptions::var).
+    Override,
+    /// The template from the variables section was evaluated.
+    Template,
+    /// The value was evaluated earlier and reused.
+    Reused,
+    /// The variable is not defined. The value is determined by the
+    /// [`UnknownVariablePolicy`](crate::UnknownVariablePolicy).
+    Unknown,
+    /// The variable references itself, directly or indirectly.
+    Recursion,
+}
+
+impl fmt::Display for EvalTraceSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            EvalTraceSource::Entry => "entry",
+            EvalTraceSource::ExtractionBasePath => "extraction base path",
+            EvalTraceSource::Override => "override",
+            EvalTraceSource::Template => "template",
+            EvalTraceSource::Reused => "reused",
+            EvalTraceSource::Unknown => "unknown",
+            EvalTraceSource::Recursion => "recursion",
+        })
+    }
+}
+
+impl fmt::Display for EvalTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for step in &self.steps {
+            write!(
+                f,
+                "{:indent$}%{}% ({})",
+                "",
+                step.var_name,
+                step.source,
+                indent = step.depth * 2
+            )?;
+            if let Some(template) = &step.template {
+                write!(f, " {}", template)?;
+            }
+            match &step.value {
+                Some(value) => writeln!(f, " => {}", value)?,
+                None => writeln!(f, " => error")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{EvalError, EvalOptions, EvalTraceSource, SrcSrvStream};
+
+    #[test]
+    fn trace() {
+        let stream = r#"SRCSRV: ini ------------------------------------------------
+VERSION=2
+SRCSRV: variables ------------------------------------------
+HGSERVER=https://hg.mozilla.org/mozilla-central
+SRCSRVTRG=%hgserver%/raw-file/%var3%/%var2%
+SRCSRV: source files ---------------------------------------
+/builds/SSE.cpp*mozglue/build/SSE.cpp*1706d4d5
+/builds/Bad.cpp*bad.cpp
+SRCSRV: end ------------------------------------------------"#;
+        let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
+        let (result, trace) =
+            stream.source_for_path_with_trace("/builds/SSE.cpp", "", &EvalOptions::new());
+        assert!(result.unwrap().is_some());
+        let steps: Vec<_> = trace
+            .steps
+            .iter()
+            .map(|step| (step.depth, step.var_name.as_str(), step.source))
+            .collect();
+        assert_eq!(
+            steps,
+            vec![
+                (0, "srcsrvtrg", EvalTraceSource::Template),
+                (1, "hgserver", EvalTraceSource::Template),
+                (1, "var3", EvalTraceSource::Entry),
+                (1, "var2", EvalTraceSource::Entry),
+            ]
+        );
+        assert_eq!(
+            trace.to_string(),
+            "%srcsrvtrg% (template) %hgserver%/raw-file/%var3%/%var2% => https://hg.mozilla.org/mozilla-central/raw-file/1706d4d5/mozglue/build/SSE.cpp\n\
+             \x20 %hgserver% (template) https://hg.mozilla.org/mozilla-central => https://hg.mozilla.org/mozilla-central\n\
+             \x20 %var3% (entry) => 1706d4d5\n\
+             \x20 %var2% (entry) => mozglue/build/SSE.cpp\n"
+        );
+
+        let (result, trace) =
+            stream.source_for_path_with_trace("/builds/Bad.cpp", "", &EvalOptions::new());
+        assert_eq!(result, Err(EvalError::UnknownVariable("var3".to_string())));
+        assert_eq!(trace.steps[0].value, None);
+        assert_eq!(trace.steps[2].source, EvalTraceSource::Unknown);
+    }
+}
@@ -0,0 +1,143 @@
+use crate::{HttpStatusError, SourceFetcher};
+use std::error::Error;
+use std::io::Read;
+use std::time::Duration;
+
+/// A [`SourceFetcher`] which downloads with a blocking [`ureq::Agent`], for
+/// tools and tests which don't want an async runtime.
+///
+/// Redirects are followed, and responses with an error status are returned as
+/// [`HttpStatusError`]s. The request headers from the
+/// [`AuthProvider`](crate::AuthProvider) are not sent along when a redirect
+/// leads to a different host.
+///
+/// ```no_run
+/// use srcsrv::{fetch_source, SrcSrvStream, UreqFetcher};
+/// use std::path::Path;
+///
+/// # fn wrapper(stream: &SrcSrvStream) -> Result<(), Box<dyn std::error::Error>> {
+/// let fetcher = UreqFetcher::new();
+/// let path = r#"C:\build\renderdoc\renderdoc\data\glsl\gl_texsample.h"#;
+/// if let Some(method) = stream.source_for_path(path, "")? {
+///     fetch_source(&fetcher, &method, Path::new("/tmp/sources/gl_texsample.h"))?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct UreqFetcher {
+    agent: ureq::Agent,
+}
+
+impl UreqFetcher {
+    /// Create a fetcher which follows up to 10 redirects, and which waits at
+    /// most 30 seconds for a connection and 60 seconds for each read.
+    pub fn new() -> Self {
+        let agent = ureq::AgentBuilder::new()
+            .redirects(10)
+            .timeout_connect(Duration::from_secs(30))
+            .timeout_read(Duration::from_secs(60))
+            .build();
+        Self::with_agent(agent)
+    }
+
+    /// Create a fetcher which downloads with `agent`, for example to use a
+    /// proxy or different timeouts.
+    pub fn with_agent(agent: ureq::Agent) -> Self {
+        Self { agent }
+    }
+}
+
+impl Default for UreqFetcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SourceFetcher for UreqFetcher {
+    fn fetch(&self, url: &str) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        self.fetch_with_headers(url, &[])
+    }
+
+    fn fetch_with_headers(
+        &self,
+        url: &str,
+        headers: &[(String, String)],
+    ) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        let mut request = self.agent.get(url);
+        for (name, value) in headers {
+            request = request.set(name, value);
+        }
+        let response = match request.call() {
+            Ok(response) => response,
+            Err(ureq::Error::Status(status, _)) => return Err(HttpStatusError { status }.into()),
+            Err(error) => return Err(error.into()),
+        };
+        let mut body = Vec::new();
+        response.into_reader().read_to_end(&mut body)?;
+        Ok(body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UreqFetcher;
+    use crate::{HttpStatusError, SourceFetcher};
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Serve `responses` to one connection each, and return the base URL and
+    /// a thread which returns the request heads.
+    fn serve(responses: &'static [&'static str]) -> (String, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request = String::new();
+                while !request.ends_with("\r\n\r\n") {
+                    reader.read_line(&mut request).unwrap();
+                }
+                stream.write_all(response.as_bytes()).unwrap();
+                requests.push(request);
+            }
+            requests
+        });
+        (url, server)
+    }
+
+    #[test]
+    fn fetch_with_redirect() {
+        let (url, server) = serve(&[
+            "HTTP/1.1 302 Found\r\nLocation: /b.cpp\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nContent-Length: 9\r\nConnection: close\r\n\r\nint b();\n",
+        ]);
+        let headers = [("X-Token".to_string(), "secret".to_string())];
+        let body = UreqFetcher::new()
+            .fetch_with_headers(&format!("{}/a.cpp", url), &headers)
+            .unwrap();
+        assert_eq!(body, b"int b();\n");
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("GET /a.cpp HTTP/1.1\r\n"));
+        assert!(requests[0].contains("X-Token: secret\r\n"));
+        assert!(requests[1].starts_with("GET /b.cpp HTTP/1.1\r\n"));
+    }
+
+    #[test]
+    fn fetch_error_status() {
+        let (url, server) = serve(&[
+            "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        ]);
+        let fetcher = UreqFetcher::new();
+        let error = fetcher.fetch(&format!("{}/a.cpp", url)).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<HttpStatusError>(),
+            Some(&HttpStatusError { status: 503 })
+        );
+        assert!(fetcher.is_retryable(&*error));
+        server.join().unwrap();
+    }
+}
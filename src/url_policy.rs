@@ -0,0 +1,230 @@
+/// Restricts the URLs of [`SourceRetrievalMethod::Download`](crate::SourceRetrievalMethod::Download) and
+/// [`SourceRetrievalMethod::DownloadWithDecode`](crate::SourceRetrievalMethod::DownloadWithDecode) to certain schemes and hosts,
+/// so that PDB files from third parties can't make a tool contact arbitrary
+/// hosts.
+///
+/// Set it with [`EvalOptions::url_policy`](crate::EvalOptions::url_policy) to
+/// reject URLs during lookups, or with `FetchOptions::url_policy` (with the
+/// `fetch` feature) to reject them right before they are fetched.
+///
+/// The default policy allows all URLs. A URL is allowed if:
+///
+///  - its scheme is one of the allowed schemes, or no scheme was allowed
+///    explicitly,
+///  - its host matches one of the allowed hosts, or no host was allowed
+///    explicitly,
+///  - and its host matches none of the denied hosts.
+///
+/// Host patterns are either a host name, which matches that host, or
+/// `*.` followed by a domain, which matches all subdomains of the domain.
+/// Schemes and hosts are compared ASCII case-insensitively.
+///
+/// ```
+/// use srcsrv::UrlPolicy;
+///
+/// let policy = UrlPolicy::new()
+///     .allow_scheme("https")
+///     .allow_host("raw.githubusercontent.com")
+///     .allow_host("*.mozilla.org")
+///     .deny_host("untrusted.mozilla.org");
+/// assert!(policy.is_allowed("https://hg.mozilla.org/mozilla-central/raw-file/abc/dom/base/Element.cpp"));
+/// assert!(!policy.is_allowed("http://hg.mozilla.org/mozilla-central/raw-file/abc/dom/base/Element.cpp"));
+/// assert!(!policy.is_allowed("https://untrusted.mozilla.org/main.cpp"));
+/// assert!(!policy.is_allowed("https://example.com/main.cpp"));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct UrlPolicy {
+    /// lowercase schemes
+    allowed_schemes: Vec<String>,
+    /// lowercase host patterns
+    allowed_hosts: Vec<String>,
+    /// lowercase host patterns
+    denied_hosts: Vec<String>,
+}
+
+impl UrlPolicy {
+    /// Create a policy which allows all URLs.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow URLs with the scheme `scheme`, e.g. `https`. Once a scheme is
+    /// allowed, URLs with other schemes are rejected.
+    pub fn allow_scheme(mut self, scheme: &str) -> Self {
+        self.allowed_schemes.push(scheme.to_ascii_lowercase());
+        self
+    }
+
+    /// Allow URLs whose host matches `host_pattern`. Once a host is allowed,
+    /// URLs with other hosts are rejected.
+    pub fn allow_host(mut self, host_pattern: &str) -> Self {
+        self.allowed_hosts.push(host_pattern.to_ascii_lowercase());
+        self
+    }
+
+    /// Reject URLs whose host matches `host_pattern`, even if the host is
+    /// allowed.
+    pub fn deny_host(mut self, host_pattern: &str) -> Self {
+        self.denied_hosts.push(host_pattern.to_ascii_lowercase());
+        self
+    }
+
+    /// Whether the policy allows `url`. URLs which don't have the form
+    /// `scheme://host/...` are only allowed by the default policy.
+    pub fn is_allowed(&self, url: &str) -> bool {
+        if *self == Self::default() {
+            return true;
+        }
+        let (scheme, host) = match url_scheme_and_host(url) {
+            Some(scheme_and_host) => scheme_and_host,
+            None => return false,
+        };
+        let scheme = scheme.to_ascii_lowercase();
+        let host = host.to_ascii_lowercase();
+        (self.allowed_schemes.is_empty() || self.allowed_schemes.contains(&scheme))
+            && (self.allowed_hosts.is_empty()
+                || self
+                    .allowed_hosts
+                    .iter()
+                    .any(|pattern| host_matches(&host, pattern)))
+            && !self
+                .denied_hosts
+                .iter()
+                .any(|pattern| host_matches(&host, pattern))
+    }
+}
+
+/// Whether the lowercase `host` matches the lowercase `pattern`.
+fn host_matches(host: &str, pattern: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(domain) => matches!(
+            host.strip_suffix(domain),
+            Some(subdomain) if subdomain.len() > 1 && subdomain.ends_with('.')
+        ),
+        None => host == pattern,
+    }
+}
+
+/// The scheme and the host of `url`, without user info and port.
+///
+/// Returns `None` if the authority contains characters which are not valid
+/// there. In particular, clients which follow the WHATWG URL standard treat
+/// `\` like `/`, so `https://a.example\@b.example/` is a request to
+/// `a.example` for them and to `b.example` for others.
+pub(crate) fn url_scheme_and_host(url: &str) -> Option<(&str, &str)> {
+    let (scheme, rest) = url.split_once("://")?;
+    let authority = rest.split(['/', '\\', '?', '#']).next().unwrap_or(rest);
+    if rest[authority.len()..].starts_with('\\') || !authority.chars().all(is_authority_char) {
+        return None;
+    }
+    let host_and_port = authority.rsplit('@').next().unwrap_or(authority);
+    let host = match host_and_port.rsplit_once(':') {
+        Some((host, port)) if port.bytes().all(|b| b.is_ascii_digit()) => host,
+        _ => host_and_port,
+    };
+    let is_valid_host = match host.strip_prefix('[') {
+        Some(ip) => {
+            matches!(ip.strip_suffix(']'), Some(ip) if !ip.is_empty() && ip.chars().all(|c| c.is_ascii_hexdigit() || c == ':' || c == '.'))
+        }
+        None => {
+            !host.is_empty()
+                && host
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-._~%".contains(c))
+        }
+    };
+    if !is_valid_host {
+        return None;
+    }
+    Some((scheme, host))
+}
+
+/// Whether `c` can appear in the authority of a URL, i.e. in the user info,
+/// the host or the port.
+fn is_authority_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "-._~%!$&'()*+,;=:@[]".contains(c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{url_scheme_and_host, UrlPolicy};
+    use crate::{EvalError, EvalOptions, SourceRetrievalMethod, SrcSrvStream, SrcSrvStreamBuilder};
+
+    #[test]
+    fn scheme_and_host() {
+        assert_eq!(
+            url_scheme_and_host("https://user:pw@Example.com:8080/a?b#c"),
+            Some(("https", "Example.com"))
+        );
+        assert_eq!(
+            url_scheme_and_host("http://example.com?x=1"),
+            Some(("http", "example.com"))
+        );
+        assert_eq!(url_scheme_and_host("https:///main.cpp"), None);
+        assert_eq!(url_scheme_and_host(r#"\\server\share\main.cpp"#), None);
+        assert_eq!(
+            url_scheme_and_host(r#"https://attacker.example\@raw.githubusercontent.com/x.cpp"#),
+            None
+        );
+        assert_eq!(
+            url_scheme_and_host(r#"https://raw.githubusercontent.com\x.cpp"#),
+            None
+        );
+        assert_eq!(
+            url_scheme_and_host("https://attacker.example @raw.githubusercontent.com/"),
+            None
+        );
+        assert_eq!(
+            url_scheme_and_host("https://[::1]:8080/main.cpp"),
+            Some(("https", "[::1]"))
+        );
+    }
+
+    #[test]
+    fn url_policy() {
+        assert!(UrlPolicy::new().is_allowed("ftp://anything"));
+
+        let policy = UrlPolicy::new().allow_scheme("HTTPS");
+        assert!(policy.is_allowed("https://example.com/main.cpp"));
+        assert!(!policy.is_allowed("http://example.com/main.cpp"));
+        assert!(!policy.is_allowed("not a url"));
+
+        let policy = UrlPolicy::new()
+            .allow_host("*.googlesource.com")
+            .deny_host("evil.googlesource.com");
+        assert!(policy.is_allowed("https://chromium.googlesource.com/chromium/src/+/abc/main.cc"));
+        assert!(!policy.is_allowed("https://googlesource.com/main.cc"));
+        assert!(!policy.is_allowed("https://evilgooglesource.com/main.cc"));
+        assert!(!policy.is_allowed("https://EVIL.googlesource.com/main.cc"));
+
+        let policy = UrlPolicy::new().allow_host("raw.githubusercontent.com");
+        assert!(policy.is_allowed("https://raw.githubusercontent.com/x"));
+        assert!(!policy.is_allowed(r#"https://attacker.example\@raw.githubusercontent.com/x"#));
+
+        let policy = UrlPolicy::new().deny_host("169.254.169.254");
+        assert!(policy.is_allowed("https://example.com/main.cpp"));
+        assert!(!policy.is_allowed("http://169.254.169.254/latest/meta-data"));
+    }
+
+    #[test]
+    fn lookup_with_url_policy() {
+        let bytes = SrcSrvStreamBuilder::new()
+            .set_var("SRCSRVTRG", "%var2%")
+            .add_source_file_entry(&[r#"C:\build\main.cpp"#, "https://example.com/main.cpp"])
+            .add_source_file_entry(&[r#"C:\build\evil.cpp"#, "http://169.254.169.254/evil.cpp"])
+            .to_bytes()
+            .unwrap();
+        let stream = SrcSrvStream::parse(&bytes).unwrap();
+        let options = EvalOptions::new().url_policy(UrlPolicy::new().allow_scheme("https"));
+        assert!(matches!(
+            stream.source_for_path_with_vars(r#"C:\build\main.cpp"#, "", &options),
+            Ok(Some(SourceRetrievalMethod::Download { .. }))
+        ));
+        assert_eq!(
+            stream.source_for_path_with_vars(r#"C:\build\evil.cpp"#, "", &options),
+            Err(EvalError::UrlNotAllowed(
+                "http://169.254.169.254/evil.cpp".to_string()
+            ))
+        );
+    }
+}
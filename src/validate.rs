@@ -0,0 +1,287 @@
+use crate::case_insensitive::CaseInsensitiveStr;
+use crate::stats::used_variables;
+use crate::{is_entry_var_name, split_entry, AstNode, SrcSrvStream};
+use std::collections::HashSet;
+use std::fmt;
+
+/// A problem of a stream which was found by [`SrcSrvStream::validate`]
+/// without evaluating any entries.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum ValidationFinding {
+    /// The template of the variable `referenced_by` references the variable
+    /// `name`, which is neither defined in the stream nor one of the
+    /// variables var1, ..., var10 and targ. Evaluating it fails with
+    /// [`EvalError::UnknownVariable`](crate::EvalError::UnknownVariable).
+    UndefinedVariable { name: String, referenced_by: String },
+    /// The template of the variable `referenced_by` calls the function `name`,
+    /// which is not one of the built-in functions `%fnvar%`, `%fnbksl%` and
+    /// `%fnfile%`, so the debugger can't evaluate it.
+    UnknownFunction { name: String, referenced_by: String },
+    /// The variable `name` references itself, directly or through other
+    /// variables, so evaluating it fails with
+    /// [`EvalError::Recursion`](crate::EvalError::Recursion).
+    RecursiveVariable { name: String },
+    /// The variable `name` is not referenced by any of the `SRCSRV*`
+    /// variables, directly or indirectly, see
+    /// [`SrcSrvStreamStats::unused_variables`](crate::SrcSrvStreamStats::unused_variables).
+    UnusedVariable { name: String },
+    /// The `SRCSRVERRVAR` variable names `name`, which is not one of var1,
+    /// ..., var10, so errors are never remembered per server.
+    InvalidErrorVariable { name: String },
+    /// The entry for `original_path` has `field_count` values, but the
+    /// templates reference `var{required_field_count}`.
+    MissingEntryFields {
+        original_path: String,
+        field_count: usize,
+        required_field_count: usize,
+    },
+}
+
+impl fmt::Display for ValidationFinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationFinding::UndefinedVariable {
+                name,
+                referenced_by,
+            } => write!(
+                f,
+                "The variable {} references the undefined variable {}.",
+                referenced_by, name
+            ),
+            ValidationFinding::UnknownFunction {
+                name,
+                referenced_by,
+            } => write!(
+                f,
+                "The variable {} calls the unknown function {}.",
+                referenced_by, name
+            ),
+            ValidationFinding::RecursiveVariable { name } => {
+                write!(f, "The variable {} references itself.", name)
+            }
+            ValidationFinding::UnusedVariable { name } => {
+                write!(f, "The variable {} is never used.", name)
+            }
+            ValidationFinding::InvalidErrorVariable { name } => write!(
+                f,
+                "SRCSRVERRVAR names {}, which is not one of var1, ..., var10.",
+                name
+            ),
+            ValidationFinding::MissingEntryFields {
+                original_path,
+                field_count,
+                required_field_count,
+            } => write!(
+                f,
+                "The entry for {} has {} values, but the templates reference var{}.",
+                original_path, field_count, required_field_count
+            ),
+        }
+    }
+}
+
+impl<'a> SrcSrvStream<'a> {
+    /// Check the variables and entries of this stream for problems which make
+    /// entries fail to evaluate or which point to mistakes in the indexing
+    /// script, without evaluating any entries. The findings about the
+    /// variables come first, in the order of the variables section, followed
+    /// by the findings about the entries, in stream order.
+    ///
+    /// Problems which only show up during evaluation, such as an entry which
+    /// names an undefined variable for `%fnvar%`, are not found; use
+    /// [`SrcSrvStream::stats`] to count the entries which fail to evaluate.
+    ///
+    /// ```
+    /// use srcsrv::{SrcSrvStream, ValidationFinding};
+    ///
+    /// # fn wrapper(stream: &SrcSrvStream) {
+    /// for finding in stream.validate() {
+    ///     if let ValidationFinding::UnusedVariable { .. } = finding {
+    ///         continue;
+    ///     }
+    ///     eprintln!("{}", finding);
+    /// }
+    /// # }
+    /// ```
+    pub fn validate(&self) -> Vec<ValidationFinding> {
+        let mut findings = Vec::new();
+        if let Some(name) = self.get_raw_var("SRCSRVERRVAR") {
+            if entry_var_index(&name.to_ascii_lowercase()).is_none() {
+                findings.push(ValidationFinding::InvalidErrorVariable {
+                    name: name.to_string(),
+                });
+            }
+        }
+
+        let mut seen = HashSet::new();
+        for (var_name, _) in &self.var_lines {
+            if !seen.insert(var_name.to_ascii_lowercase()) {
+                continue;
+            }
+            let node = self.var_node(var_name).expect("the variable is defined");
+            let mut referenced = Vec::new();
+            node.collect_variables(&mut referenced);
+            for name in dedup(referenced) {
+                let lowercase_name = name.to_ascii_lowercase();
+                if self.var_node(name).is_none()
+                    && lowercase_name != "targ"
+                    && !is_entry_var_name(&lowercase_name)
+                {
+                    findings.push(ValidationFinding::UndefinedVariable {
+                        name: name.to_string(),
+                        referenced_by: var_name.to_string(),
+                    });
+                }
+            }
+            for name in node.functions() {
+                if !matches!(name, "fnvar" | "fnbksl" | "fnfile") {
+                    findings.push(ValidationFinding::UnknownFunction {
+                        name: name.to_string(),
+                        referenced_by: var_name.to_string(),
+                    });
+                }
+            }
+            if self.references_itself(var_name) {
+                findings.push(ValidationFinding::RecursiveVariable {
+                    name: var_name.to_string(),
+                });
+            }
+        }
+
+        let used = used_variables(self);
+        seen.clear();
+        for (var_name, _) in &self.var_lines {
+            let lowercase_name = var_name.to_ascii_lowercase();
+            if !used.contains(&lowercase_name) && seen.insert(lowercase_name) {
+                findings.push(ValidationFinding::UnusedVariable {
+                    name: var_name.to_string(),
+                });
+            }
+        }
+
+        let required_field_count = used
+            .iter()
+            .filter_map(|name| entry_var_index(name))
+            .max()
+            .unwrap_or(0);
+        for line in &self.source_file_entries {
+            let entry = split_entry(line);
+            if entry.len() < required_field_count {
+                findings.push(ValidationFinding::MissingEntryFields {
+                    original_path: entry[0].to_string(),
+                    field_count: entry.len(),
+                    required_field_count,
+                });
+            }
+        }
+        findings
+    }
+
+    fn var_node(&self, var_name: &str) -> Option<&AstNode<'a>> {
+        self.var_fields
+            .get(CaseInsensitiveStr::new(var_name))
+            .map(|field| field.node())
+    }
+
+    /// Whether the variable `var_name` is reachable from its own template.
+    fn references_itself(&self, var_name: &str) -> bool {
+        let target = var_name.to_ascii_lowercase();
+        let mut visited = HashSet::new();
+        let mut pending = vec![target.clone()];
+        while let Some(name) = pending.pop() {
+            let mut referenced = Vec::new();
+            if let Some(node) = self.var_node(&name) {
+                node.collect_variables(&mut referenced);
+            }
+            for referenced in referenced {
+                let referenced = referenced.to_ascii_lowercase();
+                if referenced == target {
+                    return true;
+                }
+                if visited.insert(referenced.clone()) {
+                    pending.push(referenced);
+                }
+            }
+        }
+        false
+    }
+}
+
+/// N if `var_name` is varN with N in 1..=10.
+fn entry_var_index(var_name: &str) -> Option<usize> {
+    var_name
+        .strip_prefix("var")
+        .and_then(|index| index.parse().ok())
+        .filter(|index| (1..=10).contains(index))
+}
+
+/// `names` without the case-insensitive repetitions, in order.
+fn dedup(names: Vec<&str>) -> Vec<&str> {
+    let mut seen = HashSet::new();
+    names
+        .into_iter()
+        .filter(|name| seen.insert(name.to_ascii_lowercase()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{SrcSrvStream, ValidationFinding};
+
+    #[test]
+    fn validate_stream() {
+        let stream = r#"SRCSRV: ini ------------------------------------------------
+VERSION=2
+SRCSRV: variables ------------------------------------------
+SRCSRVERRVAR=server
+SERVER=https://example.com/%Missing%
+LOOP=%loop2%
+LOOP2=%LOOP%
+SRCSRVTRG=%server%/%var3%/%fnLower%(%var2%)
+SRCSRV: source files ---------------------------------------
+c:\build\a.cpp*a.cpp*abc
+c:\build\b.cpp*b.cpp
+SRCSRV: end ------------------------------------------------"#;
+        let stream = SrcSrvStream::parse(stream.as_bytes()).unwrap();
+        let findings = stream.validate();
+        assert_eq!(
+            findings,
+            [
+                ValidationFinding::InvalidErrorVariable {
+                    name: "server".to_string()
+                },
+                ValidationFinding::UndefinedVariable {
+                    name: "Missing".to_string(),
+                    referenced_by: "SERVER".to_string()
+                },
+                ValidationFinding::RecursiveVariable {
+                    name: "LOOP".to_string()
+                },
+                ValidationFinding::RecursiveVariable {
+                    name: "LOOP2".to_string()
+                },
+                ValidationFinding::UnknownFunction {
+                    name: "fnLower".to_string(),
+                    referenced_by: "SRCSRVTRG".to_string()
+                },
+                ValidationFinding::UnusedVariable {
+                    name: "LOOP".to_string()
+                },
+                ValidationFinding::UnusedVariable {
+                    name: "LOOP2".to_string()
+                },
+                ValidationFinding::MissingEntryFields {
+                    original_path: r#"c:\build\b.cpp"#.to_string(),
+                    field_count: 2,
+                    required_field_count: 3
+                },
+            ]
+        );
+        assert_eq!(
+            findings[7].to_string(),
+            r#"The entry for c:\build\b.cpp has 2 values, but the templates reference var3."#
+        );
+    }
+}
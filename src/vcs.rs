@@ -0,0 +1,94 @@
+/// The kind of version control system that a stream was indexed against.
+/// Returned by [`SrcSrvStream::vcs_kind`](crate::SrcSrvStream::vcs_kind).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum VcsKind {
+    /// Files are downloaded over HTTP(S), e.g. from a raw file URL of a hosted repository.
+    Http,
+    /// Git
+    Git,
+    /// Mercurial
+    Hg,
+    /// Team Foundation Server / Azure DevOps (`tf.exe`)
+    Tfs,
+    /// Perforce (`p4.exe`)
+    Perforce,
+    /// Microsoft Source Depot (`sd.exe`)
+    SourceDepot,
+    /// Subversion
+    Svn,
+    /// CVS
+    Cvs,
+    /// A version control system which this crate does not recognize.
+    Other,
+}
+
+impl VcsKind {
+    /// Classify a version control description, such as the value of the
+    /// `VERCTRL` ini field or of the `SRCSRVVERCTRL` variable.
+    /// Returns `None` if the description is empty.
+    pub fn from_description(description: &str) -> Option<VcsKind> {
+        let description = description.trim().to_ascii_lowercase();
+        let kind = match description.as_str() {
+            "" => return None,
+            "http" | "https" | "web" => VcsKind::Http,
+            "git" | "github" | "gitiles" => VcsKind::Git,
+            "hg" | "mercurial" => VcsKind::Hg,
+            "tfs" | "tf" | "vsts" | "team foundation server" | "team foundation" => VcsKind::Tfs,
+            "p4" | "perforce" => VcsKind::Perforce,
+            "sd" | "source depot" | "sourcedepot" => VcsKind::SourceDepot,
+            "svn" | "subversion" => VcsKind::Svn,
+            "cvs" => VcsKind::Cvs,
+            _ => VcsKind::Other,
+        };
+        Some(kind)
+    }
+
+    /// Classify a command by the program that it runs. Returns `None` if the
+    /// program is not recognized.
+    pub(crate) fn from_command(command: &str) -> Option<VcsKind> {
+        let command = command.to_ascii_lowercase();
+        command
+            .split(|c: char| c.is_whitespace() || c == '"' || c == '&' || c == '(')
+            .find_map(|token| {
+                let program = token.rsplit(['\\', '/']).next().unwrap_or(token);
+                let program = program.strip_suffix(".exe").unwrap_or(program);
+                match program {
+                    "git" => Some(VcsKind::Git),
+                    "hg" => Some(VcsKind::Hg),
+                    "tf" => Some(VcsKind::Tfs),
+                    "p4" => Some(VcsKind::Perforce),
+                    "sd" => Some(VcsKind::SourceDepot),
+                    "svn" => Some(VcsKind::Svn),
+                    "cvs" => Some(VcsKind::Cvs),
+                    _ => None,
+                }
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::VcsKind;
+
+    #[test]
+    fn classification() {
+        assert_eq!(
+            VcsKind::from_description("Team Foundation Server"),
+            Some(VcsKind::Tfs)
+        );
+        assert_eq!(VcsKind::from_description("Subversion"), Some(VcsKind::Svn));
+        assert_eq!(VcsKind::from_description("bzr"), Some(VcsKind::Other));
+        assert_eq!(VcsKind::from_description(""), None);
+        assert_eq!(
+            VcsKind::from_command(r#"tf.exe view /version:%var4% /noprompt "$%var3%""#),
+            Some(VcsKind::Tfs)
+        );
+        assert_eq!(
+            VcsKind::from_command(r#"cmd /c "C:\Program Files\Git\bin\git.exe" show %var3%"#),
+            Some(VcsKind::Git)
+        );
+        assert_eq!(VcsKind::from_command("cmd /c copy a b"), None);
+    }
+}
@@ -0,0 +1,1434 @@
+use crate::errors::WriteError;
+use std::collections::HashSet;
+use std::result::Result;
+
+pub(crate) const INI_SECTION_HEADER: &str =
+    "SRCSRV: ini ------------------------------------------------";
+pub(crate) const VARIABLES_SECTION_HEADER: &str =
+    "SRCSRV: variables ------------------------------------------";
+pub(crate) const SOURCE_FILES_SECTION_HEADER: &str =
+    "SRCSRV: source files ---------------------------------------";
+pub(crate) const END_LINE: &str = "SRCSRV: end ------------------------------------------------";
+
+/// The formatting of a stream which doesn't affect its meaning: the section
+/// header lines, the line terminators and the text around the sections.
+/// With [`ParseOptions::preserve_layout`](crate::ParseOptions::preserve_layout),
+/// this is kept so that the stream can be written back unchanged.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct StreamLayout {
+    /// The text before the ini section, i.e. a byte order mark or nothing.
+    pub(crate) prefix: String,
+    /// The terminator of all lines before the end line.
+    pub(crate) line_ending: &'static str,
+    pub(crate) ini_section_header: String,
+    pub(crate) variables_section_header: String,
+    pub(crate) source_files_section_header: String,
+    pub(crate) end_line: String,
+    /// Everything after the end line, including its line terminator.
+    pub(crate) suffix: String,
+}
+
+impl Default for StreamLayout {
+    fn default() -> Self {
+        StreamLayout {
+            prefix: String::new(),
+            line_ending: "\r\n",
+            ini_section_header: INI_SECTION_HEADER.to_string(),
+            variables_section_header: VARIABLES_SECTION_HEADER.to_string(),
+            source_files_section_header: SOURCE_FILES_SECTION_HEADER.to_string(),
+            end_line: END_LINE.to_string(),
+            suffix: "\r\n".to_string(),
+        }
+    }
+}
+
+/// A repository which the files of a stream created with
+/// [`SrcSrvStreamBuilder::multi_repository`] can be downloaded from.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum SourceRepository {
+    /// Files are downloaded from `base_url` followed by their
+    /// repository-relative path, see [`SrcSrvStreamBuilder::http`].
+    Http { base_url: String },
+    /// Files are downloaded from `raw.githubusercontent.com`, see
+    /// [`SrcSrvStreamBuilder::github`].
+    GitHub {
+        owner: String,
+        repo: String,
+        /// A tag, branch name or commit hash.
+        revision: String,
+    },
+    /// Files are downloaded from a Mercurial server, see
+    /// [`SrcSrvStreamBuilder::hg`].
+    Hg {
+        server_url: String,
+        revision: String,
+    },
+    /// Files are downloaded through the Azure DevOps items REST API, see
+    /// [`SrcSrvStreamBuilder::azure_devops`].
+    AzureDevOps {
+        organization: String,
+        project: String,
+        repository: String,
+        commit: String,
+        api_version: String,
+    },
+}
+
+impl SourceRepository {
+    /// The raw value of the variable for this repository, which evaluates to
+    /// the URL of the file whose repository-relative path is in `var3`.
+    fn url_template(&self) -> String {
+        match self {
+            SourceRepository::Http { base_url } => {
+                format!("{}/%var3%", base_url.trim_end_matches('/'))
+            }
+            SourceRepository::GitHub {
+                owner,
+                repo,
+                revision,
+            } => format!(
+                "https://raw.githubusercontent.com/{}/{}/{}/%var3%",
+                owner, repo, revision
+            ),
+            SourceRepository::Hg {
+                server_url,
+                revision,
+            } => format!(
+                "{}/raw-file/{}/%var3%",
+                server_url.trim_end_matches('/'),
+                revision
+            ),
+            SourceRepository::AzureDevOps {
+                organization,
+                project,
+                repository,
+                commit,
+                api_version,
+            } => format!(
+                "https://dev.azure.com/{}/{}/_apis/git/repositories/{}/items?path=/%var3%&versionDescriptor.versionType=commit&versionDescriptor.version={}&api-version={}&download=true",
+                organization, project, repository, commit, api_version
+            ),
+        }
+    }
+}
+
+/// How a stream created with [`SrcSrvStreamBuilder::gitiles`] describes that
+/// the downloaded files need to be decoded from base64.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GitilesLayout {
+    /// A `SRCSRVCMD` which downloads and decodes the file with a Python
+    /// one-liner, like the streams of Chrome. This works in the debuggers,
+    /// if Python 3 is installed, and this crate recognizes it as a
+    /// [`SourceRetrievalMethod::DownloadWithDecode`](crate::SourceRetrievalMethod::DownloadWithDecode).
+    PythonCommand,
+    /// No command, just the URL in `SRCSRVTRG` and the variable
+    /// `HTTP_CONTENT_ENCODING=base64`, which this crate evaluates to a
+    /// [`SourceRetrievalMethod::DownloadWithDecode`](crate::SourceRetrievalMethod::DownloadWithDecode)
+    /// without running anything. The debuggers ignore the variable and would
+    /// show the encoded file, so only use this for consumers which understand
+    /// it.
+    DecodeVariable,
+}
+
+/// The command of [`GitilesLayout::PythonCommand`], which creates the
+/// directory of the target and writes the decoded response to it.
+const GITILES_PYTHON_COMMAND: &str = r#"cmd /c "mkdir "%SRC_EXTRACT_TARGET_DIR%" & python -c "import urllib.request, base64;url = \"%HTTP_EXTRACT_URL%\";u = urllib.request.urlopen(url);open(r\"%SRC_EXTRACT_TARGET%\", \"wb\").write(base64.b64decode(u.read()))"""#;
+
+/// Builds the bytes of a `srcsrv` stream.
+///
+/// The resulting bytes can be embedded into a PDB file as the `srcsrv` named
+/// stream, for example with `pdbstr.exe -w -s:srcsrv -i:<file>`.
+///
+/// ```
+/// use srcsrv::{SrcSrvStream, SrcSrvStreamBuilder, SourceRetrievalMethod};
+///
+/// # fn wrapper() -> std::result::Result<(), Box<dyn std::error::Error>> {
+/// let mut builder = SrcSrvStreamBuilder::new();
+/// builder
+///     .set_ini_field("VERCTRL", "http")
+///     .set_var("HTTP_ALIAS", "https://raw.githubusercontent.com/baldurk/renderdoc/v1.15/")
+///     .set_var("HTTP_EXTRACT_TARGET", "%HTTP_ALIAS%%var2%")
+///     .set_var("SRCSRVTRG", "%HTTP_EXTRACT_TARGET%")
+///     .add_source_file_entry(&[
+///         r#"C:\build\renderdoc\renderdoc\maths\matrix.cpp"#,
+///         "renderdoc/maths/matrix.cpp",
+///     ]);
+/// let bytes = builder.to_bytes()?;
+///
+/// let stream = SrcSrvStream::parse(&bytes)?;
+/// assert_eq!(
+///     stream.source_for_path(r#"C:\build\renderdoc\renderdoc\maths\matrix.cpp"#, "")?,
+///     Some(SourceRetrievalMethod::Download {
+///         url: "https://raw.githubusercontent.com/baldurk/renderdoc/v1.15/renderdoc/maths/matrix.cpp".to_string()
+///     })
+/// );
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SrcSrvStreamBuilder {
+    /// (field name, field value), in insertion order
+    ini_fields: Vec<(String, String)>,
+    /// (variable name, raw variable value), in insertion order
+    var_fields: Vec<(String, String)>,
+    /// [var1, ..., var10] for each file entry, in insertion order
+    source_file_entries: Vec<Vec<String>>,
+    /// (local path prefix, repository-relative path prefix), see
+    /// map_path_prefix
+    path_prefix_mappings: Vec<(String, String)>,
+    layout: StreamLayout,
+}
+
+impl Default for SrcSrvStreamBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SrcSrvStreamBuilder {
+    /// Create a builder for a stream with `VERSION=2` and no other fields,
+    /// variables or file entries.
+    pub fn new() -> Self {
+        SrcSrvStreamBuilder {
+            ini_fields: vec![("VERSION".to_string(), "2".to_string())],
+            var_fields: Vec::new(),
+            source_file_entries: Vec::new(),
+            path_prefix_mappings: Vec::new(),
+            layout: StreamLayout::default(),
+        }
+    }
+
+    /// Create a builder for a stream which downloads each file from
+    /// `base_url` followed by the repository-relative path of the file, like
+    /// the streams written by Firefox and renderdoc. Add the files with
+    /// [`SrcSrvStreamBuilder::add_http_entry`].
+    ///
+    /// The stream has `VERSION=2` and `VERCTRL=http`, the variable
+    /// `HTTP_ALIAS` with the base URL, `HTTP_EXTRACT_TARGET=%HTTP_ALIAS%%var2%`
+    /// and `SRCSRVTRG=%HTTP_EXTRACT_TARGET%`. A `/` is appended to `base_url`
+    /// if it doesn't end with one.
+    ///
+    /// ```
+    /// use srcsrv::{SrcSrvStream, SrcSrvStreamBuilder, SourceRetrievalMethod};
+    ///
+    /// # fn wrapper() -> std::result::Result<(), Box<dyn std::error::Error>> {
+    /// let bytes = SrcSrvStreamBuilder::http("https://raw.githubusercontent.com/baldurk/renderdoc/v1.15")
+    ///     .add_http_entry(
+    ///         r#"C:\build\renderdoc\renderdoc\maths\matrix.cpp"#,
+    ///         "renderdoc/maths/matrix.cpp",
+    ///         None,
+    ///     )
+    ///     .to_bytes()?;
+    ///
+    /// let stream = SrcSrvStream::parse(&bytes)?;
+    /// assert_eq!(
+    ///     stream.source_for_path(r#"C:\build\renderdoc\renderdoc\maths\matrix.cpp"#, "")?,
+    ///     Some(SourceRetrievalMethod::Download {
+    ///         url: "https://raw.githubusercontent.com/baldurk/renderdoc/v1.15/renderdoc/maths/matrix.cpp".to_string()
+    ///     })
+    /// );
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn http(base_url: &str) -> Self {
+        Self::http_with_target(base_url, "%HTTP_ALIAS%%var2%")
+    }
+
+    /// Like [`SrcSrvStreamBuilder::http`], but the revision of each file is
+    /// inserted between the base URL and the path, with
+    /// `HTTP_EXTRACT_TARGET=%HTTP_ALIAS%%var3%/%var2%`. For example, with the
+    /// base URL `https://hg.mozilla.org/mozilla-central/raw-file/`, files are
+    /// downloaded from `https://hg.mozilla.org/mozilla-central/raw-file/<revision>/<path>`.
+    ///
+    /// Every file needs to be added with a revision.
+    pub fn http_with_revision(base_url: &str) -> Self {
+        Self::http_with_target(base_url, "%HTTP_ALIAS%%var3%/%var2%")
+    }
+
+    /// Create a builder for a stream which downloads the files from
+    /// `raw.githubusercontent.com`, at
+    /// `https://raw.githubusercontent.com/<owner>/<repo>/<revision>/<path>`,
+    /// like the stream that renderdoc's indexing script writes. `revision` is
+    /// a tag, branch name or commit hash.
+    ///
+    /// Add the files with [`SrcSrvStreamBuilder::add_http_entry`] or
+    /// [`SrcSrvStreamBuilder::add_http_entries_below`].
+    ///
+    /// ```
+    /// use srcsrv::{SrcSrvStream, SrcSrvStreamBuilder, SourceRetrievalMethod};
+    ///
+    /// # fn wrapper() -> std::result::Result<(), Box<dyn std::error::Error>> {
+    /// let bytes = SrcSrvStreamBuilder::github("baldurk", "renderdoc", "v1.15")
+    ///     .add_http_entries_below(
+    ///         r#"C:\build\renderdoc"#,
+    ///         &[
+    ///             r#"C:\build\renderdoc\renderdoc\maths\matrix.cpp"#,
+    ///             r#"C:\Program Files\Microsoft Visual Studio\include\vector"#,
+    ///         ],
+    ///     )
+    ///     .to_bytes()?;
+    ///
+    /// let stream = SrcSrvStream::parse(&bytes)?;
+    /// assert_eq!(
+    ///     stream.source_for_path(r#"C:\build\renderdoc\renderdoc\maths\matrix.cpp"#, "")?,
+    ///     Some(SourceRetrievalMethod::Download {
+    ///         url: "https://raw.githubusercontent.com/baldurk/renderdoc/v1.15/renderdoc/maths/matrix.cpp".to_string()
+    ///     })
+    /// );
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn github(owner: &str, repo: &str, revision: &str) -> Self {
+        Self::http(&format!(
+            "https://raw.githubusercontent.com/{}/{}/{}/",
+            owner, repo, revision
+        ))
+    }
+
+    /// Create a builder for a stream which downloads the files from a
+    /// Mercurial server at `<server_url>/raw-file/<revision>/<path>`, like the
+    /// streams of Firefox, for example with the server URL
+    /// `https://hg.mozilla.org/mozilla-central`.
+    ///
+    /// The stream has the variables `HGSERVER` and `REVISION`, and
+    /// `HTTP_EXTRACT_TARGET=%HGSERVER%/raw-file/%REVISION%/%var2%`. Add the
+    /// files with [`SrcSrvStreamBuilder::add_http_entry`] or
+    /// [`SrcSrvStreamBuilder::add_http_entries_below`].
+    ///
+    /// ```
+    /// use srcsrv::{SrcSrvStream, SrcSrvStreamBuilder, SourceRetrievalMethod};
+    ///
+    /// # fn wrapper() -> std::result::Result<(), Box<dyn std::error::Error>> {
+    /// let bytes = SrcSrvStreamBuilder::hg(
+    ///     "https://hg.mozilla.org/mozilla-central",
+    ///     "1706d4d54ec68fae1280305b70a02cb24c16ff68",
+    /// )
+    /// .add_http_entries_below(
+    ///     "/builds/worker/checkouts/gecko",
+    ///     &["/builds/worker/checkouts/gecko/mozglue/build/SSE.cpp"],
+    /// )
+    /// .to_bytes()?;
+    ///
+    /// let stream = SrcSrvStream::parse(&bytes)?;
+    /// assert_eq!(
+    ///     stream.source_for_path("/builds/worker/checkouts/gecko/mozglue/build/SSE.cpp", "")?,
+    ///     Some(SourceRetrievalMethod::Download {
+    ///         url: "https://hg.mozilla.org/mozilla-central/raw-file/1706d4d54ec68fae1280305b70a02cb24c16ff68/mozglue/build/SSE.cpp".to_string()
+    ///     })
+    /// );
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn hg(server_url: &str, revision: &str) -> Self {
+        let mut builder = Self::new();
+        builder
+            .set_ini_field("VERCTRL", "http")
+            .set_var("HGSERVER", server_url.trim_end_matches('/'))
+            .set_var("REVISION", revision)
+            .set_var(
+                "HTTP_EXTRACT_TARGET",
+                "%HGSERVER%/raw-file/%REVISION%/%var2%",
+            )
+            .set_var("SRCSRVTRG", "%HTTP_EXTRACT_TARGET%");
+        builder
+    }
+
+    /// Create a builder for a stream which fetches the files from a gitiles
+    /// server, such as `googlesource.com`, at
+    /// `<repo_url>/+/<revision>/<path>?format=TEXT`. Gitiles returns the
+    /// file contents base64-encoded at this URL, and `layout` selects how the
+    /// stream describes the decoding, see [`GitilesLayout`].
+    ///
+    /// The stream has the variables `GITILES_REPO` and `REVISION`. Add the
+    /// files with [`SrcSrvStreamBuilder::add_http_entry`] or
+    /// [`SrcSrvStreamBuilder::add_http_entries_below`].
+    ///
+    /// ```
+    /// use srcsrv::{ContentEncoding, GitilesLayout, SrcSrvStream, SrcSrvStreamBuilder, SourceRetrievalMethod};
+    ///
+    /// # fn wrapper() -> std::result::Result<(), Box<dyn std::error::Error>> {
+    /// let bytes = SrcSrvStreamBuilder::gitiles(
+    ///     "https://pdfium.googlesource.com/pdfium.git",
+    ///     "dab1161c861cc239e48a17e1a5d729aa12785a53",
+    ///     GitilesLayout::PythonCommand,
+    /// )
+    /// .add_http_entry(r#"C:\build\pdfium\core\fdrm\fx_crypt.cpp"#, "core/fdrm/fx_crypt.cpp", None)
+    /// .to_bytes()?;
+    ///
+    /// let stream = SrcSrvStream::parse(&bytes)?;
+    /// assert_eq!(
+    ///     stream.source_for_path(r#"C:\build\pdfium\core\fdrm\fx_crypt.cpp"#, r#"C:\Cache"#)?,
+    ///     Some(SourceRetrievalMethod::DownloadWithDecode {
+    ///         url: "https://pdfium.googlesource.com/pdfium.git/+/dab1161c861cc239e48a17e1a5d729aa12785a53/core/fdrm/fx_crypt.cpp?format=TEXT".to_string(),
+    ///         encoding: ContentEncoding::Base64,
+    ///     })
+    /// );
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn gitiles(repo_url: &str, revision: &str, layout: GitilesLayout) -> Self {
+        let mut builder = Self::new();
+        builder
+            .set_ini_field("VERCTRL", "http")
+            .set_var("GITILES_REPO", repo_url.trim_end_matches('/'))
+            .set_var("REVISION", revision)
+            .set_var(
+                "HTTP_EXTRACT_URL",
+                "%GITILES_REPO%/+/%REVISION%/%var2%?format=TEXT",
+            );
+        match layout {
+            GitilesLayout::PythonCommand => {
+                builder
+                    .set_var(
+                        "SRC_EXTRACT_TARGET_DIR",
+                        r#"%targ%\%fnbksl%(%var2%)\%REVISION%"#,
+                    )
+                    .set_var(
+                        "SRC_EXTRACT_TARGET",
+                        r#"%SRC_EXTRACT_TARGET_DIR%\%fnfile%(%var1%)"#,
+                    )
+                    .set_var("SRC_EXTRACT_CMD", GITILES_PYTHON_COMMAND)
+                    .set_var("SRCSRVTRG", "%SRC_EXTRACT_TARGET%")
+                    .set_var("SRCSRVCMD", "%SRC_EXTRACT_CMD%");
+            }
+            GitilesLayout::DecodeVariable => {
+                builder
+                    .set_var("HTTP_CONTENT_ENCODING", "base64")
+                    .set_var("SRCSRVTRG", "%HTTP_EXTRACT_URL%");
+            }
+        }
+        builder
+    }
+
+    /// Create a builder for a stream which downloads the files of an Azure
+    /// DevOps Git repository through the items REST API, at
+    /// `https://dev.azure.com/<organization>/<project>/_apis/git/repositories/<repository>/items?path=/<path>&versionDescriptor.versionType=commit&versionDescriptor.version=<commit>&api-version=<api_version>&download=true`.
+    ///
+    /// `project` and `repository` can be names or IDs. They are inserted into
+    /// the URL as they are, so prefer the IDs for names which would need to be
+    /// percent-encoded, e.g. because they contain spaces. `api_version` is
+    /// the version of the REST API, such as `7.1`.
+    ///
+    /// The stream has the variables `AZURE_DEVOPS_REPO`, `REVISION` and
+    /// `API_VERSION`. Add the files with
+    /// [`SrcSrvStreamBuilder::add_http_entry`] or
+    /// [`SrcSrvStreamBuilder::add_http_entries_below`].
+    ///
+    /// ```
+    /// use srcsrv::{SrcSrvStream, SrcSrvStreamBuilder, SourceRetrievalMethod};
+    ///
+    /// # fn wrapper() -> std::result::Result<(), Box<dyn std::error::Error>> {
+    /// let bytes = SrcSrvStreamBuilder::azure_devops(
+    ///     "contoso",
+    ///     "Fabrikam",
+    ///     "engine",
+    ///     "5a3c0e9b1f7d2c4e6a8b0d1f3e5c7a9b2d4f6e8a",
+    ///     "7.1",
+    /// )
+    /// .add_http_entry(r#"D:\a\1\s\src\main.cpp"#, "src/main.cpp", None)
+    /// .to_bytes()?;
+    ///
+    /// let stream = SrcSrvStream::parse(&bytes)?;
+    /// assert_eq!(
+    ///     stream.source_for_path(r#"D:\a\1\s\src\main.cpp"#, "")?,
+    ///     Some(SourceRetrievalMethod::Download {
+    ///         url: "https://dev.azure.com/contoso/Fabrikam/_apis/git/repositories/engine/items?path=/src/main.cpp&versionDescriptor.versionType=commit&versionDescriptor.version=5a3c0e9b1f7d2c4e6a8b0d1f3e5c7a9b2d4f6e8a&api-version=7.1&download=true".to_string()
+    ///     })
+    /// );
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn azure_devops(
+        organization: &str,
+        project: &str,
+        repository: &str,
+        commit: &str,
+        api_version: &str,
+    ) -> Self {
+        let mut builder = Self::new();
+        builder
+            .set_ini_field("VERCTRL", "http")
+            .set_var(
+                "AZURE_DEVOPS_REPO",
+                &format!(
+                    "https://dev.azure.com/{}/{}/_apis/git/repositories/{}",
+                    organization, project, repository
+                ),
+            )
+            .set_var("REVISION", commit)
+            .set_var("API_VERSION", api_version)
+            .set_var(
+                "HTTP_EXTRACT_TARGET",
+                "%AZURE_DEVOPS_REPO%/items?path=/%var2%&versionDescriptor.versionType=commit&versionDescriptor.version=%REVISION%&api-version=%API_VERSION%&download=true",
+            )
+            .set_var("SRCSRVTRG", "%HTTP_EXTRACT_TARGET%");
+        builder
+    }
+
+    /// Create a builder for a stream which gets the files from Team
+    /// Foundation Server with `tf.exe view`, with the same fields and
+    /// variables as the streams written by Microsoft's TFS indexing tools, so
+    /// that debuggers treat it identically. Add the files with
+    /// [`SrcSrvStreamBuilder::add_tfs_entry`].
+    ///
+    /// The stream has `VERSION=3`, `INDEXVERSION=2`,
+    /// `VERCTRL=Team Foundation Server`, the variables `TFS_EXTRACT_CMD` and
+    /// `TFS_EXTRACT_TARGET`, `SRCSRVVERCTRL=tfs`, and `SRCSRVERRDESC=access`
+    /// with `SRCSRVERRVAR=var2`, so that the debugger stops running commands
+    /// for a server once it has denied access.
+    ///
+    /// ```
+    /// use srcsrv::{EvalOptions, SrcSrvStream, SrcSrvStreamBuilder, SourceRetrievalMethod};
+    /// use std::collections::HashMap;
+    ///
+    /// # fn wrapper() -> std::result::Result<(), Box<dyn std::error::Error>> {
+    /// let bytes = SrcSrvStreamBuilder::tfs()
+    ///     .add_tfs_entry(
+    ///         r#"f:\dd\vctools\inc\cvinfo.h"#,
+    ///         "http://vstfdevdiv:8080/DevDiv2",
+    ///         "$/DevDiv/Fx/Rel/vctools/inc/cvinfo.h",
+    ///         1363200,
+    ///     )
+    ///     .to_bytes()?;
+    ///
+    /// let stream = SrcSrvStream::parse(&bytes)?;
+    /// let options = EvalOptions::new().recognize_commands(true);
+    /// assert_eq!(
+    ///     stream.source_for_path_with_vars(r#"f:\dd\vctools\inc\cvinfo.h"#, r#"C:\Cache"#, &options)?,
+    ///     Some(SourceRetrievalMethod::TfsItem {
+    ///         server: "http://vstfdevdiv:8080/DevDiv2".to_string(),
+    ///         item_path: "$/DevDiv/Fx/Rel/vctools/inc/cvinfo.h".to_string(),
+    ///         version: "1363200".to_string(),
+    ///         target_path: r#"C:\Cache\VSTFDEVDIV_DEVDIV2\DevDiv\Fx\Rel\vctools\inc\cvinfo.h\1363200\cvinfo.h"#.to_string(),
+    ///         env: HashMap::new(),
+    ///         error_persistence_version_control: Some("VSTFDEVDIV_DEVDIV2".to_string()),
+    ///     })
+    /// );
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn tfs() -> Self {
+        let mut builder = Self::new();
+        builder
+            .set_ini_field("VERSION", "3")
+            .set_ini_field("INDEXVERSION", "2")
+            .set_ini_field("VERCTRL", "Team Foundation Server")
+            .set_var(
+                "TFS_EXTRACT_CMD",
+                r#"tf.exe view /version:%var4% /noprompt "$%var3%" /server:%fnvar%(%var2%) /output:%srcsrvtrg%"#,
+            )
+            .set_var(
+                "TFS_EXTRACT_TARGET",
+                r#"%targ%\%var2%%fnbksl%(%var3%)\%var4%\%fnfile%(%var1%)"#,
+            )
+            .set_var("SRCSRVVERCTRL", "tfs")
+            .set_var("SRCSRVERRDESC", "access")
+            .set_var("SRCSRVERRVAR", "var2")
+            .set_var("SRCSRVTRG", "%TFS_extract_target%")
+            .set_var("SRCSRVCMD", "%TFS_extract_cmd%");
+        builder
+    }
+
+    /// Add a file entry to a stream which was created with
+    /// [`SrcSrvStreamBuilder::tfs`]. `local_path` is the path of the file at
+    /// build time, `server_url` the URL of the team project collection,
+    /// `item_path` the server path of the file, with or without the leading
+    /// `$`, and `changeset` the changeset of the file.
+    ///
+    /// Like Microsoft's tools, the entry refers to the server with a variable
+    /// whose name is derived from the URL, e.g. `VSTFDEVDIV_DEVDIV2` for
+    /// `http://vstfdevdiv.redmond.corp.microsoft.com:8080/DevDiv2`. The
+    /// variable is added to the stream if it doesn't exist yet. If a variable
+    /// with this name already refers to a different server, e.g. for
+    /// `http://tfs.contoso.com/DefaultCollection` and
+    /// `http://tfs.fabrikam.com/DefaultCollection`, the name gets a suffix
+    /// like `_2`.
+    pub fn add_tfs_entry(
+        &mut self,
+        local_path: &str,
+        server_url: &str,
+        item_path: &str,
+        changeset: u64,
+    ) -> &mut Self {
+        let base_name = tfs_server_var_name(server_url);
+        let mut server_var = base_name.clone();
+        let mut suffix = 1;
+        loop {
+            match get_field(&self.var_fields, &server_var) {
+                Some(value) if value == server_url => break,
+                Some(_) => {
+                    suffix += 1;
+                    server_var = format!("{}_{}", base_name, suffix);
+                }
+                None => {
+                    self.set_var(&server_var, server_url);
+                    break;
+                }
+            }
+        }
+        let item_path = item_path.replace('\\', "/");
+        let item_path = item_path.trim_start_matches('$').trim_start_matches('/');
+        self.add_source_file_entry(&[
+            local_path,
+            &server_var,
+            &format!("/{}", item_path),
+            &changeset.to_string(),
+        ])
+    }
+
+    /// Create a builder for a stream which gets the files from the Perforce
+    /// server at `port` with `p4.exe print`. Add the files with
+    /// [`SrcSrvStreamBuilder::add_perforce_entry`] or
+    /// [`SrcSrvStreamBuilder::add_perforce_entries_below`].
+    ///
+    /// The stream has `VERCTRL=Perforce`, the variables `P4PORT`,
+    /// `P4_EXTRACT_CMD` and `P4_EXTRACT_TARGET`, and `SRCSRVVERCTRL=perforce`.
+    /// Each entry has the depot path without the leading `//` in `var2` and
+    /// the revision, like `#7`, or the changelist, like `@1234`, in `var3`.
+    ///
+    /// ```
+    /// use srcsrv::{EvalOptions, SrcSrvStream, SrcSrvStreamBuilder, SourceRetrievalMethod};
+    /// use std::collections::HashMap;
+    ///
+    /// # fn wrapper() -> std::result::Result<(), Box<dyn std::error::Error>> {
+    /// let bytes = SrcSrvStreamBuilder::perforce("ssl:perforce.example.com:1666")
+    ///     .add_perforce_entries_below(
+    ///         "//depot/game",
+    ///         r#"D:\p4\game"#,
+    ///         &[r#"D:\p4\game\src\main.cpp"#],
+    ///         "@1234",
+    ///     )
+    ///     .to_bytes()?;
+    ///
+    /// let stream = SrcSrvStream::parse(&bytes)?;
+    /// let options = EvalOptions::new().recognize_commands(true);
+    /// assert_eq!(
+    ///     stream.source_for_path_with_vars(r#"D:\p4\game\src\main.cpp"#, r#"C:\Cache"#, &options)?,
+    ///     Some(SourceRetrievalMethod::Perforce {
+    ///         port: Some("ssl:perforce.example.com:1666".to_string()),
+    ///         depot_path: "//depot/game/src/main.cpp".to_string(),
+    ///         revision: "1234".to_string(),
+    ///         target_path: r#"C:\Cache\depot\game\src\main.cpp\@1234\main.cpp"#.to_string(),
+    ///         env: HashMap::new(),
+    ///         error_persistence_version_control: None,
+    ///     })
+    /// );
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn perforce(port: &str) -> Self {
+        let mut builder = Self::new();
+        builder
+            .set_ini_field("VERCTRL", "Perforce")
+            .set_var("P4PORT", port)
+            .set_var(
+                "P4_EXTRACT_CMD",
+                r#"p4.exe -p %P4PORT% print -o "%srcsrvtrg%" -q "//%var2%%var3%""#,
+            )
+            .set_var(
+                "P4_EXTRACT_TARGET",
+                r#"%targ%\%fnbksl%(%var2%)\%var3%\%fnfile%(%var1%)"#,
+            )
+            .set_var("SRCSRVVERCTRL", "perforce")
+            .set_var("SRCSRVTRG", "%P4_EXTRACT_TARGET%")
+            .set_var("SRCSRVCMD", "%P4_EXTRACT_CMD%");
+        builder
+    }
+
+    /// Add a file entry to a stream which was created with
+    /// [`SrcSrvStreamBuilder::perforce`]. `local_path` is the path of the file
+    /// at build time and `depot_path` its path in the depot, like
+    /// `//depot/game/src/main.cpp`. `revision` is the revision of the file,
+    /// like `7` or `#7`, or the changelist of the build, like `@1234`.
+    pub fn add_perforce_entry(
+        &mut self,
+        local_path: &str,
+        depot_path: &str,
+        revision: &str,
+    ) -> &mut Self {
+        let depot_path = depot_path.replace('\\', "/");
+        let depot_path = depot_path.trim_start_matches('/');
+        let revision = if revision.starts_with(['#', '@']) {
+            revision.to_string()
+        } else {
+            format!("#{}", revision)
+        };
+        self.add_source_file_entry(&[local_path, depot_path, &revision])
+    }
+
+    /// Add a file entry with [`SrcSrvStreamBuilder::add_perforce_entry`] for
+    /// each of `local_paths` which is below `checkout_root`, the directory of
+    /// the workspace at build time which is mapped to the depot directory
+    /// `depot_root`. Paths are compared like in
+    /// [`SrcSrvStreamBuilder::add_http_entries_below`].
+    pub fn add_perforce_entries_below<S: AsRef<str>>(
+        &mut self,
+        depot_root: &str,
+        checkout_root: &str,
+        local_paths: &[S],
+        revision: &str,
+    ) -> &mut Self {
+        for local_path in local_paths {
+            let local_path = local_path.as_ref();
+            if let Some(relative_path) = self.repo_relative_path(checkout_root, local_path) {
+                let depot_path = format!(
+                    "{}/{}",
+                    depot_root.trim_end_matches('/'),
+                    relative_path.replace('\\', "/").trim_start_matches('/')
+                );
+                self.add_perforce_entry(local_path, &depot_path, revision);
+            }
+        }
+        self
+    }
+
+    /// Create a builder for a stream whose files are downloaded from several
+    /// repositories, for example a project and its submodules, which can be
+    /// on different servers, at different revisions and use different URL
+    /// schemes. Add the files with
+    /// [`SrcSrvStreamBuilder::add_repository_entry`] or
+    /// [`SrcSrvStreamBuilder::add_repository_entries_below`].
+    ///
+    /// Each repository gets a variable `REPO1`, `REPO2`, etc. with the URL of
+    /// its files, and each file entry names the variable of its repository in
+    /// `var2`, which `SRCSRVTRG=%fnvar%(%var2%)` looks up. Only repositories
+    /// whose files can be downloaded without a command can be mixed this way,
+    /// see [`SourceRepository`].
+    ///
+    /// ```
+    /// use srcsrv::{SrcSrvStream, SrcSrvStreamBuilder, SourceRepository, SourceRetrievalMethod};
+    ///
+    /// # fn wrapper() -> std::result::Result<(), Box<dyn std::error::Error>> {
+    /// let app = SourceRepository::GitHub {
+    ///     owner: "example".to_string(),
+    ///     repo: "app".to_string(),
+    ///     revision: "0123abcd".to_string(),
+    /// };
+    /// let zlib = SourceRepository::GitHub {
+    ///     owner: "madler".to_string(),
+    ///     repo: "zlib".to_string(),
+    ///     revision: "v1.3.1".to_string(),
+    /// };
+    /// let bytes = SrcSrvStreamBuilder::multi_repository()
+    ///     // Submodules first, so that their files aren't added for the outer repository.
+    ///     .add_repository_entries_below(&zlib, r#"C:\build\app\third_party\zlib"#, &[
+    ///         r#"C:\build\app\third_party\zlib\inflate.c"#,
+    ///     ])
+    ///     .add_repository_entries_below(&app, r#"C:\build\app"#, &[
+    ///         r#"C:\build\app\src\main.cpp"#,
+    ///         r#"C:\build\app\third_party\zlib\inflate.c"#,
+    ///     ])
+    ///     .to_bytes()?;
+    ///
+    /// let stream = SrcSrvStream::parse(&bytes)?;
+    /// assert_eq!(
+    ///     stream.source_for_path(r#"C:\build\app\third_party\zlib\inflate.c"#, "")?,
+    ///     Some(SourceRetrievalMethod::Download {
+    ///         url: "https://raw.githubusercontent.com/madler/zlib/v1.3.1/inflate.c".to_string()
+    ///     })
+    /// );
+    /// assert_eq!(
+    ///     stream.source_for_path(r#"C:\build\app\src\main.cpp"#, "")?,
+    ///     Some(SourceRetrievalMethod::Download {
+    ///         url: "https://raw.githubusercontent.com/example/app/0123abcd/src/main.cpp".to_string()
+    ///     })
+    /// );
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn multi_repository() -> Self {
+        let mut builder = Self::new();
+        builder
+            .set_ini_field("VERCTRL", "http")
+            .set_var("SRCSRVTRG", "%fnvar%(%var2%)");
+        builder
+    }
+
+    /// Add a file entry to a stream which was created with
+    /// [`SrcSrvStreamBuilder::multi_repository`]. `local_path` is the path of
+    /// the file at build time, and `repo_relative_path` is its path in
+    /// `repository`, with backslashes replaced by forward slashes.
+    ///
+    /// The variable of `repository` is added to the stream if this is the
+    /// first file from it.
+    pub fn add_repository_entry(
+        &mut self,
+        local_path: &str,
+        repository: &SourceRepository,
+        repo_relative_path: &str,
+    ) -> &mut Self {
+        let template = repository.url_template();
+        let existing_var = self
+            .var_fields
+            .iter()
+            .find(|(name, value)| is_repository_var_name(name) && *value == template)
+            .map(|(name, _)| name.clone());
+        let repository_var = match existing_var {
+            Some(name) => name,
+            None => {
+                let repository_count = self
+                    .var_fields
+                    .iter()
+                    .filter(|(name, _)| is_repository_var_name(name))
+                    .count();
+                let name = format!("REPO{}", repository_count + 1);
+                self.set_var(&name, &template);
+                name
+            }
+        };
+        let url_path = repo_relative_path.replace('\\', "/");
+        let url_path = url_path.trim_start_matches('/');
+        self.add_source_file_entry(&[local_path, &repository_var, url_path])
+    }
+
+    /// Add a file entry with [`SrcSrvStreamBuilder::add_repository_entry`]
+    /// for each of `local_paths` which is below `checkout_root`, the
+    /// directory of the checkout of `repository` at build time, and which
+    /// doesn't have an entry yet. Paths are compared like in
+    /// [`SrcSrvStreamBuilder::add_http_entries_below`].
+    ///
+    /// Add the files of submodules before the files of the repositories which
+    /// contain them, so that each file is attributed to the innermost
+    /// repository.
+    pub fn add_repository_entries_below<S: AsRef<str>>(
+        &mut self,
+        repository: &SourceRepository,
+        checkout_root: &str,
+        local_paths: &[S],
+    ) -> &mut Self {
+        let existing_paths: HashSet<String> = self
+            .source_file_entries
+            .iter()
+            .filter_map(|vars| vars.first())
+            .map(|path| path.to_ascii_lowercase())
+            .collect();
+        for local_path in local_paths {
+            let local_path = local_path.as_ref();
+            if existing_paths.contains(&local_path.to_ascii_lowercase()) {
+                continue;
+            }
+            if let Some(relative_path) = self.repo_relative_path(checkout_root, local_path) {
+                self.add_repository_entry(local_path, repository, &relative_path);
+            }
+        }
+        self
+    }
+
+    fn http_with_target(base_url: &str, extract_target: &str) -> Self {
+        let mut builder = Self::new();
+        let separator = if base_url.ends_with('/') { "" } else { "/" };
+        builder
+            .set_ini_field("VERCTRL", "http")
+            .set_var("HTTP_ALIAS", &format!("{}{}", base_url, separator))
+            .set_var("HTTP_EXTRACT_TARGET", extract_target)
+            .set_var("SRCSRVTRG", "%HTTP_EXTRACT_TARGET%");
+        builder
+    }
+
+    /// Add a file entry to a stream which was created with
+    /// [`SrcSrvStreamBuilder::http`],
+    /// [`SrcSrvStreamBuilder::http_with_revision`],
+    /// [`SrcSrvStreamBuilder::github`], [`SrcSrvStreamBuilder::hg`],
+    /// [`SrcSrvStreamBuilder::gitiles`] or
+    /// [`SrcSrvStreamBuilder::azure_devops`].
+    /// `local_path` is the path of the file at build time, and
+    /// `repo_relative_path` is appended to the base URL, with backslashes
+    /// replaced by forward slashes. `revision` is only used by streams which
+    /// were created with
+    /// [`SrcSrvStreamBuilder::http_with_revision`].
+    pub fn add_http_entry(
+        &mut self,
+        local_path: &str,
+        repo_relative_path: &str,
+        revision: Option<&str>,
+    ) -> &mut Self {
+        let url_path = repo_relative_path.replace('\\', "/");
+        let url_path = url_path.trim_start_matches('/');
+        match revision {
+            Some(revision) => self.add_source_file_entry(&[local_path, url_path, revision]),
+            None => self.add_source_file_entry(&[local_path, url_path]),
+        }
+    }
+
+    /// Add a file entry with [`SrcSrvStreamBuilder::add_http_entry`] for each
+    /// of `local_paths` which is below `checkout_root`, the directory of the
+    /// repository checkout at build time, with the path relative to it.
+    /// Paths are compared ASCII case-insensitively, with `/` and `\` as
+    /// separators. Other paths, such as the headers of the compiler, are
+    /// skipped, unless they match one of the mappings of
+    /// [`SrcSrvStreamBuilder::map_path_prefix`].
+    pub fn add_http_entries_below<S: AsRef<str>>(
+        &mut self,
+        checkout_root: &str,
+        local_paths: &[S],
+    ) -> &mut Self {
+        for local_path in local_paths {
+            let local_path = local_path.as_ref();
+            if let Some(relative_path) = self.repo_relative_path(checkout_root, local_path) {
+                self.add_http_entry(local_path, &relative_path, None);
+            }
+        }
+        self
+    }
+
+    /// Map the local paths which start with the directory `local_prefix` to
+    /// repository-relative paths which start with `repo_relative_prefix`, in
+    /// [`SrcSrvStreamBuilder::add_http_entries_below`] and
+    /// [`SrcSrvStreamBuilder::add_repository_entries_below`]. Mappings take
+    /// precedence over the checkout root, and the mapping with the longest
+    /// matching prefix is used. Use an empty `repo_relative_prefix` to map a
+    /// directory to the root of the repository.
+    ///
+    /// This lets callers pass the paths from the compiler unchanged, for
+    /// example when the build ran in a different directory than the checkout,
+    /// or for headers which the build copied into the object directory.
+    ///
+    /// ```
+    /// use srcsrv::{SrcSrvStream, SrcSrvStreamBuilder, SourceRetrievalMethod};
+    ///
+    /// # fn wrapper() -> std::result::Result<(), Box<dyn std::error::Error>> {
+    /// let bytes = SrcSrvStreamBuilder::hg("https://hg.mozilla.org/mozilla-central", "1706d4d5")
+    ///     .map_path_prefix("/builds/worker/workspace/obj-build/dist/include/mozilla", "mfbt")
+    ///     .add_http_entries_below(
+    ///         "/builds/worker/checkouts/gecko",
+    ///         &[
+    ///             "/builds/worker/checkouts/gecko/mozglue/build/SSE.cpp",
+    ///             "/builds/worker/workspace/obj-build/dist/include/mozilla/Assertions.h",
+    ///         ],
+    ///     )
+    ///     .to_bytes()?;
+    ///
+    /// let stream = SrcSrvStream::parse(&bytes)?;
+    /// assert_eq!(
+    ///     stream.source_for_path("/builds/worker/workspace/obj-build/dist/include/mozilla/Assertions.h", "")?,
+    ///     Some(SourceRetrievalMethod::Download {
+    ///         url: "https://hg.mozilla.org/mozilla-central/raw-file/1706d4d5/mfbt/Assertions.h".to_string()
+    ///     })
+    /// );
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn map_path_prefix(&mut self, local_prefix: &str, repo_relative_prefix: &str) -> &mut Self {
+        self.path_prefix_mappings
+            .push((local_prefix.to_string(), repo_relative_prefix.to_string()));
+        self
+    }
+
+    /// The repository-relative path of `local_path`, from the longest
+    /// matching path prefix mapping or from `checkout_root`.
+    fn repo_relative_path(&self, checkout_root: &str, local_path: &str) -> Option<String> {
+        let mapping = self
+            .path_prefix_mappings
+            .iter()
+            .filter_map(|(local_prefix, repo_relative_prefix)| {
+                let rest = strip_path_prefix(local_path, local_prefix)?;
+                Some((local_prefix.len(), repo_relative_prefix, rest))
+            })
+            .max_by_key(|(prefix_len, ..)| *prefix_len);
+        match mapping {
+            Some((_, repo_relative_prefix, rest)) => Some(format!(
+                "{}/{}",
+                repo_relative_prefix.trim_end_matches(['/', '\\']),
+                rest.trim_start_matches(['/', '\\'])
+            )),
+            None => strip_path_prefix(local_path, checkout_root).map(str::to_string),
+        }
+    }
+
+    /// Create a builder which writes the stream with `layout`, and with the
+    /// given fields, including fields which are defined more than once.
+    pub(crate) fn with_layout(
+        layout: StreamLayout,
+        ini_fields: &[(&str, &str)],
+        var_fields: &[(&str, &str)],
+    ) -> Self {
+        let to_strings = |fields: &[(&str, &str)]| {
+            fields
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect()
+        };
+        SrcSrvStreamBuilder {
+            ini_fields: to_strings(ini_fields),
+            var_fields: to_strings(var_fields),
+            source_file_entries: Vec::new(),
+            path_prefix_mappings: Vec::new(),
+            layout,
+        }
+    }
+
+    /// Set the value of a field in the ini section, such as `VERSION`,
+    /// `INDEXVERSION`, `VERCTRL` or `DATETIME`.
+    /// The field name is case-insensitive; setting a field that already exists
+    /// replaces its value.
+    pub fn set_ini_field(&mut self, field_name: &str, value: &str) -> &mut Self {
+        set_field(&mut self.ini_fields, field_name, value);
+        self
+    }
+
+    /// Set the raw, unevaluated value of a variable in the variables section.
+    /// The variable name is case-insensitive; setting a variable that already
+    /// exists replaces its value.
+    ///
+    /// Every stream needs a `SRCSRVTRG` variable.
+    pub fn set_var(&mut self, var_name: &str, value: &str) -> &mut Self {
+        set_field(&mut self.var_fields, var_name, value);
+        self
+    }
+
+    /// Add an entry to the source files section. `vars` are the values of
+    /// var1, ..., varN for this entry, and var1 should be the original file path.
+    pub fn add_source_file_entry<S: AsRef<str>>(&mut self, vars: &[S]) -> &mut Self {
+        self.source_file_entries
+            .push(vars.iter().map(|var| var.as_ref().to_string()).collect());
+        self
+    }
+
+    /// Replace the raw value of each variable, and the values of var2, ...,
+    /// varN of each entry, with the result of `f`.
+    pub(crate) fn map_values(&mut self, f: impl Fn(&str) -> String) {
+        for (_, value) in &mut self.var_fields {
+            *value = f(value);
+        }
+        for vars in &mut self.source_file_entries {
+            for value in vars.iter_mut().skip(1) {
+                *value = f(value);
+            }
+        }
+    }
+
+    /// Serialize the stream into the `srcsrv` text format.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, WriteError> {
+        if !has_field(&self.ini_fields, "VERSION") {
+            return Err(WriteError::MissingVersion);
+        }
+        if !has_field(&self.var_fields, "SRCSRVTRG") {
+            return Err(WriteError::MissingSrcSrvTrgField);
+        }
+        for (name, value) in self.ini_fields.iter().chain(&self.var_fields) {
+            check_field(name, value)?;
+        }
+        for vars in &self.source_file_entries {
+            if vars.is_empty() {
+                return Err(WriteError::EmptySourceFileEntry);
+            }
+            if vars.len() > 10 {
+                return Err(WriteError::TooManyEntryValues);
+            }
+            for var in vars {
+                check_value(var)?;
+                if var.contains('*') {
+                    return Err(WriteError::AsteriskInEntryValue(var.clone()));
+                }
+            }
+        }
+
+        Ok(write_stream(
+            &self.layout,
+            self.ini_fields
+                .iter()
+                .map(|(n, v)| (n.as_str(), v.as_str())),
+            self.var_fields
+                .iter()
+                .map(|(n, v)| (n.as_str(), v.as_str())),
+            self.source_file_entries.iter().map(|vars| vars.as_slice()),
+        ))
+    }
+}
+
+/// Write the stream sections without any validation.
+pub(crate) fn write_stream<'s, S: AsRef<str>>(
+    layout: &StreamLayout,
+    ini_fields: impl Iterator<Item = (&'s str, &'s str)>,
+    var_fields: impl Iterator<Item = (&'s str, &'s str)>,
+    source_file_entries: impl Iterator<Item = impl AsRef<[S]>>,
+) -> Vec<u8> {
+    let line_ending = layout.line_ending;
+    let mut s = layout.prefix.clone();
+
+    push_line(&mut s, &layout.ini_section_header, line_ending);
+    for (name, value) in ini_fields {
+        push_field_line(&mut s, name, value, line_ending);
+    }
+
+    push_line(&mut s, &layout.variables_section_header, line_ending);
+    for (name, value) in var_fields {
+        push_field_line(&mut s, name, value, line_ending);
+    }
+
+    push_line(&mut s, &layout.source_files_section_header, line_ending);
+    for vars in source_file_entries {
+        for (i, var) in vars.as_ref().iter().enumerate() {
+            if i != 0 {
+                s.push('*');
+            }
+            s.push_str(var.as_ref());
+        }
+        s.push_str(line_ending);
+    }
+
+    s.push_str(&layout.end_line);
+    s.push_str(&layout.suffix);
+    s.into_bytes()
+}
+
+fn set_field(fields: &mut Vec<(String, String)>, name: &str, value: &str) {
+    match fields
+        .iter_mut()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+    {
+        Some((_, v)) => *v = value.to_string(),
+        None => fields.push((name.to_string(), value.to_string())),
+    }
+}
+
+/// The part of `path` after the directory `prefix`, starting with a
+/// separator, if `path` is below `prefix`. Paths are compared ASCII
+/// case-insensitively, with `/` and `\` as equivalent separators.
+fn strip_path_prefix<'p>(path: &'p str, prefix: &str) -> Option<&'p str> {
+    let is_separator = |b: u8| b == b'/' || b == b'\\';
+    let prefix = prefix.trim_end_matches(['/', '\\']);
+    let matches = path
+        .get(..prefix.len())?
+        .bytes()
+        .zip(prefix.bytes())
+        .all(|(a, b)| a.eq_ignore_ascii_case(&b) || (is_separator(a) && is_separator(b)));
+    let rest = &path[prefix.len()..];
+    if matches && rest.starts_with(['/', '\\']) {
+        Some(rest)
+    } else {
+        None
+    }
+}
+
+/// Whether `name` is the name of a repository variable of
+/// [`SrcSrvStreamBuilder::multi_repository`], i.e. `REPO` and a number.
+fn is_repository_var_name(name: &str) -> bool {
+    match name.strip_prefix("REPO") {
+        Some(number) => !number.is_empty() && number.bytes().all(|b| b.is_ascii_digit()),
+        None => false,
+    }
+}
+
+/// The name of the variable for a TFS server in the streams of Microsoft's
+/// tools: the first label of the host name and the last path component of the
+/// URL, in uppercase, e.g. `VSTFDEVDIV_DEVDIV2` for
+/// `http://vstfdevdiv.redmond.corp.microsoft.com:8080/DevDiv2`.
+fn tfs_server_var_name(server_url: &str) -> String {
+    let without_scheme = match server_url.split_once("://") {
+        Some((_, rest)) => rest,
+        None => server_url,
+    };
+    let (host, path) = match without_scheme.split_once('/') {
+        Some((host, path)) => (host, path),
+        None => (without_scheme, ""),
+    };
+    let host_label = host.split(['.', ':']).next().unwrap_or(host);
+    let mut name = host_label.to_string();
+    if let Some(collection) = path.rsplit('/').find(|c| !c.is_empty()) {
+        name.push('_');
+        name.push_str(collection);
+    }
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn has_field(fields: &[(String, String)], name: &str) -> bool {
+    fields.iter().any(|(n, _)| n.eq_ignore_ascii_case(name))
+}
+
+fn get_field<'f>(fields: &'f [(String, String)], name: &str) -> Option<&'f str> {
+    fields
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.as_str())
+}
+
+fn check_value(value: &str) -> Result<(), WriteError> {
+    if value.contains(['\r', '\n']) {
+        return Err(WriteError::LineBreakInValue(value.to_string()));
+    }
+    Ok(())
+}
+
+fn check_field(name: &str, value: &str) -> Result<(), WriteError> {
+    if name.is_empty() || name.contains(['=', '\r', '\n']) {
+        return Err(WriteError::InvalidFieldName(name.to_string()));
+    }
+    check_value(value)
+}
+
+fn push_field_line(s: &mut String, name: &str, value: &str, line_ending: &str) {
+    s.push_str(name);
+    s.push('=');
+    s.push_str(value);
+    s.push_str(line_ending);
+}
+
+fn push_line(s: &mut String, line: &str, line_ending: &str) {
+    s.push_str(line);
+    s.push_str(line_ending);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        ContentEncoding, EvalOptions, GitilesLayout, SourceRepository, SourceRetrievalMethod,
+        SrcSrvStream, SrcSrvStreamBuilder, WriteError,
+    };
+
+    #[test]
+    fn round_trip() {
+        let mut builder = SrcSrvStreamBuilder::new();
+        builder
+            .set_ini_field("VERCTRL", "http")
+            .set_ini_field("version", "1")
+            .set_var("HGSERVER", "https://hg.mozilla.org/mozilla-central")
+            .set_var("SRCSRVTRG", "%hgserver%/raw-file/%var3%/%var2%")
+            .add_source_file_entry(&[
+                "/builds/worker/checkouts/gecko/mozglue/build/SSE.cpp",
+                "mozglue/build/SSE.cpp",
+                "1706d4d54ec68fae1280305b70a02cb24c16ff68",
+            ]);
+        let bytes = builder.to_bytes().unwrap();
+        assert_eq!(
+            std::str::from_utf8(&bytes).unwrap(),
+            "SRCSRV: ini ------------------------------------------------\r\n\
+             VERSION=1\r\n\
+             VERCTRL=http\r\n\
+             SRCSRV: variables ------------------------------------------\r\n\
+             HGSERVER=https://hg.mozilla.org/mozilla-central\r\n\
+             SRCSRVTRG=%hgserver%/raw-file/%var3%/%var2%\r\n\
+             SRCSRV: source files ---------------------------------------\r\n\
+             /builds/worker/checkouts/gecko/mozglue/build/SSE.cpp*mozglue/build/SSE.cpp*1706d4d54ec68fae1280305b70a02cb24c16ff68\r\n\
+             SRCSRV: end ------------------------------------------------\r\n"
+        );
+
+        let stream = SrcSrvStream::parse(&bytes).unwrap();
+        assert_eq!(stream.version(), 1);
+        assert_eq!(
+            stream
+                .source_for_path("/builds/worker/checkouts/gecko/mozglue/build/SSE.cpp", "")
+                .unwrap(),
+            Some(SourceRetrievalMethod::Download {
+                url: "https://hg.mozilla.org/mozilla-central/raw-file/1706d4d54ec68fae1280305b70a02cb24c16ff68/mozglue/build/SSE.cpp".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn invalid_values() {
+        let mut builder = SrcSrvStreamBuilder::new();
+        assert_eq!(builder.to_bytes(), Err(WriteError::MissingSrcSrvTrgField));
+        builder.set_var("SRCSRVTRG", "%var2%");
+        builder.add_source_file_entry(&["a*b"]);
+        assert_eq!(
+            builder.to_bytes(),
+            Err(WriteError::AsteriskInEntryValue("a*b".to_string()))
+        );
+    }
+
+    #[test]
+    fn http_preset() {
+        let bytes = SrcSrvStreamBuilder::http_with_revision(
+            "https://hg.mozilla.org/mozilla-central/raw-file",
+        )
+        .add_http_entry(
+            "/builds/worker/checkouts/gecko/mozglue/build/SSE.cpp",
+            r#"mozglue\build\SSE.cpp"#,
+            Some("1706d4d54ec68fae1280305b70a02cb24c16ff68"),
+        )
+        .to_bytes()
+        .unwrap();
+        assert_eq!(
+            std::str::from_utf8(&bytes).unwrap(),
+            "SRCSRV: ini ------------------------------------------------\r\n\
+             VERSION=2\r\n\
+             VERCTRL=http\r\n\
+             SRCSRV: variables ------------------------------------------\r\n\
+             HTTP_ALIAS=https://hg.mozilla.org/mozilla-central/raw-file/\r\n\
+             HTTP_EXTRACT_TARGET=%HTTP_ALIAS%%var3%/%var2%\r\n\
+             SRCSRVTRG=%HTTP_EXTRACT_TARGET%\r\n\
+             SRCSRV: source files ---------------------------------------\r\n\
+             /builds/worker/checkouts/gecko/mozglue/build/SSE.cpp*mozglue/build/SSE.cpp*1706d4d54ec68fae1280305b70a02cb24c16ff68\r\n\
+             SRCSRV: end ------------------------------------------------\r\n"
+        );
+
+        let stream = SrcSrvStream::parse(&bytes).unwrap();
+        assert_eq!(
+            stream
+                .source_for_path("/builds/worker/checkouts/gecko/mozglue/build/SSE.cpp", "")
+                .unwrap(),
+            Some(SourceRetrievalMethod::Download {
+                url: "https://hg.mozilla.org/mozilla-central/raw-file/1706d4d54ec68fae1280305b70a02cb24c16ff68/mozglue/build/SSE.cpp".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn gitiles_decode_variable() {
+        let bytes = SrcSrvStreamBuilder::gitiles(
+            "https://chromium.googlesource.com/chromium/src/",
+            "4c8e1b2f",
+            GitilesLayout::DecodeVariable,
+        )
+        .add_http_entries_below(
+            r#"C:\b\s\w\ir\cache\builder\src"#,
+            &[r#"C:\b\s\w\ir\cache\builder\src\base\files\file.cc"#],
+        )
+        .to_bytes()
+        .unwrap();
+        let stream = SrcSrvStream::parse(&bytes).unwrap();
+        assert_eq!(stream.get_raw_var("HTTP_CONTENT_ENCODING"), Some("base64"));
+        assert_eq!(stream.get_raw_var("SRCSRVCMD"), None);
+        assert_eq!(
+            stream
+                .source_for_path(r#"C:\b\s\w\ir\cache\builder\src\base\files\file.cc"#, r#"C:\Cache"#)
+                .unwrap(),
+            Some(SourceRetrievalMethod::DownloadWithDecode {
+                url: "https://chromium.googlesource.com/chromium/src/+/4c8e1b2f/base/files/file.cc?format=TEXT".to_string(),
+                encoding: ContentEncoding::Base64,
+            })
+        );
+    }
+
+    #[test]
+    fn tfs_server_var_name() {
+        assert_eq!(
+            super::tfs_server_var_name("http://vstfdevdiv.redmond.corp.microsoft.com:8080/DevDiv2"),
+            "VSTFDEVDIV_DEVDIV2"
+        );
+        assert_eq!(
+            super::tfs_server_var_name("https://tfs.example.com/tfs/Default-Collection/"),
+            "TFS_DEFAULT_COLLECTION"
+        );
+        assert_eq!(
+            super::tfs_server_var_name("http://buildbox:8080"),
+            "BUILDBOX"
+        );
+    }
+
+    #[test]
+    fn tfs_server_var_collision() {
+        let bytes = SrcSrvStreamBuilder::tfs()
+            .add_tfs_entry(
+                r#"c:\build\a.cpp"#,
+                "http://tfs.contoso.com/DefaultCollection",
+                "$/Contoso/a.cpp",
+                1,
+            )
+            .add_tfs_entry(
+                r#"c:\build\b.cpp"#,
+                "http://tfs.fabrikam.com/DefaultCollection",
+                "$/Fabrikam/b.cpp",
+                2,
+            )
+            .add_tfs_entry(
+                r#"c:\build\c.cpp"#,
+                "http://tfs.contoso.com/DefaultCollection",
+                "$/Contoso/c.cpp",
+                3,
+            )
+            .to_bytes()
+            .unwrap();
+        let stream = SrcSrvStream::parse(&bytes).unwrap();
+        assert_eq!(
+            stream.get_raw_var("TFS_DEFAULTCOLLECTION"),
+            Some("http://tfs.contoso.com/DefaultCollection")
+        );
+        assert_eq!(
+            stream.get_raw_var("TFS_DEFAULTCOLLECTION_2"),
+            Some("http://tfs.fabrikam.com/DefaultCollection")
+        );
+        let server = |path| match stream
+            .source_for_path_with_vars(path, "", &EvalOptions::new().recognize_commands(true))
+            .unwrap()
+        {
+            Some(SourceRetrievalMethod::TfsItem { server, .. }) => server,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(
+            server(r#"c:\build\a.cpp"#),
+            "http://tfs.contoso.com/DefaultCollection"
+        );
+        assert_eq!(
+            server(r#"c:\build\b.cpp"#),
+            "http://tfs.fabrikam.com/DefaultCollection"
+        );
+        assert_eq!(
+            server(r#"c:\build\c.cpp"#),
+            "http://tfs.contoso.com/DefaultCollection"
+        );
+    }
+
+    #[test]
+    fn multi_repository_vars() {
+        let hg = SourceRepository::Hg {
+            server_url: "https://hg.mozilla.org/mozilla-central/".to_string(),
+            revision: "1706d4d5".to_string(),
+        };
+        let http = SourceRepository::Http {
+            base_url: "https://example.com/src".to_string(),
+        };
+        let bytes = SrcSrvStreamBuilder::multi_repository()
+            .add_repository_entry("/builds/a.cpp", &hg, "a.cpp")
+            .add_repository_entry("/builds/vendor/b.cpp", &http, r#"\vendor\b.cpp"#)
+            .add_repository_entry("/builds/c.cpp", &hg, "c.cpp")
+            .to_bytes()
+            .unwrap();
+        assert_eq!(
+            std::str::from_utf8(&bytes).unwrap(),
+            "SRCSRV: ini ------------------------------------------------\r\n\
+             VERSION=2\r\n\
+             VERCTRL=http\r\n\
+             SRCSRV: variables ------------------------------------------\r\n\
+             SRCSRVTRG=%fnvar%(%var2%)\r\n\
+             REPO1=https://hg.mozilla.org/mozilla-central/raw-file/1706d4d5/%var3%\r\n\
+             REPO2=https://example.com/src/%var3%\r\n\
+             SRCSRV: source files ---------------------------------------\r\n\
+             /builds/a.cpp*REPO1*a.cpp\r\n\
+             /builds/vendor/b.cpp*REPO2*vendor/b.cpp\r\n\
+             /builds/c.cpp*REPO1*c.cpp\r\n\
+             SRCSRV: end ------------------------------------------------\r\n"
+        );
+        let stream = SrcSrvStream::parse(&bytes).unwrap();
+        assert_eq!(
+            stream.source_for_path("/builds/vendor/b.cpp", "").unwrap(),
+            Some(SourceRetrievalMethod::Download {
+                url: "https://example.com/src/vendor/b.cpp".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn path_prefix_mappings() {
+        let mut builder = SrcSrvStreamBuilder::http("https://example.com/src");
+        builder
+            .map_path_prefix(r#"D:\a\1\s\"#, "")
+            .map_path_prefix("d:/a/1/s/out/gen", "generated");
+        assert_eq!(
+            builder.repo_relative_path(r#"C:\src"#, r#"D:\a\1\s\base\file.cc"#),
+            Some(r#"/base\file.cc"#.to_string())
+        );
+        assert_eq!(
+            builder.repo_relative_path(r#"C:\src"#, r#"D:\A\1\S\out\gen\version.h"#),
+            Some("generated/version.h".to_string())
+        );
+        assert_eq!(
+            builder.repo_relative_path(r#"C:\src"#, r#"C:/src/main.cpp"#),
+            Some("/main.cpp".to_string())
+        );
+        assert_eq!(
+            builder.repo_relative_path(r#"C:\src"#, r#"D:\a\1\sdk\windows.h"#),
+            None
+        );
+    }
+}